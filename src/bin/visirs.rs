@@ -0,0 +1,143 @@
+//! Command line front end for CI jobs
+//!
+//! visirs group <dir> [--threshold <bits>] [--json <file>]
+//! visirs hash <file>
+//! visirs compare <a> <b>
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::ExitCode;
+
+use visualimg_rs::visual_grouping::grouping::compare_assets;
+use visualimg_rs::visual_grouping::scan::{
+    ScanOptions, media_type_from_extension, scan_directory, sniff_media_type,
+};
+use visualimg_rs::{
+    Asset, AssetKind, AssetSource, GroupingOptions, group_assets_by_visual_similarity,
+    process_asset,
+};
+
+const USAGE: &str = "Usage:
+  visirs group <dir> [--threshold <bits>] [--json <file>]
+  visirs hash <file>
+  visirs compare <a> <b>";
+
+/// Everything went fine and, for `group`, at least two files were grouped
+const EXIT_OK: u8 = 0;
+/// `group` ran but every file ended up on its own
+const EXIT_NO_GROUPS: u8 = 1;
+/// Bad arguments or a failure
+const EXIT_ERROR: u8 = 2;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("group") => group(&args[1..]),
+        Some("hash") => hash(&args[1..]),
+        Some("compare") => compare(&args[1..]),
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(EXIT_OK)
+        }
+        _ => Err(anyhow::anyhow!("Unknown command\n{}", USAGE)),
+    };
+
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("visirs: {:#}", err);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+/// Group the media files of a directory and write the groups as JSON
+fn group(args: &[String]) -> Result<u8> {
+    let mut dir = None;
+    let mut options = GroupingOptions::default();
+    let mut json_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => {
+                let value = args.next().context("--threshold needs a value")?;
+                options.threshold = value
+                    .parse()
+                    .with_context(|| format!("Invalid threshold {}", value))?;
+            }
+            "--json" => json_path = Some(args.next().context("--json needs a file")?),
+            flag if flag.starts_with("--") => anyhow::bail!("Unknown option {}\n{}", flag, USAGE),
+            path if dir.is_none() => dir = Some(path),
+            path => anyhow::bail!("Unexpected argument {}\n{}", path, USAGE),
+        }
+    }
+    let dir = dir.with_context(|| format!("Missing directory\n{}", USAGE))?;
+
+    let assets = scan_directory(dir, &ScanOptions::default())?;
+    let result = group_assets_by_visual_similarity(assets, Some(options), None, None)?;
+    for failed in &result.failed {
+        eprintln!("visirs: skipped {}: {}", failed.asset_id, failed.reason);
+    }
+
+    let json = serde_json::to_string_pretty(&result.groups).context("Failed to serialize groups")?;
+    match json_path {
+        Some(path) => {
+            std::fs::write(path, json).with_context(|| format!("Failed to write {}", path))?;
+        }
+        None => println!("{}", json),
+    }
+
+    let grouped = result.groups.iter().any(|group| group.assets.len() > 1);
+    Ok(if grouped { EXIT_OK } else { EXIT_NO_GROUPS })
+}
+
+/// Print the hex hash of every sampled frame, one per line
+fn hash(args: &[String]) -> Result<u8> {
+    let [path] = args else {
+        anyhow::bail!("hash takes one file\n{}", USAGE);
+    };
+
+    let asset = file_asset(Path::new(path))?;
+    let (hashed, _temp_dir) = process_asset(&asset, &GroupingOptions::default(), None)?;
+    for frame in &hashed.frames {
+        println!("{}", frame.hash.to_hex());
+    }
+
+    Ok(EXIT_OK)
+}
+
+/// Print the smallest frame distance between two files
+fn compare(args: &[String]) -> Result<u8> {
+    let [a, b] = args else {
+        anyhow::bail!("compare takes two files\n{}", USAGE);
+    };
+
+    let report = compare_assets(&file_asset(Path::new(a))?, &file_asset(Path::new(b))?, None)?;
+    let distance = report.min_distance.context("The files can't be compared")?;
+    println!("{}", distance);
+
+    Ok(EXIT_OK)
+}
+
+/// Asset for a media file, its id is the file name
+fn file_asset(path: &Path) -> Result<Asset> {
+    let (mime_type, is_video) = media_type_from_extension(path)
+        .or_else(|| sniff_media_type(path))
+        .with_context(|| format!("Unsupported file type: {}", path.display()))?;
+    let name = path
+        .file_name()
+        .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+        .into_owned();
+
+    Ok(Asset {
+        id: name.clone(),
+        name,
+        source: AssetSource::Path(path.to_path_buf()),
+        mime_type: mime_type.to_string(),
+        is_video,
+        precomputed: None,
+        kind: AssetKind::File,
+    })
+}
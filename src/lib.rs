@@ -1,23 +1,13 @@
 #![deny(clippy::all)]
 
-mod visual_grouping;
-
-use napi_derive::napi;
-use napi::bindgen_prelude::*;
-
-#[napi]
-pub fn plus_100(input: u32) -> u32 {
-    input + 100
-}
-
-#[napi(object)]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct JsAsset {
-    pub id: String,
-    pub name: String,
-    pub path: String,
-    pub mime_type: String,
-    pub is_video: bool,
-}
+pub mod visual_grouping;
 
+#[cfg(feature = "node")]
+pub mod node;
 
+pub use visual_grouping::grouping::{
+    group_assets_by_visual_similarity, group_hashed_assets, hash_assets, process_asset,
+};
+pub use visual_grouping::{
+    Asset, AssetGroup, AssetKind, AssetSource, GroupingOptions, GroupingResult, HashedAsset,
+};
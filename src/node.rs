@@ -0,0 +1,2615 @@
+//! Node.js bindings, built with the `node` feature
+
+use napi_derive::napi;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+
+use anyhow::Context as _;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use crate::visual_grouping::grouping::{
+    ProgressCallback, assign_to_groups, compare_assets, compute_similarity_matrix,
+    export_similarity_graph, find_duplicate_pairs, find_similar_assets,
+    group_assets_by_visual_similarity, group_hashed_assets, hash_assets, suggest_threshold,
+};
+use crate::visual_grouping::audio::AudioFingerprint;
+use crate::visual_grouping::cache::HashCache;
+use crate::visual_grouping::graph::SimilarityGraph;
+use crate::visual_grouping::json::group_assets_json;
+use crate::visual_grouping::scan::{ScanOptions, scan_directory};
+use crate::visual_grouping::session::GroupingSession;
+use crate::visual_grouping::hash::{
+    HashExtras, PerceptualHash, generate_perceptual_hash, generate_perceptual_hash_from_bytes,
+    hamming_distance,
+};
+#[cfg(feature = "video")]
+use crate::visual_grouping::video::{
+    ContactSheetOptions, VideoMetadata, extract_encoded_frames, extract_frames_from_video,
+    generate_contact_sheet, init_ffmpeg, probe_video,
+};
+use crate::visual_grouping::{
+    Asset, AssetGroup, AssetGroupWithHashes, AssetHashes, AssetKind, AssetSource, AssetTimings,
+    AssignmentMode, AssignmentResult, AudioMode, CancellationToken, Cancelled, CropResistance,
+    DuplicatePair, FailedAsset, FrameData, FrameFileOptions, FrameFormat, GroupAssignment,
+    GroupedAsset, FrameSamplingConfig, FrameStrategy, GroupingOptions, GroupingResult,
+    GroupingStats, HashAlgorithm, HashConfig, HashedAsset, Invariance, Linkage, NamingStrategy,
+    PrecomputedHashes, ProgressEvent, ResizeStrategy, SimilarMatch, SimilarityMatrix,
+    SimilarityReport, ThresholdSuggestion, ThumbnailOptions, log_scope, set_log_scope,
+    DEFAULT_SCENE_CUT_THRESHOLD, DEFAULT_STRICT_THRESHOLD,
+};
+
+#[napi]
+pub fn plus_100(input: u32) -> u32 {
+    input + 100
+}
+
+/// Initialize FFmpeg, calling it more than once is a cheap no-op
+/// Does nothing when video support is not compiled in
+#[napi]
+pub fn init() -> Result<()> {
+    #[cfg(feature = "video")]
+    init_ffmpeg().map_err(to_napi_error)?;
+    Ok(())
+}
+
+/// Optional features compiled into the native module
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsCapabilities {
+    /// Videos, HEIF and AVIF images, `probeVideo` and `extractVideoFrames`
+    pub video: bool,
+    /// Assets at http(s) URLs
+    pub remote: bool,
+}
+
+/// Report which optional features this build supports
+#[napi]
+pub fn capabilities() -> JsCapabilities {
+    JsCapabilities {
+        video: cfg!(feature = "video"),
+        remote: cfg!(feature = "remote"),
+    }
+}
+
+/// Asset passed from JavaScript, exactly one of `path` or `data` must be set
+#[napi(object)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct JsAsset {
+    pub id: String,
+    pub name: String,
+    /// File path or http(s) URL, a Buffer holds the raw bytes of a path that
+    /// isn't valid UTF-8
+    #[serde(skip)]
+    pub path: Option<Either<String, Buffer>>,
+    /// Encoded file contents, used instead of reading `path` from disk
+    #[serde(skip)]
+    pub data: Option<Buffer>,
+    pub mime_type: String,
+    pub is_video: bool,
+    /// Hashes from a previous run, skips decoding when set together with width and height
+    #[serde(skip)]
+    pub frames: Option<Vec<JsFrameHash>>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Video duration in seconds, part of the precomputed hashes
+    pub duration: Option<f64>,
+    /// Placement of a group member: "square" | "vertical" | "horizontal", ignored on input
+    pub variant: Option<String>,
+    /// BlurHash placeholder of a group member when `blurhash` is set, ignored on input
+    pub blurhash: Option<String>,
+    /// Dominant colors as "#rrggbb", the most common first, part of the precomputed hashes
+    pub dominant_colors: Option<Vec<String>>,
+    /// Audio fingerprint codes of a video, part of the precomputed hashes
+    pub audio_fingerprint: Option<Vec<u32>>,
+    /// True on a group member whose audio differs from the representative's with
+    /// `audioMode: "annotate"`, ignored on input
+    pub audio_differs: Option<bool>,
+    /// "file" (default) | "imageSequence", an image sequence is a directory of
+    /// numbered images hashed like an animation. A directory path is read as
+    /// one either way
+    pub kind: Option<String>,
+    /// File names of the frames of an image sequence, with `*` and `?` wildcards
+    /// such as "frame_*.png", every image in the directory by default
+    pub sequence_pattern: Option<String>,
+    /// Ids of the other inputs with the same path, merged into this group member
+    /// with `dedupeByPath`
+    pub alias_ids: Option<Vec<String>>,
+}
+
+/// Hash of a single frame, as exchanged with JavaScript
+#[napi(object)]
+pub struct JsFrameHash {
+    pub frame_number: u32,
+    /// Presentation time of the frame in seconds, 0 for images
+    pub timestamp_secs: Option<f64>,
+    pub hash: Buffer,
+    /// Hashes of the mirrored and rotated frame, present when `invariance` is enabled
+    pub variants: Option<Vec<Buffer>>,
+    /// Hashes of the grid regions of the frame, present when `cropResistant` is set
+    pub regions: Option<Vec<Buffer>>,
+    /// BlurHash of the first frame, present when `blurhash` is set
+    pub blurhash: Option<String>,
+    /// Consecutive sampled frames collapsed into this one, present when more than 1
+    pub represented_frames: Option<u32>,
+    /// Black or flat frame of a video with nothing else, never compared
+    pub uniform: Option<bool>,
+}
+
+impl From<FrameData> for JsFrameHash {
+    fn from(frame: FrameData) -> Self {
+        JsFrameHash {
+            frame_number: frame.frame_number as u32,
+            timestamp_secs: Some(frame.timestamp_secs),
+            hash: frame.hash.bytes.into(),
+            variants: (!frame.variants.is_empty()).then(|| {
+                frame.variants.into_iter().map(|variant| variant.bytes.into()).collect()
+            }),
+            regions: (!frame.regions.is_empty()).then(|| {
+                frame.regions.into_iter().map(|region| region.bytes.into()).collect()
+            }),
+            blurhash: frame.blurhash,
+            represented_frames: (frame.represented_frames > 1)
+                .then_some(frame.represented_frames as u32),
+            uniform: frame.uniform.then_some(true),
+        }
+    }
+}
+
+impl JsFrameHash {
+    /// Frame hash passed in from JavaScript, which carries only the hash bytes,
+    /// so they are taken to be produced with `hash_config`
+    fn into_frame(self, hash_config: HashConfig) -> Result<FrameData> {
+        let frame_number = self.frame_number;
+        let parse = |bytes: Buffer| {
+            PerceptualHash::new(hash_config, bytes.to_vec())
+                .with_context(|| format!("Invalid hash for frame {}", frame_number))
+                .map_err(to_invalid_arg)
+        };
+
+        let parse_all = |hashes: Option<Vec<Buffer>>| {
+            hashes.unwrap_or_default().into_iter().map(parse).collect::<Result<Vec<_>>>()
+        };
+
+        Ok(FrameData {
+            blurhash: self.blurhash,
+            represented_frames: self.represented_frames.map_or(1, |count| count.max(1) as usize),
+            uniform: self.uniform.unwrap_or(false),
+            ..FrameData::new(
+                self.frame_number as usize,
+                self.timestamp_secs.unwrap_or_default(),
+                parse(self.hash)?,
+            )
+            .with_variants(parse_all(self.variants)?)
+            .with_regions(parse_all(self.regions)?)
+        })
+    }
+}
+
+fn to_frames(frames: Vec<JsFrameHash>, hash_config: HashConfig) -> Result<Vec<FrameData>> {
+    frames
+        .into_iter()
+        .map(|frame| frame.into_frame(hash_config))
+        .collect()
+}
+
+impl JsAsset {
+    /// Convert to an `Asset`, precomputed frames are read as `hash_config` hashes
+    fn into_asset(self, hash_config: HashConfig) -> Result<Asset> {
+        let asset = self;
+        let source = match (asset.path, asset.data) {
+            (Some(path), None) => AssetSource::Path(path_from_js(path)?),
+            (None, Some(data)) => AssetSource::Bytes(data.to_vec()),
+            _ => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("Asset {} must have exactly one of path or data", asset.id),
+                ));
+            }
+        };
+
+        let precomputed = match (asset.frames, asset.width, asset.height) {
+            (None, _, _) => None,
+            (Some(frames), Some(width), Some(height)) => Some(PrecomputedHashes {
+                frames: to_frames(frames, hash_config)?,
+                width,
+                height,
+                duration: asset.duration,
+                dominant_colors: colors_from_js(asset.dominant_colors)?,
+                audio_fingerprint: asset.audio_fingerprint.map(|codes| AudioFingerprint { codes }),
+            }),
+            (Some(_), _, _) => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("Asset {} has precomputed frames but no width/height", asset.id),
+                ));
+            }
+        };
+
+        let kind = match asset.kind.as_deref().map(str::to_ascii_lowercase).as_deref() {
+            None | Some("file") if asset.sequence_pattern.is_none() => AssetKind::File,
+            None | Some("imagesequence" | "image_sequence") => AssetKind::ImageSequence {
+                pattern: asset.sequence_pattern,
+            },
+            Some("file") => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("Asset {} has a sequencePattern but is not an imageSequence", asset.id),
+                ));
+            }
+            Some(other) => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("Unknown asset kind: {}", other),
+                ));
+            }
+        };
+
+        Ok(Asset {
+            id: asset.id,
+            name: asset.name,
+            source,
+            mime_type: asset.mime_type,
+            is_video: asset.is_video,
+            precomputed,
+            kind,
+        })
+    }
+
+    /// Group member passed back from JavaScript, its dimensions and variant are
+    /// kept as reported earlier
+    fn into_grouped_asset(self, hash_config: HashConfig) -> Result<GroupedAsset> {
+        let (width, height) = (self.width, self.height);
+        let (variant, blurhash) = (self.variant.clone(), self.blurhash.clone());
+        let dominant_colors = colors_from_js(self.dominant_colors.clone())?;
+        let audio_differs = self.audio_differs.unwrap_or(false);
+        let alias_ids = self.alias_ids.clone().unwrap_or_default();
+        Ok(GroupedAsset {
+            asset: self.into_asset(hash_config)?,
+            width: width.unwrap_or(0),
+            height: height.unwrap_or(0),
+            variant,
+            blurhash,
+            dominant_colors,
+            audio_differs,
+            alias_ids,
+        })
+    }
+}
+
+impl From<Asset> for JsAsset {
+    // In-memory data and precomputed hashes are not echoed back to JavaScript,
+    // hashes are reported once in the grouping result instead
+    fn from(asset: Asset) -> Self {
+        let (kind, sequence_pattern) = match asset.kind {
+            AssetKind::File => (None, None),
+            AssetKind::ImageSequence { pattern } => (Some("imageSequence".to_string()), pattern),
+        };
+        JsAsset {
+            id: asset.id,
+            name: asset.name,
+            path: asset.source.path().map(path_to_js),
+            data: None,
+            mime_type: asset.mime_type,
+            is_video: asset.is_video,
+            frames: None,
+            width: None,
+            height: None,
+            duration: None,
+            variant: None,
+            blurhash: None,
+            dominant_colors: None,
+            audio_fingerprint: None,
+            audio_differs: None,
+            kind,
+            sequence_pattern,
+            alias_ids: None,
+        }
+    }
+}
+
+impl From<GroupedAsset> for JsAsset {
+    fn from(member: GroupedAsset) -> Self {
+        JsAsset {
+            width: Some(member.width),
+            height: Some(member.height),
+            variant: member.variant,
+            blurhash: member.blurhash,
+            dominant_colors: colors_to_js(member.dominant_colors),
+            audio_differs: member.audio_differs.then_some(true),
+            alias_ids: (!member.alias_ids.is_empty()).then_some(member.alias_ids),
+            ..JsAsset::from(member.asset)
+        }
+    }
+}
+
+/// Path passed from JavaScript as a string or as the raw bytes of the path
+fn path_from_js(path: Either<String, Buffer>) -> Result<PathBuf> {
+    match path {
+        Either::A(path) => Ok(PathBuf::from(path)),
+        #[cfg(unix)]
+        Either::B(bytes) => {
+            use std::os::unix::ffi::OsStrExt;
+            Ok(PathBuf::from(std::ffi::OsStr::from_bytes(&bytes)))
+        }
+        // Windows paths are Unicode, Node hands them out as UTF-8
+        #[cfg(not(unix))]
+        Either::B(bytes) => String::from_utf8(bytes.to_vec())
+            .map(PathBuf::from)
+            .map_err(|_| Error::new(Status::InvalidArg, "Path is not valid UTF-8".to_string())),
+    }
+}
+
+/// Path for JavaScript, a string unless it isn't valid UTF-8
+/// Colors as "#rrggbb" strings, `None` when there are none
+fn colors_to_js(colors: Vec<[u8; 3]>) -> Option<Vec<String>> {
+    (!colors.is_empty()).then(|| {
+        colors
+            .into_iter()
+            .map(|[red, green, blue]| format!("#{:02x}{:02x}{:02x}", red, green, blue))
+            .collect()
+    })
+}
+
+/// Colors passed from JavaScript as "#rrggbb" strings
+fn colors_from_js(colors: Option<Vec<String>>) -> Result<Vec<[u8; 3]>> {
+    colors
+        .unwrap_or_default()
+        .iter()
+        .map(|color| {
+            let hex = color.strip_prefix('#').unwrap_or(color);
+            let channel = |range: std::ops::Range<usize>| {
+                hex.get(range).and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            };
+            match (hex.len(), channel(0..2), channel(2..4), channel(4..6)) {
+                (6, Some(red), Some(green), Some(blue)) => Ok([red, green, blue]),
+                _ => Err(Error::new(
+                    Status::InvalidArg,
+                    format!("Invalid color {}, expected #rrggbb", color),
+                )),
+            }
+        })
+        .collect()
+}
+
+fn path_to_js(path: &Path) -> Either<String, Buffer> {
+    match path.to_str() {
+        Some(path) => Either::A(path.to_string()),
+        #[cfg(unix)]
+        None => {
+            use std::os::unix::ffi::OsStrExt;
+            Either::B(path.as_os_str().as_bytes().to_vec().into())
+        }
+        #[cfg(not(unix))]
+        None => Either::A(path.to_string_lossy().into_owned()),
+    }
+}
+
+fn to_assets(assets: Vec<JsAsset>, hash_config: HashConfig) -> Result<Vec<Asset>> {
+    assets
+        .into_iter()
+        .map(|asset| asset.into_asset(hash_config))
+        .collect()
+}
+
+/// Group of visually similar assets, as returned to JavaScript
+#[napi(object)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct JsAssetGroup {
+    pub id: String,
+    pub name: String,
+    pub assets: Vec<JsAsset>,
+    pub representative_asset_id: String,
+    pub confidence: f64,
+    pub distances: Option<HashMap<String, f64>>,
+    /// Byte-identical copies among the members, mapped to the member hashed in their place
+    pub exact_duplicates: Option<HashMap<String, String>>,
+    /// Clusters of near-identical members, set when `strictThreshold` or `looseThreshold` is
+    pub subgroups: Option<Vec<JsAssetGroup>>,
+    /// JPEG thumbnail of the representative, set when `thumbnails` is
+    pub thumbnail_path: Option<String>,
+    /// Why the thumbnail could not be written
+    pub thumbnail_error: Option<String>,
+}
+
+impl From<AssetGroup> for JsAssetGroup {
+    fn from(group: AssetGroup) -> Self {
+        JsAssetGroup {
+            id: group.id,
+            name: group.name,
+            assets: group.assets.into_iter().map(JsAsset::from).collect(),
+            representative_asset_id: group.representative_asset_id,
+            confidence: group.confidence,
+            distances: group.distances.map(|distances| distances.into_iter().collect()),
+            exact_duplicates: (!group.exact_duplicates.is_empty())
+                .then(|| group.exact_duplicates.into_iter().collect()),
+            subgroups: (!group.subgroups.is_empty())
+                .then(|| group.subgroups.into_iter().map(JsAssetGroup::from).collect()),
+            thumbnail_path: group.thumbnail_path.map(|path| path.to_string_lossy().into_owned()),
+            thumbnail_error: group.thumbnail_error,
+        }
+    }
+}
+
+/// Asset that failed processing, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JsFailedAsset {
+    pub asset_id: String,
+    pub reason: String,
+}
+
+impl From<FailedAsset> for JsFailedAsset {
+    fn from(failed: FailedAsset) -> Self {
+        JsFailedAsset {
+            asset_id: failed.asset_id,
+            reason: failed.reason,
+        }
+    }
+}
+
+/// Computed hashes of an asset, as returned to JavaScript for caching
+#[napi(object)]
+pub struct JsAssetHashes {
+    pub asset_id: String,
+    pub frames: Vec<JsFrameHash>,
+    pub width: u32,
+    pub height: u32,
+    pub duration: Option<f64>,
+    /// True when some video frames were lost to decode errors
+    pub degraded: bool,
+    /// Dominant colors as "#rrggbb", the most common first
+    pub dominant_colors: Option<Vec<String>>,
+    /// Audio fingerprint codes, present when `audioMode` is set and the video has audio
+    pub audio_fingerprint: Option<Vec<u32>>,
+    /// "uniform" | "interval" | "cappedUniform" | "keyframes" | "scenes", how the
+    /// frames of a video or animation were sampled
+    pub sampling_policy: Option<String>,
+    /// Packets and frames of a video the decoder failed on
+    pub decode_errors: Option<u32>,
+}
+
+impl From<AssetHashes> for JsAssetHashes {
+    fn from(hashes: AssetHashes) -> Self {
+        JsAssetHashes {
+            asset_id: hashes.asset_id,
+            frames: hashes.frames.into_iter().map(JsFrameHash::from).collect(),
+            width: hashes.width,
+            height: hashes.height,
+            duration: hashes.duration,
+            degraded: hashes.degraded,
+            dominant_colors: colors_to_js(hashes.dominant_colors),
+            audio_fingerprint: hashes.audio_fingerprint.map(|fingerprint| fingerprint.codes),
+            sampling_policy: hashes.sampling_policy.map(|policy| policy.as_str().to_string()),
+            decode_errors: Some(hashes.decode_errors as u32),
+        }
+    }
+}
+
+impl JsAssetHashes {
+    fn into_asset_hashes(self, hash_config: HashConfig) -> Result<AssetHashes> {
+        Ok(AssetHashes {
+            asset_id: self.asset_id,
+            frames: to_frames(self.frames, hash_config)?,
+            width: self.width,
+            height: self.height,
+            duration: self.duration,
+            degraded: self.degraded,
+            dominant_colors: colors_from_js(self.dominant_colors)?,
+            audio_fingerprint: self.audio_fingerprint.map(|codes| AudioFingerprint { codes }),
+            sampling_policy: self
+                .sampling_policy
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(to_invalid_arg)?,
+            decode_errors: self.decode_errors.unwrap_or(0) as usize,
+        })
+    }
+}
+
+/// Result of a grouping run, as returned to JavaScript
+#[napi(object)]
+pub struct JsGroupingResult {
+    pub groups: Vec<JsAssetGroup>,
+    pub failed: Vec<JsFailedAsset>,
+    pub hashes: Vec<JsAssetHashes>,
+    pub stats: JsGroupingStats,
+    /// Time spent on each asset keyed by asset id, failed assets included
+    pub timings: HashMap<String, JsAssetTimings>,
+}
+
+impl From<GroupingResult> for JsGroupingResult {
+    fn from(result: GroupingResult) -> Self {
+        JsGroupingResult {
+            groups: result.groups.into_iter().map(JsAssetGroup::from).collect(),
+            failed: result.failed.into_iter().map(JsFailedAsset::from).collect(),
+            hashes: result.hashes.into_iter().map(JsAssetHashes::from).collect(),
+            stats: JsGroupingStats::from(result.stats),
+            timings: result
+                .timings
+                .into_iter()
+                .map(|(id, timings)| (id, JsAssetTimings::from(timings)))
+                .collect(),
+        }
+    }
+}
+
+/// Milliseconds spent processing one asset
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsAssetTimings {
+    pub probe_ms: f64,
+    pub extraction_ms: f64,
+    /// Decoding and hashing, everything besides probing and extraction
+    pub hashing_ms: f64,
+    pub total_ms: f64,
+}
+
+impl From<AssetTimings> for JsAssetTimings {
+    fn from(timings: AssetTimings) -> Self {
+        JsAssetTimings {
+            probe_ms: timings.probe_ms,
+            extraction_ms: timings.extraction_ms,
+            hashing_ms: timings.hashing_ms,
+            total_ms: timings.total_ms,
+        }
+    }
+}
+
+/// Timings in milliseconds and work counters of a grouping run
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsGroupingStats {
+    pub probe_ms: f64,
+    pub extraction_ms: f64,
+    pub hashing_ms: f64,
+    pub comparison_ms: f64,
+    pub frames_extracted: u32,
+    pub comparisons: u32,
+    pub cache_hits: u32,
+    pub peak_workers: u32,
+    /// Videos with frames decoded on a hardware device
+    pub hardware_decoded_assets: u32,
+    /// Packets and frames the video decoders failed on
+    pub decode_errors: u32,
+}
+
+impl From<GroupingStats> for JsGroupingStats {
+    fn from(stats: GroupingStats) -> Self {
+        JsGroupingStats {
+            probe_ms: stats.probe_ms,
+            extraction_ms: stats.extraction_ms,
+            hashing_ms: stats.hashing_ms,
+            comparison_ms: stats.comparison_ms,
+            frames_extracted: stats.frames_extracted as u32,
+            comparisons: stats.comparisons as u32,
+            cache_hits: stats.cache_hits as u32,
+            peak_workers: stats.peak_workers as u32,
+            hardware_decoded_assets: stats.hardware_decoded_assets as u32,
+            decode_errors: stats.decode_errors as u32,
+        }
+    }
+}
+
+/// Where group thumbnails go, `<dir>/<group id>.jpg` with the longest side `size`
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsThumbnailOptions {
+    pub dir: String,
+    pub size: u32,
+}
+
+/// Grouping options, omitted fields keep their defaults
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct JsGroupingOptions {
+    /// Same as `thresholdBits`
+    pub threshold: Option<u32>,
+    /// Maximum hamming distance in bits (exclusive) for two frames to match
+    pub threshold_bits: Option<u32>,
+    /// Threshold as a fraction of the hash bits in (0, 1), e.g. 0.23
+    pub threshold_ratio: Option<f64>,
+    /// Subgroup threshold for re-encoded copies, turns on two-tier output (default 5)
+    pub strict_threshold: Option<u32>,
+    /// Group threshold of two-tier output, same as `thresholdBits` (default 15)
+    pub loose_threshold: Option<u32>,
+    pub hash_size: Option<u32>,
+    /// "blockhash" | "mean" | "gradient" | "doubleGradient" | "verticalGradient" | "dct" (pHash)
+    pub hash_algorithm: Option<String>,
+    /// Equalize brightness and contrast before hashing, for re-exports through
+    /// encoders that shift gamma or contrast
+    pub normalize_brightness: Option<bool>,
+    /// `[r, g, b]` color transparent images are composited onto before hashing,
+    /// defaults to white
+    pub alpha_background: Option<Vec<u32>>,
+    /// How images are fitted into the square they are hashed from: "cover"
+    /// (default, crops the edges) | "contain" (pads, keeps the edges) | "stretch"
+    pub resize_strategy: Option<String>,
+    /// Side in pixels of that square, defaults to 256
+    pub resize_target_size: Option<u32>,
+    /// Also match copies that are "mirror"ed, or mirrored and rotated with "rotation",
+    /// defaults to "none"
+    pub invariance: Option<String>,
+    /// Also match crops of the same creative, such as a 16:9 banner cut from a
+    /// square original, by comparing grid regions. Disables the aspect ratio check
+    pub crop_resistant: Option<bool>,
+    /// Regions that must match in crop-resistant mode, defaults to 3
+    pub min_matching_regions: Option<u32>,
+    /// Maximum hamming distance (exclusive) for two regions to match, defaults to 10
+    pub region_threshold: Option<u32>,
+    /// "uniform" (default) | "interval" | "keyframes" | "scenes"
+    pub frame_strategy: Option<String>,
+    /// Seconds between frames, implies the "interval" strategy when no strategy is given
+    pub video_frame_interval: Option<f64>,
+    /// Most frames sampled from one video, defaults to 8
+    pub max_frames: Option<u32>,
+    /// Hard limit on the frames of one video whatever the strategy, defaults to 16
+    /// or `maxFrames` when that is higher. Interval sampling needing more spreads
+    /// this many frames evenly over the whole video
+    pub max_frames_per_video: Option<u32>,
+    /// Minimum seconds between uniform samples, defaults to 1
+    pub min_frame_interval: Option<f64>,
+    /// Hash bits that must change between consecutive frames for the "scenes"
+    /// strategy to start a new scene, defaults to 16
+    pub scene_cut_threshold: Option<u32>,
+    /// Seconds one video may spend decoding before it keeps the frames found so far,
+    /// defaults to 120, 0 never gives up
+    pub extraction_timeout_secs: Option<f64>,
+    /// Packets read looking for one sampled frame before it is skipped, defaults to 10000
+    pub max_packets_per_frame: Option<u32>,
+    /// Longest side in pixels video frames are decoded at, defaults to 512, 0 keeps the
+    /// source resolution
+    pub max_frame_dimension: Option<u32>,
+    /// Video frames with a lower luma variance are passed over as black or flat,
+    /// defaults to 16, 0 keeps every frame
+    pub min_luma_variance: Option<f64>,
+    /// "seek" (default) | "sequential", sequential decodes the video once without seeking,
+    /// which suits MPEG-TS and other poorly indexed files
+    pub extraction_mode: Option<String>,
+    /// Decode videos on VideoToolbox, VAAPI or D3D11VA when available, falling back
+    /// to software. Defaults to false
+    pub hw_accel: Option<bool>,
+    pub match_frames_ratio: Option<f64>,
+    /// Reject the whole batch when any asset fails instead of reporting it in `failed`
+    pub fail_fast: Option<bool>,
+    /// Attach the pairwise member distances to each group
+    pub include_group_distances: Option<bool>,
+    /// "anchor" (default) | "transitive"
+    pub linkage: Option<String>,
+    /// How groups are named: "commonPrefix" (default) | "firstMember"
+    pub naming_strategy: Option<String>,
+    /// `[idA, idB]` pairs always grouped together, regardless of distance
+    pub must_link: Option<Vec<Vec<String>>>,
+    /// `[idA, idB]` pairs never grouped together, wins over `mustLink`
+    pub cannot_link: Option<Vec<Vec<String>>>,
+    /// Maximum frame shift tried when aligning trimmed or offset videos
+    pub frame_offset_tolerance: Option<u32>,
+    pub min_frame_overlap: Option<u32>,
+    /// Maximum relative aspect ratio difference between compared assets, unchecked by default
+    pub max_aspect_ratio_delta: Option<f64>,
+    /// Minimum length in pixels of the shorter side for an asset to match anything
+    pub min_resolution: Option<u32>,
+    /// Assets narrower than this many pixels fail instead of being hashed, defaults to 16
+    pub min_width: Option<u32>,
+    /// Assets lower than this many pixels fail instead of being hashed, defaults to 16
+    pub min_height: Option<u32>,
+    /// Longest allowed ratio between two video durations, defaults to 2
+    pub max_duration_ratio: Option<f64>,
+    /// Compare videos regardless of their durations
+    pub allow_trimmed_variants: Option<bool>,
+    /// Match a short cutdown against any stretch of a longer video, slower per pair
+    pub detect_clips: Option<bool>,
+    /// Group an image with a video when it matches any of the video's frames
+    pub allow_image_video_matching: Option<bool>,
+    /// Members compared by `assignToGroups`: "representative" (default) | "allMembers"
+    pub assignment_mode: Option<String>,
+    /// Number of assets hashed in parallel, defaults to the core count capped at 8
+    pub max_concurrency: Option<u32>,
+    /// Extra bits over the threshold allowed between first frames before a pair is skipped
+    pub prefilter_slack: Option<u32>,
+    /// Keep extracted video frames as files in this directory, one folder per asset
+    pub frames_dir: Option<String>,
+    /// Format of the files kept in `framesDir`: "png" (default) | "jpeg" | "webp"
+    pub frame_format: Option<String>,
+    /// JPEG quality from 1 to 100, defaults to 85
+    pub frame_quality: Option<u32>,
+    /// File caching frame hashes between runs, unchanged files are not decoded again
+    pub cache_path: Option<String>,
+    /// Decode only one copy of byte-identical files and flag the others in their group
+    pub detect_exact_duplicates: Option<bool>,
+    /// Hash inputs with the same path once, as the first of them, which lists the
+    /// other ids in its `aliasIds`. Ids must be unique either way
+    pub dedupe_by_path: Option<bool>,
+    /// Fail assets whose content contradicts `isVideo` instead of correcting the flag,
+    /// corrected assets come back with the detected `isVideo` and `mimeType`
+    pub strict_type_check: Option<bool>,
+    /// Write a JPEG thumbnail of every group's representative, failures are
+    /// reported per group in `thumbnailError`
+    pub thumbnails: Option<JsThumbnailOptions>,
+    /// Compute a 4x3 BlurHash placeholder of every asset, returned as `blurhash` on
+    /// the group members
+    pub blurhash: Option<bool>,
+    /// How much dominant colors count against a hash match, 0 (default) ignores
+    /// them. At 1 a pair with opposite palettes needs identical hashes
+    pub color_weight: Option<f64>,
+    /// Consecutive video frames less than this many bits apart count as one
+    /// (default 3), 0 keeps every sampled frame
+    pub frame_dedup_distance: Option<u32>,
+    /// Seconds allowed for fetching each http(s) asset, defaults to 60, 0 waits indefinitely
+    pub request_timeout_secs: Option<f64>,
+    /// Largest http(s) asset downloaded in bytes, defaults to 2 GiB, 0 downloads any size.
+    /// Failed and oversized downloads fail only their own asset
+    pub max_download_bytes: Option<f64>,
+    /// What differing audio does to matching videos: "ignore" (default) | "separate"
+    /// splits them into their own groups | "annotate" groups them and sets `audioDiffers`
+    pub audio_mode: Option<String>,
+    /// Assets taking longer than this many milliseconds are logged as warnings,
+    /// defaults to 30000, 0 never warns. Every asset's time is in `timings` either way
+    pub slow_asset_threshold_ms: Option<u32>,
+    /// Most verbose level forwarded to `onLog`: "off" | "error" | "warn" | "info" | "debug" | "trace"
+    pub log_level: Option<String>,
+}
+
+/// Constraint pairs from JavaScript, every entry must hold exactly two ids
+fn id_pairs(
+    option: &str,
+    pairs: Option<Vec<Vec<String>>>,
+) -> anyhow::Result<Vec<(String, String)>> {
+    pairs
+        .unwrap_or_default()
+        .into_iter()
+        .map(|pair| match <[String; 2]>::try_from(pair) {
+            Ok([id1, id2]) => Ok((id1, id2)),
+            Err(pair) => Err(anyhow::anyhow!(
+                "{} entries must be [idA, idB], got {:?}",
+                option,
+                pair
+            )),
+        })
+        .collect()
+}
+
+impl TryFrom<JsGroupingOptions> for GroupingOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(options: JsGroupingOptions) -> anyhow::Result<Self> {
+        let defaults = GroupingOptions::default();
+        let frame_sampling = FrameSamplingConfig::try_from(&options)?;
+
+        let hash_algorithm = match options.hash_algorithm {
+            Some(algorithm) => algorithm.parse::<HashAlgorithm>()?,
+            None => defaults.hash_algorithm,
+        };
+
+        let invariance = match options.invariance {
+            Some(invariance) => invariance.parse::<Invariance>()?,
+            None => defaults.invariance,
+        };
+
+        let alpha_background = match options.alpha_background.as_deref() {
+            None => defaults.alpha_background,
+            Some(&[red, green, blue]) => {
+                let channel = |value: u32| {
+                    u8::try_from(value).map_err(|_| {
+                        anyhow::anyhow!("alphaBackground channels must be 0-255, got {}", value)
+                    })
+                };
+                [channel(red)?, channel(green)?, channel(blue)?]
+            }
+            Some(other) => anyhow::bail!(
+                "alphaBackground must be [r, g, b], got {} values",
+                other.len()
+            ),
+        };
+
+        let resize_strategy = match options.resize_strategy {
+            Some(strategy) => strategy.parse::<ResizeStrategy>()?,
+            None => defaults.resize_strategy,
+        };
+
+        let crop_defaults = CropResistance::default();
+        let crop_resistance = options.crop_resistant.unwrap_or(false).then(|| CropResistance {
+            min_matching_regions: options
+                .min_matching_regions
+                .map_or(crop_defaults.min_matching_regions, |regions| regions as usize),
+            region_threshold: options
+                .region_threshold
+                .unwrap_or(crop_defaults.region_threshold),
+        });
+
+        let linkage = match options.linkage {
+            Some(linkage) => linkage.parse::<Linkage>()?,
+            None => defaults.linkage,
+        };
+
+        let naming_strategy = match options.naming_strategy {
+            Some(strategy) => strategy.parse::<NamingStrategy>()?,
+            None => defaults.naming_strategy,
+        };
+
+        let assignment_mode = match options.assignment_mode {
+            Some(mode) => mode.parse::<AssignmentMode>()?,
+            None => defaults.assignment_mode,
+        };
+
+        let audio_mode = match options.audio_mode {
+            Some(mode) => mode.parse::<AudioMode>()?,
+            None => defaults.audio_mode,
+        };
+
+        let threshold_bits = options.threshold_bits.or(options.threshold);
+        if threshold_bits.is_some() && options.loose_threshold.is_some() {
+            anyhow::bail!("Set either thresholdBits or looseThreshold, not both");
+        }
+        let threshold_bits = threshold_bits.or(options.loose_threshold);
+        if threshold_bits.is_some() && options.threshold_ratio.is_some() {
+            anyhow::bail!("Set either thresholdBits or thresholdRatio, not both");
+        }
+
+        // Either two-tier threshold asks for subgroups, a lone threshold keeps groups flat
+        let strict_threshold = match (options.strict_threshold, options.loose_threshold) {
+            (None, None) => None,
+            (strict_threshold, _) => Some(strict_threshold.unwrap_or(DEFAULT_STRICT_THRESHOLD)),
+        };
+
+        Ok(GroupingOptions {
+            threshold: threshold_bits.unwrap_or(defaults.threshold),
+            threshold_ratio: options.threshold_ratio,
+            strict_threshold,
+            hash_size: options.hash_size.unwrap_or(defaults.hash_size),
+            hash_algorithm,
+            normalize: options.normalize_brightness.unwrap_or(defaults.normalize),
+            alpha_background,
+            resize_strategy,
+            resize_target_size: options.resize_target_size.unwrap_or(defaults.resize_target_size),
+            invariance,
+            crop_resistance,
+            frame_sampling,
+            match_frames_ratio: options.match_frames_ratio.unwrap_or(defaults.match_frames_ratio),
+            fail_fast: options.fail_fast.unwrap_or(defaults.fail_fast),
+            include_group_distances: options
+                .include_group_distances
+                .unwrap_or(defaults.include_group_distances),
+            linkage,
+            naming_strategy,
+            must_link: id_pairs("mustLink", options.must_link)?,
+            cannot_link: id_pairs("cannotLink", options.cannot_link)?,
+            frame_offset_tolerance: options
+                .frame_offset_tolerance
+                .map_or(defaults.frame_offset_tolerance, |offset| offset as usize),
+            min_frame_overlap: options
+                .min_frame_overlap
+                .map_or(defaults.min_frame_overlap, |overlap| overlap as usize),
+            max_aspect_ratio_delta: options
+                .max_aspect_ratio_delta
+                .or(defaults.max_aspect_ratio_delta),
+            min_resolution: options.min_resolution.unwrap_or(defaults.min_resolution),
+            min_width: options.min_width.unwrap_or(defaults.min_width),
+            min_height: options.min_height.unwrap_or(defaults.min_height),
+            max_duration_ratio: options.max_duration_ratio.unwrap_or(defaults.max_duration_ratio),
+            allow_trimmed_variants: options
+                .allow_trimmed_variants
+                .unwrap_or(defaults.allow_trimmed_variants),
+            detect_clips: options.detect_clips.unwrap_or(defaults.detect_clips),
+            allow_image_video_matching: options
+                .allow_image_video_matching
+                .unwrap_or(defaults.allow_image_video_matching),
+            assignment_mode,
+            max_concurrency: options
+                .max_concurrency
+                .map(|concurrency| concurrency as usize)
+                .or(defaults.max_concurrency),
+            prefilter_slack: options.prefilter_slack.or(defaults.prefilter_slack),
+            frames_dir: options.frames_dir.map(PathBuf::from),
+            frame_files: frame_file_options(
+                options.frame_format.as_deref(),
+                options.frame_quality,
+                None,
+            )?,
+            cache_path: options.cache_path.map(PathBuf::from),
+            detect_exact_duplicates: options
+                .detect_exact_duplicates
+                .unwrap_or(defaults.detect_exact_duplicates),
+            dedupe_by_path: options.dedupe_by_path.unwrap_or(defaults.dedupe_by_path),
+            strict_type_check: options.strict_type_check.unwrap_or(defaults.strict_type_check),
+            thumbnails: options.thumbnails.map(|thumbnails| ThumbnailOptions {
+                dir: PathBuf::from(thumbnails.dir),
+                size: thumbnails.size,
+            }),
+            blurhash: options.blurhash.unwrap_or(defaults.blurhash),
+            color_weight: options.color_weight.unwrap_or(defaults.color_weight),
+            frame_dedup_distance: options
+                .frame_dedup_distance
+                .unwrap_or(defaults.frame_dedup_distance),
+            request_timeout_secs: match options.request_timeout_secs {
+                Some(0.0) => None,
+                Some(timeout) => Some(timeout),
+                None => defaults.request_timeout_secs,
+            },
+            max_download_bytes: match options.max_download_bytes {
+                Some(0.0) => None,
+                Some(bytes) => Some(bytes as u64),
+                None => defaults.max_download_bytes,
+            },
+            audio_mode,
+            slow_asset_threshold_ms: match options.slow_asset_threshold_ms {
+                Some(0) => None,
+                Some(threshold) => Some(u64::from(threshold)),
+                None => defaults.slow_asset_threshold_ms,
+            },
+        })
+    }
+}
+
+impl TryFrom<&JsGroupingOptions> for FrameSamplingConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(options: &JsGroupingOptions) -> anyhow::Result<Self> {
+        let defaults = FrameSamplingConfig::default();
+        let max_frames = options
+            .max_frames
+            .map_or(defaults.max_frames, |max| max as usize);
+
+        let strategy = match (options.frame_strategy.as_deref(), options.video_frame_interval) {
+            (None, None) => defaults.strategy,
+            (None, Some(interval)) => FrameStrategy::Interval(interval),
+            (Some(strategy), interval) => match strategy.to_ascii_lowercase().as_str() {
+                "uniform" => FrameStrategy::Uniform,
+                "interval" => FrameStrategy::Interval(
+                    interval.context("frameStrategy \"interval\" needs videoFrameInterval")?,
+                ),
+                "keyframes" => FrameStrategy::Keyframes { max: max_frames },
+                "scenes" => FrameStrategy::Scenes {
+                    max: max_frames,
+                    cut_threshold: options
+                        .scene_cut_threshold
+                        .unwrap_or(DEFAULT_SCENE_CUT_THRESHOLD),
+                },
+                other => anyhow::bail!("Unknown frame strategy: {}", other),
+            },
+        };
+
+        Ok(FrameSamplingConfig {
+            max_frames,
+            max_frames_per_video: options
+                .max_frames_per_video
+                .map_or(defaults.max_frames_per_video.max(max_frames), |max| max as usize),
+            min_interval: options.min_frame_interval.unwrap_or(defaults.min_interval),
+            strategy,
+            timeout_secs: match options.extraction_timeout_secs {
+                Some(0.0) => None,
+                Some(timeout) => Some(timeout),
+                None => defaults.timeout_secs,
+            },
+            max_packets_per_frame: options
+                .max_packets_per_frame
+                .map_or(defaults.max_packets_per_frame, |max| max as usize),
+            max_dimension: match options.max_frame_dimension {
+                Some(0) => None,
+                Some(max) => Some(max),
+                None => defaults.max_dimension,
+            },
+            min_luma_variance: options.min_luma_variance.unwrap_or(defaults.min_luma_variance),
+            extraction_mode: match &options.extraction_mode {
+                Some(mode) => mode.parse()?,
+                None => defaults.extraction_mode,
+            },
+            hw_accel: options.hw_accel.unwrap_or(defaults.hw_accel),
+        })
+    }
+}
+
+/// Progress notification delivered to the JavaScript callback
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsProgressEvent {
+    /// "hashing" | "grouping"
+    pub phase: String,
+    pub current: u32,
+    pub total: u32,
+    pub asset_id: Option<String>,
+}
+
+impl From<ProgressEvent> for JsProgressEvent {
+    fn from(event: ProgressEvent) -> Self {
+        JsProgressEvent {
+            phase: event.phase.as_str().to_string(),
+            current: event.current as u32,
+            total: event.total as u32,
+            asset_id: event.asset_id,
+        }
+    }
+}
+
+type ProgressFunction = ThreadsafeFunction<JsProgressEvent, (), JsProgressEvent, Status, false>;
+
+/// Log message delivered to the JavaScript `onLog` callback
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsLogRecord {
+    /// "error" | "warn" | "info" | "debug" | "trace"
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+type LogFunction = ThreadsafeFunction<JsLogRecord, (), JsLogRecord, Status, false>;
+
+/// Callback registered for the duration of a single call
+struct LogSink {
+    id: u64,
+    level: log::LevelFilter,
+    callback: LogFunction,
+}
+
+static LOG_SINKS: Mutex<Vec<LogSink>> = Mutex::new(Vec::new());
+static NEXT_LOG_SINK_ID: AtomicU64 = AtomicU64::new(0);
+/// Whether `NodeLogger` is the installed `log` backend
+static NODE_LOGGER_INSTALLED: OnceLock<bool> = OnceLock::new();
+
+/// Keep the global max level at the most verbose registered sink, so disabled
+/// levels are rejected by `log` itself from an atomic without locking the sinks
+fn update_max_level(sinks: &[LogSink]) {
+    if NODE_LOGGER_INSTALLED.get() == Some(&true) {
+        let level = sinks.iter().map(|sink| sink.level).max();
+        log::set_max_level(level.unwrap_or(log::LevelFilter::Off));
+    }
+}
+
+/// `log` backend forwarding each record to the callback of the call that
+/// logged it, found through the log scope of the logging thread
+struct NodeLogger;
+
+impl log::Log for NodeLogger {
+    // Levels no sink wants are already rejected through the max level, the
+    // level of this scope's own sink is checked in `log`
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        log_scope().is_some()
+    }
+
+    fn log(&self, record: &log::Record) {
+        let Some(scope) = log_scope() else {
+            return;
+        };
+        let Ok(sinks) = LOG_SINKS.lock() else {
+            return;
+        };
+
+        let sink = sinks.iter().find(|sink| sink.id == scope);
+        if let Some(sink) = sink.filter(|sink| record.level() <= sink.level) {
+            sink.callback.call(
+                JsLogRecord {
+                    level: record.level().as_str().to_ascii_lowercase(),
+                    target: record.target().to_string(),
+                    message: record.args().to_string(),
+                },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static NODE_LOGGER: NodeLogger = NodeLogger;
+
+/// Unregisters its log sink and restores the thread's log scope when dropped
+struct LogSinkGuard {
+    id: u64,
+    previous_scope: Option<u64>,
+}
+
+impl Drop for LogSinkGuard {
+    fn drop(&mut self) {
+        set_log_scope(self.previous_scope);
+        if let Ok(mut sinks) = LOG_SINKS.lock() {
+            sinks.retain(|sink| sink.id != self.id);
+            update_max_level(&sinks);
+        }
+    }
+}
+
+/// Forward log records up to `level` to `callback` until the guard is dropped
+/// Only records of the calling thread and the worker pools it builds are
+/// forwarded, so concurrent calls each receive their own. Nothing is logged
+/// anywhere unless a callback is registered
+fn register_log_sink(callback: LogFunction, level: log::LevelFilter) -> LogSinkGuard {
+    NODE_LOGGER_INSTALLED.get_or_init(|| log::set_logger(&NODE_LOGGER).is_ok());
+
+    let id = NEXT_LOG_SINK_ID.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut sinks) = LOG_SINKS.lock() {
+        sinks.push(LogSink {
+            id,
+            level,
+            callback,
+        });
+        update_max_level(&sinks);
+    }
+
+    LogSinkGuard {
+        id,
+        previous_scope: set_log_scope(Some(id)),
+    }
+}
+
+fn parse_log_level(level: Option<&str>) -> Result<log::LevelFilter> {
+    match level {
+        Some(level) => level.parse::<log::LevelFilter>().map_err(|_| {
+            Error::new(Status::InvalidArg, format!("Unknown log level: {}", level))
+        }),
+        None => Ok(log::LevelFilter::Info),
+    }
+}
+
+/// Convert an anyhow error (including its context chain) into a napi error
+/// Cancelled jobs reject with the `Cancelled` status so callers can tell them apart
+fn to_napi_error(err: anyhow::Error) -> Error {
+    if err.is::<Cancelled>() {
+        return Error::new(Status::Cancelled, "Cancelled".to_string());
+    }
+    Error::new(Status::GenericFailure, format!("{:#}", err))
+}
+
+/// Convert an anyhow error caused by bad caller input into a napi error
+fn to_invalid_arg(err: anyhow::Error) -> Error {
+    Error::new(Status::InvalidArg, format!("{:#}", err))
+}
+
+/// Handle used to cancel a running grouping job from JavaScript
+#[napi]
+pub struct GroupingHandle {
+    token: CancellationToken,
+}
+
+#[napi]
+impl GroupingHandle {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        GroupingHandle {
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// Abort the job, its promise rejects with the `Cancelled` error code
+    #[napi]
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    #[napi(getter)]
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+impl Default for GroupingHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Background task running the grouping pipeline on the libuv thread pool
+pub struct GroupAssetsTask {
+    assets: Vec<Asset>,
+    options: GroupingOptions,
+    on_progress: Option<ProgressFunction>,
+    cancel: Option<CancellationToken>,
+    on_log: Option<LogFunction>,
+    log_level: log::LevelFilter,
+}
+
+#[napi]
+impl Task for GroupAssetsTask {
+    type Output = GroupingResult;
+    type JsValue = JsGroupingResult;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let _log_sink = self
+            .on_log
+            .take()
+            .map(|callback| register_log_sink(callback, self.log_level));
+        let assets = std::mem::take(&mut self.assets);
+        let on_progress = self.on_progress.take().map(|callback| {
+            move |event: ProgressEvent| {
+                callback.call(
+                    JsProgressEvent::from(event),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        });
+
+        group_assets_by_visual_similarity(
+            assets,
+            Some(self.options.clone()),
+            on_progress.as_ref().map(|callback| callback as &ProgressCallback),
+            self.cancel.as_ref(),
+        )
+        .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsGroupingResult::from(output))
+    }
+}
+
+/// Group assets by visual similarity without blocking the event loop
+/// `onProgress` is optional and receives events from the worker thread,
+/// `handle` allows the job to be cancelled while it runs and `onLog`
+/// receives the log messages of this call up to `options.logLevel`
+#[napi(ts_return_type = "Promise<JsGroupingResult>")]
+pub fn group_assets_visually(
+    assets: Vec<JsAsset>,
+    options: Option<JsGroupingOptions>,
+    on_progress: Option<ProgressFunction>,
+    handle: Option<&GroupingHandle>,
+    on_log: Option<LogFunction>,
+) -> Result<AsyncTask<GroupAssetsTask>> {
+    let options = options.unwrap_or_default();
+    let log_level = parse_log_level(options.log_level.as_deref())?;
+    let options = GroupingOptions::try_from(options).map_err(to_invalid_arg)?;
+    let assets = to_assets(assets, options.hash_config())?;
+
+    Ok(AsyncTask::new(GroupAssetsTask {
+        assets,
+        options,
+        on_progress,
+        cancel: handle.map(|handle| handle.token.clone()),
+        on_log,
+        log_level,
+    }))
+}
+
+/// Generate the perceptual hash of an image file path or encoded image buffer
+#[napi]
+pub fn hash_image(source: Either<String, Buffer>) -> Result<Buffer> {
+    let defaults = GroupingOptions::default();
+    let (config, extras) = (defaults.hash_config(), HashExtras::default());
+
+    let hashes = match source {
+        Either::A(path) => {
+            if !Path::new(&path).exists() {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("File not found: {}", path),
+                ));
+            }
+
+            generate_perceptual_hash(&path, &config, extras)
+        }
+        Either::B(data) => generate_perceptual_hash_from_bytes(&data, &config, extras),
+    }
+    .map_err(to_napi_error)?;
+
+    Ok(hashes.hash.bytes.into())
+}
+
+/// Hamming distance between two perceptual hashes
+#[napi]
+pub fn hash_distance(a: Buffer, b: Buffer) -> Result<u32> {
+    if a.len() != b.len() {
+        return Err(Error::new(
+            Status::InvalidArg,
+            format!(
+                "RangeError: hashes must be the same length (got {} and {} bytes)",
+                a.len(),
+                b.len()
+            ),
+        ));
+    }
+
+    hamming_distance(&a, &b).map_err(to_napi_error)
+}
+
+/// Options for `scanDirectory`
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct JsScanOptions {
+    /// Glob patterns files have to match, e.g. `*.mp4` for file names or `story/**` for paths
+    pub include: Option<Vec<String>>,
+    /// Glob patterns of files to leave out
+    pub exclude: Option<Vec<String>>,
+    /// Descend into subdirectories (default true)
+    pub recursive: Option<bool>,
+    /// Detect the type of extensionless files from their first bytes (default false)
+    pub sniff_content: Option<bool>,
+}
+
+impl From<JsScanOptions> for ScanOptions {
+    fn from(options: JsScanOptions) -> Self {
+        let defaults = ScanOptions::default();
+        ScanOptions {
+            include: options.include.unwrap_or(defaults.include),
+            exclude: options.exclude.unwrap_or(defaults.exclude),
+            recursive: options.recursive.unwrap_or(defaults.recursive),
+            sniff_content: options.sniff_content.unwrap_or(defaults.sniff_content),
+        }
+    }
+}
+
+/// Background task listing the media files of a directory
+pub struct ScanDirectoryTask {
+    path: String,
+    options: ScanOptions,
+}
+
+#[napi]
+impl Task for ScanDirectoryTask {
+    type Output = Vec<Asset>;
+    type JsValue = Vec<JsAsset>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        scan_directory(&self.path, &self.options).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsAsset::from).collect())
+    }
+}
+
+/// Build assets for the images and videos in a directory, ready for `groupAssetsVisually`
+/// Ids are the paths relative to the directory
+#[napi(js_name = "scanDirectory", ts_return_type = "Promise<JsAsset[]>")]
+pub fn scan_directory_assets(
+    path: String,
+    options: Option<JsScanOptions>,
+) -> AsyncTask<ScanDirectoryTask> {
+    AsyncTask::new(ScanDirectoryTask {
+        path,
+        options: ScanOptions::from(options.unwrap_or_default()),
+    })
+}
+
+/// Delete the hash cache file written through the `cachePath` option
+#[napi]
+pub fn clear_cache(cache_path: String) -> Result<()> {
+    HashCache::clear(&cache_path).map_err(to_napi_error)
+}
+
+/// Options for `extractVideoFrames`
+#[cfg(feature = "video")]
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct JsExtractFramesOptions {
+    /// Directory the frames are written to, a temp directory is used when omitted
+    pub output_dir: Option<String>,
+    pub max_frames: Option<u32>,
+    /// "png" (default) | "jpeg" | "webp"
+    pub frame_format: Option<String>,
+    /// JPEG quality from 1 to 100, defaults to 85
+    pub frame_quality: Option<u32>,
+    /// File name of each frame, `{index}` is replaced by the frame index and `{ext}`
+    /// by the extension of the format, defaults to "frame_{index}.{ext}"
+    pub file_name: Option<String>,
+}
+
+/// Frame file settings from the `frameFormat` and `frameQuality` options
+fn frame_file_options(
+    format: Option<&str>,
+    quality: Option<u32>,
+    file_name: Option<String>,
+) -> anyhow::Result<FrameFileOptions> {
+    let format = match format.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("png") if quality.is_none() => FrameFormat::Png,
+        Some("jpeg" | "jpg") => {
+            let quality = quality.unwrap_or(85);
+            if !(1..=100).contains(&quality) {
+                anyhow::bail!("frameQuality must be between 1 and 100, got {}", quality);
+            }
+            FrameFormat::Jpeg { quality: quality as u8 }
+        }
+        Some("webp") if quality.is_none() => FrameFormat::Webp,
+        None | Some("png" | "webp") => anyhow::bail!("frameQuality only applies to \"jpeg\""),
+        Some(other) => anyhow::bail!("Unknown frame format: {}", other),
+    };
+
+    let defaults = FrameFileOptions::default();
+    let files = FrameFileOptions {
+        format,
+        file_name: file_name.unwrap_or(defaults.file_name),
+    };
+    files.validate()?;
+    Ok(files)
+}
+
+/// Default sampling with the `maxFrames` option of the frame extraction functions
+#[cfg(feature = "video")]
+fn extraction_sampling(max_frames: Option<u32>) -> anyhow::Result<FrameSamplingConfig> {
+    let mut sampling = FrameSamplingConfig::default();
+    if let Some(max_frames) = max_frames {
+        sampling.max_frames = max_frames as usize;
+        sampling.max_frames_per_video = sampling.max_frames_per_video.max(max_frames as usize);
+    }
+    sampling.validate()?;
+    Ok(sampling)
+}
+
+/// Background task extracting video frames to disk
+#[cfg(feature = "video")]
+pub struct ExtractFramesTask {
+    path: String,
+    options: JsExtractFramesOptions,
+    // Keeps an internally created temp directory alive until the promise settles
+    temp_dir: Option<tempfile::TempDir>,
+}
+
+#[cfg(feature = "video")]
+#[napi]
+impl Task for ExtractFramesTask {
+    type Output = Vec<String>;
+    type JsValue = Vec<String>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let sampling = extraction_sampling(self.options.max_frames).map_err(to_invalid_arg)?;
+        let files = frame_file_options(
+            self.options.frame_format.as_deref(),
+            self.options.frame_quality,
+            self.options.file_name.clone(),
+        )
+        .map_err(to_invalid_arg)?;
+
+        let frame_paths = match &self.options.output_dir {
+            Some(output_dir) => {
+                extract_frames_from_video(&self.path, output_dir, &sampling, &files, None)
+            }
+            None => {
+                let temp_dir = tempfile::TempDir::new().map_err(|err| {
+                    Error::from_reason(format!("Failed to create temp directory: {}", err))
+                })?;
+                let frame_paths = extract_frames_from_video(
+                    &self.path,
+                    temp_dir.path(),
+                    &sampling,
+                    &files,
+                    None,
+                );
+                self.temp_dir = Some(temp_dir);
+                frame_paths
+            }
+        };
+
+        frame_paths
+            .map(|extracted| {
+                let frame_paths = extracted.frame_paths.iter();
+                frame_paths.map(|path| path.to_string_lossy().into_owned()).collect()
+            })
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+
+    fn finally(&mut self, _env: Env) -> Result<()> {
+        self.temp_dir.take();
+        Ok(())
+    }
+}
+
+/// Extract sampled frames from a video as image files
+#[cfg(feature = "video")]
+#[napi(ts_return_type = "Promise<string[]>")]
+pub fn extract_video_frames(
+    path: String,
+    options: Option<JsExtractFramesOptions>,
+) -> AsyncTask<ExtractFramesTask> {
+    AsyncTask::new(ExtractFramesTask {
+        path,
+        options: options.unwrap_or_default(),
+        temp_dir: None,
+    })
+}
+
+/// Options for `extractVideoFrameBuffers`
+#[cfg(feature = "video")]
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct JsExtractFrameBuffersOptions {
+    pub max_frames: Option<u32>,
+    /// "png" (default) | "jpeg" | "webp"
+    pub frame_format: Option<String>,
+    /// JPEG quality from 1 to 100, defaults to 85
+    pub frame_quality: Option<u32>,
+}
+
+/// Frame returned by `extractVideoFrameBuffers`
+#[cfg(feature = "video")]
+#[napi(object)]
+pub struct JsFrameBuffer {
+    /// Presentation time in seconds
+    pub timestamp: f64,
+    /// The encoded frame, PNG unless `frameFormat` asks for another format
+    pub png: Buffer,
+}
+
+/// Background task extracting encoded video frames into memory
+#[cfg(feature = "video")]
+pub struct ExtractFrameBuffersTask {
+    path: String,
+    options: JsExtractFrameBuffersOptions,
+}
+
+#[cfg(feature = "video")]
+#[napi]
+impl Task for ExtractFrameBuffersTask {
+    type Output = Vec<JsFrameBuffer>;
+    type JsValue = Vec<JsFrameBuffer>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let sampling = extraction_sampling(self.options.max_frames).map_err(to_invalid_arg)?;
+        let files = frame_file_options(
+            self.options.frame_format.as_deref(),
+            self.options.frame_quality,
+            None,
+        )
+        .map_err(to_invalid_arg)?;
+
+        let frames = extract_encoded_frames(&self.path, &sampling, files.format, None)
+            .map_err(to_napi_error)?;
+        Ok(frames
+            .into_iter()
+            .map(|frame| JsFrameBuffer {
+                timestamp: frame.timestamp_secs,
+                png: frame.bytes.into(),
+            })
+            .collect())
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Extract sampled frames from a video as encoded image Buffers, no file is written
+#[cfg(feature = "video")]
+#[napi(ts_return_type = "Promise<JsFrameBuffer[]>")]
+pub fn extract_video_frame_buffers(
+    path: String,
+    options: Option<JsExtractFrameBuffersOptions>,
+) -> AsyncTask<ExtractFrameBuffersTask> {
+    AsyncTask::new(ExtractFrameBuffersTask {
+        path,
+        options: options.unwrap_or_default(),
+    })
+}
+
+/// Layout options for `generateContactSheet`
+#[cfg(feature = "video")]
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct JsContactSheetOptions {
+    /// Grouping options whose frame sampling the sheet follows, so it shows the hashed frames
+    pub grouping: Option<JsGroupingOptions>,
+    /// Height of every tile in pixels, defaults to 180
+    pub tile_height: Option<u32>,
+    /// Tiles per row, all frames go on one row when omitted
+    pub columns: Option<u32>,
+    /// Pixels between tiles, defaults to 4
+    pub spacing: Option<u32>,
+    /// Draw the timestamp of each frame on its tile, defaults to true
+    pub show_timestamps: Option<bool>,
+}
+
+#[cfg(feature = "video")]
+impl TryFrom<JsContactSheetOptions> for ContactSheetOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(options: JsContactSheetOptions) -> anyhow::Result<Self> {
+        let defaults = ContactSheetOptions::default();
+        let sampling = match &options.grouping {
+            Some(grouping) => FrameSamplingConfig::try_from(grouping)?,
+            None => defaults.sampling,
+        };
+
+        Ok(ContactSheetOptions {
+            sampling,
+            tile_height: options.tile_height.unwrap_or(defaults.tile_height),
+            columns: options.columns.map(|columns| columns as usize).or(defaults.columns),
+            spacing: options.spacing.unwrap_or(defaults.spacing),
+            show_timestamps: options.show_timestamps.unwrap_or(defaults.show_timestamps),
+        })
+    }
+}
+
+/// Background task writing a contact sheet
+#[cfg(feature = "video")]
+pub struct ContactSheetTask {
+    path: String,
+    output_path: String,
+    options: ContactSheetOptions,
+}
+
+#[cfg(feature = "video")]
+#[napi]
+impl Task for ContactSheetTask {
+    type Output = ();
+    type JsValue = ();
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        generate_contact_sheet(&self.path, &self.output_path, &self.options)
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, _output: Self::Output) -> Result<Self::JsValue> {
+        Ok(())
+    }
+}
+
+/// Write one image showing the sampled frames of a video side by side,
+/// in the format given by the extension of `outputPath`
+#[cfg(feature = "video")]
+#[napi(js_name = "generateContactSheet", ts_return_type = "Promise<void>")]
+pub fn generate_contact_sheet_file(
+    path: String,
+    output_path: String,
+    options: Option<JsContactSheetOptions>,
+) -> Result<AsyncTask<ContactSheetTask>> {
+    let options = ContactSheetOptions::try_from(options.unwrap_or_default())
+        .map_err(to_invalid_arg)?;
+    Ok(AsyncTask::new(ContactSheetTask {
+        path,
+        output_path,
+        options,
+    }))
+}
+
+/// Video properties returned by `probeVideo`
+#[cfg(feature = "video")]
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsVideoMetadata {
+    /// Duration in seconds
+    pub duration: f64,
+    pub fps: Option<f64>,
+    pub codec_name: String,
+    /// Bits per second
+    pub bit_rate: Option<f64>,
+    pub pixel_format: String,
+    pub coded_width: u32,
+    pub coded_height: u32,
+    /// Pixel width over pixel height, 1 for square pixels
+    pub sample_aspect_ratio: f64,
+    /// Clockwise rotation in degrees
+    pub rotation: u32,
+    /// Upright size with square pixels
+    pub width: u32,
+    pub height: u32,
+}
+
+#[cfg(feature = "video")]
+impl From<VideoMetadata> for JsVideoMetadata {
+    fn from(metadata: VideoMetadata) -> Self {
+        let (sar_num, sar_den) = metadata.sample_aspect_ratio;
+        JsVideoMetadata {
+            duration: metadata.duration,
+            fps: metadata.fps,
+            codec_name: metadata.codec_name,
+            bit_rate: metadata.bit_rate.map(|bit_rate| bit_rate as f64),
+            pixel_format: metadata.pixel_format,
+            coded_width: metadata.coded_width,
+            coded_height: metadata.coded_height,
+            sample_aspect_ratio: f64::from(sar_num) / f64::from(sar_den),
+            rotation: metadata.rotation,
+            width: metadata.display_width,
+            height: metadata.display_height,
+        }
+    }
+}
+
+/// Background task probing a video file
+#[cfg(feature = "video")]
+pub struct ProbeVideoTask {
+    path: String,
+}
+
+#[cfg(feature = "video")]
+#[napi]
+impl Task for ProbeVideoTask {
+    type Output = VideoMetadata;
+    type JsValue = JsVideoMetadata;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        probe_video(&self.path).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsVideoMetadata::from(output))
+    }
+}
+
+/// Read duration, frame rate, codec and frame geometry of a video in one pass
+#[cfg(feature = "video")]
+#[napi(js_name = "probeVideo", ts_return_type = "Promise<JsVideoMetadata>")]
+pub fn probe_video_file(path: String) -> AsyncTask<ProbeVideoTask> {
+    AsyncTask::new(ProbeVideoTask { path })
+}
+
+/// Detailed comparison of two assets, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsSimilarityReport {
+    pub asset_id_a: String,
+    pub asset_id_b: String,
+    pub type_mismatch: bool,
+    pub aspect_ratio_mismatch: bool,
+    pub below_min_resolution: bool,
+    pub duration_mismatch: bool,
+    /// True when `colorWeight` rejected the pair on its dominant colors
+    pub palette_mismatch: bool,
+    /// Distance between the dominant colors from 0 to 1
+    pub palette_distance: Option<f64>,
+    /// True when the two videos sound different under `audioMode`
+    pub audio_mismatch: bool,
+    pub frame_distances: Vec<u32>,
+    /// Timestamps in seconds of the asset A frame behind each frame distance
+    pub frame_timestamps_a: Vec<f64>,
+    /// Timestamps in seconds of the asset B frame behind each frame distance
+    pub frame_timestamps_b: Vec<f64>,
+    pub min_distance: Option<u32>,
+    pub max_distance: Option<u32>,
+    pub mean_distance: Option<f64>,
+    pub aspect_ratio_a: f64,
+    pub aspect_ratio_b: f64,
+    pub threshold: u32,
+    pub frame_offset: i32,
+    pub matched_frames_ratio: f64,
+    pub score: f64,
+    pub similar: bool,
+}
+
+impl From<SimilarityReport> for JsSimilarityReport {
+    fn from(report: SimilarityReport) -> Self {
+        JsSimilarityReport {
+            asset_id_a: report.asset_id_a,
+            asset_id_b: report.asset_id_b,
+            type_mismatch: report.type_mismatch,
+            aspect_ratio_mismatch: report.aspect_ratio_mismatch,
+            below_min_resolution: report.below_min_resolution,
+            duration_mismatch: report.duration_mismatch,
+            palette_mismatch: report.palette_mismatch,
+            palette_distance: report.palette_distance,
+            audio_mismatch: report.audio_mismatch,
+            frame_distances: report.frame_distances,
+            frame_timestamps_a: report.frame_timestamps_a,
+            frame_timestamps_b: report.frame_timestamps_b,
+            min_distance: report.min_distance,
+            max_distance: report.max_distance,
+            mean_distance: report.mean_distance,
+            aspect_ratio_a: report.aspect_ratio_a,
+            aspect_ratio_b: report.aspect_ratio_b,
+            threshold: report.threshold,
+            frame_offset: report.frame_offset,
+            matched_frames_ratio: report.matched_frames_ratio,
+            score: report.score,
+            similar: report.similar,
+        }
+    }
+}
+
+/// Background task comparing two assets
+pub struct CompareAssetsTask {
+    a: Asset,
+    b: Asset,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for CompareAssetsTask {
+    type Output = SimilarityReport;
+    type JsValue = JsSimilarityReport;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        compare_assets(&self.a, &self.b, Some(self.options.clone())).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsSimilarityReport::from(output))
+    }
+}
+
+/// Compare two assets and report per-frame distances and the verdict
+#[napi(js_name = "compareAssets", ts_return_type = "Promise<JsSimilarityReport>")]
+pub fn compare_assets_visually(
+    a: JsAsset,
+    b: JsAsset,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<CompareAssetsTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(CompareAssetsTask {
+        a: a.into_asset(options.hash_config())?,
+        b: b.into_asset(options.hash_config())?,
+        options,
+    }))
+}
+
+/// Background task grouping a JSON array of assets into a JSON array of groups
+pub struct GroupAssetsJsonTask {
+    json: String,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for GroupAssetsJsonTask {
+    type Output = String;
+    type JsValue = String;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let json = std::mem::take(&mut self.json);
+
+        group_assets_json(&json, Some(self.options.clone()), None, None).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Group assets given as a JSON array and resolve with the groups as a JSON array
+/// Skips the per-object conversion of `groupAssetsVisually`, for very large lists,
+/// Assets and groups use the serde layout of the Rust types, as the CLI writes them
+#[napi(js_name = "groupAssetsFromJson", ts_return_type = "Promise<string>")]
+pub fn group_assets_from_json(
+    json: String,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<GroupAssetsJsonTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(GroupAssetsJsonTask { json, options }))
+}
+
+/// Grouping session keeping hashed assets alive across calls
+/// Only newly added assets are processed, grouping reuses the stored hashes
+#[napi]
+pub struct VisualGroupingSession {
+    inner: Arc<Mutex<GroupingSession>>,
+}
+
+fn lock_session(session: &Mutex<GroupingSession>) -> Result<MutexGuard<'_, GroupingSession>> {
+    session
+        .lock()
+        .map_err(|_| Error::from_reason("Grouping session lock was poisoned".to_string()))
+}
+
+#[napi]
+impl VisualGroupingSession {
+    #[napi(constructor)]
+    pub fn new(options: Option<JsGroupingOptions>) -> Result<Self> {
+        let options =
+            GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+        let session = GroupingSession::new(options).map_err(to_invalid_arg)?;
+
+        Ok(VisualGroupingSession {
+            inner: Arc::new(Mutex::new(session)),
+        })
+    }
+
+    /// Hash and store assets not yet in the session, resolves with the assets that failed
+    #[napi(ts_return_type = "Promise<JsFailedAsset[]>")]
+    pub fn add_assets(&self, assets: Vec<JsAsset>) -> Result<AsyncTask<AddAssetsTask>> {
+        let hash_config = lock_session(&self.inner)?.options().hash_config();
+
+        Ok(AsyncTask::new(AddAssetsTask {
+            session: self.inner.clone(),
+            assets: to_assets(assets, hash_config)?,
+        }))
+    }
+
+    /// Group the stored assets, optionally with a different threshold
+    #[napi(ts_return_type = "Promise<JsAssetGroup[]>")]
+    pub fn get_groups(&self, threshold: Option<u32>) -> AsyncTask<SessionGroupsTask> {
+        AsyncTask::new(SessionGroupsTask {
+            session: self.inner.clone(),
+            threshold,
+        })
+    }
+
+    /// Remove an asset from the session, returns whether it was present
+    #[napi]
+    pub fn remove_asset(&self, id: String) -> Result<bool> {
+        Ok(lock_session(&self.inner)?.remove_asset(&id))
+    }
+
+    #[napi(getter)]
+    pub fn size(&self) -> Result<u32> {
+        Ok(lock_session(&self.inner)?.len() as u32)
+    }
+}
+
+/// Background task hashing new session assets
+pub struct AddAssetsTask {
+    session: Arc<Mutex<GroupingSession>>,
+    assets: Vec<Asset>,
+}
+
+#[napi]
+impl Task for AddAssetsTask {
+    type Output = Vec<FailedAsset>;
+    type JsValue = Vec<JsFailedAsset>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        // Hash outside the lock so other calls on the session are not blocked
+        let (assets, options) = {
+            let session = lock_session(&self.session)?;
+            (
+                session.new_assets(std::mem::take(&mut self.assets)),
+                session.options().clone(),
+            )
+        };
+
+        let (process_results, failed) =
+            hash_assets(&assets, &options, None, None).map_err(to_napi_error)?;
+
+        lock_session(&self.session)?.insert(
+            process_results
+                .into_iter()
+                .map(|(hashed_asset, _)| hashed_asset)
+                .collect(),
+        );
+
+        Ok(failed)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsFailedAsset::from).collect())
+    }
+}
+
+/// Background task grouping the assets stored in a session
+pub struct SessionGroupsTask {
+    session: Arc<Mutex<GroupingSession>>,
+    threshold: Option<u32>,
+}
+
+#[napi]
+impl Task for SessionGroupsTask {
+    type Output = Vec<AssetGroup>;
+    type JsValue = Vec<JsAssetGroup>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        // Group a snapshot so assets can be added while grouping runs
+        let snapshot = lock_session(&self.session)?.clone();
+
+        snapshot.groups(self.threshold).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsAssetGroup::from).collect())
+    }
+}
+
+/// Frame hashes and dimensions of a hashed asset, kept on the Rust side
+/// Pass handles back to `groupHashed` to group again without decoding
+#[napi]
+pub struct HashedAssetHandle {
+    inner: Arc<HashedAsset>,
+}
+
+#[napi]
+impl HashedAssetHandle {
+    #[napi(getter)]
+    pub fn id(&self) -> String {
+        self.inner.asset.id.clone()
+    }
+
+    #[napi(getter)]
+    pub fn width(&self) -> u32 {
+        self.inner.width
+    }
+
+    #[napi(getter)]
+    pub fn height(&self) -> u32 {
+        self.inner.height
+    }
+
+    #[napi(getter)]
+    pub fn frame_count(&self) -> u32 {
+        self.inner.frames.len() as u32
+    }
+}
+
+/// Background task hashing assets into handles
+pub struct HashAssetsTask {
+    assets: Vec<Asset>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for HashAssetsTask {
+    type Output = Vec<HashedAsset>;
+    type JsValue = Vec<HashedAssetHandle>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let (process_results, failed) =
+            hash_assets(&self.assets, &self.options, None, None).map_err(to_napi_error)?;
+
+        for failed_asset in &failed {
+            log::warn!("Skipping {}: {}", failed_asset.asset_id, failed_asset.reason);
+        }
+
+        Ok(process_results
+            .into_iter()
+            .map(|(hashed_asset, _)| hashed_asset)
+            .collect())
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output
+            .into_iter()
+            .map(|hashed_asset| HashedAssetHandle {
+                inner: Arc::new(hashed_asset),
+            })
+            .collect())
+    }
+}
+
+/// Hash assets once and keep the result for `groupHashed`
+/// Assets that fail to hash are logged and left out
+#[napi(js_name = "hashAssets", ts_return_type = "Promise<HashedAssetHandle[]>")]
+pub fn hash_assets_to_handles(
+    assets: Vec<JsAsset>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<HashAssetsTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+    let assets = to_assets(assets, options.hash_config())?;
+
+    Ok(AsyncTask::new(HashAssetsTask { assets, options }))
+}
+
+/// Background task grouping handles, no file is read
+pub struct GroupHashedTask {
+    hashed_assets: Vec<Arc<HashedAsset>>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for GroupHashedTask {
+    type Output = Vec<AssetGroup>;
+    type JsValue = Vec<JsAssetGroup>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let hashed_assets: Vec<HashedAsset> = self
+            .hashed_assets
+            .iter()
+            .map(|hashed_asset| HashedAsset::clone(hashed_asset))
+            .collect();
+
+        group_hashed_assets(&hashed_assets, &self.options, None, None).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsAssetGroup::from).collect())
+    }
+}
+
+/// Group handles returned by `hashAssets` with `threshold`, which replaces the
+/// thresholds in `options`, so thresholds can be tried without decoding again
+#[napi(js_name = "groupHashed", ts_return_type = "Promise<JsAssetGroup[]>")]
+pub fn group_hashed(
+    handles: Vec<ClassInstance<'_, HashedAssetHandle>>,
+    threshold: u32,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<GroupHashedTask>> {
+    let options = JsGroupingOptions {
+        threshold: Some(threshold),
+        threshold_bits: None,
+        threshold_ratio: None,
+        loose_threshold: None,
+        ..options.unwrap_or_default()
+    };
+    let options = GroupingOptions::try_from(options).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(GroupHashedTask {
+        hashed_assets: handles.iter().map(|handle| handle.inner.clone()).collect(),
+        options,
+    }))
+}
+
+/// Candidate matching a query asset, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsSimilarMatch {
+    pub candidate_id: String,
+    pub min_distance: u32,
+    pub score: f64,
+}
+
+impl From<SimilarMatch> for JsSimilarMatch {
+    fn from(similar: SimilarMatch) -> Self {
+        JsSimilarMatch {
+            candidate_id: similar.candidate_id,
+            min_distance: similar.min_distance,
+            score: similar.score,
+        }
+    }
+}
+
+/// Background task matching one asset against a list of candidates
+pub struct FindSimilarTask {
+    target: Asset,
+    candidates: Vec<Asset>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for FindSimilarTask {
+    type Output = Vec<SimilarMatch>;
+    type JsValue = Vec<JsSimilarMatch>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        find_similar_assets(&self.target, &self.candidates, Some(self.options.clone()))
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsSimilarMatch::from).collect())
+    }
+}
+
+/// Find which candidates are visually similar to `target`, closest first
+#[napi(ts_return_type = "Promise<JsSimilarMatch[]>")]
+pub fn find_similar_to(
+    target: JsAsset,
+    candidates: Vec<JsAsset>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<FindSimilarTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(FindSimilarTask {
+        target: target.into_asset(options.hash_config())?,
+        candidates: to_assets(candidates, options.hash_config())?,
+        options,
+    }))
+}
+
+/// Pair of near-identical assets, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsDuplicatePair {
+    pub asset_id_a: String,
+    pub asset_id_b: String,
+    pub distance: u32,
+    /// Copy with more pixels, the one worth keeping
+    pub higher_resolution_asset_id: String,
+}
+
+impl From<DuplicatePair> for JsDuplicatePair {
+    fn from(pair: DuplicatePair) -> Self {
+        JsDuplicatePair {
+            asset_id_a: pair.asset_id_a,
+            asset_id_b: pair.asset_id_b,
+            distance: pair.distance,
+            higher_resolution_asset_id: pair.higher_resolution_asset_id,
+        }
+    }
+}
+
+/// Background task finding near-identical asset pairs
+pub struct FindDuplicatesTask {
+    assets: Vec<Asset>,
+    max_distance: u32,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for FindDuplicatesTask {
+    type Output = Vec<DuplicatePair>;
+    type JsValue = Vec<JsDuplicatePair>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        find_duplicate_pairs(&self.assets, self.max_distance, Some(self.options.clone()))
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsDuplicatePair::from).collect())
+    }
+}
+
+/// Find pairs of assets whose frames are all at most `maxDistance` apart (default 0),
+/// closest first. Hashing settings come from `options`
+#[napi(ts_return_type = "Promise<JsDuplicatePair[]>")]
+pub fn find_duplicates(
+    assets: Vec<JsAsset>,
+    max_distance: Option<u32>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<FindDuplicatesTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(FindDuplicatesTask {
+        assets: to_assets(assets, options.hash_config())?,
+        max_distance: max_distance.unwrap_or(0),
+        options,
+    }))
+}
+
+/// Pairwise distance matrix, as returned to JavaScript
+/// `distances[i][j]` is the minimum frame distance, or -1 when the pair is incomparable
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsSimilarityMatrix {
+    pub ids: Vec<String>,
+    pub distances: Vec<Vec<i32>>,
+    /// Mean frame distance per pair, -1 when the pair is incomparable
+    pub mean_distances: Vec<Vec<f64>>,
+    pub failed: Vec<JsFailedAsset>,
+}
+
+impl From<SimilarityMatrix> for JsSimilarityMatrix {
+    fn from(matrix: SimilarityMatrix) -> Self {
+        let distances = matrix
+            .pairs
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|pair| pair.min_distance.map_or(-1, |distance| distance as i32))
+                    .collect()
+            })
+            .collect();
+
+        let mean_distances = matrix
+            .pairs
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|pair| pair.mean_distance.unwrap_or(-1.0))
+                    .collect()
+            })
+            .collect();
+
+        JsSimilarityMatrix {
+            ids: matrix.ids,
+            distances,
+            mean_distances,
+            failed: matrix.failed.into_iter().map(JsFailedAsset::from).collect(),
+        }
+    }
+}
+
+/// Background task computing the pairwise similarity matrix
+pub struct SimilarityMatrixTask {
+    assets: Vec<Asset>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for SimilarityMatrixTask {
+    type Output = SimilarityMatrix;
+    type JsValue = JsSimilarityMatrix;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let assets = std::mem::take(&mut self.assets);
+        compute_similarity_matrix(assets, Some(self.options.clone())).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsSimilarityMatrix::from(output))
+    }
+}
+
+/// Compute the raw pairwise distances between all assets
+#[napi(ts_return_type = "Promise<JsSimilarityMatrix>")]
+pub fn similarity_matrix(
+    assets: Vec<JsAsset>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<SimilarityMatrixTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(SimilarityMatrixTask {
+        assets: to_assets(assets, options.hash_config())?,
+        options,
+    }))
+}
+
+/// Suggested threshold with the distance histogram behind it, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsThresholdSuggestion {
+    pub threshold: u32,
+    /// Below 0.8 no clear split was found and `threshold` is the configured one
+    pub confidence: f64,
+    /// Number of asset pairs per first-frame distance in bits
+    pub histogram: Vec<u32>,
+    pub failed: Vec<JsFailedAsset>,
+}
+
+impl From<ThresholdSuggestion> for JsThresholdSuggestion {
+    fn from(suggestion: ThresholdSuggestion) -> Self {
+        JsThresholdSuggestion {
+            threshold: suggestion.threshold,
+            confidence: suggestion.confidence,
+            histogram: suggestion.histogram.into_iter().map(|count| count as u32).collect(),
+            failed: suggestion.failed.into_iter().map(JsFailedAsset::from).collect(),
+        }
+    }
+}
+
+/// Background task suggesting a threshold
+pub struct SuggestThresholdTask {
+    assets: Vec<Asset>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for SuggestThresholdTask {
+    type Output = ThresholdSuggestion;
+    type JsValue = JsThresholdSuggestion;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let assets = std::mem::take(&mut self.assets);
+        suggest_threshold(assets, Some(self.options.clone())).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsThresholdSuggestion::from(output))
+    }
+}
+
+/// Suggest a threshold from the distribution of pairwise first-frame distances
+#[napi(js_name = "suggestThreshold", ts_return_type = "Promise<JsThresholdSuggestion>")]
+pub fn suggest_threshold_for_assets(
+    assets: Vec<JsAsset>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<SuggestThresholdTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(SuggestThresholdTask {
+        assets: to_assets(assets, options.hash_config())?,
+        options,
+    }))
+}
+
+/// Asset in the similarity graph, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsGraphNode {
+    pub id: String,
+    pub name: String,
+    pub is_video: bool,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Similar pair in the similarity graph, weighted by its mean frame distance
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsGraphEdge {
+    pub source: String,
+    pub target: String,
+    pub distance: f64,
+}
+
+/// Similarity graph, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsSimilarityGraph {
+    pub nodes: Vec<JsGraphNode>,
+    pub edges: Vec<JsGraphEdge>,
+    pub failed: Vec<JsFailedAsset>,
+    /// The graph as Graphviz DOT source
+    pub dot: String,
+}
+
+impl From<SimilarityGraph> for JsSimilarityGraph {
+    fn from(graph: SimilarityGraph) -> Self {
+        let dot = graph.to_dot();
+
+        JsSimilarityGraph {
+            nodes: graph
+                .nodes
+                .into_iter()
+                .map(|node| JsGraphNode {
+                    id: node.id,
+                    name: node.name,
+                    is_video: node.is_video,
+                    width: node.width,
+                    height: node.height,
+                })
+                .collect(),
+            edges: graph
+                .edges
+                .into_iter()
+                .map(|edge| JsGraphEdge {
+                    source: edge.source,
+                    target: edge.target,
+                    distance: edge.distance,
+                })
+                .collect(),
+            failed: graph.failed.into_iter().map(JsFailedAsset::from).collect(),
+            dot,
+        }
+    }
+}
+
+/// Background task building the similarity graph
+pub struct SimilarityGraphTask {
+    assets: Vec<Asset>,
+    cutoff: Option<u32>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for SimilarityGraphTask {
+    type Output = SimilarityGraph;
+    type JsValue = JsSimilarityGraph;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let assets = std::mem::take(&mut self.assets);
+        export_similarity_graph(assets, self.cutoff, Some(self.options.clone()))
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsSimilarityGraph::from(output))
+    }
+}
+
+/// Build the graph of similar assets for visualization: an edge links every pair
+/// whose frames are under `cutoff` bits apart (default: the grouping threshold)
+#[napi(js_name = "exportSimilarityGraph", ts_return_type = "Promise<JsSimilarityGraph>")]
+pub fn export_similarity_graph_of_assets(
+    assets: Vec<JsAsset>,
+    cutoff: Option<u32>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<SimilarityGraphTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(SimilarityGraphTask {
+        assets: to_assets(assets, options.hash_config())?,
+        cutoff,
+        options,
+    }))
+}
+
+/// Persisted group together with its members' hashes, as exchanged with JavaScript
+#[napi(object)]
+pub struct JsAssetGroupWithHashes {
+    pub id: String,
+    pub name: String,
+    pub assets: Vec<JsAsset>,
+    pub representative_asset_id: String,
+    pub confidence: f64,
+    pub distances: Option<HashMap<String, f64>>,
+    pub exact_duplicates: Option<HashMap<String, String>>,
+    pub hashes: Vec<JsAssetHashes>,
+}
+
+impl JsAssetGroupWithHashes {
+    fn into_group(self, hash_config: HashConfig) -> Result<AssetGroupWithHashes> {
+        let group = self;
+        Ok(AssetGroupWithHashes {
+            group: AssetGroup {
+                id: group.id,
+                name: group.name,
+                assets: group
+                    .assets
+                    .into_iter()
+                    .map(|member| member.into_grouped_asset(hash_config))
+                    .collect::<Result<_>>()?,
+                representative_asset_id: group.representative_asset_id,
+                confidence: group.confidence,
+                distances: group.distances.map(|distances| distances.into_iter().collect()),
+                exact_duplicates: group
+                    .exact_duplicates
+                    .map(|duplicates| duplicates.into_iter().collect())
+                    .unwrap_or_default(),
+                subgroups: Vec::new(),
+                thumbnail_path: None,
+                thumbnail_error: None,
+            },
+            hashes: group
+                .hashes
+                .into_iter()
+                .map(|hashes| hashes.into_asset_hashes(hash_config))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl From<AssetGroupWithHashes> for JsAssetGroupWithHashes {
+    fn from(group: AssetGroupWithHashes) -> Self {
+        let hashes = group.hashes.into_iter().map(JsAssetHashes::from).collect();
+        let group = JsAssetGroup::from(group.group);
+
+        JsAssetGroupWithHashes {
+            id: group.id,
+            name: group.name,
+            assets: group.assets,
+            representative_asset_id: group.representative_asset_id,
+            confidence: group.confidence,
+            distances: group.distances,
+            exact_duplicates: group.exact_duplicates,
+            hashes,
+        }
+    }
+}
+
+/// Group a new asset was placed into, as returned to JavaScript
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct JsGroupAssignment {
+    pub asset_id: String,
+    pub group_id: String,
+    pub created_group: bool,
+}
+
+impl From<GroupAssignment> for JsGroupAssignment {
+    fn from(assignment: GroupAssignment) -> Self {
+        JsGroupAssignment {
+            asset_id: assignment.asset_id,
+            group_id: assignment.group_id,
+            created_group: assignment.created_group,
+        }
+    }
+}
+
+/// Result of assigning new assets into existing groups, as returned to JavaScript
+#[napi(object)]
+pub struct JsAssignmentResult {
+    pub groups: Vec<JsAssetGroupWithHashes>,
+    pub assignments: Vec<JsGroupAssignment>,
+    pub failed: Vec<JsFailedAsset>,
+}
+
+impl From<AssignmentResult> for JsAssignmentResult {
+    fn from(result: AssignmentResult) -> Self {
+        JsAssignmentResult {
+            groups: result
+                .groups
+                .into_iter()
+                .map(JsAssetGroupWithHashes::from)
+                .collect(),
+            assignments: result
+                .assignments
+                .into_iter()
+                .map(JsGroupAssignment::from)
+                .collect(),
+            failed: result.failed.into_iter().map(JsFailedAsset::from).collect(),
+        }
+    }
+}
+
+/// Background task slotting new assets into existing groups
+pub struct AssignToGroupsTask {
+    new_assets: Vec<Asset>,
+    existing_groups: Vec<AssetGroupWithHashes>,
+    options: GroupingOptions,
+}
+
+#[napi]
+impl Task for AssignToGroupsTask {
+    type Output = AssignmentResult;
+    type JsValue = JsAssignmentResult;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let new_assets = std::mem::take(&mut self.new_assets);
+        let existing_groups = std::mem::take(&mut self.existing_groups);
+        assign_to_groups(new_assets, existing_groups, Some(self.options.clone()))
+            .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(JsAssignmentResult::from(output))
+    }
+}
+
+/// Add new assets to groups from a previous run without decoding the old members
+#[napi(js_name = "assignToGroups", ts_return_type = "Promise<JsAssignmentResult>")]
+pub fn assign_assets_to_groups(
+    new_assets: Vec<JsAsset>,
+    existing_groups: Vec<JsAssetGroupWithHashes>,
+    options: Option<JsGroupingOptions>,
+) -> Result<AsyncTask<AssignToGroupsTask>> {
+    let options = GroupingOptions::try_from(options.unwrap_or_default()).map_err(to_invalid_arg)?;
+
+    Ok(AsyncTask::new(AssignToGroupsTask {
+        new_assets: to_assets(new_assets, options.hash_config())?,
+        existing_groups: existing_groups
+            .into_iter()
+            .map(|group| group.into_group(options.hash_config()))
+            .collect::<Result<_>>()?,
+        options,
+    }))
+}
@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Frames, ImageFormat};
+use std::borrow::Cow;
+use std::io::Cursor;
+
+use super::sequence::{image_sequence, sample_image_sequence};
+use super::{Asset, AssetSource, FrameSamplingConfig, FrameStrategy};
+
+/// Display time browsers use for GIF frames without a delay
+const DEFAULT_FRAME_DELAY: f64 = 0.1;
+
+/// Frame sampled from an animation
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    /// Time in seconds at which the frame starts showing
+    pub timestamp_secs: f64,
+    pub image: image::DynamicImage,
+}
+
+/// Frames sampled across an animated GIF or WebP
+#[derive(Debug, Clone)]
+pub struct SampledAnimation {
+    pub frames: Vec<AnimationFrame>,
+    /// Length of one loop in seconds
+    pub duration: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Image formats that may hold an animation, by mime type
+fn animation_format(mime_type: &str) -> Option<ImageFormat> {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Sample frames from an asset when it is an animated GIF or WebP, or an
+/// image sequence. Returns `None` for other types and for single frame files,
+/// which are hashed like any other image
+pub fn sample_animated_asset(
+    asset: &Asset,
+    sampling: &FrameSamplingConfig,
+) -> Result<Option<SampledAnimation>> {
+    if let Some((dir, pattern)) = image_sequence(asset)? {
+        return sample_image_sequence(dir, pattern, sampling).map(Some);
+    }
+
+    let Some(format) = animation_format(&asset.mime_type) else {
+        return Ok(None);
+    };
+
+    let bytes = match &asset.source {
+        AssetSource::Path(path) => {
+            Cow::Owned(std::fs::read(path).context("Failed to read animation file")?)
+        }
+        AssetSource::Bytes(bytes) => Cow::Borrowed(bytes.as_slice()),
+    };
+
+    sample_animation(&bytes, format, sampling)
+}
+
+/// Sample frames from encoded GIF or WebP data, spaced like video frames
+/// Keyframe and scene sampling don't apply to animations, they sample uniformly
+/// The animation is decoded twice, once for the frame delays and once for the
+/// sampled frames, so only those are held in memory
+pub fn sample_animation(
+    bytes: &[u8],
+    format: ImageFormat,
+    sampling: &FrameSamplingConfig,
+) -> Result<Option<SampledAnimation>> {
+    let Some(frames) = decode_frames(bytes, format)? else {
+        return Ok(None);
+    };
+
+    let mut start_times = Vec::new();
+    let mut duration = 0.0;
+    for frame in frames {
+        let frame = frame.context("Failed to decode animation frame")?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = numer as f64 / denom as f64 / 1000.0;
+
+        start_times.push(duration);
+        duration += if delay > 0.0 { delay } else { DEFAULT_FRAME_DELAY };
+    }
+
+    if start_times.len() < 2 {
+        return Ok(None);
+    }
+
+    let frame_times = sample_times(sampling, duration);
+
+    // Index of the frame showing at each sample time
+    let mut sampled_indices: Vec<usize> = frame_times
+        .iter()
+        .map(|&time| start_times.partition_point(|&start| start <= time).saturating_sub(1))
+        .collect();
+    sampled_indices.dedup();
+
+    let frames = decode_frames(bytes, format)?.context("Animation disappeared on second pass")?;
+    let mut sampled = Vec::with_capacity(sampled_indices.len());
+    let mut next = sampled_indices.iter().peekable();
+    for (index, frame) in frames.enumerate() {
+        let Some(&&wanted) = next.peek() else {
+            break;
+        };
+        if index != wanted {
+            continue;
+        }
+        next.next();
+
+        let frame = frame.context("Failed to decode animation frame")?;
+        sampled.push(AnimationFrame {
+            timestamp_secs: start_times[index],
+            image: image::DynamicImage::ImageRgba8(frame.into_buffer()),
+        });
+    }
+
+    let (width, height) = sampled
+        .first()
+        .map(|frame| (frame.image.width(), frame.image.height()))
+        .context("No frames sampled from animation")?;
+
+    Ok(Some(SampledAnimation {
+        frames: sampled,
+        duration,
+        width,
+        height,
+    }))
+}
+
+/// Sample times of an animation lasting `duration` seconds, which has no
+/// keyframes or scenes to find so those strategies sample uniformly
+pub fn sample_times(sampling: &FrameSamplingConfig, duration: f64) -> Vec<f64> {
+    match sampling.strategy {
+        FrameStrategy::Keyframes { .. } | FrameStrategy::Scenes { .. } => FrameSamplingConfig {
+            strategy: FrameStrategy::Uniform,
+            ..*sampling
+        }
+        .frame_times(duration),
+        FrameStrategy::Uniform | FrameStrategy::Interval(_) => sampling.frame_times(duration),
+    }
+}
+
+/// Frame iterator of an animation, `None` for a WebP without animation
+fn decode_frames(bytes: &[u8], format: ImageFormat) -> Result<Option<Frames<'_>>> {
+    let reader = Cursor::new(bytes);
+
+    match format {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(reader).context("Failed to decode GIF")?;
+            Ok(Some(decoder.into_frames()))
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader).context("Failed to decode WebP")?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            Ok(Some(decoder.into_frames()))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    fn encode_gif(colors: &[[u8; 4]], delay_ms: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            let frames = colors.iter().map(|&color| {
+                let buffer = RgbaImage::from_pixel(16, 8, Rgba(color));
+                Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_samples_frames_across_animation() {
+        let bytes = encode_gif(&[[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]], 1000);
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(1.0),
+            ..FrameSamplingConfig::default()
+        };
+
+        let animation = sample_animation(&bytes, ImageFormat::Gif, &sampling)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((animation.width, animation.height), (16, 8));
+        assert!((animation.duration - 3.0).abs() < 1e-9);
+
+        let timestamps: Vec<f64> = animation.frames.iter().map(|f| f.timestamp_secs).collect();
+        assert_eq!(timestamps, vec![0.0, 1.0, 2.0]);
+
+        // GIF palettes are quantized, compare the strongest channel only
+        let channels: Vec<usize> = animation
+            .frames
+            .iter()
+            .map(|frame| {
+                let pixel = frame.image.to_rgba8().get_pixel(0, 0).0;
+                (0..3).max_by_key(|&channel| pixel[channel]).unwrap()
+            })
+            .collect();
+        assert_eq!(channels, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_single_frame_gif_is_not_animated() {
+        let bytes = encode_gif(&[[255, 0, 0, 255]], 100);
+
+        let animation =
+            sample_animation(&bytes, ImageFormat::Gif, &FrameSamplingConfig::default()).unwrap();
+        assert!(animation.is_none());
+    }
+}
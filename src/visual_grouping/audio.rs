@@ -0,0 +1,250 @@
+//! Audio fingerprints telling apart videos that look the same but sound
+//! different, such as one cut exported with several voiceovers
+//! Haitsma-Kalker style: every frame of mono audio gives a 32 bit code from
+//! the signs of the energy differences between neighbouring bands, compared
+//! with the previous frame
+
+use serde::{Deserialize, Serialize};
+
+/// Rate in Hz audio is resampled to before fingerprinting
+pub const SAMPLE_RATE: u32 = 11025;
+
+/// Seconds of audio fingerprinted from the start of a video
+pub const MAX_SECONDS: f64 = 120.0;
+
+/// Bit error rate under which two fingerprints count as the same audio,
+/// unrelated audio lands around 0.5
+pub const MAX_BIT_ERROR_RATE: f64 = 0.35;
+
+/// Samples per analysed frame, a power of two for the FFT
+const FRAME_SIZE: usize = 2048;
+
+/// Samples between the starts of consecutive frames
+const HOP_SIZE: usize = 1024;
+
+/// Bands between `MIN_FREQUENCY` and `MAX_FREQUENCY`, one more than the code bits
+const BAND_COUNT: usize = 33;
+
+const MIN_FREQUENCY: f64 = 300.0;
+const MAX_FREQUENCY: f64 = 2000.0;
+
+/// Codes one fingerprint is shifted by at most against the other, about 1.5s
+const MAX_OFFSET: usize = 16;
+
+/// Codes two fingerprints must overlap by for a shifted comparison to count
+const MIN_OVERLAP: usize = 8;
+
+/// Sub-fingerprints of the audio track of an asset, one per `HOP_SIZE` samples
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioFingerprint {
+    pub codes: Vec<u32>,
+}
+
+impl AudioFingerprint {
+    /// True without audio or when the energy in no band ever changed
+    pub fn is_silent(&self) -> bool {
+        self.codes.iter().all(|&code| code == 0)
+    }
+
+    /// Lowest fraction of differing bits over the shifts of one fingerprint
+    /// against the other, `None` when either is empty
+    pub fn bit_error_rate(&self, other: &AudioFingerprint) -> Option<f64> {
+        let shorter = self.codes.len().min(other.codes.len());
+        if shorter == 0 {
+            return None;
+        }
+        let min_overlap = MIN_OVERLAP.min(shorter);
+
+        let shifted = |codes1: &[u32], codes2: &[u32]| {
+            (0..=MAX_OFFSET)
+                .map_while(|offset| codes1.get(offset..))
+                .filter_map(|codes1| {
+                    let overlap = codes1.len().min(codes2.len());
+                    (overlap >= min_overlap).then(|| {
+                        let errors: u32 = codes1
+                            .iter()
+                            .zip(codes2)
+                            .map(|(code1, code2)| (code1 ^ code2).count_ones())
+                            .sum();
+                        errors as f64 / (overlap * 32) as f64
+                    })
+                })
+                .fold(None, |best: Option<f64>, rate| Some(best.map_or(rate, |b| b.min(rate))))
+        };
+
+        match (shifted(&self.codes, &other.codes), shifted(&other.codes, &self.codes)) {
+            (Some(rate1), Some(rate2)) => Some(rate1.min(rate2)),
+            (rate1, rate2) => rate1.or(rate2),
+        }
+    }
+}
+
+/// Whether two assets have the same audio
+/// Silent and audio-less assets match each other, but not assets with sound
+pub fn audio_matches(
+    fingerprint1: Option<&AudioFingerprint>,
+    fingerprint2: Option<&AudioFingerprint>,
+) -> bool {
+    let audible = |fingerprint: &&AudioFingerprint| !fingerprint.is_silent();
+    match (fingerprint1.filter(audible), fingerprint2.filter(audible)) {
+        (Some(fingerprint1), Some(fingerprint2)) => fingerprint1
+            .bit_error_rate(fingerprint2)
+            .is_some_and(|rate| rate < MAX_BIT_ERROR_RATE),
+        (fingerprint1, fingerprint2) => fingerprint1.is_none() && fingerprint2.is_none(),
+    }
+}
+
+/// Fingerprint of mono samples at `SAMPLE_RATE`, empty when they are shorter
+/// than two frames
+pub fn fingerprint_samples(samples: &[f32]) -> AudioFingerprint {
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / FRAME_SIZE as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect();
+    let twiddles: Vec<(f32, f32)> = (0..FRAME_SIZE / 2)
+        .map(|k| (-2.0 * std::f32::consts::PI * k as f32 / FRAME_SIZE as f32).sin_cos())
+        .collect();
+    let edges = band_edges();
+
+    let mut real = vec![0.0; FRAME_SIZE];
+    let mut imaginary = vec![0.0; FRAME_SIZE];
+    let mut previous: Option<[f32; BAND_COUNT]> = None;
+    let mut codes = Vec::new();
+
+    for start in (0..samples.len().saturating_sub(FRAME_SIZE - 1)).step_by(HOP_SIZE) {
+        let frame = &samples[start..start + FRAME_SIZE];
+        for (i, (&sample, &weight)) in frame.iter().zip(&window).enumerate() {
+            real[i] = sample * weight;
+            imaginary[i] = 0.0;
+        }
+        fft(&mut real, &mut imaginary, &twiddles);
+
+        let mut energies = [0.0; BAND_COUNT];
+        for (band, energy) in energies.iter_mut().enumerate() {
+            *energy = (edges[band]..edges[band + 1])
+                .map(|bin| real[bin] * real[bin] + imaginary[bin] * imaginary[bin])
+                .sum();
+        }
+
+        if let Some(previous) = previous {
+            let code = (0..BAND_COUNT - 1).fold(0u32, |code, band| {
+                let difference = energies[band] - energies[band + 1];
+                let previous_difference = previous[band] - previous[band + 1];
+                code << 1 | (difference - previous_difference > 0.0) as u32
+            });
+            codes.push(code);
+        }
+        previous = Some(energies);
+    }
+
+    AudioFingerprint { codes }
+}
+
+/// FFT bins bounding the bands, spaced logarithmically like pitch
+fn band_edges() -> [usize; BAND_COUNT + 1] {
+    let bin_hz = SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+    std::array::from_fn(|band| {
+        let ratio = band as f64 / BAND_COUNT as f64;
+        let frequency = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(ratio);
+        (frequency / bin_hz).round() as usize
+    })
+}
+
+/// In-place iterative radix-2 FFT of a `FRAME_SIZE` signal
+/// `twiddles` holds the sine and cosine of `-2πk / FRAME_SIZE` for each k
+fn fft(real: &mut [f32], imaginary: &mut [f32], twiddles: &[(f32, f32)]) {
+    let size = real.len();
+
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= size {
+        let stride = size / length;
+        for start in (0..size).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + length / 2);
+                let odd_real = real[b] * cos - imaginary[b] * sin;
+                let odd_imaginary = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - odd_real;
+                imaginary[b] = imaginary[a] - odd_imaginary;
+                real[a] += odd_real;
+                imaginary[a] += odd_imaginary;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One tone per band, each swelling and fading at its own rate
+    fn voice(seconds: f64, seed: u32) -> Vec<f32> {
+        let tones: Vec<(f64, f64, f64)> = (0..BAND_COUNT)
+            .map(|band| {
+                let ratio = (band as f64 + 0.5) / BAND_COUNT as f64;
+                let frequency = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(ratio);
+                let mix = (band as u32 + 1 + seed * 97).wrapping_mul(2_654_435_761);
+                let rate = 0.5 + (mix % 1000) as f64 / 250.0;
+                let phase = (mix / 1000 % 628) as f64 / 100.0;
+                (frequency, rate, phase)
+            })
+            .collect();
+
+        (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let sample: f64 = tones
+                    .iter()
+                    .map(|&(frequency, rate, phase)| {
+                        let envelope = 1.0 + (2.0 * std::f64::consts::PI * rate * t + phase).sin();
+                        envelope * (2.0 * std::f64::consts::PI * frequency * t).sin()
+                    })
+                    .sum();
+                (sample / BAND_COUNT as f64) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_audio_matches_and_other_audio_does_not() {
+        let original = voice(6.0, 1);
+        let fingerprint = fingerprint_samples(&original);
+        assert_eq!(fingerprint.codes.len(), (original.len() - FRAME_SIZE) / HOP_SIZE);
+
+        // Quieter and starting a few frames later is still the same voiceover
+        let quieter: Vec<f32> = original[3 * HOP_SIZE + 100..].iter().map(|s| s * 0.5).collect();
+        let rate = fingerprint.bit_error_rate(&fingerprint_samples(&quieter)).unwrap();
+        assert!(rate < 0.1, "{}", rate);
+
+        let other = fingerprint_samples(&voice(6.0, 2));
+        let rate = fingerprint.bit_error_rate(&other).unwrap();
+        assert!(rate > MAX_BIT_ERROR_RATE, "{}", rate);
+        assert!(!audio_matches(Some(&fingerprint), Some(&other)));
+
+        // Missing or silent audio only matches missing or silent audio
+        let silence = fingerprint_samples(&vec![0.0; original.len()]);
+        assert!(audio_matches(None, None));
+        assert!(audio_matches(None, Some(&AudioFingerprint::default())));
+        assert!(!audio_matches(None, Some(&fingerprint)));
+        assert!(audio_matches(Some(&silence), Some(&silence)));
+        assert!(audio_matches(None, Some(&silence)));
+        assert!(!audio_matches(Some(&silence), Some(&fingerprint)));
+    }
+}
@@ -0,0 +1,168 @@
+//! BlurHash encoder, see https://blurha.sh
+//! A handful of DCT components of the image packed into a short base 83
+//! string that UIs decode into a blurred placeholder
+
+use anyhow::Result;
+use std::f64::consts::PI;
+
+/// Components along x and y, the encoding most decoders expect
+pub const DEFAULT_COMPONENTS: (u32, u32) = (4, 3);
+
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// BlurHash of an RGBA image, alpha is ignored
+/// `components` is the number of cosine components along x and y, each from 1 to 9
+pub fn encode(components: (u32, u32), width: u32, height: u32, rgba: &[u8]) -> Result<String> {
+    let (components_x, components_y) = components;
+    if !(1..=9).contains(&components_x) || !(1..=9).contains(&components_y) {
+        anyhow::bail!(
+            "BlurHash components must be between 1 and 9, got {}x{}",
+            components_x,
+            components_y
+        );
+    }
+    if width == 0 || height == 0 {
+        anyhow::bail!("Can't compute the BlurHash of an empty image");
+    }
+    if rgba.len() != width as usize * height as usize * 4 {
+        anyhow::bail!(
+            "Expected {} RGBA bytes for a {}x{} image",
+            width as usize * height as usize * 4,
+            width,
+            height
+        );
+    }
+
+    // Cosines are separable, so one table per axis covers every component
+    let basis = |components: u32, size: u32| -> Vec<Vec<f64>> {
+        (0..components)
+            .map(|component| {
+                (0..size)
+                    .map(|position| {
+                        (PI * component as f64 * position as f64 / size as f64).cos()
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+    let basis_x = basis(components_x, width);
+    let basis_y = basis(components_y, height);
+
+    let linear: Vec<[f64; 3]> = rgba
+        .chunks_exact(4)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]].map(srgb_to_linear))
+        .collect();
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for cosines_y in &basis_y {
+        for cosines_x in &basis_x {
+            let mut factor = [0.0; 3];
+            for (y, cosine_y) in cosines_y.iter().enumerate() {
+                let row = &linear[y * width as usize..(y + 1) * width as usize];
+                for (pixel, cosine_x) in row.iter().zip(cosines_x) {
+                    let weight = cosine_x * cosine_y;
+                    for (sum, value) in factor.iter_mut().zip(pixel) {
+                        *sum += weight * value;
+                    }
+                }
+            }
+            factors.push(factor);
+        }
+    }
+
+    let pixels = width as f64 * height as f64;
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let dc = dc.map(|value| value / pixels);
+    // Every component but the DC one is counted twice by the decoder
+    let ac: Vec<[f64; 3]> = ac
+        .iter()
+        .map(|factor| factor.map(|value| value * 2.0 / pixels))
+        .collect();
+
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    push_base83(&mut hash, (components_x - 1) + (components_y - 1) * 9, 1);
+
+    let max_value = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual_max = ac.iter().flatten().fold(0.0_f64, |max, value| max.max(value.abs()));
+        let quantised_max = (actual_max * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        push_base83(&mut hash, quantised_max, 1);
+        (quantised_max + 1) as f64 / 166.0
+    };
+
+    let [red, green, blue] = dc.map(linear_to_srgb);
+    push_base83(&mut hash, (red << 16) + (green << 8) + blue, 4);
+
+    for factor in &ac {
+        let [red, green, blue] = factor.map(|value| {
+            let scaled = (value / max_value).abs().sqrt().copysign(value);
+            (scaled * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, red * 19 * 19 + green * 19 + blue, 2);
+    }
+
+    Ok(hash)
+}
+
+fn push_base83(hash: &mut String, value: u32, digits: u32) {
+    for digit in (0..digits).rev() {
+        let index = value / 83_u32.pow(digit) % 83;
+        hash.push(BASE83[index as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_follows_the_blurhash_format() {
+        let solid = |[red, green, blue]: [u8; 3]| [red, green, blue, 255].repeat(8 * 6);
+
+        // Black has no energy at all, every AC component sits at the midpoint
+        let black = encode(DEFAULT_COMPONENTS, 8, 6, &solid([0, 0, 0])).unwrap();
+        assert_eq!(black, format!("L00000{}", "fQ".repeat(11)));
+
+        let orange = encode((1, 1), 8, 6, &solid([255, 128, 0])).unwrap();
+        let dc = (255 << 16) + (128 << 8);
+        let digits: String = (0..4)
+            .rev()
+            .map(|digit| BASE83[(dc / 83_u32.pow(digit) % 83) as usize] as char)
+            .collect();
+        assert_eq!(orange, format!("00{}", digits));
+
+        // Left half black, right half white puts the energy in the first x component
+        let split: Vec<u8> = (0..6 * 8)
+            .flat_map(|index| if index % 8 < 4 { [0, 0, 0, 255] } else { [255; 4] })
+            .collect();
+        let split = encode(DEFAULT_COMPONENTS, 8, 6, &split).unwrap();
+        assert_eq!(split.len(), 28);
+        assert_ne!(&split[1..2], "0");
+        assert_ne!(&split[6..8], "fQ");
+
+        assert!(encode((0, 3), 8, 6, &solid([0, 0, 0])).is_err());
+        assert!(encode(DEFAULT_COMPONENTS, 8, 5, &solid([0, 0, 0])).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::UNIX_EPOCH;
+
+use super::audio::AudioFingerprint;
+use super::{
+    Asset, AssetSource, AudioMode, FrameData, FrameSamplingConfig, GroupingOptions, HashedAsset,
+    SamplingPolicy,
+};
+
+/// Bumped whenever the entry layout changes, older files are rebuilt
+const CACHE_VERSION: u32 = 2;
+
+/// Hashes of one file, valid while the file keeps its size and mtime and the
+/// hashing settings stay the same
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    file_size: u64,
+    mtime_nanos: u64,
+    /// Hashing settings the frames were produced with, see `settings_key`
+    settings: String,
+    is_video: bool,
+    frames: Vec<FrameData>,
+    width: u32,
+    height: u32,
+    duration: Option<f64>,
+    is_animated: bool,
+    dominant_colors: Vec<[u8; 3]>,
+    #[serde(default)]
+    audio_fingerprint: Option<AudioFingerprint>,
+    #[serde(default)]
+    sampling_policy: Option<SamplingPolicy>,
+    #[serde(default)]
+    decode_errors: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// Entries by canonical path
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// On-disk cache of frame hashes, keyed by canonical path, file size and mtime
+/// Stored as a single JSON file that is loaded when the cache is opened and
+/// written back by `save`. Shared between hashing threads
+#[derive(Debug)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    dirty: AtomicBool,
+}
+
+impl HashCache {
+    /// Load the cache file at `path`
+    /// A missing, unreadable or corrupted file gives an empty cache that
+    /// replaces the file on the next `save`
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<CacheFile>(&bytes) {
+                Ok(file) if file.version == CACHE_VERSION => file.entries,
+                Ok(file) => {
+                    log::info!(
+                        "Rebuilding hash cache {} from version {}",
+                        path.display(),
+                        file.version
+                    );
+                    HashMap::new()
+                }
+                Err(err) => {
+                    log::warn!("Rebuilding corrupted hash cache {}: {}", path.display(), err);
+                    HashMap::new()
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                log::warn!("Ignoring unreadable hash cache {}: {}", path.display(), err);
+                HashMap::new()
+            }
+        };
+
+        HashCache {
+            path,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Delete the cache file at `path`, a missing file is not an error
+    pub fn clear<P: AsRef<Path>>(path: P) -> Result<()> {
+        match std::fs::remove_file(path.as_ref()) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err)
+                .with_context(|| format!("Failed to remove {}", path.as_ref().display())),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Cached hashes of a file asset, `None` when the file changed since it
+    /// was hashed or was hashed with different settings
+    pub fn get(&self, asset: &Asset, options: &GroupingOptions) -> Option<HashedAsset> {
+        let (key, file_size, mtime_nanos) = file_key(asset)?;
+        let settings = settings_key(options);
+
+        let entries = self.lock();
+        let entry = entries.get(&key)?;
+        if entry.file_size != file_size
+            || entry.mtime_nanos != mtime_nanos
+            || entry.settings != settings
+            || entry.is_video != asset.is_video
+        {
+            return None;
+        }
+
+        log::debug!("Hash cache hit for {}", key);
+
+        Some(HashedAsset {
+            asset: asset.clone(),
+            // Deserialized frames carry no packed form, rebuild it
+            frames: entry
+                .frames
+                .iter()
+                .map(|frame| FrameData {
+                    blurhash: frame.blurhash.clone(),
+                    represented_frames: frame.represented_frames,
+                    uniform: frame.uniform,
+                    ..FrameData::new(frame.frame_number, frame.timestamp_secs, frame.hash.clone())
+                        .with_variants(frame.variants.clone())
+                        .with_regions(frame.regions.clone())
+                })
+                .collect(),
+            aspect_ratio: entry.width as f64 / entry.height as f64,
+            width: entry.width,
+            height: entry.height,
+            duration: entry.duration,
+            degraded: false,
+            is_animated: entry.is_animated,
+            dominant_colors: entry.dominant_colors.clone(),
+            audio_fingerprint: entry.audio_fingerprint.clone(),
+            sampling_policy: entry.sampling_policy,
+            decode_errors: entry.decode_errors,
+        })
+    }
+
+    /// Store the hashes of a file asset
+    /// Degraded results are skipped so the lost frames are retried next time
+    pub fn insert(&self, hashed_asset: &HashedAsset, options: &GroupingOptions) {
+        if hashed_asset.degraded {
+            return;
+        }
+        let Some((key, file_size, mtime_nanos)) = file_key(&hashed_asset.asset) else {
+            return;
+        };
+
+        let entry = CacheEntry {
+            file_size,
+            mtime_nanos,
+            settings: settings_key(options),
+            is_video: hashed_asset.asset.is_video,
+            frames: hashed_asset.frames.clone(),
+            width: hashed_asset.width,
+            height: hashed_asset.height,
+            duration: hashed_asset.duration,
+            is_animated: hashed_asset.is_animated,
+            dominant_colors: hashed_asset.dominant_colors.clone(),
+            audio_fingerprint: hashed_asset.audio_fingerprint.clone(),
+            sampling_policy: hashed_asset.sampling_policy,
+            decode_errors: hashed_asset.decode_errors,
+        };
+
+        self.lock().insert(key, entry);
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Write the cache file if entries were added since it was opened
+    /// The file is replaced atomically, so an interrupted save leaves the old one
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries: self.lock().clone(),
+        };
+
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(dir).context("Failed to create the hash cache directory")?;
+
+        let mut temp_file =
+            tempfile::NamedTempFile::new_in(dir).context("Failed to create the hash cache file")?;
+        serde_json::to_writer(&mut temp_file, &file).context("Failed to write the hash cache")?;
+        temp_file.flush().context("Failed to write the hash cache")?;
+        temp_file
+            .persist(&self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+        self.dirty.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CacheEntry>> {
+        // A panicking writer can only leave a complete entry map behind
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Canonical path, size and mtime of a file asset, `None` for in-memory
+/// assets and files that can't be inspected
+fn file_key(asset: &Asset) -> Option<(String, u64, u64)> {
+    let AssetSource::Path(path) = &asset.source else {
+        return None;
+    };
+
+    let canonical = std::fs::canonicalize(path).ok()?;
+    // A directory of image sequence frames keeps its mtime when a frame is edited
+    let metadata = std::fs::metadata(&canonical).ok().filter(|metadata| metadata.is_file())?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    Some((
+        canonical.to_string_lossy().into_owned(),
+        metadata.len(),
+        mtime.as_nanos() as u64,
+    ))
+}
+
+/// Every option that changes the produced hashes
+fn settings_key(options: &GroupingOptions) -> String {
+    serde_json::to_string(&(
+        options.hash_config(),
+        options.hash_extras(),
+        // Hardware decoding hashes within the threshold of software decoding
+        FrameSamplingConfig {
+            hw_accel: false,
+            ..options.frame_sampling
+        },
+        options.frame_dedup_distance,
+        // Separate and annotate store the same fingerprint
+        options.audio_mode != AudioMode::Ignore,
+    ))
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::AssetKind;
+    use crate::visual_grouping::grouping::process_asset;
+
+    fn image_asset(path: &Path) -> Asset {
+        Asset {
+            id: "image".to_string(),
+            name: "image.png".to_string(),
+            source: AssetSource::Path(path.to_path_buf()),
+            mime_type: "image/png".to_string(),
+            is_video: false,
+            precomputed: None,
+            kind: AssetKind::File,
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trip_and_invalidation() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let image_path = temp_dir.path().join("image.png");
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, 0]))
+            .save(&image_path)
+            .unwrap();
+        let cache_path = temp_dir.path().join("cache").join("hashes.json");
+
+        let asset = image_asset(&image_path);
+        let options = GroupingOptions::default();
+        let (hashed, _) = process_asset(&asset, &options, None).unwrap();
+
+        let cache = HashCache::open(&cache_path);
+        assert!(cache.get(&asset, &options).is_none());
+        cache.insert(&hashed, &options);
+        cache.save().unwrap();
+
+        let reopened = HashCache::open(&cache_path);
+        let cached = reopened.get(&asset, &options).unwrap();
+        assert_eq!(cached.frames[0].hash, hashed.frames[0].hash);
+        assert_eq!(cached.frames[0].packed, hashed.frames[0].packed);
+        assert_eq!((cached.width, cached.height), (32, 32));
+
+        // Other hashing settings miss
+        let other = GroupingOptions {
+            hash_size: 16,
+            ..GroupingOptions::default()
+        };
+        assert!(reopened.get(&asset, &other).is_none());
+
+        // A rewritten file with another size misses
+        image::RgbImage::new(48, 32).save(&image_path).unwrap();
+        assert!(reopened.get(&asset, &options).is_none());
+
+        // A corrupted file is rebuilt rather than failing
+        std::fs::write(&cache_path, b"{not json").unwrap();
+        let rebuilt = HashCache::open(&cache_path);
+        assert!(rebuilt.is_empty());
+        rebuilt.insert(&hashed, &options);
+        rebuilt.save().unwrap();
+        assert_eq!(HashCache::open(&cache_path).len(), 1);
+
+        HashCache::clear(&cache_path).unwrap();
+        assert!(!cache_path.exists());
+        HashCache::clear(&cache_path).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use super::{FailedAsset, HashedAsset};
+
+/// Asset in the similarity graph
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GraphNode {
+    pub id: String,
+    pub name: String,
+    pub is_video: bool,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<&HashedAsset> for GraphNode {
+    fn from(hashed: &HashedAsset) -> Self {
+        GraphNode {
+            id: hashed.asset.id.clone(),
+            name: hashed.asset.name.clone(),
+            is_video: hashed.asset.is_video,
+            width: hashed.width,
+            height: hashed.height,
+        }
+    }
+}
+
+/// Pair of similar assets, `source` comes before `target` in the node list
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    /// Mean frame distance under the best frame alignment
+    pub distance: f64,
+}
+
+/// Assets as nodes and their similar pairs as weighted edges
+/// Built from the same comparisons as grouping, so at the grouping threshold
+/// every group is a connected set of nodes
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SimilarityGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    pub failed: Vec<FailedAsset>,
+}
+
+impl SimilarityGraph {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).context("Failed to serialize the similarity graph")
+    }
+
+    /// Graphviz DOT source of the graph, images as boxes and videos as ellipses,
+    /// edges labelled with their distance
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph similarity {\n");
+
+        for node in &self.nodes {
+            let _ = writeln!(
+                dot,
+                "  \"{}\" [label=\"{}\\n{}x{}\", shape={}];",
+                escape_dot(&node.id),
+                escape_dot(&node.name),
+                node.width,
+                node.height,
+                if node.is_video { "ellipse" } else { "box" }
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                dot,
+                "  \"{}\" -- \"{}\" [label=\"{:.1}\"];",
+                escape_dot(&edge.source),
+                escape_dot(&edge.target),
+                edge.distance
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escape a string for use inside a quoted DOT id
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_output() {
+        let node = |id: &str, is_video| GraphNode {
+            id: id.to_string(),
+            name: format!("{}.mp4", id),
+            is_video,
+            width: 1920,
+            height: 1080,
+        };
+        let graph = SimilarityGraph {
+            nodes: vec![node("a", true), node("say \"b\"", false)],
+            edges: vec![GraphEdge {
+                source: "a".to_string(),
+                target: "say \"b\"".to_string(),
+                distance: 2.5,
+            }],
+            failed: Vec::new(),
+        };
+
+        assert_eq!(
+            graph.to_dot(),
+            concat!(
+                "graph similarity {\n",
+                "  \"a\" [label=\"a.mp4\\n1920x1080\", shape=ellipse];\n",
+                "  \"say \\\"b\\\"\" [label=\"say \\\"b\\\".mp4\\n1920x1080\", shape=box];\n",
+                "  \"a\" -- \"say \\\"b\\\"\" [label=\"2.5\"];\n",
+                "}\n",
+            )
+        );
+
+        let parsed: SimilarityGraph = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.edges, graph.edges);
+    }
+}
@@ -1,210 +1,3712 @@
-use super::{Asset, AssetGroup, FrameData, HashedAsset};
-use crate::visual_grouping::hash::{generate_perceptual_hash, hamming_distance};
+use super::{
+    Asset, AssetGroup, AssetGroupWithHashes, AssetHashes, AssetKind, AssetSource, AssetTimings,
+    AssignmentMode, AssignmentResult, AudioMode, CancellationToken, Cancelled, DuplicatePair,
+    FailedAsset, FrameData, FrameStrategy, GroupAssignment, GroupedAsset, GroupingOptions,
+    GroupingResult, GroupingStats, HashedAsset, Invariance, Linkage, NamingStrategy, NoVideoStream,
+    PairDistance, PrecomputedHashes, ProgressEvent, ProgressPhase, SamplingPolicy, SimilarMatch,
+    SimilarityMatrix, SimilarityReport, ThresholdSuggestion, log_scope, set_log_scope,
+};
+use crate::visual_grouping::animation::{SampledAnimation, sample_animated_asset};
+use crate::visual_grouping::audio::{AudioFingerprint, audio_matches};
+use crate::visual_grouping::cache::HashCache;
+use crate::visual_grouping::graph::{GraphEdge, GraphNode, SimilarityGraph};
+use crate::visual_grouping::hash::{
+    HashExtras, generate_perceptual_hash, generate_perceptual_hash_from_bytes,
+    generate_perceptual_hash_from_image, palette_distance,
+};
+use crate::visual_grouping::index::HashIndex;
+use crate::visual_grouping::naming::{derive_group_name, extract_base_name};
+#[cfg(feature = "remote")]
+use crate::visual_grouping::remote::fetch_asset as fetch_remote_asset;
+use crate::visual_grouping::scan::{check_asset_file, sniff_media_type, sniff_media_type_from_bytes};
+use crate::visual_grouping::sequence::image_sequence;
+use crate::visual_grouping::image_info::{get_image_dimensions, get_image_dimensions_from_bytes};
+use crate::visual_grouping::thumbnail::write_group_thumbnails;
+#[cfg(feature = "video")]
 use crate::visual_grouping::video::{
-    extract_frames_from_video, get_image_dimensions, get_video_dimension,
+    FrameExtractionStats, audio_fingerprint, extract_frames_as_images, probe_video,
 };
 use anyhow::{Context, Result};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 use tempfile::TempDir;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+#[cfg(feature = "video")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Callback receiving progress events, may be invoked from a worker thread
+pub type ProgressCallback = dyn Fn(ProgressEvent) + Send + Sync;
+
+/// Hashed assets, each with the temp directory its frames were extracted to
+pub type HashedAssets = Vec<(HashedAsset, Option<TempDir>)>;
+
+/// Counters behind `GroupingStats`, shared by the hashing and comparison workers
+#[derive(Debug, Default)]
+struct StatsCollector {
+    probe_nanos: AtomicU64,
+    extraction_nanos: AtomicU64,
+    frames_extracted: AtomicUsize,
+    comparisons: AtomicUsize,
+    cache_hits: AtomicUsize,
+    hardware_decoded_assets: AtomicUsize,
+    decode_errors: AtomicUsize,
+    active_workers: AtomicUsize,
+    peak_workers: AtomicUsize,
+    timings: Mutex<BTreeMap<String, AssetTimings>>,
+}
+
+impl StatsCollector {
+    fn add_time(counter: &AtomicU64, elapsed: Duration) {
+        counter.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Count a busy worker until the guard is dropped
+    fn worker(&self) -> WorkerGuard<'_> {
+        let active = self.active_workers.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak_workers.fetch_max(active, Ordering::SeqCst);
+        WorkerGuard(self)
+    }
+
+    /// Add the counters of one asset processed in `elapsed`, keeping its timings
+    /// and warning when it took longer than `slow_asset_threshold_ms`
+    fn record_asset(
+        &self,
+        asset: &Asset,
+        asset_stats: &StatsCollector,
+        elapsed: Duration,
+        options: &GroupingOptions,
+    ) {
+        let counters = [
+            (&self.frames_extracted, &asset_stats.frames_extracted),
+            (&self.cache_hits, &asset_stats.cache_hits),
+            (&self.hardware_decoded_assets, &asset_stats.hardware_decoded_assets),
+            (&self.decode_errors, &asset_stats.decode_errors),
+        ];
+        for (total, count) in counters {
+            total.fetch_add(count.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        let probe_nanos = asset_stats.probe_nanos.load(Ordering::Relaxed);
+        let extraction_nanos = asset_stats.extraction_nanos.load(Ordering::Relaxed);
+        self.probe_nanos.fetch_add(probe_nanos, Ordering::Relaxed);
+        self.extraction_nanos.fetch_add(extraction_nanos, Ordering::Relaxed);
+
+        let total_nanos = elapsed.as_nanos() as u64;
+        let timings = AssetTimings {
+            probe_ms: probe_nanos as f64 / 1e6,
+            extraction_ms: extraction_nanos as f64 / 1e6,
+            hashing_ms: total_nanos.saturating_sub(probe_nanos + extraction_nanos) as f64 / 1e6,
+            total_ms: total_nanos as f64 / 1e6,
+        };
+        if let Some(threshold) = options.slow_asset_threshold_ms
+            && elapsed.as_millis() > u128::from(threshold)
+        {
+            log::warn!(
+                "Slow asset: {} ({}) took {:.0} ms (probe {:.0} ms, extraction {:.0} ms)",
+                asset.name,
+                asset.id,
+                timings.total_ms,
+                timings.probe_ms,
+                timings.extraction_ms
+            );
+        }
+        self.lock_timings().insert(asset.id.clone(), timings);
+    }
+
+    /// Timings recorded by `record_asset`, leaving none behind
+    fn take_timings(&self) -> BTreeMap<String, AssetTimings> {
+        std::mem::take(&mut *self.lock_timings())
+    }
+
+    fn lock_timings(&self) -> MutexGuard<'_, BTreeMap<String, AssetTimings>> {
+        self.timings.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn stats(&self, hashing: Duration, comparison: Duration) -> GroupingStats {
+        let millis = |nanos: &AtomicU64| nanos.load(Ordering::Relaxed) as f64 / 1e6;
+        GroupingStats {
+            probe_ms: millis(&self.probe_nanos),
+            extraction_ms: millis(&self.extraction_nanos),
+            hashing_ms: hashing.as_secs_f64() * 1e3,
+            comparison_ms: comparison.as_secs_f64() * 1e3,
+            frames_extracted: self.frames_extracted.load(Ordering::Relaxed),
+            comparisons: self.comparisons.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            peak_workers: self.peak_workers.load(Ordering::SeqCst),
+            hardware_decoded_assets: self.hardware_decoded_assets.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct WorkerGuard<'a>(&'a StatsCollector);
+
+impl Drop for WorkerGuard<'_> {
+    fn drop(&mut self) {
+        self.0.active_workers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Build a HashedAsset from hashes supplied by the caller
+/// Hashes must match the byte length of the configured hash size
+fn hashed_asset_from_precomputed(
+    asset: &Asset,
+    precomputed: &PrecomputedHashes,
+    options: &GroupingOptions,
+) -> Result<HashedAsset> {
+    if precomputed.frames.is_empty() {
+        anyhow::bail!("Precomputed hashes for asset {} contain no frames", asset.id);
+    }
+
+    if precomputed.width == 0 || precomputed.height == 0 {
+        anyhow::bail!(
+            "Precomputed dimensions for asset {} must be non-zero, got {}x{}",
+            asset.id,
+            precomputed.width,
+            precomputed.height
+        );
+    }
+
+    // Hash lengths were checked against their grid when the frames were built
+    let hash_config = options.hash_config();
+    for frame in &precomputed.frames {
+        if let Some(hash) = std::iter::once(&frame.hash)
+            .chain(&frame.variants)
+            .chain(&frame.regions)
+            .find(|hash| !hash_config.produces(hash))
+        {
+            anyhow::bail!(
+                "Precomputed hash for frame {} of asset {} is a {} hash, expected {}",
+                frame.frame_number,
+                asset.id,
+                hash.config(),
+                hash_config
+            );
+        }
+    }
+
+    Ok(HashedAsset {
+        asset: asset.clone(),
+        // Deserialized frames carry no packed form, rebuild it
+        frames: precomputed
+            .frames
+            .iter()
+            .map(|frame| {
+                // Extra hashes are only kept when the options use them
+                let extras = options.hash_extras();
+                let mut rebuilt =
+                    FrameData::new(frame.frame_number, frame.timestamp_secs, frame.hash.clone());
+                if extras.invariance != Invariance::None {
+                    rebuilt = rebuilt.with_variants(frame.variants.clone());
+                }
+                if extras.regions {
+                    rebuilt = rebuilt.with_regions(frame.regions.clone());
+                }
+                if extras.blurhash {
+                    rebuilt.blurhash = frame.blurhash.clone();
+                }
+                rebuilt.represented_frames = frame.represented_frames;
+                rebuilt.uniform = frame.uniform;
+                rebuilt
+            })
+            .collect(),
+        aspect_ratio: precomputed.width as f64 / precomputed.height as f64,
+        width: precomputed.width,
+        height: precomputed.height,
+        duration: precomputed.duration,
+        degraded: false,
+        is_animated: !asset.is_video && precomputed.duration.is_some(),
+        dominant_colors: precomputed.dominant_colors.clone(),
+        audio_fingerprint: precomputed.audio_fingerprint.clone(),
+        sampling_policy: None,
+        decode_errors: 0,
+    })
+}
 
 /// Process an asset extract frame hashes
 /// Returns the HashedAsset and optionally a temp directory for cleanup
-pub fn process_asset(asset: &Asset) -> Result<(HashedAsset, Option<TempDir>)> {
-    let (frame_paths, dimensions, temp_dir) = if asset.is_video {
-        let temp_dir = TempDir::new().context("Failed to create temp directory")?;
-        let frame_paths = extract_frames_from_video(&asset.path, &temp_dir)
-            .context("Failed to extract frames from video")?;
+/// In-memory images are hashed without touching disk, in-memory videos are
+/// written to the temp directory first so ffmpeg can open them.
+/// Video frames are hashed in memory unless `frames_dir` asks for frame files.
+/// Assets carrying precomputed hashes are not decoded at all, assets at http(s)
+/// URLs are fetched first and keep the URL as their source.
+/// The file content decides between image and video, see `strict_type_check`
+pub fn process_asset(
+    asset: &Asset,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+) -> Result<(HashedAsset, Option<TempDir>)> {
+    process_asset_with_stats(asset, options, cancel, &StatsCollector::default())
+}
+
+/// `process_asset` recording probe and extraction times and extracted frames
+fn process_asset_with_stats(
+    asset: &Asset,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+) -> Result<(HashedAsset, Option<TempDir>)> {
+    if let Some(precomputed) = &asset.precomputed {
+        let hashed_asset = hashed_asset_from_precomputed(asset, precomputed, options)?;
+        return Ok((hashed_asset, None));
+    }
 
-        let dimensions =
-            get_video_dimension(&asset.path).context("Failed to get the video dimensions")?;
+    let remote = match asset.source.url() {
+        Some(url) => Some(fetch_remote_asset(asset, url, options)?),
+        None => None,
+    };
+    let local = remote.as_ref().map_or(asset, |(fetched, _)| fetched);
 
-        (frame_paths, dimensions, Some(temp_dir))
-    } else {
-        // for images, treat as a single frame
-        let frame_paths = vec![asset.path.clone()];
+    let corrected = match image_sequence(local)? {
+        // Image sequences hash like animations, whatever they were declared as
+        Some(_) if local.is_video => Some(Asset {
+            is_video: false,
+            ..local.clone()
+        }),
+        Some(_) => None,
+        None => checked_media_type(local, options)?,
+    };
+    let checked = corrected.as_ref().unwrap_or(local);
+    let (mut hashed_asset, temp_dir) = match hash_asset_content(checked, options, cancel, stats) {
+        Err(err) if err.is::<NoVideoStream>() => {
+            hash_mislabeled_image(checked, options, cancel, stats)?
+        }
+        result => result?,
+    };
+
+    let Some((_, download_dir)) = remote else {
+        return Ok((hashed_asset, temp_dir));
+    };
+    // Groups point at the URL rather than at the downloaded copy
+    hashed_asset.asset.source = asset.source.clone();
+    Ok((hashed_asset, download_dir.or(temp_dir)))
+}
 
-        // Get image dimensions
-        let dimensions = get_image_dimensions(&asset.path).context(
-            "Failed to get image dimensions"
-        )?;
+/// Hash an asset declared as a video whose file has no video stream as the
+/// image it may be, content sniffing misses formats without magic bytes such
+/// as TGA. Fails with a bare `NoVideoStream` otherwise, and always under
+/// `strict_type_check`
+fn hash_mislabeled_image(
+    asset: &Asset,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+) -> Result<(HashedAsset, Option<TempDir>)> {
+    if options.strict_type_check {
+        return Err(NoVideoStream.into());
+    }
 
-        (frame_paths, dimensions, None)
+    let format = match &asset.source {
+        AssetSource::Path(path) => image::ImageFormat::from_path(path).ok(),
+        AssetSource::Bytes(bytes) => image::guess_format(bytes).ok(),
+    };
+    let image = Asset {
+        mime_type: format.map_or(asset.mime_type.clone(), |format| {
+            format.to_mime_type().to_string()
+        }),
+        is_video: false,
+        ..asset.clone()
     };
 
-    let aspect_ratio = dimensions.0 as f64 / dimensions.1 as f64;
+    match hash_asset_content(&image, options, cancel, stats) {
+        Ok(hashed) => {
+            log::warn!(
+                "Asset {} declared video but contains an image, treating it as {}",
+                asset.id,
+                image.mime_type
+            );
+            Ok(hashed)
+        }
+        Err(_) => Err(NoVideoStream.into()),
+    }
+}
 
-    // Generate hashes for all the images
-    let mut frame_hashes = Vec::new();
-    for (index, frame_path) in frame_paths.iter().enumerate() {
-        let hash = generate_perceptual_hash(frame_path).context(format!("Failed to generate hash for frame {}", index))?;
+/// Remote assets need reqwest, which was not compiled in
+#[cfg(not(feature = "remote"))]
+fn fetch_remote_asset(
+    _asset: &Asset,
+    url: &str,
+    _options: &GroupingOptions,
+) -> Result<(Asset, Option<TempDir>)> {
+    anyhow::bail!(
+        "Remote asset support is not compiled in, rebuild with the `remote` feature to fetch {}",
+        url
+    )
+}
 
-        frame_hashes.push(FrameData {
-           frame_number: index,
-            hash,
-        });
+/// Copy of `asset` with `is_video` and `mime_type` taken from its content when
+/// they disagree with the declared flag, `None` when the flag holds or the
+/// content isn't recognized. Errors instead under `strict_type_check`
+fn checked_media_type(asset: &Asset, options: &GroupingOptions) -> Result<Option<Asset>> {
+    let sniffed = match &asset.source {
+        AssetSource::Path(path) => sniff_media_type(path),
+        AssetSource::Bytes(bytes) => sniff_media_type_from_bytes(&bytes[..bytes.len().min(16)]),
+    };
+    let Some((mime_type, is_video)) = sniffed.filter(|&(_, is_video)| is_video != asset.is_video)
+    else {
+        return Ok(None);
+    };
+
+    let (declared, found) = if is_video {
+        ("image", "a video stream")
+    } else {
+        ("video", "an image")
+    };
+    if options.strict_type_check {
+        anyhow::bail!("Asset {} declared {} but contains {}", asset.id, declared, found);
     }
+    log::warn!(
+        "Asset {} declared {} but contains {}, treating it as {}",
+        asset.id,
+        declared,
+        found,
+        mime_type
+    );
+
+    Ok(Some(Asset {
+        mime_type: mime_type.to_string(),
+        is_video,
+        ..asset.clone()
+    }))
+}
+
+/// Decode and hash an asset whose type has been checked
+fn hash_asset_content(
+    asset: &Asset,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+) -> Result<(HashedAsset, Option<TempDir>)> {
+    let hash_config = options.hash_config();
+    let mut audio_fingerprint = None;
+    let mut decode_errors = 0;
+
+    let (frame_hashes, colors, dimensions, duration, degraded, temp_dir) = if asset.is_video {
+        let (hashed_video, fingerprint, errors) = hash_video(asset, options, cancel, stats)?;
+        check_min_size(hashed_video.2, options)?;
+        audio_fingerprint = fingerprint;
+        decode_errors = errors;
+        hashed_video
+    } else if let Some(animation) = sampled_animation(asset, options, stats)? {
+        check_min_size((animation.width, animation.height), options)?;
+        stats.frames_extracted.fetch_add(animation.frames.len(), Ordering::Relaxed);
+        let mut dominant_colors = Vec::new();
+        let frame_hashes = animation
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let mut hashes = generate_perceptual_hash_from_image(
+                    &frame.image,
+                    &hash_config,
+                    first_frame_extras(options, index == 0),
+                )
+                .context(format!("Failed to generate hash for frame {}", index))?;
+                if index == 0 {
+                    dominant_colors = std::mem::take(&mut hashes.dominant_colors);
+                }
+
+                Ok(FrameData::from_hashes(index, frame.timestamp_secs, hashes))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let dimensions = (animation.width, animation.height);
+
+        (frame_hashes, dominant_colors, dimensions, Some(animation.duration), false, None)
+    } else {
+        // for images, treat as a single frame
+        let dimensions = match &asset.source {
+            AssetSource::Path(path) => get_image_dimensions(path),
+            AssetSource::Bytes(bytes) => get_image_dimensions_from_bytes(bytes),
+        }
+        .context("Failed to get image dimensions")?;
+        check_min_size(dimensions, options)?;
+
+        let mut hashes = match &asset.source {
+            AssetSource::Path(path) => {
+                generate_perceptual_hash(path, &hash_config, options.hash_extras())
+            }
+            AssetSource::Bytes(bytes) => {
+                generate_perceptual_hash_from_bytes(bytes, &hash_config, options.hash_extras())
+            }
+        }
+        .context("Failed to generate hash for image")?;
+
+        let dominant_colors = std::mem::take(&mut hashes.dominant_colors);
+        let frame_hashes = vec![FrameData::from_hashes(0, 0.0, hashes)];
+
+        (frame_hashes, dominant_colors, dimensions, None, false, None)
+    };
+
+    let frame_hashes = dedupe_consecutive_frames(frame_hashes, options.frame_dedup_distance);
+    let aspect_ratio = dimensions.0 as f64 / dimensions.1 as f64;
+    let sampling_policy = duration.map(|duration| match options.frame_sampling.strategy {
+        // Animations fall back to uniform sampling, they have no keyframes to seek
+        FrameStrategy::Keyframes { .. } | FrameStrategy::Scenes { .. } if !asset.is_video => {
+            SamplingPolicy::Uniform
+        }
+        _ => options.frame_sampling.sampling_policy(duration),
+    });
 
     let hashed_asset = HashedAsset {
         asset: asset.clone(),
-        frames: frame_hashes, 
+        frames: frame_hashes,
         aspect_ratio,
         width: dimensions.0,
         height: dimensions.1,
+        duration,
+        degraded,
+        // Only animations get a duration without being videos
+        is_animated: !asset.is_video && duration.is_some(),
+        dominant_colors: colors,
+        audio_fingerprint,
+        sampling_policy,
+        decode_errors,
     };
 
     Ok((hashed_asset, temp_dir))
 }
 
-/// Check if two assets are visually similar
-/// Returns if ALL frames have hamming distance < thresold
-///
-/// Note: With 8-bit hashing (64-bits total), we use thresold of 15
-/// which is roughly 23% of the 64-bit hash, previding good balance
-pub fn are_assets_visually_similar(
-    asset1: &HashedAsset, 
-    asset2: &HashedAsset,
-    thresold: u32,
-) -> bool {
-    // CRITICAL: Only campare assets of the same type (image vs video)
-    // This provents videos from being grouped with images
-    if asset1.asset.is_video != asset2.asset.is_video {
-        return false;
+/// Collapse every frame less than `max_distance` bits from the frame kept
+/// before it into that frame, which then counts the frames it stands for.
+/// Frames keep their number and timestamp, 0 keeps every frame
+fn dedupe_consecutive_frames(frames: Vec<FrameData>, max_distance: u32) -> Vec<FrameData> {
+    if max_distance == 0 {
+        return frames;
+    }
+
+    let mut kept: Vec<FrameData> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        if let Some(previous) = kept.last_mut()
+            && previous.distance(&frame).is_ok_and(|distance| distance < max_distance)
+        {
+            previous.represented_frames += frame.represented_frames;
+            continue;
+        }
+        kept.push(frame);
     }
+    kept
+}
 
-    // If one has significantly more frames than the other, they might still be the same video 
-    // We'll compare the overlapping frame_hashes
-    let min_frame_count = asset1.frames.len().min(asset2.frames.len());
+/// Hash extras of a video or animation frame, only the first frame gets a
+/// BlurHash and dominant colors
+fn first_frame_extras(options: &GroupingOptions, is_first: bool) -> HashExtras {
+    let extras = options.hash_extras();
+    HashExtras {
+        blurhash: extras.blurhash && is_first,
+        dominant_colors: if is_first { extras.dominant_colors } else { 0 },
+        ..extras
+    }
+}
 
-    if min_frame_count == 0 {
-        return false;
+/// Fail assets narrower than `min_width` or lower than `min_height`
+fn check_min_size((width, height): (u32, u32), options: &GroupingOptions) -> Result<()> {
+    if width < options.min_width || height < options.min_height || width == 0 || height == 0 {
+        anyhow::bail!(
+            "Asset is {}x{}, smaller than the minimum of {}x{}",
+            width,
+            height,
+            options.min_width,
+            options.min_height
+        );
     }
+    Ok(())
+}
+
+/// Frame hashes, display size, duration, degradation and temp directory of a video
+type HashedVideo =
+    (Vec<FrameData>, Vec<[u8; 3]>, (u32, u32), Option<f64>, bool, Option<TempDir>);
+
+/// Hash the sampled frames of a video and probe its size and duration, and
+/// fingerprint its audio unless `audio_mode` is `Ignore`. Also returns the
+/// decode errors met on the way
+/// In-memory videos are written to a temp directory first so ffmpeg can open them
+#[cfg(feature = "video")]
+fn hash_video(
+    asset: &Asset,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+) -> Result<(HashedVideo, Option<AudioFingerprint>, usize)> {
+    let mut temp_dir = None;
+
+    let video_path = match &asset.source {
+        AssetSource::Path(path) => path.clone(),
+        AssetSource::Bytes(bytes) => {
+            let dir = TempDir::new().context("Failed to create temp directory")?;
+            let video_path = dir.path().join("source_video");
+            std::fs::write(&video_path, bytes).context("Failed to write video data to temp file")?;
+            temp_dir = Some(dir);
+            video_path
+        }
+    };
+
+    // Probed first so files without a video stream fail before any decoding
+    let started = Instant::now();
+    let metadata = probe_video(&video_path).context("Failed to probe the video")?;
+    StatsCollector::add_time(&stats.probe_nanos, started.elapsed());
+    let dimensions = (metadata.display_width, metadata.display_height);
+
+    let (frame_hashes, dominant_colors, extraction) =
+        hash_video_frames(asset, &video_path, options, cancel, stats)?;
+    stats.frames_extracted.fetch_add(frame_hashes.len(), Ordering::Relaxed);
+
+    let fingerprint = match options.audio_mode {
+        AudioMode::Ignore => None,
+        // Unreadable audio is compared as missing audio, the frames still count
+        AudioMode::Separate | AudioMode::Annotate => audio_fingerprint(&video_path)
+            .unwrap_or_else(|err| {
+                log::warn!("Failed to fingerprint the audio of {}: {:#}", asset.id, err);
+                None
+            }),
+    };
+
+    let duration = Some(metadata.duration);
+    let degraded = extraction.is_degraded();
+    let hashed_video = (frame_hashes, dominant_colors, dimensions, duration, degraded, temp_dir);
+    Ok((hashed_video, fingerprint, extraction.decode_errors))
+}
+
+/// Videos need ffmpeg, which was not compiled in
+#[cfg(not(feature = "video"))]
+fn hash_video(
+    asset: &Asset,
+    _options: &GroupingOptions,
+    _cancel: Option<&CancellationToken>,
+    _stats: &StatsCollector,
+) -> Result<(HashedVideo, Option<AudioFingerprint>, usize)> {
+    anyhow::bail!(
+        "Video support is not compiled in, rebuild with the `video` feature to process {}",
+        asset.name
+    )
+}
+
+/// `sample_animated_asset`, timed as frame extraction
+fn sampled_animation(
+    asset: &Asset,
+    options: &GroupingOptions,
+    stats: &StatsCollector,
+) -> Result<Option<SampledAnimation>> {
+    let started = Instant::now();
+    let animation = sample_animated_asset(asset, &options.frame_sampling)?;
+    StatsCollector::add_time(&stats.extraction_nanos, started.elapsed());
+    Ok(animation)
+}
+
+/// Hash each sampled video frame in memory as soon as it is decoded
+/// With `frames_dir` set, a copy of every frame is also written to a
+/// subdirectory per asset in the `frame_files` format. A frame that fails to
+/// save is still hashed.
+/// Returns the frame hashes, the dominant colors of the first frame and the
+/// extraction counts
+#[cfg(feature = "video")]
+fn hash_video_frames(
+    asset: &Asset,
+    video_path: &Path,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+) -> Result<(Vec<FrameData>, Vec<[u8; 3]>, FrameExtractionStats)> {
+    let hash_config = options.hash_config();
+    let mut frame_hashes = Vec::new();
+    let mut dominant_colors = Vec::new();
+    // Hashing runs inside the decode loop, it is not extraction time
+    let mut hashing = Duration::ZERO;
 
-    // Check all overlapping frames
-    for i in 0..min_frame_count {
-        let hash1= &asset1.frames[i].hash;
-        let hash2= &asset2.frames[i].hash;
+    let asset_frames_dir = match &options.frames_dir {
+        Some(frames_dir) => {
+            // Asset ids may contain path separators
+            let asset_dir: String = asset
+                .id
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                .collect();
+            let dir = frames_dir.join(asset_dir);
+            std::fs::create_dir_all(&dir).context("Failed to create frame output directory")?;
+            Some(dir)
+        }
+        None => None,
+    };
 
-        match hamming_distance(hash1, hash2) {
-            Ok(distance) => {
-                if distance >= thresold {
-                    return false;
+    let started = Instant::now();
+    let extraction = extract_frames_as_images(
+        video_path,
+        &options.frame_sampling,
+        cancel,
+        |index, timestamp_secs, frame| {
+            if let Some(dir) = &asset_frames_dir {
+                let frame_path = options.frame_files.frame_path(dir, index);
+                if let Err(err) = options.frame_files.save(&frame, &frame_path) {
+                    log::warn!("Failed to keep frame {} of {}: {:#}", index, asset.id, err);
                 }
             }
 
-            Err(_) => {
-                return false;
+            let hash_started = Instant::now();
+            let mut hashes = generate_perceptual_hash_from_image(
+                &image::DynamicImage::ImageRgb8(frame),
+                &hash_config,
+                first_frame_extras(options, frame_hashes.is_empty()),
+            )
+            .context(format!("Failed to generate hash for frame {}", index))?;
+            hashing += hash_started.elapsed();
+            if frame_hashes.is_empty() {
+                dominant_colors = std::mem::take(&mut hashes.dominant_colors);
             }
+
+            frame_hashes.push(FrameData::from_hashes(frame_hashes.len(), timestamp_secs, hashes));
+            Ok(())
+        },
+    )
+    .context("Failed to extract frames from video")?;
+    StatsCollector::add_time(&stats.extraction_nanos, started.elapsed().saturating_sub(hashing));
+    if extraction.hardware_decoded {
+        stats.hardware_decoded_assets.fetch_add(1, Ordering::Relaxed);
+    }
+    stats.decode_errors.fetch_add(extraction.decode_errors, Ordering::Relaxed);
+
+    if extraction.only_uniform {
+        for frame in &mut frame_hashes {
+            frame.uniform = true;
         }
     }
 
-    return true;
+    Ok((frame_hashes, dominant_colors, extraction))
 }
 
-/// Group assets by visual similarity
-pub fn group_assets_by_visual_similarity(
-    assets: Vec<Asset>,
-    thresold: Option<u32>,
-) -> Result<Vec<AssetGroup>> {
-    let thresold = thresold.unwrap_or(15);
+/// Compare two hashed assets and report the intermediate numbers behind
+/// the similarity verdict
+/// Assets are similar if at least `match_frames_ratio` of the overlapping
+/// frames have hamming distance < threshold (all frames with the default ratio of 1.0)
+/// under the best frame alignment within `frame_offset_tolerance`, or anywhere
+/// inside the longer video with `detect_clips`
+pub fn compare_hashed_assets(
+    asset1: &HashedAsset,
+    asset2: &HashedAsset,
+    options: &GroupingOptions,
+) -> SimilarityReport {
+    // A ratio threshold follows the size of the hashes actually compared
+    let hash_bits = asset1.frames.first().map_or(options.hash_bits(), FrameData::bits);
 
-    if assets.is_empty() {
-        return Ok(Vec::new());
+    let mut report = SimilarityReport {
+        asset_id_a: asset1.asset.id.clone(),
+        asset_id_b: asset2.asset.id.clone(),
+        type_mismatch: asset1.asset.is_video != asset2.asset.is_video,
+        aspect_ratio_mismatch: false,
+        below_min_resolution: false,
+        duration_mismatch: false,
+        palette_mismatch: false,
+        palette_distance: palette_distance(&asset1.dominant_colors, &asset2.dominant_colors),
+        audio_mismatch: false,
+        frame_distances: Vec::new(),
+        frame_timestamps_a: Vec::new(),
+        frame_timestamps_b: Vec::new(),
+        min_distance: None,
+        max_distance: None,
+        mean_distance: None,
+        aspect_ratio_a: asset1.aspect_ratio,
+        aspect_ratio_b: asset2.aspect_ratio,
+        threshold: options.threshold_for_bits(hash_bits),
+        frame_offset: 0,
+        matched_frames_ratio: 0.0,
+        score: 0.0,
+        similar: false,
+    };
+
+    // CRITICAL: Only campare assets of the same type (image vs video)
+    // This provents videos from being grouped with images
+    if report.type_mismatch && !options.allow_image_video_matching {
+        return report;
     }
 
-    println!("Processing {} assets for visual grouping...", assets.len());
+    let shortest_side = asset1.width.min(asset1.height).min(asset2.width.min(asset2.height));
+    if shortest_side < options.min_resolution {
+        report.below_min_resolution = true;
+        return report;
+    }
 
-    // Process all assets to extract frames and generate hashes
-    // keep temp directories alive until grouping is complete 
-    let process_results: Vec<(HashedAsset, Option<TempDir>)> = assets.iter().map(|asset| {
-        println!(
-            "Processing asset: {} ({})", 
-            asset.name,
-            if asset.is_video {"video"} else {"image"}
-        );
-        let result = process_asset(asset)?;
-        println!("Completed processing: {}", asset.name);
-        Ok(result)
-    }).collect::<Result<Vec<_>>>()?;
+    // Center-crop hashing drops the edges, so a 9:16 and a 1:1 crop of
+    // different creatives can hash alike. Resizes keep their aspect ratio.
+    // Crop-resistant matching is meant for differing aspect ratios
+    if let Some(max_delta) = options.max_aspect_ratio_delta
+        && options.crop_resistance.is_none()
+    {
+        let delta = (asset1.aspect_ratio - asset2.aspect_ratio).abs()
+            / asset1.aspect_ratio.max(asset2.aspect_ratio);
+        if delta > max_delta {
+            report.aspect_ratio_mismatch = true;
+            return report;
+        }
+    }
+
+    // Animations have frames to align with the video's, like another video
+    if report.type_mismatch && !asset1.is_animated && !asset2.is_animated {
+        let mut report = match_image_against_video_frames(asset1, asset2, report, options);
+        apply_palette_veto(&mut report, hash_bits, options);
+        return report;
+    }
 
-    let hashed_assets: Vec<HashedAsset> = process_results.iter().map(|(hashed_asset, _)| hashed_asset.clone()).collect();
+    // Only the overlapping frames are compared, so a short bumper could
+    // match the opening of a much longer film. Clips are shorter by design
+    if !options.allow_trimmed_variants
+        && !options.detect_clips
+        && let (Some(duration1), Some(duration2)) = (asset1.duration, asset2.duration)
+    {
+        let shorter = duration1.min(duration2);
+        let longer = duration1.max(duration2);
+        if shorter > 0.0 && longer / shorter > options.max_duration_ratio {
+            report.duration_mismatch = true;
+            return report;
+        }
+    }
 
-    println!("Generated hashes for {} assets", hashed_assets.len());
+    // If one has significantly more frames than the other, they might still be the same video
+    // We'll compare the overlapping frame_hashes, shifted by up to
+    // `frame_offset_tolerance` frames to handle trimmed or offset videos
+    let max_offset = options.frame_offset_tolerance as isize;
+    let mut best: Option<(bool, f64, f64, isize, Vec<u32>)> = None;
 
-    // Group assets by visual similarity
-    let mut groups: Vec<AssetGroup> = Vec::new();
-    let mut assigned: HashSet<String> = HashSet::new();
+    for offset in alignment_offsets(asset1, asset2, max_offset, options.detect_clips) {
+        let Ok(distances) = aligned_frame_distances(asset1, asset2, offset) else {
+            return report;
+        };
 
-    for i in 0..hashed_assets.len() {
-        if assigned.contains(&hashed_assets[i].asset.id) {
+        if distances.is_empty() || distances.len() < options.min_frame_overlap {
             continue;
         }
 
-        let mut group = AssetGroup {
-            id: uuid::Uuid::new_v4().to_string(),
-            name: extract_base_name(&hashed_assets[i].asset.name),
-            assets: vec![hashed_assets[i].asset.clone()],
+        let matched_frames = aligned_frames(asset1, asset2, offset)
+            .zip(&distances)
+            .filter(|&((frame1, frame2), &distance)| {
+                frames_match(frame1, frame2, distance, report.threshold, options)
+            })
+            .count();
+        let matched_ratio = matched_frames as f64 / distances.len() as f64;
+        let similar = matched_ratio >= options.match_frames_ratio;
+        let mean = distances.iter().map(|&d| d as f64).sum::<f64>() / distances.len() as f64;
+
+        // Prefer similar alignments, then the lowest mean distance
+        let is_better = match &best {
+            None => true,
+            Some((best_similar, best_mean, _, _, _)) => {
+                (similar, -mean) > (*best_similar, -*best_mean)
+            }
         };
 
-        assigned.insert(hashed_assets[i].asset.id.clone());
+        if is_better {
+            best = Some((similar, mean, matched_ratio, offset, distances));
+        }
+    }
 
-        // Find all similar assets 
-        for j in (i+1)..hashed_assets.len() {
-            if assigned.contains(&hashed_assets[j].asset.id) {
-                continue;
-            }
+    let Some((similar, mean, matched_ratio, offset, distances)) = best else {
+        return report;
+    };
 
-            let is_similar = are_assets_visually_similar(&hashed_assets[i], &hashed_assets[j], thresold);
+    report.min_distance = distances.iter().copied().min();
+    report.max_distance = distances.iter().copied().max();
+    report.mean_distance = Some(mean);
+    report.frame_offset = offset as i32;
+    report.frame_distances = distances;
+    (report.frame_timestamps_a, report.frame_timestamps_b) = aligned_frames(asset1, asset2, offset)
+        .map(|(frame1, frame2)| (frame1.timestamp_secs, frame2.timestamp_secs))
+        .unzip();
+    report.matched_frames_ratio = matched_ratio;
+    report.score = (1.0 - mean / hash_bits as f64).clamp(0.0, 1.0);
+    report.similar = similar;
+    apply_palette_veto(&mut report, hash_bits, options);
+    apply_audio_check(&mut report, asset1, asset2, options);
 
-            // Debug logging
-            if !hashed_assets[i].frames.is_empty() && !hashed_assets[j].frames.is_empty() {
-                if let Ok(distance) = hamming_distance(
-                    &hashed_assets[i].frames[0].hash,
-                    &hashed_assets[j].frames[0].hash,
-                ) {
-                    let type1 = if hashed_assets[i].asset.is_video {"video"} else {"image"};
-                    let type2 = if hashed_assets[j].asset.is_video {"video"} else {"image"};
-                    println!(
-                        "Comparing {} \"{}]\" vs {} \"{}\": distance={}, similar={}",
-                        type1, hashed_assets[i].asset.name,
-                        type2, hashed_assets[j].asset.name,
-                        distance, is_similar
-                    );
-                }
-            }
+    report
+}
+
+/// Reject a matching pair when `color_weight` times the palette distance,
+/// counted in hash bits, pushes its closest frames over the threshold
+fn apply_palette_veto(report: &mut SimilarityReport, hash_bits: u32, options: &GroupingOptions) {
+    if !report.similar || options.color_weight == 0.0 {
+        return;
+    }
+    let (Some(palette_distance), Some(min_distance)) =
+        (report.palette_distance, report.min_distance)
+    else {
+        return;
+    };
+
+    // Region matches may pass with a whole-frame distance over the threshold
+    let threshold = report.threshold as f64;
+    let distance = (min_distance as f64).min(threshold);
+    let penalty = options.color_weight * palette_distance * hash_bits as f64;
+    if distance + penalty >= threshold {
+        report.similar = false;
+        report.palette_mismatch = true;
+    }
+}
+
+/// Flag a matching pair of videos whose audio differs, unless `audio_mode` is
+/// `Ignore`. With `AudioMode::Separate` the pair is rejected as well
+fn apply_audio_check(
+    report: &mut SimilarityReport,
+    asset1: &HashedAsset,
+    asset2: &HashedAsset,
+    options: &GroupingOptions,
+) {
+    if !report.similar
+        || options.audio_mode == AudioMode::Ignore
+        || !asset1.asset.is_video
+        || !asset2.asset.is_video
+    {
+        return;
+    }
+
+    if !audio_matches(asset1.audio_fingerprint.as_ref(), asset2.audio_fingerprint.as_ref()) {
+        report.audio_mismatch = true;
+        report.similar = options.audio_mode != AudioMode::Separate;
+    }
+}
+
+/// Frame offsets tried when aligning two assets: up to `max_offset` either way,
+/// plus with `detect_clips` every offset placing all of the shorter asset's
+/// frames inside the longer one's
+fn alignment_offsets(
+    asset1: &HashedAsset,
+    asset2: &HashedAsset,
+    max_offset: isize,
+    detect_clips: bool,
+) -> std::ops::RangeInclusive<isize> {
+    if !detect_clips {
+        return -max_offset..=max_offset;
+    }
+
+    let spare = asset2.frames.len() as isize - asset1.frames.len() as isize;
+    (-max_offset).min(spare)..=max_offset.max(spare)
+}
+
+/// Compare the single hash of an image against every frame of a video
+/// Similar when any frame is under the threshold, e.g. a poster frame exported
+/// from the video. `frame_offset` points at the best matching video frame
+fn match_image_against_video_frames(
+    asset1: &HashedAsset,
+    asset2: &HashedAsset,
+    mut report: SimilarityReport,
+    options: &GroupingOptions,
+) -> SimilarityReport {
+    let (image, video, sign) = if asset1.asset.is_video {
+        (asset2, asset1, -1)
+    } else {
+        (asset1, asset2, 1)
+    };
+
+    let Some(image_frame) = image.frames.first() else {
+        return report;
+    };
+
+    let Ok(distances) = video
+        .frames
+        .iter()
+        .map(|frame| image_frame.min_distance(frame))
+        .collect::<Result<Vec<u32>>>()
+    else {
+        return report;
+    };
+
+    let Some((best_frame, &best_distance)) = distances
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, distance)| *distance)
+    else {
+        return report;
+    };
+
+    let matched_frames = video
+        .frames
+        .iter()
+        .zip(&distances)
+        .filter(|&(frame, &distance)| {
+            frames_match(image_frame, frame, distance, report.threshold, options)
+        })
+        .count();
+    let mean = distances.iter().map(|&d| d as f64).sum::<f64>() / distances.len() as f64;
+
+    report.min_distance = Some(best_distance);
+    report.max_distance = distances.iter().copied().max();
+    report.mean_distance = Some(mean);
+    report.frame_offset = sign * best_frame as i32;
+    report.matched_frames_ratio = matched_frames as f64 / distances.len() as f64;
+    report.score = (1.0 - best_distance as f64 / image_frame.bits() as f64).clamp(0.0, 1.0);
+    report.similar = matched_frames > 0;
+    report.frame_distances = distances;
+
+    let video_timestamps: Vec<f64> = video.frames.iter().map(|f| f.timestamp_secs).collect();
+    let image_timestamps = vec![image_frame.timestamp_secs; video_timestamps.len()];
+    (report.frame_timestamps_a, report.frame_timestamps_b) = if sign == 1 {
+        (image_timestamps, video_timestamps)
+    } else {
+        (video_timestamps, image_timestamps)
+    };
+
+    report
+}
+
+/// Frame `i` of `asset1` paired with frame `i + offset` of `asset2`
+/// for every `i` where both frames exist and neither is uniform
+fn aligned_frames<'a>(
+    asset1: &'a HashedAsset,
+    asset2: &'a HashedAsset,
+    offset: isize,
+) -> impl Iterator<Item = (&'a FrameData, &'a FrameData)> {
+    asset1.frames.iter().enumerate().filter_map(move |(i, frame1)| {
+        let j = usize::try_from(i as isize + offset).ok()?;
+        asset2
+            .frames
+            .get(j)
+            .filter(|frame2| !frame1.uniform && !frame2.uniform)
+            .map(|frame2| (frame1, frame2))
+    })
+}
+
+/// Distances between the frames paired by `aligned_frames`
+fn aligned_frame_distances(
+    asset1: &HashedAsset,
+    asset2: &HashedAsset,
+    offset: isize,
+) -> Result<Vec<u32>> {
+    aligned_frames(asset1, asset2, offset)
+        .map(|(frame1, frame2)| frame1.min_distance(frame2))
+        .collect()
+}
+
+/// Whether two compared frames match: under the threshold, or in crop-resistant
+/// mode with enough matching grid regions
+fn frames_match(
+    frame1: &FrameData,
+    frame2: &FrameData,
+    distance: u32,
+    threshold: u32,
+    options: &GroupingOptions,
+) -> bool {
+    if distance < threshold {
+        return true;
+    }
+
+    options.crop_resistance.is_some_and(|crop| {
+        frame1
+            .matching_regions(frame2, crop.region_threshold)
+            .is_ok_and(|matched| matched >= crop.min_matching_regions)
+    })
+}
+
+/// Check if two assets are visually similar
+///
+/// Note: With 8-bit hashing (64-bits total), we use thresold of 15
+/// which is roughly 23% of the 64-bit hash, previding good balance
+pub fn are_assets_visually_similar(
+    asset1: &HashedAsset,
+    asset2: &HashedAsset,
+    options: &GroupingOptions,
+) -> bool {
+    compare_hashed_assets(asset1, asset2, options).similar
+}
+
+/// Process two assets and report how similar they are
+pub fn compare_assets(
+    asset1: &Asset,
+    asset2: &Asset,
+    options: Option<GroupingOptions>,
+) -> Result<SimilarityReport> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+
+    let (hashed1, _temp_dir1) = process_asset(asset1, &options, None)
+        .with_context(|| format!("Failed to process asset {} ({})", asset1.id, asset1.name))?;
+    let (hashed2, _temp_dir2) = process_asset(asset2, &options, None)
+        .with_context(|| format!("Failed to process asset {} ({})", asset2.id, asset2.name))?;
 
-            if is_similar {
-                group.assets.push(hashed_assets[j].asset.clone());
-                assigned.insert(hashed_assets[j].asset.id.clone());
+    Ok(compare_hashed_assets(&hashed1, &hashed2, &options))
+}
+
+/// Find the candidates visually similar to `target`, sorted by ascending distance
+/// Candidates that fail to process are skipped unless fail_fast is set
+pub fn find_similar_assets(
+    target: &Asset,
+    candidates: &[Asset],
+    options: Option<GroupingOptions>,
+) -> Result<Vec<SimilarMatch>> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+
+    let (hashed_target, _temp_dir) = process_asset(target, &options, None)
+        .with_context(|| format!("Failed to process asset {} ({})", target.id, target.name))?;
+
+    let (process_results, _failed) = hash_assets(candidates, &options, None, None)?;
+
+    let mut matches: Vec<SimilarMatch> = process_results
+        .iter()
+        .filter(|(candidate, _)| candidate.asset.id != target.id)
+        .filter_map(|(candidate, _)| {
+            let report = compare_hashed_assets(&hashed_target, candidate, &options);
+            if !report.similar {
+                return None;
             }
-        }
 
-        groups.push(group);
+            let min_distance = report.min_distance?;
+            Some(SimilarMatch {
+                candidate_id: candidate.asset.id.clone(),
+                min_distance,
+                score: 1.0 - min_distance as f64 / options.hash_bits() as f64,
+            })
+        })
+        .collect();
+
+    matches.sort_by(|a, b| {
+        a.min_distance
+            .cmp(&b.min_distance)
+            .then_with(|| a.candidate_id.cmp(&b.candidate_id))
+    });
+
+    Ok(matches)
+}
+
+/// Find every pair of assets whose aligned frames are all at most
+/// `max_distance` apart, closest pairs first
+/// Hashing follows `options` like grouping does, so precomputed hashes are
+/// used as is. Assets that fail to process are skipped unless fail_fast is set
+pub fn find_duplicate_pairs(
+    assets: &[Asset],
+    max_distance: u32,
+    options: Option<GroupingOptions>,
+) -> Result<Vec<DuplicatePair>> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+
+    let (process_results, _failed) = hash_assets(assets, &options, None, None)?;
+    let hashed_assets: Vec<&HashedAsset> =
+        process_results.iter().map(|(hashed_asset, _)| hashed_asset).collect();
+
+    // Every frame has to match, within `max_distance` rather than the threshold
+    let duplicate_options = GroupingOptions {
+        threshold: max_distance.saturating_add(1),
+        threshold_ratio: None,
+        match_frames_ratio: 1.0,
+        ..options.clone()
+    };
+
+    let mut pairs: Vec<DuplicatePair> = thread_pool(&options)?.install(|| {
+        (0..hashed_assets.len())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let asset1 = hashed_assets[i];
+                let duplicate_options = &duplicate_options;
+                hashed_assets[i + 1..].iter().filter_map(move |&asset2| {
+                    let report = compare_hashed_assets(asset1, asset2, duplicate_options);
+                    if !report.similar {
+                        return None;
+                    }
+
+                    let pixels = |asset: &HashedAsset| asset.width as u64 * asset.height as u64;
+                    let higher = if pixels(asset2) > pixels(asset1) { asset2 } else { asset1 };
+                    Some(DuplicatePair {
+                        asset_id_a: asset1.asset.id.clone(),
+                        asset_id_b: asset2.asset.id.clone(),
+                        distance: report.max_distance?,
+                        higher_resolution_asset_id: higher.asset.id.clone(),
+                    })
+                })
+            })
+            .collect()
+    });
+
+    pairs.sort_by(|a, b| {
+        a.distance
+            .cmp(&b.distance)
+            .then_with(|| a.asset_id_a.cmp(&b.asset_id_a))
+            .then_with(|| a.asset_id_b.cmp(&b.asset_id_b))
+    });
+
+    Ok(pairs)
+}
+
+/// Compute the pairwise distances between already hashed assets
+pub fn build_similarity_matrix(
+    hashed_assets: &[HashedAsset],
+    options: &GroupingOptions,
+) -> Vec<Vec<PairDistance>> {
+    let count = hashed_assets.len();
+    let mut pairs = vec![vec![PairDistance::default(); count]; count];
+
+    for (i, asset1) in hashed_assets.iter().enumerate() {
+        for (j, asset2) in hashed_assets.iter().enumerate().skip(i) {
+            let report = compare_hashed_assets(asset1, asset2, options);
+            let pair = PairDistance {
+                comparable: report.min_distance.is_some(),
+                min_distance: report.min_distance,
+                mean_distance: report.mean_distance,
+            };
+
+            pairs[j][i] = pair.clone();
+            pairs[i][j] = pair;
+        }
     }
 
-    println!(
-        "Created {} visual groups from {} assets",
-        groups.len(),
-        assets.len()
-    );
+    pairs
+}
 
-    Ok(groups)
+/// Process all assets and compute the full pairwise similarity matrix
+pub fn compute_similarity_matrix(
+    assets: Vec<Asset>,
+    options: Option<GroupingOptions>,
+) -> Result<SimilarityMatrix> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+
+    let (process_results, failed) = hash_assets(&assets, &options, None, None)?;
+    let hashed_assets: Vec<HashedAsset> = process_results
+        .into_iter()
+        .map(|(hashed_asset, _)| hashed_asset)
+        .collect();
+
+    Ok(SimilarityMatrix {
+        ids: hashed_assets
+            .iter()
+            .map(|hashed_asset| hashed_asset.asset.id.clone())
+            .collect(),
+        pairs: build_similarity_matrix(&hashed_assets, &options),
+        failed,
+    })
+}
+
+/// Process all assets and link every pair whose best-aligned frames pass
+/// `cutoff` bits (exclusive), the grouping threshold when unset
+/// Pairs are compared exactly like grouping does, so at the grouping threshold
+/// the edges are the pairs grouping considers similar. Edge weights are the
+/// mean distance of the best alignment
+pub fn export_similarity_graph(
+    assets: Vec<Asset>,
+    cutoff: Option<u32>,
+    options: Option<GroupingOptions>,
+) -> Result<SimilarityGraph> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+
+    let (process_results, failed) = hash_assets(&assets, &options, None, None)?;
+    let hashed_assets: Vec<&HashedAsset> =
+        process_results.iter().map(|(hashed_asset, _)| hashed_asset).collect();
+
+    let edge_options = match cutoff {
+        Some(cutoff) => GroupingOptions {
+            threshold: cutoff,
+            threshold_ratio: None,
+            ..options.clone()
+        },
+        None => options.clone(),
+    };
+
+    // flat_map_iter keeps the row order, edges follow the node order
+    let edges: Vec<GraphEdge> = thread_pool(&options)?.install(|| {
+        (0..hashed_assets.len())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let asset1 = hashed_assets[i];
+                let edge_options = &edge_options;
+                hashed_assets[i + 1..].iter().filter_map(move |&asset2| {
+                    let report = compare_hashed_assets(asset1, asset2, edge_options);
+                    if !report.similar {
+                        return None;
+                    }
+
+                    Some(GraphEdge {
+                        source: asset1.asset.id.clone(),
+                        target: asset2.asset.id.clone(),
+                        distance: report.mean_distance?,
+                    })
+                })
+            })
+            .collect()
+    });
+
+    Ok(SimilarityGraph {
+        nodes: hashed_assets.iter().map(|&hashed_asset| GraphNode::from(hashed_asset)).collect(),
+        edges,
+        failed,
+    })
 }
 
-/// Extract base name from filename (remove extension and common suffixes) 
-fn extract_base_name(filename: &str) -> String {
-    let base = filename.rsplit_once('.')
-        .map(|(name, _)| name)
-        .unwrap_or(filename);
+/// Lowest confidence at which `suggest_threshold` trusts the valley it found
+/// Otsu's separability of a single normal mode is about 0.64, two clear modes
+/// score above 0.9
+const MIN_SUGGESTION_CONFIDENCE: f64 = 0.8;
+
+/// Process all assets and suggest a threshold splitting the pairwise
+/// first-frame distances into "same creative" and "different creative" pairs
+/// The split is the one maximizing the between-class variance of the distance
+/// histogram (Otsu's method), its separability is the confidence. Below
+/// `MIN_SUGGESTION_CONFIDENCE` the configured threshold is returned instead
+pub fn suggest_threshold(
+    assets: Vec<Asset>,
+    options: Option<GroupingOptions>,
+) -> Result<ThresholdSuggestion> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
 
-    let base = regex::Regex::new(r"[_\-\s]*((\d{1,4})\s*[:xXw×]\s*(\d{1,4}))$")
-        .ok()
-        .and_then(|re| re.replace(base, "").into_owned().into())
-        .unwrap_or_else (|| base.to_string());
+    let (process_results, failed) = hash_assets(&assets, &options, None, None)?;
+    let first_frames: Vec<&FrameData> = process_results
+        .iter()
+        .filter_map(|(hashed_asset, _)| hashed_asset.frames.first())
+        .collect();
 
-    let base = regex::Regex::new(r"(?i)[_\-\s]*(post|story|feed|infeed|square|vertical|horizontal)$")
-        .ok()
-        .and_then(|re| re.replace(&base, "").into_owned().into())
-        .unwrap_or(base);
+    let distances: Vec<u32> = thread_pool(&options)?.install(|| {
+        (0..first_frames.len())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let frame1 = first_frames[i];
+                first_frames[i + 1..]
+                    .iter()
+                    .filter_map(move |frame2| frame1.min_distance(frame2).ok())
+            })
+            .collect()
+    });
 
-    base.trim().to_string()
+    let hash_bits = first_frames.first().map_or(options.hash_bits(), |frame| frame.bits());
+    let mut histogram = vec![0; hash_bits as usize + 1];
+    for distance in distances {
+        histogram[distance as usize] += 1;
+    }
+
+    let (threshold, confidence) = match otsu_threshold(&histogram) {
+        Some((threshold, confidence)) if confidence >= MIN_SUGGESTION_CONFIDENCE => {
+            (threshold, confidence)
+        }
+        Some((_, confidence)) => (options.threshold_for_bits(hash_bits), confidence),
+        None => (options.threshold_for_bits(hash_bits), 0.0),
+    };
+
+    Ok(ThresholdSuggestion {
+        threshold,
+        confidence,
+        histogram,
+        failed,
+    })
+}
+
+/// Otsu's method on a histogram of distances: the threshold `t` putting
+/// distances under `t` in one class and the rest in the other so that the
+/// between-class variance is largest, together with that variance's share
+/// of the total variance. Empty-bucket plateaus resolve to their middle.
+/// `None` without at least two distinct distances
+fn otsu_threshold(histogram: &[usize]) -> Option<(u32, f64)> {
+    let total: usize = histogram.iter().sum();
+    let weighted = |range: std::ops::Range<usize>| -> f64 {
+        histogram[range.clone()]
+            .iter()
+            .zip(range)
+            .map(|(&count, distance)| count as f64 * distance as f64)
+            .sum()
+    };
+    let mean = weighted(0..histogram.len()) / total.max(1) as f64;
+    let variance = histogram
+        .iter()
+        .enumerate()
+        .map(|(distance, &count)| count as f64 * (distance as f64 - mean).powi(2))
+        .sum::<f64>()
+        / total.max(1) as f64;
+    if total == 0 || variance <= 0.0 {
+        return None;
+    }
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    let mut below = 0;
+    for threshold in 1..histogram.len() {
+        below += histogram[threshold - 1];
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+
+        let mean_below = weighted(0..threshold) / below as f64;
+        let mean_above = weighted(threshold..histogram.len()) / above as f64;
+        let between = below as f64 * above as f64 * (mean_below - mean_above).powi(2)
+            / (total as f64).powi(2);
+
+        match &mut best {
+            Some((best_between, _, last)) if (between - *best_between).abs() < 1e-9 => {
+                *last = threshold;
+            }
+            Some((best_between, _, _)) if between < *best_between => {}
+            Some(entry) => *entry = (between, threshold, threshold),
+            None => best = Some((between, threshold, threshold)),
+        }
+    }
+
+    best.map(|(between, first, last)| ((first + last).div_ceil(2) as u32, between / variance))
+}
+
+fn report_progress(
+    on_progress: Option<&ProgressCallback>,
+    phase: ProgressPhase,
+    current: usize,
+    total: usize,
+    asset_id: &str,
+) {
+    if let Some(callback) = on_progress {
+        callback(ProgressEvent {
+            phase,
+            current,
+            total,
+            asset_id: Some(asset_id.to_string()),
+        });
+    }
+}
+
+/// Process all assets to extract frames and generate hashes
+/// Assets are processed concurrently on up to `max_concurrency` threads, results
+/// keep the input order. Progress counts completed assets, so events arrive in
+/// completion order. Assets that fail are collected into the returned failures
+/// unless fail_fast is set
+pub fn hash_assets(
+    assets: &[Asset],
+    options: &GroupingOptions,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+) -> Result<(HashedAssets, Vec<FailedAsset>)> {
+    let pool = thread_pool(options)?;
+    hash_assets_with_stats(assets, options, on_progress, cancel, &StatsCollector::default(), &pool)
+}
+
+/// `hash_assets` on the workers of `pool`, recording its work into `stats`
+fn hash_assets_with_stats(
+    assets: &[Asset],
+    options: &GroupingOptions,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+    pool: &rayon::ThreadPool,
+) -> Result<(HashedAssets, Vec<FailedAsset>)> {
+    let completed = AtomicUsize::new(0);
+    let cache = options.cache_path.as_ref().map(HashCache::open);
+
+    // Inner result is a per-asset failure, the outer one aborts the whole run
+    let outcomes: Vec<Result<(HashedAsset, Option<TempDir>), FailedAsset>> = pool.install(|| {
+        assets
+            .par_iter()
+            .map(|asset| {
+                if let Some(cancel) = cancel {
+                    cancel.check()?;
+                }
+                let _worker = stats.worker();
+                log::debug!(
+                    "Processing asset: {} ({})",
+                    asset.name,
+                    if asset.is_video { "video" } else { "image" }
+                );
+                let asset_stats = StatsCollector::default();
+                let started = Instant::now();
+                let result =
+                    process_asset_cached(asset, options, cancel, cache.as_ref(), &asset_stats);
+                stats.record_asset(asset, &asset_stats, started.elapsed(), options);
+                let outcome = match result {
+                    Ok(result) => {
+                        log::debug!("Completed processing: {}", asset.name);
+                        Ok(result)
+                    }
+                    Err(err)
+                        if (options.fail_fast && !err.is::<NoVideoStream>())
+                            || err.is::<Cancelled>() =>
+                    {
+                        return Err(err.context(format!(
+                            "Failed to process asset {} ({})",
+                            asset.id, asset.name
+                        )));
+                    }
+                    Err(err) => {
+                        log::warn!("Failed processing: {}: {:#}", asset.name, err);
+                        Err(FailedAsset {
+                            asset_id: asset.id.clone(),
+                            reason: format!("{:#}", err),
+                        })
+                    }
+                };
+
+                let current = completed.fetch_add(1, Ordering::SeqCst) + 1;
+                report_progress(
+                    on_progress,
+                    ProgressPhase::Hashing,
+                    current,
+                    assets.len(),
+                    &asset.id,
+                );
+
+                Ok(outcome)
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut process_results: HashedAssets = Vec::new();
+    let mut failed: Vec<FailedAsset> = Vec::new();
+    for outcome in outcomes {
+        match outcome {
+            Ok(result) => process_results.push(result),
+            Err(failure) => failed.push(failure),
+        }
+    }
+
+    // Losing the cache only costs a re-hash next time, so it never fails the run
+    if let Some(cache) = &cache
+        && let Err(err) = cache.save()
+    {
+        log::warn!("Failed to save hash cache: {:#}", err);
+    }
+
+    Ok((process_results, failed))
+}
+
+/// `process_asset` that reads file assets from the hash cache when they are
+/// unchanged since they were hashed and stores freshly hashed ones
+/// The cache is bypassed while `frames_dir` asks for frame files to be written
+/// and for http(s) assets, which have no file to check
+fn process_asset_cached(
+    asset: &Asset,
+    options: &GroupingOptions,
+    cancel: Option<&CancellationToken>,
+    cache: Option<&HashCache>,
+    stats: &StatsCollector,
+) -> Result<(HashedAsset, Option<TempDir>)> {
+    let cache = match cache {
+        Some(cache)
+            if asset.precomputed.is_none()
+                && options.frames_dir.is_none()
+                && asset.source.url().is_none() =>
+        {
+            cache
+        }
+        _ => return process_asset_with_stats(asset, options, cancel, stats),
+    };
+
+    let corrected = checked_media_type(asset, options)?;
+    let asset = corrected.as_ref().unwrap_or(asset);
+    if let Some(hashed_asset) = cache.get(asset, options) {
+        stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+        return Ok((hashed_asset, None));
+    }
+
+    let (hashed_asset, temp_dir) = hash_asset_content(asset, options, cancel, stats)?;
+    cache.insert(&hashed_asset, options);
+    Ok((hashed_asset, temp_dir))
+}
+
+/// Mean aligned-frame distance between every pair of members,
+/// incomparable pairs count as the full hash length
+fn member_distances(members: &[&HashedAsset], options: &GroupingOptions) -> Vec<Vec<f64>> {
+    let mut distances = vec![vec![0.0; members.len()]; members.len()];
+
+    for (i, asset1) in members.iter().enumerate() {
+        for (j, asset2) in members.iter().enumerate().skip(i + 1) {
+            let distance = compare_hashed_assets(asset1, asset2, options)
+                .mean_distance
+                .unwrap_or(options.hash_bits() as f64);
+            distances[i][j] = distance;
+            distances[j][i] = distance;
+        }
+    }
+
+    distances
+}
+
+/// Index of the medoid: the member with the smallest summed distance to all
+/// others, ties broken by the highest resolution
+fn select_representative(members: &[&HashedAsset], distances: &[Vec<f64>]) -> usize {
+    let resolution = |index: usize| members[index].width as u64 * members[index].height as u64;
+
+    (0..members.len())
+        .min_by(|&a, &b| {
+            let sum_a: f64 = distances[a].iter().sum();
+            let sum_b: f64 = distances[b].iter().sum();
+            sum_a
+                .total_cmp(&sum_b)
+                .then_with(|| resolution(b).cmp(&resolution(a)))
+                .then_with(|| members[a].asset.id.cmp(&members[b].asset.id))
+        })
+        .unwrap_or(0)
+}
+
+/// Group id derived from the member ids, the same members always get the same id
+fn group_id(members: &[&HashedAsset]) -> String {
+    let mut ids: Vec<&str> = members.iter().map(|member| member.asset.id.as_str()).collect();
+    ids.sort_unstable();
+    uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, ids.join("\n").as_bytes()).to_string()
+}
+
+/// Build the output group for a set of similar assets
+/// Members are listed by id, the group is named following `naming_strategy`.
+/// Confidence is `1.0 - max_pairwise_distance / hash_bits`, singleton groups score 1.0
+fn build_group(members: &[&HashedAsset], options: &GroupingOptions) -> AssetGroup {
+    let mut members = members.to_vec();
+    members.sort_by(|a, b| a.asset.id.cmp(&b.asset.id));
+    let members = &members[..];
+
+    let distances = member_distances(members, options);
+
+    let max_distance = distances.iter().flatten().copied().fold(0.0, f64::max);
+    let confidence = (1.0 - max_distance / options.hash_bits() as f64).clamp(0.0, 1.0);
+
+    let representative = select_representative(members, &distances);
+
+    let name = match options.naming_strategy {
+        NamingStrategy::CommonPrefix => {
+            let assets: Vec<Asset> = members.iter().map(|member| member.asset.clone()).collect();
+            derive_group_name(&assets)
+        }
+        NamingStrategy::FirstMember => extract_base_name(&members[0].asset.name),
+    };
+
+    let distances = options.include_group_distances.then(|| {
+        let mut pairs = BTreeMap::new();
+        for (i, asset1) in members.iter().enumerate() {
+            for (j, asset2) in members.iter().enumerate().skip(i + 1) {
+                pairs.insert(format!("{}|{}", asset1.asset.id, asset2.asset.id), distances[i][j]);
+            }
+        }
+        pairs
+    });
+
+    let cover = members[representative];
+    let assets = members
+        .iter()
+        .map(|&member| GroupedAsset {
+            audio_differs: options.audio_mode == AudioMode::Annotate
+                && member.asset.is_video
+                && cover.asset.is_video
+                && !audio_matches(
+                    member.audio_fingerprint.as_ref(),
+                    cover.audio_fingerprint.as_ref(),
+                ),
+            ..GroupedAsset::from(member)
+        })
+        .collect();
+
+    AssetGroup {
+        id: group_id(members),
+        name,
+        assets,
+        representative_asset_id: cover.asset.id.clone(),
+        confidence,
+        distances,
+        exact_duplicates: BTreeMap::new(),
+        subgroups: Vec::new(),
+        thumbnail_path: None,
+        thumbnail_error: None,
+    }
+}
+
+/// Regroup the members of a group under `strict_threshold`
+/// Empty when no strict threshold is set or there is a single member
+fn strict_subgroups(
+    members: &[HashedAsset],
+    options: &GroupingOptions,
+    stats: &StatsCollector,
+    pool: &rayon::ThreadPool,
+) -> Result<Vec<AssetGroup>> {
+    let Some(strict_threshold) = options.strict_threshold else {
+        return Ok(Vec::new());
+    };
+    if members.len() < 2 {
+        return Ok(Vec::new());
+    }
+
+    let strict_options = GroupingOptions {
+        threshold: strict_threshold,
+        threshold_ratio: None,
+        strict_threshold: None,
+        ..options.clone()
+    };
+    group_hashed_assets_with_stats(members, &strict_options, None, None, stats, pool)
+}
+
+/// Compare two assets while grouping, logging the decision at debug level
+fn is_similar_pair(asset1: &HashedAsset, asset2: &HashedAsset, options: &GroupingOptions) -> bool {
+    if !log::log_enabled!(log::Level::Debug) {
+        return are_assets_visually_similar(asset1, asset2, options);
+    }
+
+    let report = compare_hashed_assets(asset1, asset2, options);
+
+    let type1 = if asset1.asset.is_video { "video" } else { "image" };
+    let type2 = if asset2.asset.is_video { "video" } else { "image" };
+    if report.aspect_ratio_mismatch {
+        log::debug!(
+            "Comparing {} \"{}\" vs {} \"{}\": rejected on aspect ratio ({:.3} vs {:.3})",
+            type1, asset1.asset.name,
+            type2, asset2.asset.name,
+            report.aspect_ratio_a, report.aspect_ratio_b
+        );
+    } else if report.below_min_resolution {
+        log::debug!(
+            "Comparing {} \"{}\" vs {} \"{}\": rejected below min resolution {}px",
+            type1, asset1.asset.name,
+            type2, asset2.asset.name,
+            options.min_resolution
+        );
+    } else if report.duration_mismatch {
+        log::debug!(
+            "Comparing {} \"{}\" vs {} \"{}\": rejected on duration ({:.2}s vs {:.2}s)",
+            type1, asset1.asset.name,
+            type2, asset2.asset.name,
+            asset1.duration.unwrap_or_default(), asset2.duration.unwrap_or_default()
+        );
+    } else if report.palette_mismatch {
+        log::debug!(
+            "Comparing {} \"{}\" vs {} \"{}\": rejected on colors (palette distance {:.3})",
+            type1, asset1.asset.name,
+            type2, asset2.asset.name,
+            report.palette_distance.unwrap_or_default()
+        );
+    } else if report.audio_mismatch && !report.similar {
+        log::debug!(
+            "Comparing {} \"{}\" vs {} \"{}\": rejected on audio",
+            type1, asset1.asset.name,
+            type2, asset2.asset.name
+        );
+    } else if let Some(distance) = report.frame_distances.first() {
+        log::debug!(
+            "Comparing {} \"{}\" vs {} \"{}\": distance={}, similar={}",
+            type1, asset1.asset.name,
+            type2, asset2.asset.name,
+            distance, report.similar
+        );
+    }
+
+    report.similar
+}
+
+/// Disjoint-set forest over asset indices, used for transitive grouping
+struct DisjointSet {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl DisjointSet {
+    fn new(size: usize) -> Self {
+        DisjointSet {
+            parent: (0..size).collect(),
+            rank: vec![0; size],
+        }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+
+        // Path compression
+        let mut current = index;
+        while self.parent[current] != root {
+            let next = self.parent[current];
+            self.parent[current] = root;
+            current = next;
+        }
+
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (root_a, root_b) = (self.find(a), self.find(b));
+        if root_a == root_b {
+            return;
+        }
+
+        match self.rank[root_a].cmp(&self.rank[root_b]) {
+            std::cmp::Ordering::Less => self.parent[root_a] = root_b,
+            std::cmp::Ordering::Greater => self.parent[root_b] = root_a,
+            std::cmp::Ordering::Equal => {
+                self.parent[root_b] = root_a;
+                self.rank[root_a] += 1;
+            }
+        }
+    }
+
+    /// Union the sets of `a` and `b` unless that would put both assets of a
+    /// `cannot_link` pair into one set, returns whether the sets are joined
+    fn union_allowed(&mut self, a: usize, b: usize, cannot_link: &[(usize, usize)]) -> bool {
+        let (root_a, root_b) = (self.find(a), self.find(b));
+        if root_a == root_b {
+            return true;
+        }
+
+        for &(x, y) in cannot_link {
+            let (root_x, root_y) = (self.find(x), self.find(y));
+            if (root_x == root_a && root_y == root_b) || (root_x == root_b && root_y == root_a) {
+                return false;
+            }
+        }
+
+        self.union(a, b);
+        true
+    }
+
+    /// Members of every set, sets ordered by their first member
+    fn sets(&mut self) -> Vec<Vec<usize>> {
+        let mut sets: Vec<Vec<usize>> = Vec::new();
+        let mut set_by_root: HashMap<usize, usize> = HashMap::new();
+        for index in 0..self.parent.len() {
+            let root = self.find(index);
+            let set_index = *set_by_root.entry(root).or_insert_with(|| {
+                sets.push(Vec::new());
+                sets.len() - 1
+            });
+            sets[set_index].push(index);
+        }
+
+        sets
+    }
+}
+
+/// Caller-supplied `must_link` and `cannot_link` pairs as asset indices
+/// Pairs naming an asset that isn't being grouped are dropped
+#[derive(Debug, Default)]
+struct LinkConstraints {
+    must_link: Vec<(usize, usize)>,
+    cannot_link: Vec<(usize, usize)>,
+}
+
+impl LinkConstraints {
+    fn new(hashed_assets: &[HashedAsset], options: &GroupingOptions) -> Self {
+        let indices: HashMap<&str, usize> = hashed_assets
+            .iter()
+            .enumerate()
+            .map(|(index, hashed_asset)| (hashed_asset.asset.id.as_str(), index))
+            .collect();
+
+        let resolve = |pairs: &[(String, String)]| -> Vec<(usize, usize)> {
+            pairs
+                .iter()
+                .filter_map(|(id1, id2)| {
+                    match (indices.get(id1.as_str()), indices.get(id2.as_str())) {
+                        (Some(&a), Some(&b)) if a != b => Some((a.min(b), a.max(b))),
+                        _ => {
+                            log::debug!("Ignoring link constraint {} / {}", id1, id2);
+                            None
+                        }
+                    }
+                })
+                .collect()
+        };
+
+        LinkConstraints {
+            must_link: resolve(&options.must_link),
+            cannot_link: resolve(&options.cannot_link),
+        }
+    }
+
+    /// Whether `candidate` may join a group made of `members`
+    fn allows_join(&self, candidate: usize, members: &[usize]) -> bool {
+        !members.iter().any(|&member| {
+            let pair = (candidate.min(member), candidate.max(member));
+            self.cannot_link.contains(&pair)
+        })
+    }
+}
+
+/// Group already hashed assets by visual similarity
+/// Similar pairs are found in parallel, then groups are built single-threaded
+/// in asset id order, so neither thread scheduling nor the input order changes
+/// the output. Groups are sorted by representative id.
+/// With `Linkage::Anchor` each unassigned asset only collects assets similar to
+/// itself. With `Linkage::Transitive` all similar pairs are unioned, so chains
+/// A~B~C end up in one group.
+/// `cannot_link` pairs never share a group and `must_link` pairs always do,
+/// unless that would break a `cannot_link` pair. With a `strict_threshold`
+/// every group also lists the groups its members form under that threshold
+pub fn group_hashed_assets(
+    hashed_assets: &[HashedAsset],
+    options: &GroupingOptions,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+) -> Result<Vec<AssetGroup>> {
+    group_hashed_assets_with_stats(
+        hashed_assets,
+        options,
+        on_progress,
+        cancel,
+        &StatsCollector::default(),
+        &thread_pool(options)?,
+    )
+}
+
+/// `group_hashed_assets` on the workers of `pool`, counting comparisons and busy
+/// workers into `stats`
+fn group_hashed_assets_with_stats(
+    hashed_assets: &[HashedAsset],
+    options: &GroupingOptions,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+    pool: &rayon::ThreadPool,
+) -> Result<Vec<AssetGroup>> {
+    // Anchors are seeded in id order rather than input order
+    let mut sorted = hashed_assets.to_vec();
+    sorted.sort_by(|a, b| a.asset.id.cmp(&b.asset.id));
+    let hashed_assets = &sorted[..];
+
+    let neighbors = similar_pairs(hashed_assets, options, on_progress, cancel, stats, pool)?;
+    let constraints = LinkConstraints::new(hashed_assets, options);
+
+    let member_groups = match options.linkage {
+        Linkage::Anchor => anchor_linkage(&neighbors, &constraints),
+        Linkage::Transitive => transitive_linkage(&neighbors, &constraints),
+    };
+    let member_groups = merge_must_links(member_groups, &constraints, hashed_assets);
+
+    let mut groups: Vec<AssetGroup> = member_groups
+        .iter()
+        .map(|indices| {
+            let members: Vec<&HashedAsset> =
+                indices.iter().map(|&index| &hashed_assets[index]).collect();
+            let mut group = build_group(&members, options);
+            if options.strict_threshold.is_some() {
+                let members: Vec<HashedAsset> = members.into_iter().cloned().collect();
+                group.subgroups = strict_subgroups(&members, options, stats, pool)?;
+            }
+            Ok(group)
+        })
+        .collect::<Result<_>>()?;
+    groups.sort_by(|a, b| a.representative_asset_id.cmp(&b.representative_asset_id));
+
+    Ok(groups)
+}
+
+/// Build the worker pool used for hashing and pair evaluation
+fn thread_pool(options: &GroupingOptions) -> Result<rayon::ThreadPool> {
+    // Workers log on behalf of the job building the pool
+    let scope = log_scope();
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(options.concurrency())
+        .thread_name(|index| format!("visirs-worker-{}", index))
+        .start_handler(move |_| {
+            set_log_scope(scope);
+        })
+        .build()
+        .context("Failed to create the worker thread pool")
+}
+
+/// Whether first-frame candidates from a `HashIndex` cover every possible match
+/// Same conditions as `passes_prefilter`, and every frame pair has to match so the
+/// first frames are always under the threshold
+fn can_use_hash_index(options: &GroupingOptions) -> bool {
+    options.prefilter_slack.is_some()
+        && options.match_frames_ratio >= 1.0
+        && options.crop_resistance.is_none()
+        && options.frame_offset_tolerance == 0
+        && !options.detect_clips
+        && !options.allow_image_video_matching
+}
+
+/// BK-tree over the first frame of every asset, values are asset indices
+fn first_frame_index(hashed_assets: &[HashedAsset]) -> Result<HashIndex<usize>> {
+    let mut index = HashIndex::new();
+    for (i, hashed_asset) in hashed_assets.iter().enumerate() {
+        if let Some(frame) = hashed_asset.frames.first() {
+            index.insert(i, &frame.hash.bytes)?;
+        }
+    }
+    Ok(index)
+}
+
+/// For every asset `i`, the sorted indices `j > i` of the assets similar to it
+/// Candidates come from a first-frame `HashIndex` when that can't miss a match,
+/// otherwise every later asset is a candidate.
+/// Rows are evaluated in parallel, progress counts completed rows
+fn similar_pairs(
+    hashed_assets: &[HashedAsset],
+    options: &GroupingOptions,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+    stats: &StatsCollector,
+    pool: &rayon::ThreadPool,
+) -> Result<Vec<Vec<usize>>> {
+    let completed = AtomicUsize::new(0);
+
+    let index = if can_use_hash_index(options) {
+        Some(first_frame_index(hashed_assets)?)
+    } else {
+        None
+    };
+    // First frames under the threshold, the slack only widens the prefilter
+    let radius = options.effective_threshold().saturating_sub(1);
+
+    pool.install(|| {
+        (0..hashed_assets.len())
+            .into_par_iter()
+            .map(|i| {
+                if let Some(cancel) = cancel {
+                    cancel.check()?;
+                }
+                let _worker = stats.worker();
+
+                let candidates: Vec<usize> = match (&index, hashed_assets[i].frames.first()) {
+                    (Some(index), Some(frame)) => {
+                        // Variants find the assets that are a mirrored or
+                        // rotated copy of this one
+                        let mut candidates: Vec<usize> = std::iter::once(&frame.hash)
+                            .chain(&frame.variants)
+                            .flat_map(|hash| index.query(&hash.bytes, radius))
+                            .filter(|&j| j > i)
+                            .collect();
+                        candidates.sort_unstable();
+                        candidates.dedup();
+                        candidates
+                    }
+                    (Some(_), None) => Vec::new(),
+                    (None, _) => ((i + 1)..hashed_assets.len()).collect(),
+                };
+
+                let compared: Vec<usize> = candidates
+                    .into_iter()
+                    .filter(|&j| passes_prefilter(&hashed_assets[i], &hashed_assets[j], options))
+                    .collect();
+                stats.comparisons.fetch_add(compared.len(), Ordering::Relaxed);
+
+                let row: Vec<usize> = compared
+                    .into_iter()
+                    .filter(|&j| is_similar_pair(&hashed_assets[i], &hashed_assets[j], options))
+                    .collect();
+
+                let current = completed.fetch_add(1, Ordering::SeqCst) + 1;
+                report_progress(
+                    on_progress,
+                    ProgressPhase::Grouping,
+                    current,
+                    hashed_assets.len(),
+                    &hashed_assets[i].asset.id,
+                );
+
+                Ok(row)
+            })
+            .collect()
+    })
+}
+
+/// Cheap first-frame check run before the full frame-by-frame comparison
+/// Only applies to same-type pairs compared without frame offsets, where the
+/// first frames are always one of the compared pairs
+fn passes_prefilter(asset1: &HashedAsset, asset2: &HashedAsset, options: &GroupingOptions) -> bool {
+    let Some(slack) = options.prefilter_slack else {
+        return true;
+    };
+
+    // Crops match by regions even when their first frames are far apart
+    if asset1.asset.is_video != asset2.asset.is_video
+        || options.frame_offset_tolerance > 0
+        || options.detect_clips
+        || options.crop_resistance.is_some()
+    {
+        return true;
+    }
+
+    match (asset1.frames.first(), asset2.frames.first()) {
+        (Some(frame1), Some(frame2)) => match frame1.min_distance(frame2) {
+            Ok(distance) => distance < options.effective_threshold().saturating_add(slack),
+            Err(_) => true,
+        },
+        _ => true,
+    }
+}
+
+/// Each unassigned asset becomes an anchor collecting the unassigned assets similar to it,
+/// skipping those cannot-linked to a member collected so far
+fn anchor_linkage(neighbors: &[Vec<usize>], constraints: &LinkConstraints) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut assigned: HashSet<usize> = HashSet::new();
+
+    for (i, row) in neighbors.iter().enumerate() {
+        if !assigned.insert(i) {
+            continue;
+        }
+
+        let mut members = vec![i];
+        for &j in row {
+            if !assigned.contains(&j) && constraints.allows_join(j, &members) {
+                assigned.insert(j);
+                members.push(j);
+            }
+        }
+
+        groups.push(members);
+    }
+
+    groups
+}
+
+/// Union every similar pair, groups are the connected components
+/// Unions that would join a cannot-link pair are skipped.
+/// Groups are ordered by their first member
+fn transitive_linkage(neighbors: &[Vec<usize>], constraints: &LinkConstraints) -> Vec<Vec<usize>> {
+    let mut sets = DisjointSet::new(neighbors.len());
+
+    for (i, row) in neighbors.iter().enumerate() {
+        for &j in row {
+            sets.union_allowed(i, j, &constraints.cannot_link);
+        }
+    }
+
+    sets.sets()
+}
+
+/// Join the groups holding the two assets of each `must_link` pair, keeping
+/// them apart with a warning when that conflicts with a `cannot_link` pair
+fn merge_must_links(
+    groups: Vec<Vec<usize>>,
+    constraints: &LinkConstraints,
+    hashed_assets: &[HashedAsset],
+) -> Vec<Vec<usize>> {
+    if constraints.must_link.is_empty() {
+        return groups;
+    }
+
+    let mut sets = DisjointSet::new(hashed_assets.len());
+    for group in &groups {
+        for &member in &group[1..] {
+            sets.union(group[0], member);
+        }
+    }
+
+    for &(a, b) in &constraints.must_link {
+        if !sets.union_allowed(a, b, &constraints.cannot_link) {
+            log::warn!(
+                "Must-link {} / {} conflicts with a cannot-link constraint, keeping them apart",
+                hashed_assets[a].asset.id,
+                hashed_assets[b].asset.id
+            );
+        }
+    }
+
+    sets.sets()
+}
+
+/// Group assets by visual similarity
+/// `on_progress` is called after each asset is hashed and after each anchor
+/// asset has been compared against the remaining assets.
+/// Triggering `cancel` aborts the job with a `Cancelled` error
+pub fn group_assets_by_visual_similarity(
+    assets: Vec<Asset>,
+    options: Option<GroupingOptions>,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+) -> Result<GroupingResult> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+    validate_assets(&assets)?;
+
+    if assets.is_empty() {
+        return Ok(GroupingResult::default());
+    }
+
+    log::info!("Processing {} assets for visual grouping...", assets.len());
+
+    let asset_count = assets.len();
+    let (assets, aliases) = if options.dedupe_by_path {
+        merge_same_path_assets(assets)
+    } else {
+        (assets, HashMap::new())
+    };
+    // One pool serves every phase of the job
+    let pool = thread_pool(&options)?;
+    let (assets, unreadable) = preflight_assets(assets, &options, &pool)?;
+    let (assets, duplicates) = if options.detect_exact_duplicates {
+        split_exact_duplicates(assets, &pool)
+    } else {
+        (assets, HashMap::new())
+    };
+
+    let stats = StatsCollector::default();
+
+    // keep temp directories alive until grouping is complete
+    let hashing_started = Instant::now();
+    let (process_results, hash_failures) =
+        hash_assets_with_stats(&assets, &options, on_progress, cancel, &stats, &pool)?;
+    let hashing = hashing_started.elapsed();
+    let mut failed = unreadable;
+    failed.extend(hash_failures);
+
+    let hashed_assets: Vec<HashedAsset> =
+        process_results.iter().map(|(hashed_asset, _)| hashed_asset.clone()).collect();
+
+    log::info!("Generated hashes for {} assets", hashed_assets.len());
+
+    // Group assets by visual similarity
+    let comparison_started = Instant::now();
+    let mut groups = group_hashed_assets_with_stats(
+        &hashed_assets,
+        &options,
+        on_progress,
+        cancel,
+        &stats,
+        &pool,
+    )?;
+    let comparison = comparison_started.elapsed();
+    let mut hashes: Vec<AssetHashes> = hashed_assets.iter().map(AssetHashes::from).collect();
+    if !duplicates.is_empty() {
+        expand_exact_duplicates(&duplicates, &mut groups, &mut hashes, &mut failed);
+    }
+    // After the exact duplicates, whose copies would otherwise inherit aliases
+    if !aliases.is_empty() {
+        expand_path_aliases(&aliases, &mut groups, &mut hashes, &mut failed);
+    }
+
+    if let Some(thumbnails) = &options.thumbnails {
+        pool.install(|| write_group_thumbnails(&mut groups, thumbnails, &options.frame_sampling));
+    }
+
+    log::info!(
+        "Created {} visual groups from {} assets ({} failed)",
+        groups.len(),
+        asset_count,
+        failed.len()
+    );
+
+    Ok(GroupingResult {
+        groups,
+        failed,
+        hashes,
+        stats: stats.stats(hashing, comparison),
+        timings: stats.take_timings(),
+    })
+}
+
+/// Reject inputs without an id or a path and ids given more than once, which
+/// would otherwise be grouped as a single asset. Lists every offending entry
+fn validate_assets(assets: &[Asset]) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut indices: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, asset) in assets.iter().enumerate() {
+        if asset.id.is_empty() {
+            problems.push(format!("asset {} has an empty id", index));
+        } else {
+            indices.entry(&asset.id).or_default().push(index);
+        }
+        if let AssetSource::Path(path) = &asset.source
+            && path.as_os_str().is_empty()
+        {
+            problems.push(format!("asset {} ({}) has an empty path", index, asset.id));
+        }
+    }
+    for (id, indices) in indices.iter().filter(|(_, indices)| indices.len() > 1) {
+        let indices: Vec<String> = indices.iter().map(usize::to_string).collect();
+        problems.push(format!("id {} is used by assets {}", id, indices.join(", ")));
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid assets: {}", problems.join("; "));
+    }
+    Ok(())
+}
+
+/// Check every asset file before anything is decoded, see `check_asset_file`
+/// Returns the assets that passed and a failure for each of the others, or
+/// with `fail_fast` an error listing all of them
+fn preflight_assets(
+    assets: Vec<Asset>,
+    options: &GroupingOptions,
+    pool: &rayon::ThreadPool,
+) -> Result<(Vec<Asset>, Vec<FailedAsset>)> {
+    let problems: Vec<Option<String>> = pool.install(|| {
+        assets
+            .par_iter()
+            .map(|asset| {
+                // Precomputed hashes are used without reading the file
+                if asset.precomputed.is_some() {
+                    return None;
+                }
+                check_asset_file(asset).err().map(|err| format!("{:#}", err))
+            })
+            .collect()
+    });
+
+    if options.fail_fast && problems.iter().any(Option::is_some) {
+        let listed: Vec<String> = assets
+            .iter()
+            .zip(&problems)
+            .filter_map(|(asset, problem)| Some(format!("{}: {}", asset.id, problem.as_ref()?)))
+            .collect();
+        anyhow::bail!("{} assets cannot be read: {}", listed.len(), listed.join("; "));
+    }
+
+    let mut readable = Vec::with_capacity(assets.len());
+    let mut failed = Vec::new();
+    for (asset, problem) in assets.into_iter().zip(problems) {
+        match problem {
+            Some(reason) => {
+                log::warn!("Skipping {}: {}", asset.name, reason);
+                failed.push(FailedAsset {
+                    asset_id: asset.id,
+                    reason,
+                });
+            }
+            None => readable.push(asset),
+        }
+    }
+    Ok((readable, failed))
+}
+
+/// Keep the first of the assets pointing at the same canonical path
+/// Returns the assets left to hash and the ids of the others keyed by the id
+/// of the one kept. Paths that can't be resolved, such as URLs, are compared as given
+fn merge_same_path_assets(assets: Vec<Asset>) -> (Vec<Asset>, HashMap<String, Vec<String>>) {
+    let mut first_ids: HashMap<(PathBuf, AssetKind), String> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+    for asset in assets {
+        let AssetSource::Path(path) = &asset.source else {
+            unique.push(asset);
+            continue;
+        };
+
+        let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        match first_ids.get(&(canonical.clone(), asset.kind.clone())) {
+            Some(first_id) => {
+                log::debug!("{} has the same path as {}", asset.id, first_id);
+                aliases.entry(first_id.clone()).or_default().push(asset.id);
+            }
+            None => {
+                first_ids.insert((canonical, asset.kind.clone()), asset.id.clone());
+                unique.push(asset);
+            }
+        }
+    }
+
+    log::info!(
+        "Merged {} assets with the same path",
+        aliases.values().map(Vec::len).sum::<usize>()
+    );
+
+    (unique, aliases)
+}
+
+/// Report the ids merged by `merge_same_path_assets` on the asset kept for
+/// them, and give them its hashes and failures
+fn expand_path_aliases(
+    aliases: &HashMap<String, Vec<String>>,
+    groups: &mut [AssetGroup],
+    hashes: &mut Vec<AssetHashes>,
+    failed: &mut Vec<FailedAsset>,
+) {
+    for group in groups.iter_mut() {
+        set_group_aliases(group, aliases);
+    }
+
+    let aliased_hashes: Vec<AssetHashes> = hashes
+        .iter()
+        .flat_map(|asset_hashes| {
+            let ids = aliases.get(&asset_hashes.asset_id).into_iter().flatten();
+            ids.map(|id| AssetHashes {
+                asset_id: id.clone(),
+                ..asset_hashes.clone()
+            })
+        })
+        .collect();
+    hashes.extend(aliased_hashes);
+
+    let aliased_failures: Vec<FailedAsset> = failed
+        .iter()
+        .flat_map(|failure| {
+            let ids = aliases.get(&failure.asset_id).into_iter().flatten();
+            ids.map(|id| FailedAsset {
+                asset_id: id.clone(),
+                reason: failure.reason.clone(),
+            })
+        })
+        .collect();
+    failed.extend(aliased_failures);
+}
+
+/// Set the merged ids of each member, in the subgroups as well
+fn set_group_aliases(group: &mut AssetGroup, aliases: &HashMap<String, Vec<String>>) {
+    for member in &mut group.assets {
+        if let Some(ids) = aliases.get(&member.asset.id) {
+            member.alias_ids.clone_from(ids);
+        }
+    }
+    for subgroup in &mut group.subgroups {
+        set_group_aliases(subgroup, aliases);
+    }
+}
+
+/// SHA-256 of an asset's encoded content, `None` when it can't be read
+fn content_digest(asset: &Asset) -> Option<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    match &asset.source {
+        AssetSource::Path(path) => {
+            let mut file = std::fs::File::open(path).ok()?;
+            std::io::copy(&mut file, &mut hasher).ok()?;
+        }
+        AssetSource::Bytes(bytes) => hasher.update(bytes),
+    }
+    Some(hasher.finalize().into())
+}
+
+/// Byte-identical copies set aside from hashing, keyed by the id of the asset
+/// hashed for them
+type ExactDuplicates = HashMap<String, Vec<Asset>>;
+
+/// Set aside assets whose content is byte-identical to an earlier asset
+/// Returns the assets left to hash and the copies keyed by the id of the first
+/// asset with the same content. Assets with precomputed hashes or unreadable
+/// content are always hashed themselves
+fn split_exact_duplicates(
+    assets: Vec<Asset>,
+    pool: &rayon::ThreadPool,
+) -> (Vec<Asset>, ExactDuplicates) {
+    let digests: Vec<Option<[u8; 32]>> = pool.install(|| {
+        assets
+            .par_iter()
+            .map(|asset| asset.precomputed.is_none().then(|| content_digest(asset)).flatten())
+            .collect()
+    });
+
+    let mut first_ids: HashMap<[u8; 32], String> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut duplicates: ExactDuplicates = HashMap::new();
+    for (asset, digest) in assets.into_iter().zip(digests) {
+        let Some(digest) = digest else {
+            unique.push(asset);
+            continue;
+        };
+
+        match first_ids.get(&digest) {
+            Some(first_id) => {
+                log::debug!("{} is an exact duplicate of {}", asset.id, first_id);
+                duplicates.entry(first_id.clone()).or_default().push(asset);
+            }
+            None => {
+                first_ids.insert(digest, asset.id.clone());
+                unique.push(asset);
+            }
+        }
+    }
+
+    log::info!(
+        "Found {} exact duplicates",
+        duplicates.values().map(Vec::len).sum::<usize>()
+    );
+
+    (unique, duplicates)
+}
+
+/// Put the copies set aside by `split_exact_duplicates` back next to the asset
+/// that was hashed for them, sharing its hashes, distances and failures
+fn expand_exact_duplicates(
+    duplicates: &ExactDuplicates,
+    groups: &mut [AssetGroup],
+    hashes: &mut Vec<AssetHashes>,
+    failed: &mut Vec<FailedAsset>,
+) {
+    for group in groups.iter_mut() {
+        expand_group_duplicates(group, duplicates);
+    }
+
+    let copied_hashes: Vec<AssetHashes> = hashes
+        .iter()
+        .flat_map(|asset_hashes| {
+            let copies = duplicates.get(&asset_hashes.asset_id).into_iter().flatten();
+            copies.map(|copy| AssetHashes {
+                asset_id: copy.id.clone(),
+                ..asset_hashes.clone()
+            })
+        })
+        .collect();
+    hashes.extend(copied_hashes);
+
+    let copied_failures: Vec<FailedAsset> = failed
+        .iter()
+        .flat_map(|failure| {
+            let copies = duplicates.get(&failure.asset_id).into_iter().flatten();
+            copies.map(|copy| FailedAsset {
+                asset_id: copy.id.clone(),
+                reason: failure.reason.clone(),
+            })
+        })
+        .collect();
+    failed.extend(copied_failures);
+}
+
+/// Add the copies of each member next to it, in the subgroups as well
+fn expand_group_duplicates(group: &mut AssetGroup, duplicates: &ExactDuplicates) {
+    let mut assets = Vec::with_capacity(group.assets.len());
+    for member in std::mem::take(&mut group.assets) {
+        let copies = duplicates.get(&member.asset.id);
+        for copy in copies.into_iter().flatten() {
+            group.exact_duplicates.insert(copy.id.clone(), member.asset.id.clone());
+            assets.push(GroupedAsset {
+                asset: copy.clone(),
+                ..member.clone()
+            });
+        }
+        assets.push(member);
+    }
+    assets.sort_by(|a, b| a.asset.id.cmp(&b.asset.id));
+    group.assets = assets;
+
+    // Keys list the smaller id first, like the ones of `build_group`
+    let pair_key = |id1: &str, id2: &str| {
+        if id1 <= id2 {
+            format!("{}|{}", id1, id2)
+        } else {
+            format!("{}|{}", id2, id1)
+        }
+    };
+    if let Some(distances) = &mut group.distances {
+        let mut copied = BTreeMap::new();
+        for (copy_id, original_id) in &group.exact_duplicates {
+            copied.insert(pair_key(original_id, copy_id), 0.0);
+            for (key, distance) in distances.iter() {
+                let Some((id1, id2)) = key.split_once('|') else {
+                    continue;
+                };
+                if id1 == original_id {
+                    copied.insert(pair_key(copy_id, id2), *distance);
+                } else if id2 == original_id {
+                    copied.insert(pair_key(id1, copy_id), *distance);
+                }
+            }
+        }
+        distances.extend(copied);
+    }
+
+    for subgroup in &mut group.subgroups {
+        expand_group_duplicates(subgroup, duplicates);
+    }
+}
+
+/// Rebuild the hashed members of a persisted group from its stored hashes
+fn restore_group_members(
+    group: &AssetGroupWithHashes,
+    options: &GroupingOptions,
+) -> Result<Vec<HashedAsset>> {
+    group
+        .group
+        .assets
+        .iter()
+        .map(|member| {
+            let asset = &member.asset;
+            let hashes = group
+                .hashes
+                .iter()
+                .find(|hashes| hashes.asset_id == asset.id)
+                .with_context(|| {
+                    format!("Group {} has no hashes for asset {}", group.group.id, asset.id)
+                })?;
+
+            let precomputed = PrecomputedHashes {
+                frames: hashes.frames.clone(),
+                width: hashes.width,
+                height: hashes.height,
+                duration: hashes.duration,
+                dominant_colors: hashes.dominant_colors.clone(),
+                audio_fingerprint: hashes.audio_fingerprint.clone(),
+            };
+            let mut hashed_asset = hashed_asset_from_precomputed(asset, &precomputed, options)?;
+            hashed_asset.degraded = hashes.degraded;
+            Ok(hashed_asset)
+        })
+        .collect()
+}
+
+/// Slot new assets into previously computed groups, only the new assets are decoded
+/// Each new asset joins the closest group whose representative (or any member
+/// with `AssignmentMode::AllMembers`) it is similar to. Assets matching no
+/// existing group are grouped among themselves into new groups
+pub fn assign_to_groups(
+    new_assets: Vec<Asset>,
+    existing_groups: Vec<AssetGroupWithHashes>,
+    options: Option<GroupingOptions>,
+) -> Result<AssignmentResult> {
+    let options = options.unwrap_or_default();
+    options.validate().context("Invalid grouping options")?;
+
+    let mut groups = existing_groups;
+    let mut members = groups
+        .iter()
+        .map(|group| restore_group_members(group, &options))
+        .collect::<Result<Vec<Vec<HashedAsset>>>>()?;
+    let mut changed = vec![false; groups.len()];
+
+    let pool = thread_pool(&options)?;
+    let stats = StatsCollector::default();
+    let (process_results, failed) =
+        hash_assets_with_stats(&new_assets, &options, None, None, &stats, &pool)?;
+
+    let mut assignments = Vec::new();
+    let mut unassigned: Vec<HashedAsset> = Vec::new();
+
+    for (hashed_asset, _) in process_results {
+        let closest = groups
+            .iter()
+            .zip(&members)
+            .enumerate()
+            .filter_map(|(index, (group, group_members))| {
+                let candidates: Vec<&HashedAsset> = match options.assignment_mode {
+                    AssignmentMode::Representative => group_members
+                        .iter()
+                        .find(|member| member.asset.id == group.group.representative_asset_id)
+                        .or(group_members.first())
+                        .into_iter()
+                        .collect(),
+                    AssignmentMode::AllMembers => group_members.iter().collect(),
+                };
+
+                candidates
+                    .into_iter()
+                    .map(|candidate| compare_hashed_assets(&hashed_asset, candidate, &options))
+                    .filter(|report| report.similar)
+                    .filter_map(|report| report.mean_distance)
+                    .min_by(f64::total_cmp)
+                    .map(|distance| (index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        match closest {
+            Some((index, _)) => {
+                log::debug!(
+                    "Assigned {} to existing group {}",
+                    hashed_asset.asset.name,
+                    groups[index].group.name
+                );
+                assignments.push(GroupAssignment {
+                    asset_id: hashed_asset.asset.id.clone(),
+                    group_id: groups[index].group.id.clone(),
+                    created_group: false,
+                });
+                members[index].push(hashed_asset);
+                changed[index] = true;
+            }
+            None => unassigned.push(hashed_asset),
+        }
+    }
+
+    // Membership changed, so the representative and confidence may have too
+    for ((group, group_members), _) in groups
+        .iter_mut()
+        .zip(&members)
+        .zip(&changed)
+        .filter(|(_, changed)| **changed)
+    {
+        let member_refs: Vec<&HashedAsset> = group_members.iter().collect();
+        let rebuilt = build_group(&member_refs, &options);
+
+        group.group.assets = rebuilt.assets;
+        group.group.representative_asset_id = rebuilt.representative_asset_id;
+        group.group.confidence = rebuilt.confidence;
+        group.group.distances = rebuilt.distances;
+        group.group.subgroups = strict_subgroups(group_members, &options, &stats, &pool)?;
+        group.hashes = group_members.iter().map(AssetHashes::from).collect();
+    }
+
+    let existing_count = groups.len();
+    let new_groups =
+        group_hashed_assets_with_stats(&unassigned, &options, None, None, &stats, &pool)?;
+    for group in new_groups {
+        let hashes = unassigned
+            .iter()
+            .filter(|hashed_asset| {
+                group
+                    .assets
+                    .iter()
+                    .any(|member| member.asset.id == hashed_asset.asset.id)
+            })
+            .map(AssetHashes::from)
+            .collect();
+
+        assignments.extend(group.assets.iter().map(|member| GroupAssignment {
+            asset_id: member.asset.id.clone(),
+            group_id: group.id.clone(),
+            created_group: true,
+        }));
+        groups.push(AssetGroupWithHashes { group, hashes });
+    }
+
+    log::info!(
+        "Assigned {} assets: {} into existing groups, {} new groups ({} failed)",
+        new_assets.len(),
+        assignments.iter().filter(|assignment| !assignment.created_group).count(),
+        groups.len() - existing_count,
+        failed.len()
+    );
+
+    Ok(AssignmentResult {
+        groups,
+        assignments,
+        failed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::{
+        AssetKind, CropResistance, DEFAULT_STRICT_THRESHOLD, HashAlgorithm, HashConfig,
+    };
+    use crate::visual_grouping::hash::PerceptualHash;
+    use crate::visual_grouping::scan::{ScanOptions, scan_directory};
+
+    /// Frame with a 64 bit hash of the default config
+    fn frame(frame_number: usize, hash: u64) -> FrameData {
+        let hash = PerceptualHash::new(HashConfig::default(), hash.to_be_bytes().to_vec());
+        FrameData::new(frame_number, frame_number as f64, hash.unwrap())
+    }
+
+    fn hashed_image(id: &str, hash: u64) -> HashedAsset {
+        HashedAsset {
+            asset: Asset {
+                id: id.to_string(),
+                name: format!("{}.png", id),
+                source: AssetSource::Path(format!("{}.png", id).into()),
+                mime_type: "image/png".to_string(),
+                is_video: false,
+                precomputed: None,
+                kind: AssetKind::File,
+            },
+            frames: vec![frame(0, hash)],
+            aspect_ratio: 1.0,
+            width: 100,
+            height: 100,
+            duration: None,
+            degraded: false,
+            is_animated: false,
+            dominant_colors: Vec::new(),
+            audio_fingerprint: None,
+            sampling_policy: None,
+            decode_errors: 0,
+        }
+    }
+
+    // A~B and B~C are 10 bits apart, A and C are 20 bits apart
+    fn chain() -> Vec<HashedAsset> {
+        vec![
+            hashed_image("a", 0),
+            hashed_image("b", (1 << 10) - 1),
+            hashed_image("c", (1 << 20) - 1),
+        ]
+    }
+
+    fn group_ids(assets: &[HashedAsset], options: &GroupingOptions) -> Vec<Vec<String>> {
+        let mut groups: Vec<Vec<String>> = group_hashed_assets(assets, options, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|group| {
+                let mut ids: Vec<String> =
+                    group.assets.into_iter().map(|member| member.asset.id).collect();
+                ids.sort();
+                ids
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    fn hashed_video(id: &str, frame_hashes: &[u64]) -> HashedAsset {
+        let mut hashed_asset = hashed_image(id, 0);
+        hashed_asset.asset.is_video = true;
+        hashed_asset.frames = frame_hashes
+            .iter()
+            .enumerate()
+            .map(|(frame_number, &hash)| frame(frame_number, hash))
+            .collect();
+        hashed_asset
+    }
+
+    #[test]
+    fn test_frame_match_ratio_tolerates_noisy_frame() {
+        let original = hashed_video("original", &[0, 0, 0, 0]);
+        // Third frame is a flash cut, 40 bits away from the original
+        let noisy = hashed_video("noisy", &[0, 0, (1 << 40) - 1, 0]);
+
+        let strict = GroupingOptions::default();
+        let report = compare_hashed_assets(&original, &noisy, &strict);
+        assert!(!report.similar);
+        assert_eq!(report.matched_frames_ratio, 0.75);
+        assert!(report.score > 0.8);
+
+        let lenient = GroupingOptions {
+            match_frames_ratio: 0.75,
+            ..GroupingOptions::default()
+        };
+        assert!(are_assets_visually_similar(&original, &noisy, &lenient));
+    }
+
+    #[test]
+    fn test_static_frames_are_collapsed() {
+        // Ten frames of a logo with encoder noise, then a cut to other content
+        let content = (1 << 40) - 1;
+        let hashes = [0, 1, 0, 3, 1, 0, 2, 0, 1, 0, content, content ^ 1];
+        let frames: Vec<FrameData> = hashes
+            .iter()
+            .enumerate()
+            .map(|(frame_number, &hash)| frame(frame_number, hash))
+            .collect();
+
+        let deduped = dedupe_consecutive_frames(frames.clone(), 3);
+        let kept: Vec<(usize, usize)> = deduped
+            .iter()
+            .map(|frame| (frame.frame_number, frame.represented_frames))
+            .collect();
+        assert_eq!(kept, vec![(0, 10), (10, 2)]);
+        assert_eq!(deduped[1].timestamp_secs, 10.0);
+
+        // Collapsing again changes nothing, disabling keeps every frame
+        let again = dedupe_consecutive_frames(deduped.clone(), 3);
+        assert_eq!(again.len(), 2);
+        assert_eq!(again[0].represented_frames, 10);
+        assert_eq!(dedupe_consecutive_frames(frames, 0).len(), hashes.len());
+
+        let mut long = hashed_video("long", &[0; 10]);
+        long.frames = dedupe_consecutive_frames(long.frames, 3);
+        let mut short = hashed_video("short", &[1, 0, 1]);
+        short.frames = dedupe_consecutive_frames(short.frames, 3);
+        let report = compare_hashed_assets(&long, &short, &GroupingOptions::default());
+        assert_eq!(report.frame_distances, vec![1]);
+        assert!(report.similar);
+    }
+
+    #[test]
+    fn test_color_weight_vetoes_borderline_matches() {
+        let mut dark = hashed_image("dark", 0);
+        dark.dominant_colors = vec![[10, 10, 10], [40, 40, 40]];
+        let mut light = hashed_image("light", (1 << 10) - 1);
+        light.dominant_colors = vec![[250, 250, 250], [200, 200, 200]];
+
+        let report = compare_hashed_assets(&dark, &light, &GroupingOptions::default());
+        assert!(report.similar);
+        assert!(report.palette_distance.unwrap() > 0.5);
+
+        let weighted = GroupingOptions {
+            color_weight: 0.2,
+            ..GroupingOptions::default()
+        };
+        let report = compare_hashed_assets(&dark, &light, &weighted);
+        assert!(!report.similar);
+        assert!(report.palette_mismatch);
+
+        // Same palette, or no colors to compare, keeps the hash verdict
+        let mut twin = hashed_image("twin", (1 << 10) - 1);
+        twin.dominant_colors = dark.dominant_colors.clone();
+        assert!(compare_hashed_assets(&dark, &twin, &weighted).similar);
+        twin.dominant_colors.clear();
+        assert!(compare_hashed_assets(&light, &twin, &weighted).similar);
+    }
+
+    #[test]
+    fn test_audio_mismatch_only_rejects_in_separate_mode() {
+        let codes: Vec<u32> = (0..40u32).map(|i| i.wrapping_mul(2_654_435_761)).collect();
+        let mut english = hashed_video("english", &[0, 0, 0]);
+        english.audio_fingerprint = Some(AudioFingerprint { codes: codes.clone() });
+        let mut german = hashed_video("german", &[0, 0, 0]);
+        german.audio_fingerprint = Some(AudioFingerprint {
+            codes: codes.iter().map(|code| code.rotate_left(16)).collect(),
+        });
+        let mut other_cut = hashed_video("other", &[u64::MAX, u64::MAX, u64::MAX]);
+        other_cut.audio_fingerprint = german.audio_fingerprint.clone();
+
+        let options = |audio_mode| GroupingOptions {
+            audio_mode,
+            ..GroupingOptions::default()
+        };
+        let report = compare_hashed_assets(&english, &german, &options(AudioMode::Ignore));
+        assert!(report.similar && !report.audio_mismatch);
+        let report = compare_hashed_assets(&english, &german, &options(AudioMode::Annotate));
+        assert!(report.similar && report.audio_mismatch);
+        let report = compare_hashed_assets(&english, &german, &options(AudioMode::Separate));
+        assert!(!report.similar && report.audio_mismatch);
+
+        // Audio is only compared once the frames match
+        let report = compare_hashed_assets(&german, &other_cut, &options(AudioMode::Separate));
+        assert!(!report.similar && !report.audio_mismatch);
+
+        let groups = group_ids(&[english, german], &options(AudioMode::Separate));
+        assert_eq!(groups, [vec!["english"], vec!["german"]]);
+    }
+
+    #[test]
+    fn test_aspect_ratio_mismatch_rejects_identical_hashes() {
+        let square = hashed_image("square", 0);
+        let mut story = hashed_image("story", 0);
+        story.width = 1080;
+        story.height = 1920;
+        story.aspect_ratio = 1080.0 / 1920.0;
+
+        let checked = GroupingOptions {
+            max_aspect_ratio_delta: Some(0.1),
+            ..GroupingOptions::default()
+        };
+        let report = compare_hashed_assets(&square, &story, &checked);
+        assert!(report.aspect_ratio_mismatch);
+        assert!(!report.similar);
+
+        assert!(are_assets_visually_similar(&square, &story, &GroupingOptions::default()));
+    }
+
+    #[test]
+    fn test_square_and_story_resizes_group_by_default() {
+        let story = image::RgbImage::from_fn(90, 160, |x, y| {
+            image::Rgb([(x * 2) as u8, (y * 3 / 2) as u8, 0])
+        });
+        let square =
+            image::imageops::resize(&story, 160, 160, image::imageops::FilterType::Triangle);
+        let assets = vec![png_asset("square", &square), png_asset("story", &story)];
+
+        let result = group_assets_by_visual_similarity(assets, None, None, None).unwrap();
+        let groups: Vec<Vec<&str>> = result
+            .groups
+            .iter()
+            .map(|group| group.assets.iter().map(|member| member.asset.id.as_str()).collect())
+            .collect();
+        assert_eq!(groups, [vec!["square", "story"]]);
+    }
+
+    #[test]
+    fn test_image_matches_video_frame_when_cross_type_enabled() {
+        let poster: u64 = 0x0F0F_0F0F_0F0F_0F0F;
+        let mut frames = vec![u64::MAX; 10];
+        frames[3] = poster;
+        let video = hashed_video("video", &frames);
+        let image = hashed_image("poster", poster);
+
+        let strict = GroupingOptions::default();
+        let report = compare_hashed_assets(&image, &video, &strict);
+        assert!(report.type_mismatch);
+        assert!(!report.similar);
+
+        let cross_type = GroupingOptions {
+            allow_image_video_matching: true,
+            ..GroupingOptions::default()
+        };
+        let report = compare_hashed_assets(&image, &video, &cross_type);
+        assert!(report.similar);
+        assert_eq!(report.min_distance, Some(0));
+        assert_eq!(report.frame_offset, 3);
+        assert_eq!(report.frame_distances.len(), 10);
+
+        // Same verdict with the video as the first asset
+        let report = compare_hashed_assets(&video, &image, &cross_type);
+        assert!(report.similar);
+        assert_eq!(report.frame_offset, -3);
+    }
+
+    #[test]
+    fn test_detect_clips_finds_cutdown_inside_master() {
+        let master_hashes: Vec<u64> =
+            (1..=12u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+        let mut master = hashed_video("master", &master_hashes);
+        master.duration = Some(12.0);
+        // Six frames cut from the master starting at frame 4, one re-encoded
+        let mut clip_hashes = master_hashes[4..10].to_vec();
+        clip_hashes[2] ^= 0b111;
+        let mut clip = hashed_video("clip", &clip_hashes);
+        clip.duration = Some(5.5);
+
+        let aligned = GroupingOptions::default();
+        let report = compare_hashed_assets(&clip, &master, &aligned);
+        assert!(report.duration_mismatch);
+        assert!(!report.similar);
+
+        let clips = GroupingOptions {
+            detect_clips: true,
+            ..GroupingOptions::default()
+        };
+        let report = compare_hashed_assets(&clip, &master, &clips);
+        assert!(report.similar);
+        assert_eq!(report.frame_offset, 4);
+        assert_eq!(report.frame_distances, [0, 0, 3, 0, 0, 0]);
+
+        // Same window found with the master as the first asset
+        let report = compare_hashed_assets(&master, &clip, &clips);
+        assert!(report.similar);
+        assert_eq!(report.frame_offset, -4);
+
+        let groups = group_ids(&[clip, master], &clips);
+        assert_eq!(groups, vec![vec!["clip", "master"]]);
+    }
+
+    #[test]
+    fn test_anchor_linkage_seeds_in_id_order() {
+        let options = GroupingOptions::default();
+        let mut assets = chain();
+
+        assert_eq!(group_ids(&assets, &options), vec![vec!["a", "b"], vec!["c"]]);
+
+        // "a" stays the first anchor, so "b" doesn't seed a group with "c"
+        assets.reverse();
+        assert_eq!(group_ids(&assets, &options), vec![vec!["a", "b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn test_transitive_linkage_is_order_independent() {
+        let options = GroupingOptions {
+            linkage: Linkage::Transitive,
+            ..GroupingOptions::default()
+        };
+
+        let orders: [[usize; 3]; 6] = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        for order in orders {
+            let assets: Vec<HashedAsset> = order.iter().map(|&i| chain()[i].clone()).collect();
+            assert_eq!(group_ids(&assets, &options), vec![vec!["a", "b", "c"]]);
+        }
+    }
+
+    #[test]
+    fn test_link_constraints_override_distances() {
+        let pair = |id1: &str, id2: &str| vec![(id1.to_string(), id2.to_string())];
+
+        // a and c are 20 bits apart, must-link still joins their groups
+        let must_link = GroupingOptions {
+            must_link: pair("a", "c"),
+            ..GroupingOptions::default()
+        };
+        assert_eq!(group_ids(&chain(), &must_link), vec![vec!["a", "b", "c"]]);
+
+        // Identical hashes stay apart when cannot-linked
+        let twins = vec![hashed_image("x", 0), hashed_image("y", 0)];
+        let cannot_link = GroupingOptions {
+            cannot_link: pair("y", "x"),
+            ..GroupingOptions::default()
+        };
+        assert_eq!(group_ids(&twins, &cannot_link), vec![vec!["x"], vec!["y"]]);
+
+        // b can't join anchor a, so it anchors its own group with c
+        let cannot_link = GroupingOptions {
+            cannot_link: pair("a", "b"),
+            ..GroupingOptions::default()
+        };
+        assert_eq!(group_ids(&chain(), &cannot_link), vec![vec!["a"], vec!["b", "c"]]);
+
+        // The chain a~b~c is cut where it would join a and c
+        let transitive = GroupingOptions {
+            linkage: Linkage::Transitive,
+            cannot_link: pair("a", "c"),
+            ..GroupingOptions::default()
+        };
+        assert_eq!(group_ids(&chain(), &transitive), vec![vec!["a", "b"], vec!["c"]]);
+
+        // Cannot-link wins over a conflicting must-link
+        let conflicting = GroupingOptions {
+            must_link: pair("a", "c"),
+            cannot_link: pair("a", "c"),
+            ..GroupingOptions::default()
+        };
+        assert_eq!(group_ids(&chain(), &conflicting), vec![vec!["a", "b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn test_strict_threshold_adds_subgroups() {
+        let assets = vec![
+            hashed_image("a", 0),
+            hashed_image("b", 0b11),
+            hashed_image("c", (1 << 10) - 1),
+        ];
+        let subgroup_ids = |group: &AssetGroup| -> Vec<Vec<String>> {
+            group
+                .subgroups
+                .iter()
+                .map(|subgroup| subgroup.assets.iter().map(|m| m.asset.id.clone()).collect())
+                .collect()
+        };
+
+        let flat = group_hashed_assets(&assets, &GroupingOptions::default(), None, None).unwrap();
+        assert_eq!(flat.len(), 1);
+        assert!(flat[0].subgroups.is_empty());
+
+        let two_tier = GroupingOptions {
+            strict_threshold: Some(DEFAULT_STRICT_THRESHOLD),
+            ..GroupingOptions::default()
+        };
+        let groups = group_hashed_assets(&assets, &two_tier, None, None).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].assets.len(), 3);
+        assert_eq!(subgroup_ids(&groups[0]), vec![vec!["a", "b"], vec!["c"]]);
+
+        let invalid = GroupingOptions {
+            strict_threshold: Some(20),
+            ..GroupingOptions::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    /// `clusters` groups of `variants` near-duplicates each, about 8 bits of
+    /// noise per variant so some pairs land on either side of the threshold
+    fn synthetic_clusters(clusters: usize, variants: usize) -> Vec<HashedAsset> {
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut assets = Vec::new();
+        for cluster in 0..clusters {
+            let base = next();
+            for variant in 0..variants {
+                let noise = next() & next() & next();
+                let id = format!("{}-{}", cluster, variant);
+                assets.push(hashed_image(&id, base ^ noise));
+            }
+        }
+        assets
+    }
+
+    #[test]
+    fn test_grouping_output_ignores_input_order() {
+        let assets = synthetic_clusters(6, 5);
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+
+        for linkage in [Linkage::Anchor, Linkage::Transitive] {
+            let options = GroupingOptions {
+                linkage,
+                include_group_distances: true,
+                ..GroupingOptions::default()
+            };
+            let serialized = |assets: &[HashedAsset]| {
+                let groups = group_hashed_assets(assets, &options, None, None).unwrap();
+                serde_json::to_string(&groups).unwrap()
+            };
+            let expected = serialized(&assets);
+
+            for _ in 0..20 {
+                // Fisher-Yates with a xorshift generator
+                let mut shuffled = assets.clone();
+                for i in (1..shuffled.len()).rev() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    shuffled.swap(i, (state % (i as u64 + 1)) as usize);
+                }
+                assert_eq!(serialized(&shuffled), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_grouping_is_deterministic() {
+        let assets = synthetic_clusters(60, 5);
+
+        for linkage in [Linkage::Anchor, Linkage::Transitive] {
+            let sequential = GroupingOptions {
+                linkage,
+                max_concurrency: Some(1),
+                ..GroupingOptions::default()
+            };
+            let parallel = GroupingOptions {
+                max_concurrency: Some(4),
+                ..sequential.clone()
+            };
+
+            let expected = group_ids(&assets, &sequential);
+            for _ in 0..3 {
+                assert_eq!(group_ids(&assets, &parallel), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_index_matches_brute_force_groups() {
+        let assets = synthetic_clusters(80, 4);
+
+        for linkage in [Linkage::Anchor, Linkage::Transitive] {
+            let indexed = GroupingOptions {
+                linkage,
+                ..GroupingOptions::default()
+            };
+            let brute_force = GroupingOptions {
+                prefilter_slack: None,
+                ..indexed.clone()
+            };
+            assert!(can_use_hash_index(&indexed));
+            assert!(!can_use_hash_index(&brute_force));
+            // Partial frame matches can have first frames past the threshold
+            let partial = GroupingOptions {
+                match_frames_ratio: 0.5,
+                ..indexed.clone()
+            };
+            assert!(!can_use_hash_index(&partial));
+
+            assert_eq!(group_ids(&assets, &indexed), group_ids(&assets, &brute_force));
+        }
+    }
+
+    fn png_asset(id: &str, image: &image::RgbImage) -> Asset {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+
+        Asset {
+            id: id.to_string(),
+            name: format!("{}.png", id),
+            source: AssetSource::Bytes(bytes.into_inner()),
+            mime_type: "image/png".to_string(),
+            is_video: false,
+            precomputed: None,
+            kind: AssetKind::File,
+        }
+    }
+
+    #[test]
+    fn test_invariance_matches_mirrored_and_rotated_copies() {
+        let original = image::RgbImage::from_fn(64, 64, |x, y| {
+            let value = (x * 3 + y) as u8;
+            image::Rgb([value, value, value])
+        });
+        let assets = [
+            png_asset("original", &original),
+            png_asset("mirrored", &image::imageops::flip_horizontal(&original)),
+            png_asset("rotated", &image::imageops::rotate90(&original)),
+        ];
+
+        let similar = |invariance, other: usize| {
+            let options = GroupingOptions {
+                invariance,
+                ..GroupingOptions::default()
+            };
+            let (a, _) = process_asset(&assets[0], &options, None).unwrap();
+            let (b, _) = process_asset(&assets[other], &options, None).unwrap();
+            assert_eq!(a.frames[0].variants.is_empty(), invariance == Invariance::None);
+            compare_hashed_assets(&a, &b, &options).similar
+        };
+
+        assert!(!similar(Invariance::None, 1));
+        assert!(!similar(Invariance::None, 2));
+        assert!(similar(Invariance::Mirror, 1));
+        assert!(!similar(Invariance::Mirror, 2));
+        assert!(similar(Invariance::Rotation, 1));
+        assert!(similar(Invariance::Rotation, 2));
+    }
+
+    #[test]
+    fn test_crop_resistance_groups_wide_crop_with_original() {
+        // Smooth content, so the one pixel offset between the crop's regions and
+        // the original's barely changes the region hashes
+        let original = image::RgbImage::from_fn(240, 240, |x, y| {
+            let (x, y) = (x as f64, y as f64);
+            let value = 128.0
+                + 60.0 * (x / 11.0).sin() * (y / 17.0).cos()
+                + 50.0 * ((x + 2.0 * y) / 29.0).sin();
+            image::Rgb([value as u8, (255.0 - value) as u8, (value / 2.0) as u8])
+        });
+        // 16:9 banner cut from the middle of the square original
+        let banner = image::imageops::crop_imm(&original, 0, 52, 240, 135).to_image();
+        let assets = [png_asset("original", &original), png_asset("banner", &banner)];
+
+        let groups = |crop_resistance| {
+            let options = GroupingOptions {
+                crop_resistance,
+                ..GroupingOptions::default()
+            };
+            options.validate().unwrap();
+            let hashed: Vec<HashedAsset> = assets
+                .iter()
+                .map(|asset| process_asset(asset, &options, None).unwrap().0)
+                .collect();
+            let regions = if crop_resistance.is_some() { 9 } else { 0 };
+            assert_eq!(hashed[0].frames[0].regions.len(), regions);
+            group_ids(&hashed, &options).len()
+        };
+
+        assert_eq!(groups(None), 2);
+        assert_eq!(groups(Some(CropResistance::default())), 1);
+    }
+
+    #[test]
+    fn test_normalize_groups_brightened_copy() {
+        // Orange and gray blocks: brightening clips the orange red channel, so
+        // the gray blocks overtake the orange ones in channel sum but not in luma
+        let original = image::RgbImage::from_fn(64, 64, |x, y| {
+            if (x / 8 + y / 8) % 2 == 0 {
+                image::Rgb([255, 150, 0])
+            } else {
+                image::Rgb([128, 128, 128])
+            }
+        });
+        // +15% brightness
+        let brightened = image::imageops::brighten(&original, 38);
+        let assets = [png_asset("original", &original), png_asset("brightened", &brightened)];
+
+        let groups = |normalize| {
+            let options = GroupingOptions {
+                normalize,
+                ..GroupingOptions::default()
+            };
+            let hashed: Vec<HashedAsset> = assets
+                .iter()
+                .map(|asset| process_asset(asset, &options, None).unwrap().0)
+                .collect();
+            group_ids(&hashed, &options).len()
+        };
+
+        assert_eq!(groups(false), 2);
+        assert_eq!(groups(true), 1);
+    }
+
+    #[test]
+    fn test_threshold_ratio_scales_with_hash_size() {
+        // 16x16 hashes, 256 bits, differing in the first `bits` bits
+        let hashed_256 = |id: &str, bits: usize| {
+            let mut hash = vec![0u8; 32];
+            for bit in 0..bits {
+                hash[bit / 8] |= 0x80 >> (bit % 8);
+            }
+            let mut asset = hashed_image(id, 0);
+            let config = HashConfig::square(HashAlgorithm::default(), 16);
+            asset.frames = vec![FrameData::new(0, 0.0, PerceptualHash::new(config, hash).unwrap())];
+            asset
+        };
+
+        let options = GroupingOptions {
+            hash_size: 16,
+            threshold_ratio: Some(0.1),
+            ..GroupingOptions::default()
+        };
+        options.validate().unwrap();
+
+        // 10% of 256 bits is 26, a fixed threshold of 15 would reject both
+        let base = hashed_256("base", 0);
+        let near = compare_hashed_assets(&base, &hashed_256("near", 20), &options);
+        let far = compare_hashed_assets(&base, &hashed_256("far", 30), &options);
+        assert_eq!(near.threshold, 26);
+        assert!(near.similar);
+        assert!(!far.similar);
+
+        for ratio in [0.0, 1.0, -0.5, f64::NAN] {
+            let invalid = GroupingOptions {
+                threshold_ratio: Some(ratio),
+                ..GroupingOptions::default()
+            };
+            assert!(invalid.validate().is_err(), "ratio {}", ratio);
+        }
+    }
+
+    #[test]
+    fn test_exact_duplicates_are_hashed_once_and_flagged() {
+        let gradient = image::RgbImage::from_fn(64, 64, |x, _| image::Rgb([(x * 4) as u8; 3]));
+        let inverted =
+            image::RgbImage::from_fn(64, 64, |x, _| image::Rgb([255 - (x * 4) as u8; 3]));
+
+        let original = png_asset("original", &gradient);
+        let copy = Asset {
+            id: "copy".to_string(),
+            name: "copy.png".to_string(),
+            ..original.clone()
+        };
+        let assets = vec![original, png_asset("other", &inverted), copy];
+
+        let options = GroupingOptions {
+            detect_exact_duplicates: true,
+            include_group_distances: true,
+            ..GroupingOptions::default()
+        };
+        let result = group_assets_by_visual_similarity(assets, Some(options), None, None).unwrap();
+
+        let group = result
+            .groups
+            .iter()
+            .find(|group| group.assets.iter().any(|member| member.asset.id == "original"))
+            .unwrap();
+        let ids: Vec<&str> = group.assets.iter().map(|member| member.asset.id.as_str()).collect();
+        assert_eq!(ids, ["copy", "original"]);
+        assert_eq!(group.exact_duplicates.get("copy").map(String::as_str), Some("original"));
+        assert_eq!(group.distances.as_ref().unwrap().get("copy|original"), Some(&0.0));
+
+        assert_eq!(result.hashes.len(), 3);
+    }
+
+    #[test]
+    fn test_declared_type_is_checked_against_content() {
+        let image = image::RgbImage::from_fn(32, 32, |x, _| image::Rgb([(x * 8) as u8; 3]));
+        let mislabelled = Asset {
+            mime_type: "video/mp4".to_string(),
+            is_video: true,
+            ..png_asset("still", &image)
+        };
+
+        let (hashed, _) = process_asset(&mislabelled, &GroupingOptions::default(), None).unwrap();
+        assert!(!hashed.asset.is_video);
+        assert_eq!(hashed.asset.mime_type, "image/png");
+        assert_eq!(hashed.frames.len(), 1);
+
+        let strict = GroupingOptions {
+            strict_type_check: true,
+            ..GroupingOptions::default()
+        };
+        let err = process_asset(&mislabelled, &strict, None).unwrap_err();
+        assert_eq!(err.to_string(), "Asset still declared video but contains an image");
+
+        let video = Asset {
+            source: AssetSource::Bytes(b"\0\0\0\x18ftypisom\0\0\0\0".to_vec()),
+            ..png_asset("clip", &image)
+        };
+        let err = process_asset(&video, &strict, None).unwrap_err();
+        assert_eq!(err.to_string(), "Asset clip declared image but contains a video stream");
+    }
+
+    #[test]
+    fn test_tiny_images_fail_with_their_size() {
+        let tracking_pixel = png_asset("pixel", &image::RgbImage::new(1, 1));
+        let sliver = png_asset("sliver", &image::RgbImage::new(1, 10000));
+
+        let err = process_asset(&tracking_pixel, &GroupingOptions::default(), None).unwrap_err();
+        assert_eq!(err.to_string(), "Asset is 1x1, smaller than the minimum of 16x16");
+        assert!(process_asset(&sliver, &GroupingOptions::default(), None).is_err());
+
+        let assets = vec![tracking_pixel, sliver.clone()];
+        let result = group_assets_by_visual_similarity(assets, None, None, None).unwrap();
+        assert!(result.groups.is_empty());
+        assert_eq!(result.failed.len(), 2);
+
+        let permissive = GroupingOptions {
+            min_width: 1,
+            min_height: 1,
+            ..GroupingOptions::default()
+        };
+        let (hashed, _) = process_asset(&sliver, &permissive, None).unwrap();
+        assert_eq!((hashed.width, hashed.height), (1, 10000));
+        assert!(hashed.aspect_ratio.is_finite());
+    }
+
+    fn path_asset(path: &std::path::Path) -> Asset {
+        Asset {
+            id: "poster".to_string(),
+            name: "poster".to_string(),
+            source: AssetSource::Path(path.to_path_buf()),
+            mime_type: "image/png".to_string(),
+            is_video: false,
+            precomputed: None,
+            kind: AssetKind::File,
+        }
+    }
+
+    #[test]
+    fn test_paths_with_spaces_and_backslashes() {
+        let temp_dir = TempDir::new().unwrap();
+        // A separator on Windows, part of the file name elsewhere
+        let path = temp_dir.path().join("Spring Sale").join(r"Media Files\hero banner.png");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, 0]))
+            .save(&path)
+            .unwrap();
+
+        let asset = path_asset(&path);
+        let (hashed, _) = process_asset(&asset, &GroupingOptions::default(), None).unwrap();
+        assert_eq!(hashed.asset.source.path(), Some(path.as_path()));
+
+        let windows_path = r"C:\Media Files\Spring Sale\hero banner.png";
+        let json = serde_json::to_string(&path_asset(windows_path.as_ref())).unwrap();
+        let parsed: Asset = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.source.path(), Some(std::path::Path::new(windows_path)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths_are_kept_verbatim() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(std::ffi::OsStr::from_bytes(b"caf\xe9 poster.png"));
+        image::RgbImage::from_fn(32, 32, |x, _| image::Rgb([(x * 8) as u8; 3]))
+            .save(&path)
+            .unwrap();
+
+        let scanned = scan_directory(temp_dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(scanned.len(), 1);
+        assert_eq!(scanned[0].source.path(), Some(path.as_path()));
+
+        let (hashed, _) = process_asset(&scanned[0], &GroupingOptions::default(), None).unwrap();
+        assert_eq!(hashed.asset.source.path(), Some(path.as_path()));
+        assert_eq!((hashed.width, hashed.height), (32, 32));
+    }
+
+    #[test]
+    fn test_find_duplicate_pairs_from_precomputed_hashes() {
+        let precomputed = |id: &str, hash: u64, size: u32| Asset {
+            precomputed: Some(PrecomputedHashes {
+                frames: vec![frame(0, hash)],
+                width: size,
+                height: size,
+                duration: None,
+                dominant_colors: Vec::new(),
+                audio_fingerprint: None,
+            }),
+            ..hashed_image(id, hash).asset
+        };
+        let assets = vec![
+            precomputed("small", 0, 100),
+            precomputed("large", 0, 200),
+            precomputed("near", 0b111, 100),
+            precomputed("far", u64::MAX, 100),
+        ];
+
+        let exact = find_duplicate_pairs(&assets, 0, None).unwrap();
+        assert_eq!(
+            exact,
+            [DuplicatePair {
+                asset_id_a: "small".to_string(),
+                asset_id_b: "large".to_string(),
+                distance: 0,
+                higher_resolution_asset_id: "large".to_string(),
+            }]
+        );
+
+        let near: Vec<(String, String, u32)> = find_duplicate_pairs(&assets, 3, None)
+            .unwrap()
+            .into_iter()
+            .map(|pair| (pair.asset_id_a, pair.asset_id_b, pair.distance))
+            .collect();
+        assert_eq!(
+            near,
+            [
+                ("small".to_string(), "large".to_string(), 0),
+                ("large".to_string(), "near".to_string(), 3),
+                ("small".to_string(), "near".to_string(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grouping_reports_stats() {
+        let gradient =
+            image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 0]));
+        let assets = vec![
+            png_asset("a", &gradient),
+            png_asset("b", &gradient),
+            png_asset("c", &image::imageops::flip_vertical(&gradient)),
+        ];
+
+        for max_concurrency in [Some(1), None] {
+            let options = GroupingOptions {
+                max_concurrency,
+                prefilter_slack: None,
+                ..GroupingOptions::default()
+            };
+            let stats = group_assets_by_visual_similarity(assets.clone(), Some(options), None, None)
+                .unwrap()
+                .stats;
+
+            assert_eq!(stats.comparisons, 3);
+            assert_eq!(stats.frames_extracted, 0);
+            assert_eq!(stats.cache_hits, 0);
+            assert!(stats.peak_workers >= 1);
+            if max_concurrency == Some(1) {
+                assert_eq!(stats.peak_workers, 1);
+            }
+            assert!(stats.hashing_ms > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_worker_pools_log_under_the_scope_of_their_job() {
+        let options = GroupingOptions {
+            max_concurrency: Some(2),
+            ..GroupingOptions::default()
+        };
+        let scopes = |scope| {
+            let previous = set_log_scope(scope);
+            let pool = thread_pool(&options).unwrap();
+            set_log_scope(previous);
+            pool.install(|| (0..8).into_par_iter().map(|_| log_scope()).collect::<Vec<_>>())
+        };
+
+        assert_eq!(scopes(Some(7)), [Some(7); 8]);
+        assert_eq!(scopes(None), [None; 8]);
+        assert_eq!(log_scope(), None);
+    }
+
+    #[test]
+    fn test_grouping_reports_per_asset_timings() {
+        let gradient =
+            image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 0]));
+        let mut broken = png_asset("broken", &gradient);
+        broken.source = AssetSource::Bytes(b"not an image".to_vec());
+        let assets = vec![png_asset("a", &gradient), png_asset("b", &gradient), broken];
+
+        let options = GroupingOptions {
+            slow_asset_threshold_ms: Some(0),
+            ..GroupingOptions::default()
+        };
+        let result = group_assets_by_visual_similarity(assets, Some(options), None, None).unwrap();
+
+        // Failed assets are timed too
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.timings.keys().collect::<Vec<_>>(), ["a", "b", "broken"]);
+        for timings in result.timings.values() {
+            assert!(timings.total_ms > 0.0);
+            let parts = timings.probe_ms + timings.extraction_ms + timings.hashing_ms;
+            assert!((parts - timings.total_ms).abs() < 1e-3);
+        }
+        let probe_ms: f64 = result.timings.values().map(|timings| timings.probe_ms).sum();
+        assert!((probe_ms - result.stats.probe_ms).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_invalid_inputs_are_rejected_and_same_paths_merged() {
+        let gradient =
+            image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 0]));
+        let mut no_path = png_asset("no_path", &gradient);
+        no_path.source = AssetSource::Path(PathBuf::new());
+        let assets = vec![
+            png_asset("a", &gradient),
+            png_asset("", &gradient),
+            png_asset("a", &gradient),
+            no_path,
+            png_asset("b", &gradient),
+            png_asset("b", &gradient),
+        ];
+
+        // Every offending entry is named, not just the first
+        let err = group_assets_by_visual_similarity(assets, None, None, None).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("asset 1 has an empty id"), "{}", message);
+        assert!(message.contains("asset 3 (no_path) has an empty path"), "{}", message);
+        assert!(message.contains("id a is used by assets 0, 2"), "{}", message);
+        assert!(message.contains("id b is used by assets 4, 5"), "{}", message);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        gradient.save(dir.path().join("a.png")).unwrap();
+        let path_asset = |id: &str, path: PathBuf| Asset {
+            source: AssetSource::Path(path),
+            ..png_asset(id, &gradient)
+        };
+        let assets = vec![
+            path_asset("first", dir.path().join("a.png")),
+            path_asset("second", dir.path().join(".").join("a.png")),
+            path_asset("missing", dir.path().join("missing.png")),
+            path_asset("missing_again", dir.path().join("missing.png")),
+            png_asset("bytes", &gradient),
+        ];
+        let options = GroupingOptions {
+            dedupe_by_path: true,
+            ..GroupingOptions::default()
+        };
+        let result = group_assets_by_visual_similarity(assets, Some(options), None, None).unwrap();
+
+        assert_eq!(result.groups.len(), 1);
+        let members = &result.groups[0].assets;
+        let ids: Vec<&str> = members.iter().map(|member| member.asset.id.as_str()).collect();
+        assert_eq!(ids, ["bytes", "first"]);
+        assert_eq!(members[1].alias_ids, ["second"]);
+        assert!(members[0].alias_ids.is_empty());
+
+        // Merged ids share the hashes and failures of the asset kept for them
+        let mut hashed: Vec<&str> = result.hashes.iter().map(|h| h.asset_id.as_str()).collect();
+        hashed.sort();
+        assert_eq!(hashed, ["bytes", "first", "second"]);
+        let mut failed: Vec<&str> = result.failed.iter().map(|f| f.asset_id.as_str()).collect();
+        failed.sort();
+        assert_eq!(failed, ["missing", "missing_again"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_files_are_reported_before_hashing() {
+        let gradient =
+            image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 0]));
+        let dir = tempfile::TempDir::new().unwrap();
+        gradient.save(dir.path().join("good.png")).unwrap();
+        std::fs::write(dir.path().join("empty.png"), b"").unwrap();
+        let path_asset = |id: &str, file: &str| Asset {
+            source: AssetSource::Path(dir.path().join(file)),
+            ..png_asset(id, &gradient)
+        };
+        std::os::unix::fs::symlink(dir.path().join("gone.png"), dir.path().join("link.png"))
+            .unwrap();
+        let assets = vec![
+            path_asset("good", "good.png"),
+            path_asset("missing", "missing.png"),
+            path_asset("empty", "empty.png"),
+            Asset {
+                kind: AssetKind::ImageSequence { pattern: None },
+                ..path_asset("sequence", "good.png")
+            },
+            path_asset("link", "link.png"),
+        ];
+
+        let result = group_assets_by_visual_similarity(assets.clone(), None, None, None).unwrap();
+        let reason = |id: &str| {
+            let failure = result.failed.iter().find(|failure| failure.asset_id == id);
+            failure.map(|failure| failure.reason.clone()).unwrap_or_default()
+        };
+        assert!(reason("missing").ends_with("missing.png does not exist"), "{}", reason("missing"));
+        assert!(reason("empty").ends_with("empty.png is empty"), "{}", reason("empty"));
+        assert!(reason("sequence").contains("is not a directory"), "{}", reason("sequence"));
+        assert!(reason("link").contains("link.png is a broken symlink to"), "{}", reason("link"));
+        assert_eq!(result.failed.len(), 4);
+        assert_eq!(result.hashes.len(), 1);
+
+        // Fail fast names every unreadable asset at once
+        let options = GroupingOptions {
+            fail_fast: true,
+            ..GroupingOptions::default()
+        };
+        let err = group_assets_by_visual_similarity(assets, Some(options), None, None)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("4 assets cannot be read"), "{}", err);
+        assert!(err.contains("missing: ") && err.contains("empty: "), "{}", err);
+    }
+
+    #[test]
+    fn test_similarity_graph_edges_follow_groups() {
+        let gradient =
+            image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 0]));
+        let assets = vec![
+            png_asset("a", &gradient),
+            png_asset("b", &gradient),
+            png_asset("c", &image::imageops::rotate180(&gradient)),
+        ];
+
+        let graph = export_similarity_graph(assets.clone(), None, None).unwrap();
+        let ids: Vec<&str> = graph.nodes.iter().map(|node| node.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(
+            graph.edges,
+            [GraphEdge {
+                source: "a".to_string(),
+                target: "b".to_string(),
+                distance: 0.0,
+            }]
+        );
+
+        let groups = group_assets_by_visual_similarity(assets.clone(), None, None, None).unwrap();
+        let sizes: Vec<usize> = groups.groups.iter().map(|group| group.assets.len()).collect();
+        assert_eq!(sizes, [2, 1]);
+
+        // Every pair is linked once the cutoff covers all the hash bits
+        let everything = export_similarity_graph(assets, Some(65), None).unwrap();
+        assert_eq!(everything.edges.len(), 3);
+    }
+
+    #[test]
+    fn test_otsu_threshold_splits_at_the_valley() {
+        // Re-encodes 2-3 bits apart, unrelated creatives 30-34 bits apart
+        let mut bimodal = vec![0; 65];
+        bimodal[2..4].fill(5);
+        bimodal[30..35].fill(20);
+        let (threshold, confidence) = otsu_threshold(&bimodal).unwrap();
+        assert_eq!(threshold, 17);
+        assert!(confidence > MIN_SUGGESTION_CONFIDENCE);
+
+        // Unrelated creatives only, binomially spread around 32 bits
+        let unimodal: Vec<usize> = (0..=64u32)
+            .map(|d| (0..d).fold(8000.0, |count, k| count * (64 - k) as f64 / (k + 1) as f64))
+            .map(|count: f64| (count / 2f64.powi(64)).round() as usize)
+            .collect();
+        let (_, confidence) = otsu_threshold(&unimodal).unwrap();
+        assert!(confidence < MIN_SUGGESTION_CONFIDENCE);
+
+        assert!(otsu_threshold(&[0, 4, 0]).is_none());
+
+        let empty = suggest_threshold(Vec::new(), None).unwrap();
+        assert_eq!((empty.threshold, empty.confidence), (15, 0.0));
+        assert_eq!(empty.histogram.len(), 65);
+    }
 }
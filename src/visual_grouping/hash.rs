@@ -1,60 +1,670 @@
 use anyhow::{Context, Result};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use img_hash::{HashAlg, HasherConfig, image as img_hash_image};
+use serde::{Deserialize, Serialize};
 
 use std::path::Path;
 
-/// Resize image to standard dimensions for comparison
-/// Uses "Cover" to fill the entire frame, cropping the edges as needed.
-/// This focuses on the central content which is most likely to be consistent
-/// across different sizes and aspect ratios of the same creative
+use super::blurhash::{self, DEFAULT_COMPONENTS};
+use super::{HashAlgorithm, HashConfig, Invariance, ResizeStrategy};
+use super::image_info::{
+    decode_image_bytes_with_ffmpeg, decode_image_with_ffmpeg, describe_image_format,
+    image_orientation, image_orientation_from_bytes, sniff_ffmpeg_image_file,
+    sniff_ffmpeg_image_format,
+};
+
+/// Perceptual hash bytes together with the algorithm and grid size that produced them
+/// Deserializing checks the byte count against the grid, like `from_hex`.
+/// The bytes are serialized as base64
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawPerceptualHash")]
+pub struct PerceptualHash {
+    pub algorithm: HashAlgorithm,
+    pub width: u8,
+    pub height: u8,
+    #[serde(serialize_with = "base64_bytes::serialize")]
+    pub bytes: Vec<u8>,
+}
+
+/// Unchecked serde form of `PerceptualHash`
+#[derive(Deserialize)]
+struct RawPerceptualHash {
+    algorithm: HashAlgorithm,
+    width: u8,
+    height: u8,
+    #[serde(deserialize_with = "base64_bytes::deserialize")]
+    bytes: Vec<u8>,
+}
+
+/// Serde helper writing bytes as standard padded base64
+/// Reading also takes a plain byte array, the form written by older versions
+mod base64_bytes {
+    use base64::Engine as _;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::BASE64;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Encoded {
+            Base64(String),
+            Bytes(Vec<u8>),
+        }
+
+        match Encoded::deserialize(deserializer)? {
+            Encoded::Base64(encoded) => BASE64
+                .decode(encoded)
+                .map_err(|err| D::Error::custom(format!("Invalid base64 encoding: {}", err))),
+            Encoded::Bytes(bytes) => Ok(bytes),
+        }
+    }
+}
+
+impl TryFrom<RawPerceptualHash> for PerceptualHash {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawPerceptualHash) -> Result<Self> {
+        let config = HashConfig::new(raw.algorithm, raw.width as u32, raw.height as u32);
+        PerceptualHash::new(config, raw.bytes)
+    }
+}
+
+impl PerceptualHash {
+    /// Wrap hash bytes produced with `config`
+    /// Fails when the number of bytes doesn't match the grid size
+    pub fn new(config: HashConfig, bytes: Vec<u8>) -> Result<Self> {
+        let (Ok(width), Ok(height)) = (u8::try_from(config.width), u8::try_from(config.height))
+        else {
+            anyhow::bail!("Hash grid {}x{} is too large", config.width, config.height);
+        };
+
+        let expected = (config.bits() as usize).div_ceil(8);
+        if bytes.len() != expected {
+            anyhow::bail!(
+                "Invalid hash length: a {} hash has {} bytes, got {}",
+                config,
+                expected,
+                bytes.len()
+            );
+        }
+
+        Ok(PerceptualHash {
+            algorithm: config.algorithm,
+            width,
+            height,
+            bytes,
+        })
+    }
+
+    /// Parse a hash produced with `config` from hexadecimal
+    pub fn from_hex(config: HashConfig, hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        if !hex.len().is_multiple_of(2) {
+            anyhow::bail!("Invalid hex encoding: odd number of digits");
+        }
+
+        let bytes = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .with_context(|| {
+                        format!("Invalid hex encoding: {:?}", String::from_utf8_lossy(pair))
+                    })
+            })
+            .collect::<Result<Vec<u8>>>()?;
+
+        PerceptualHash::new(config, bytes)
+    }
+
+    /// Parse a hash produced with `config` from standard padded base64
+    pub fn from_base64(config: HashConfig, encoded: &str) -> Result<Self> {
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid base64 encoding: {}", e))?;
+
+        PerceptualHash::new(config, bytes)
+    }
+
+    /// Lowercase hexadecimal form of the hash bytes
+    pub fn to_hex(&self) -> String {
+        self.bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Standard padded base64 form of the hash bytes
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(&self.bytes)
+    }
+
+    /// Algorithm and grid size the hash was produced with, preprocessing
+    /// isn't recorded
+    pub fn config(&self) -> HashConfig {
+        HashConfig::new(self.algorithm, self.width as u32, self.height as u32)
+    }
+
+    /// Number of bits in the hash
+    pub fn bits(&self) -> u32 {
+        self.config().bits()
+    }
+
+    /// Hamming distance to another hash
+    /// Fails for hashes produced with different algorithms or sizes, which
+    /// would give a meaningless distance
+    pub fn distance(&self, other: &PerceptualHash) -> Result<u32> {
+        self.check_comparable(other)?;
+        hamming_distance(&self.bytes, &other.bytes)
+    }
+
+    /// Fail unless both hashes were produced with the same config
+    pub fn check_comparable(&self, other: &PerceptualHash) -> Result<()> {
+        if self.config() != other.config() {
+            anyhow::bail!("Cannot compare a {} hash with a {} hash", self.config(), other.config());
+        }
+        Ok(())
+    }
+}
+
+/// Cells along the longer side of the crop-resistant region grid
+const REGION_GRID: u32 = 3;
+
+/// Hashes computed next to the primary hash of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct HashExtras {
+    /// Mirrored and rotated variants to hash
+    pub invariance: Invariance,
+    /// Hash the cells of the region grid for crop-resistant matching
+    pub regions: bool,
+    /// Encode a BlurHash placeholder of the resized image
+    #[serde(default)]
+    pub blurhash: bool,
+    /// Number of dominant colors to extract from the resized image, none when 0
+    #[serde(default)]
+    pub dominant_colors: usize,
+}
+
+/// Hash of an image plus the extra hashes asked for by `HashExtras`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageHashes {
+    pub hash: PerceptualHash,
+    /// Empty unless invariance is enabled
+    pub variants: Vec<PerceptualHash>,
+    /// Empty unless region hashes were asked for
+    pub regions: Vec<PerceptualHash>,
+    /// `None` unless a BlurHash was asked for
+    pub blurhash: Option<String>,
+    /// Most common colors first, empty unless dominant colors were asked for
+    pub dominant_colors: Vec<[u8; 3]>,
+}
+
+/// Resize image to the square of `config.target_size` it is hashed from
+/// "Cover" fills the entire square, cropping the edges as needed. This focuses
+/// on the central content which is most likely to be consistent across
+/// different sizes and aspect ratios of the same creative. "Contain" keeps the
+/// edges and pads with `config.alpha_background`, "Stretch" keeps everything
+/// at the cost of the aspect ratio. An empty image gives a square of
+/// `config.alpha_background`
 pub fn resize_for_comparison(
     img: &img_hash_image::DynamicImage,
+    config: &HashConfig,
 ) -> img_hash_image::ImageBuffer<img_hash_image::Rgba<u8>, Vec<u8>> {
     use img_hash_image::GenericImageView;
+    use img_hash_image::imageops::FilterType;
     let (width, height) = img.dimensions();
 
-    let target_size = 256u32;
+    let target_size = config.target_size;
+    let [red, green, blue] = config.alpha_background;
+    let background = img_hash_image::Rgba([red, green, blue, 255]);
+
+    if width == 0 || height == 0 {
+        return img_hash_image::RgbaImage::from_pixel(target_size, target_size, background);
+    }
+
+    match config.resize {
+        ResizeStrategy::Cover => {
+            let aspect_ratio = width as f64 / height as f64;
+            let target_aspect = 1.0;
 
-    let aspect_ratio = width as f64 / height as f64;
-    let target_aspect = 1.0;
+            let (crop_width, crop_height) = if aspect_ratio > target_aspect {
+                let new_width = (height as f64 * target_aspect) as u32;
+                (new_width.clamp(1, width), height)
+            } else {
+                let new_height = (width as f64 * target_aspect) as u32;
+                (width, new_height.clamp(1, height))
+            };
 
-    let (crop_width, crop_height) = if aspect_ratio > target_aspect {
-        let new_width = (height as f64 * target_aspect) as u32;
-        (new_width, height)
+            let x = (width - crop_width) / 2;
+            let y = (height - crop_height) / 2;
+
+            // crop and resize
+            let cropped = img.crop_imm(x, y, crop_width, crop_height);
+            let resize = cropped.resize_exact(target_size, target_size, FilterType::Lanczos3);
+
+            resize.to_rgba8()
+        }
+        ResizeStrategy::Contain => {
+            let resized = img.resize(target_size, target_size, FilterType::Lanczos3).to_rgba8();
+
+            let mut canvas =
+                img_hash_image::RgbaImage::from_pixel(target_size, target_size, background);
+            let left = (target_size - resized.width().min(target_size)) / 2;
+            let top = (target_size - resized.height().min(target_size)) / 2;
+            for (x, y, pixel) in resized.enumerate_pixels() {
+                if x + left < target_size && y + top < target_size {
+                    canvas.put_pixel(x + left, y + top, *pixel);
+                }
+            }
+
+            canvas
+        }
+        ResizeStrategy::Stretch => img
+            .resize_exact(target_size, target_size, FilterType::Lanczos3)
+            .to_rgba8(),
+    }
+}
+
+fn to_hash_alg(algorithm: HashAlgorithm) -> HashAlg {
+    match algorithm {
+        // pHash is the mean hash over DCT coefficients
+        HashAlgorithm::Dct => HashAlg::Mean,
+        HashAlgorithm::Blockhash => HashAlg::Blockhash,
+        HashAlgorithm::Mean => HashAlg::Mean,
+        HashAlgorithm::Gradient => HashAlg::Gradient,
+        HashAlgorithm::DoubleGradient => HashAlg::DoubleGradient,
+        HashAlgorithm::VerticalGradient => HashAlg::VertGradient,
+    }
+}
+
+/// Decode an image file upright
+/// HEIF and AVIF files are decoded through ffmpeg, and the EXIF orientation is
+/// applied so a camera original comes out like its upright export
+pub fn decode_image<P: AsRef<Path>>(image_path: P) -> Result<image::DynamicImage> {
+    if let Some(format) = sniff_ffmpeg_image_file(&image_path) {
+        let img = decode_image_with_ffmpeg(&image_path)
+            .with_context(|| format!("Failed to decode {} image", format))?;
+        return Ok(image::DynamicImage::ImageRgb8(img));
+    }
+
+    let mut img = image::open(image_path.as_ref()).with_context(|| {
+        format!("Failed to open image ({})", describe_image_format(&image_path))
+    })?;
+    img.apply_orientation(image_orientation(&image_path));
+
+    Ok(img)
+}
+
+/// Decode an encoded image held in memory upright, like `decode_image`
+pub fn decode_image_bytes(bytes: &[u8]) -> Result<image::DynamicImage> {
+    if let Some(format) = sniff_ffmpeg_image_format(bytes) {
+        let img = decode_image_bytes_with_ffmpeg(bytes)
+            .with_context(|| format!("Failed to decode {} image", format))?;
+        return Ok(image::DynamicImage::ImageRgb8(img));
+    }
+
+    let mut img = image::load_from_memory(bytes).with_context(|| {
+        let format = image::guess_format(bytes)
+            .map_or_else(|_| "unrecognized format".to_string(), |format| format!("{:?}", format));
+        format!("Failed to decode image data ({})", format)
+    })?;
+    img.apply_orientation(image_orientation_from_bytes(bytes));
+
+    Ok(img)
+}
+
+/// Generate a perceptual hash of an image file with the algorithm and size of `config`,
+/// plus the extra hashes asked for by `extras`
+pub fn generate_perceptual_hash<P: AsRef<Path>>(
+    image_path: P,
+    config: &HashConfig,
+    extras: HashExtras,
+) -> Result<ImageHashes> {
+    generate_perceptual_hash_from_image(&decode_image(image_path)?, config, extras)
+}
+
+/// Generate a perceptual hash of an encoded image held in memory, such as an
+/// upload a server hasn't written to disk
+pub fn generate_perceptual_hash_from_bytes(
+    bytes: &[u8],
+    config: &HashConfig,
+    extras: HashExtras,
+) -> Result<ImageHashes> {
+    generate_perceptual_hash_from_image(&decode_image_bytes(bytes)?, config, extras)
+}
+
+/// Generate a perceptual hash of an already decoded image, such as a video
+/// frame, without encoding it to a file first
+pub fn generate_perceptual_hash_from_image(
+    img: &image::DynamicImage,
+    config: &HashConfig,
+    extras: HashExtras,
+) -> Result<ImageHashes> {
+    let img = to_img_hash_image(img)?;
+
+    hash_image(&img, config, extras)
+}
+
+/// Convert to the older `image` version `img_hash` is built against
+/// Goes through the raw pixel buffer, so pixels are the same as decoding the
+/// image from a lossless file
+fn to_img_hash_image(img: &image::DynamicImage) -> Result<img_hash_image::DynamicImage> {
+    let (width, height) = (img.width(), img.height());
+
+    let converted = if img.color().has_alpha() {
+        img_hash_image::RgbaImage::from_raw(width, height, img.to_rgba8().into_raw())
+            .map(img_hash_image::DynamicImage::ImageRgba8)
+    } else {
+        img_hash_image::RgbImage::from_raw(width, height, img.to_rgb8().into_raw())
+            .map(img_hash_image::DynamicImage::ImageRgb8)
+    };
+
+    converted.context("Failed to convert image for hashing")
+}
+
+fn hash_image(
+    img: &img_hash_image::DynamicImage,
+    config: &HashConfig,
+    extras: HashExtras,
+) -> Result<ImageHashes> {
+    use img_hash_image::{GenericImageView, imageops};
+
+    if img.width() == 0 || img.height() == 0 {
+        anyhow::bail!("Can't hash an empty image");
+    }
+
+    let composited = composite_on_background(img, config.alpha_background);
+    let img = composited.as_ref().unwrap_or(img);
+    let colored = img;
+
+    let normalized;
+    let img = if config.normalize {
+        let mut luma = img.to_luma8();
+        equalize_histogram(&mut luma);
+        normalized = img_hash_image::DynamicImage::ImageLuma8(luma);
+        &normalized
+    } else {
+        img
+    };
+
+    let resized = resize_for_comparison(img, config);
+
+    // An equalized image is grey, placeholders and palettes keep the colors
+    let recolored;
+    let colored = if config.normalize && (extras.blurhash || extras.dominant_colors > 0) {
+        recolored = resize_for_comparison(colored, config);
+        &recolored
     } else {
-        let new_height = (width as f64 * target_aspect) as u32;
-        (width, new_height)
+        &resized
     };
+    let (width, height) = (colored.width(), colored.height());
 
-    let x = (width - crop_width) / 2;
-    let y = (height - crop_height) / 2;
+    let blurhash = if extras.blurhash {
+        Some(blurhash::encode(DEFAULT_COMPONENTS, width, height, colored.as_raw())?)
+    } else {
+        None
+    };
+
+    let dominant_colors = if extras.dominant_colors > 0 {
+        let colored = image::RgbaImage::from_raw(width, height, colored.as_raw().clone())
+            .context("Failed to convert image for color extraction")?;
+        extract_dominant_colors(&image::DynamicImage::ImageRgba8(colored), extras.dominant_colors)
+    } else {
+        Vec::new()
+    };
 
-    // crop and resize
-    let cropped = img.crop_imm(x, y, crop_width, crop_height);
-    let resize = cropped.resize_exact(
-        target_size,
-        target_size,
-        img_hash_image::imageops::FilterType::Lanczos3,
-    );
+    // Every resize strategy commutes with flips and quarter turns, so the
+    // variants are made from the small resized image
+    let variants = match extras.invariance {
+        Invariance::None => Vec::new(),
+        Invariance::Mirror => vec![imageops::flip_horizontal(&resized)],
+        Invariance::Rotation => vec![
+            imageops::flip_horizontal(&resized),
+            imageops::rotate90(&resized),
+            imageops::rotate180(&resized),
+            imageops::rotate270(&resized),
+        ],
+    };
 
-    resize.to_rgba8()
+    let mut hasher_config = HasherConfig::new()
+        .hash_alg(to_hash_alg(config.algorithm))
+        .hash_size(config.width, config.height);
+    if config.algorithm == HashAlgorithm::Dct {
+        hasher_config = hasher_config.preproc_dct();
+    }
+    let hasher = hasher_config.to_hasher();
+
+    let hash = |img| {
+        let hash = hasher.hash_image(&img_hash_image::DynamicImage::ImageRgba8(img));
+        PerceptualHash::new(*config, hash.as_bytes().to_vec())
+    };
+
+    let regions = if extras.regions {
+        region_grid(img.width(), img.height())
+            .into_iter()
+            .map(|(x, y, width, height)| {
+                hash(resize_for_comparison(&img.crop_imm(x, y, width, height), config))
+            })
+            .collect::<Result<_>>()?
+    } else {
+        Vec::new()
+    };
+
+    Ok(ImageHashes {
+        hash: hash(resized)?,
+        variants: variants.into_iter().map(hash).collect::<Result<_>>()?,
+        regions,
+        blurhash,
+        dominant_colors,
+    })
+}
+
+/// Rounds of k-means refinement in `extract_dominant_colors`
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Up to `k` dominant colors of an image, the most common first
+/// k-means over the pixels, seeded with the mean color and then the pixel
+/// farthest from every seed so far, so small distinct clusters get a seed and
+/// the same image always gives the same colors. Images with fewer distinct
+/// colors give fewer colors. Meant for small images such as the resized
+/// comparison image
+pub fn extract_dominant_colors(img: &image::DynamicImage, k: usize) -> Vec<[u8; 3]> {
+    let pixels: Vec<[f32; 3]> =
+        img.to_rgb8().pixels().map(|pixel| pixel.0.map(f32::from)).collect();
+    if pixels.is_empty() || k == 0 {
+        return Vec::new();
+    }
+
+    let squared_distance = |a: &[f32; 3], b: &[f32; 3]| -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+    };
+    let nearest = |centers: &[[f32; 3]], pixel: &[f32; 3]| -> (usize, f32) {
+        centers
+            .iter()
+            .map(|center| squared_distance(center, pixel))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0))
+    };
+
+    let mut mean = [0.0f64; 3];
+    for pixel in &pixels {
+        for (sum, &value) in mean.iter_mut().zip(pixel) {
+            *sum += value as f64;
+        }
+    }
+    let mut centers = vec![mean.map(|sum| (sum / pixels.len() as f64) as f32)];
+    while centers.len() < k {
+        let farthest = pixels
+            .iter()
+            .map(|pixel| (pixel, nearest(&centers, pixel).1))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match farthest {
+            Some((&pixel, distance)) if distance > 0.0 => centers.push(pixel),
+            _ => break,
+        }
+    }
+
+    let mut assignments = vec![usize::MAX; pixels.len()];
+    let mut counts = vec![0usize; centers.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        let mut sums = vec![[0.0f64; 3]; centers.len()];
+        counts.fill(0);
+
+        for (pixel, assignment) in pixels.iter().zip(&mut assignments) {
+            let (cluster, _) = nearest(&centers, pixel);
+            changed |= *assignment != cluster;
+            *assignment = cluster;
+            counts[cluster] += 1;
+            for (sum, &value) in sums[cluster].iter_mut().zip(pixel) {
+                *sum += value as f64;
+            }
+        }
+
+        for ((center, sum), &count) in centers.iter_mut().zip(&sums).zip(&counts) {
+            if count > 0 {
+                *center = sum.map(|total| (total / count as f64) as f32);
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let mut clusters: Vec<([f32; 3], usize)> = centers
+        .into_iter()
+        .zip(counts)
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    clusters.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    clusters
+        .into_iter()
+        .map(|(center, _)| center.map(|value| value.round().clamp(0.0, 255.0) as u8))
+        .collect()
+}
+
+/// Mean distance from each color of one palette to the closest color of the
+/// other, both ways, scaled to [0, 1]. `None` when either palette is empty
+pub fn palette_distance(colors1: &[[u8; 3]], colors2: &[[u8; 3]]) -> Option<f64> {
+    if colors1.is_empty() || colors2.is_empty() {
+        return None;
+    }
+
+    let distance = |a: &[u8; 3], b: &[u8; 3]| -> f64 {
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    };
+    let one_way = |from: &[[u8; 3]], to: &[[u8; 3]]| -> f64 {
+        from.iter()
+            .map(|a| to.iter().map(|b| distance(a, b)).fold(f64::INFINITY, f64::min))
+            .sum::<f64>()
+            / from.len() as f64
+    };
+
+    let max_distance = (3.0 * 255.0 * 255.0_f64).sqrt();
+    Some((one_way(colors1, colors2) + one_way(colors2, colors1)) / 2.0 / max_distance)
+}
+
+/// Blend an image with transparency over `background`, so only visible pixels
+/// affect the hash. `None` for images without an alpha channel
+fn composite_on_background(
+    img: &img_hash_image::DynamicImage,
+    background: [u8; 3],
+) -> Option<img_hash_image::DynamicImage> {
+    if !img.color().has_alpha() {
+        return None;
+    }
+
+    let rgba = img.to_rgba8();
+    let composited = img_hash_image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [red, green, blue, alpha] = rgba.get_pixel(x, y).0;
+        let alpha = alpha as u32;
+        let blend = |channel: u8, background: u8| {
+            ((channel as u32 * alpha + background as u32 * (255 - alpha) + 127) / 255) as u8
+        };
+        img_hash_image::Rgb([
+            blend(red, background[0]),
+            blend(green, background[1]),
+            blend(blue, background[2]),
+        ])
+    });
+
+    Some(img_hash_image::DynamicImage::ImageRgb8(composited))
 }
 
-pub fn generate_perceptual_hash<P: AsRef<Path>>(image_path: P) -> Result<Vec<u8>> {
-    let img = img_hash_image::open(image_path.as_ref()).context("Failed to open image")?;
+/// Spread the luma values over the full range by their cumulative histogram
+/// Any brightness or contrast change that keeps the order of the values maps
+/// to the same image
+fn equalize_histogram(luma: &mut img_hash_image::GrayImage) {
+    let mut histogram = [0u64; 256];
+    for pixel in luma.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let mut cumulative = [0u64; 256];
+    let mut total = 0;
+    for (value, count) in histogram.iter().enumerate() {
+        total += count;
+        cumulative[value] = total;
+    }
+
+    // Pixels at the darkest value map to 0, a flat image has nothing to spread
+    let darkest = histogram.iter().copied().find(|&count| count > 0).unwrap_or(0);
+    if total == darkest {
+        return;
+    }
+
+    let mut lookup = [0u8; 256];
+    for (value, mapped) in lookup.iter_mut().enumerate() {
+        let below = cumulative[value].saturating_sub(darkest);
+        *mapped = (below * 255 / (total - darkest)) as u8;
+    }
 
-    let resized = resize_for_comparison(&img);
+    for pixel in luma.pixels_mut() {
+        pixel.0[0] = lookup[pixel.0[0] as usize];
+    }
+}
 
-    let dynamic_img = img_hash_image::DynamicImage::ImageRgba8(resized);
+/// Cells of the crop-resistant region grid as `(x, y, width, height)`
+/// Cells are squares a third of the longer side wide, centered on the image,
+/// so the cells of a centered crop line up with cells of the original at the
+/// same scale. Sides shorter than a cell get a single clipped row or column
+fn region_grid(width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+    let side = (width.max(height) / REGION_GRID).max(1);
+    let columns = (width / side).clamp(1, REGION_GRID);
+    let rows = (height / side).clamp(1, REGION_GRID);
+    let left = width.saturating_sub(columns * side) / 2;
+    let top = height.saturating_sub(rows * side) / 2;
 
-    let hasher = HasherConfig::new()
-        .hash_alg(HashAlg::Blockhash)
-        .hash_size(8, 8)
-        .to_hasher();
+    (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (row, column)))
+        .map(|(row, column)| {
+            (left + column * side, top + row * side, side.min(width), side.min(height))
+        })
+        .collect()
+}
 
-    let hash = hasher.hash_image(&dynamic_img);
+/// Pack an 8 byte hash into a u64 for the fast distance path
+pub fn pack_hash(hash: &[u8]) -> Option<u64> {
+    let bytes: [u8; 8] = hash.try_into().ok()?;
+    Some(u64::from_be_bytes(bytes))
+}
 
-    Ok(hash.as_bytes().to_vec())
+/// Hamming distance between two packed 64 bit hashes
+#[inline]
+pub fn hamming_distance_u64(hash1: u64, hash2: u64) -> u32 {
+    (hash1 ^ hash2).count_ones()
 }
 
 pub fn hamming_distance(hash1: &[u8], hash2: &[u8]) -> Result<u32> {
@@ -74,6 +684,7 @@ pub fn hamming_distance(hash1: &[u8], hash2: &[u8]) -> Result<u32> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::visual_grouping::image_info::get_image_dimensions;
 
     #[test]
     fn test_hamming_distance() {
@@ -84,5 +695,252 @@ mod tests {
         let hash3 = vec![0b11110000, 0b00000000];
         let hash4 = vec![0b00001111, 0b11111111];
         assert_eq!(hamming_distance(&hash3, &hash4).unwrap(), 16);
+
+        let packed1 = pack_hash(&[0xFF, 0, 0, 0, 0, 0, 0, 0x0F]).unwrap();
+        let packed2 = pack_hash(&[0; 8]).unwrap();
+        assert_eq!(hamming_distance_u64(packed1, packed2), 12);
+        assert_eq!(pack_hash(&hash1), None);
+    }
+
+    #[test]
+    fn test_resize_strategies() {
+        // White 200x100 image with red corners and a green center
+        let img = img_hash_image::RgbImage::from_fn(200, 100, |x, y| {
+            let corner = !(20..180).contains(&x) && !(20..80).contains(&y);
+            let center = (90..110).contains(&x) && (40..60).contains(&y);
+            if corner {
+                img_hash_image::Rgb([255, 0, 0])
+            } else if center {
+                img_hash_image::Rgb([0, 255, 0])
+            } else {
+                img_hash_image::Rgb([255, 255, 255])
+            }
+        });
+        let img = img_hash_image::DynamicImage::ImageRgb8(img);
+
+        let is_red = |pixel: &img_hash_image::Rgba<u8>| pixel.0[0] > 200 && pixel.0[1] < 80;
+        let is_green = |pixel: &img_hash_image::Rgba<u8>| pixel.0[1] > 200 && pixel.0[0] < 80;
+        let resize = |resize| {
+            let config = HashConfig {
+                resize,
+                target_size: 64,
+                ..HashConfig::default()
+            };
+            let resized = resize_for_comparison(&img, &config);
+            assert_eq!(resized.dimensions(), (64, 64), "{:?}", resize);
+            assert!(is_green(resized.get_pixel(32, 32)), "{:?} center", resize);
+            resized
+        };
+
+        // Cover crops the sides away, the corners of the square are inside the image
+        let cover = resize(ResizeStrategy::Cover);
+        assert!(!is_red(cover.get_pixel(2, 2)));
+
+        // Contain fits the 64x32 image between white bars
+        let contain = resize(ResizeStrategy::Contain);
+        assert!(is_red(contain.get_pixel(2, 18)));
+        assert!(is_red(contain.get_pixel(61, 45)));
+        assert_eq!(contain.get_pixel(2, 2).0, [255, 255, 255, 255]);
+
+        let stretch = resize(ResizeStrategy::Stretch);
+        assert!(is_red(stretch.get_pixel(2, 2)));
+        assert!(is_red(stretch.get_pixel(61, 61)));
+    }
+
+    #[test]
+    fn test_degenerate_images_never_reach_the_crop() {
+        let config = HashConfig::default();
+        for resize in [ResizeStrategy::Cover, ResizeStrategy::Contain, ResizeStrategy::Stretch] {
+            let config = HashConfig { resize, ..config };
+            for (width, height) in [(0, 0), (0, 10), (1, 1), (1, 10000), (10000, 1)] {
+                let img = img_hash_image::DynamicImage::new_rgb8(width, height);
+                let resized = resize_for_comparison(&img, &config);
+                let side = config.target_size;
+                assert_eq!(resized.dimensions(), (side, side), "{:?} {}x{}", resize, width, height);
+            }
+        }
+
+        let hash = |width, height| {
+            let img = image::DynamicImage::new_rgb8(width, height);
+            generate_perceptual_hash_from_image(&img, &config, HashExtras::default())
+        };
+        assert!(hash(0, 0).is_err());
+        assert!(hash(1, 10000).is_ok());
+    }
+
+    #[test]
+    fn test_region_grid() {
+        let square = region_grid(240, 240);
+        assert_eq!(square.len(), 9);
+        assert_eq!(square[4], (80, 80, 80, 80));
+
+        // A 16:9 center crop gets the middle row, 27 + 52 = 79 in the original
+        let banner = vec![(0, 27, 80, 80), (80, 27, 80, 80), (160, 27, 80, 80)];
+        assert_eq!(region_grid(240, 135), banner);
+        let strip = vec![(0, 0, 100, 50), (100, 0, 100, 50), (200, 0, 100, 50)];
+        assert_eq!(region_grid(300, 50), strip);
+    }
+
+    #[test]
+    fn test_perceptual_hash_encodings() {
+        let config = HashConfig::square(HashAlgorithm::Gradient, 8);
+        let hash = PerceptualHash::new(config, vec![0x00, 0x01, 0x7f, 0x80, 0xab, 0xcd, 0xef, 0xff])
+            .unwrap();
+
+        assert_eq!(hash.to_hex(), "00017f80abcdefff");
+        assert_eq!(PerceptualHash::from_hex(config, &hash.to_hex()).unwrap(), hash);
+        assert_eq!(PerceptualHash::from_hex(config, "00017F80ABCDEFFF").unwrap(), hash);
+        assert_eq!(PerceptualHash::from_base64(config, &hash.to_base64()).unwrap(), hash);
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert!(json.contains(r#""bytes":"AAF/gKvN7/8=""#));
+        assert_eq!(serde_json::from_str::<PerceptualHash>(&json).unwrap(), hash);
+        let legacy = json.replace(r#""AAF/gKvN7/8=""#, "[0,1,127,128,171,205,239,255]");
+        assert_eq!(serde_json::from_str::<PerceptualHash>(&legacy).unwrap(), hash);
+
+        let error = |result: Result<PerceptualHash>| result.unwrap_err().to_string();
+        assert!(error(PerceptualHash::from_hex(config, "0001")).contains("length"));
+        assert!(error(PerceptualHash::from_hex(config, "zz017f80abcdefff")).contains("hex"));
+        assert!(error(PerceptualHash::from_hex(config, "000")).contains("hex"));
+        assert!(error(PerceptualHash::from_base64(config, "AAE=")).contains("length"));
+        assert!(error(PerceptualHash::from_base64(config, "not base64!")).contains("base64"));
+
+        let dct = PerceptualHash::new(HashConfig::square(HashAlgorithm::Dct, 8), vec![0; 8]);
+        assert!(hash.distance(&dct.unwrap()).is_err());
+        let zero = PerceptualHash::new(config, vec![0; 8]).unwrap();
+        assert_eq!(hash.distance(&zero).unwrap(), 34);
+    }
+
+    #[test]
+    fn test_transparent_pixels_are_ignored() {
+        // Dark logo on a transparent canvas, the second export leaves noise
+        // under the fully transparent pixels
+        let logo = |hidden: fn(u32, u32) -> [u8; 3]| {
+            image::RgbaImage::from_fn(64, 64, |x, y| {
+                let (dx, dy) = (x as i32 - 32, y as i32 - 24);
+                if dx * dx + dy * dy < 200 {
+                    image::Rgba([20, 20, 120, 255])
+                } else {
+                    let [red, green, blue] = hidden(x, y);
+                    image::Rgba([red, green, blue, 0])
+                }
+            })
+        };
+        let clean = image::DynamicImage::ImageRgba8(logo(|_, _| [0, 0, 0]));
+        let noisy = image::DynamicImage::ImageRgba8(logo(|x, y| {
+            [(x * 37 % 256) as u8, (y * 91 % 256) as u8, ((x ^ y) * 16 % 256) as u8]
+        }));
+
+        let config = HashConfig::square(HashAlgorithm::Gradient, 8);
+        let hash = |img, config| {
+            generate_perceptual_hash_from_image(img, &config, HashExtras::default())
+                .unwrap()
+                .hash
+        };
+        assert_eq!(hash(&clean, config), hash(&noisy, config));
+
+        let on_black = HashConfig {
+            alpha_background: [0, 0, 0],
+            ..config
+        };
+        assert_eq!(hash(&clean, on_black), hash(&noisy, on_black));
+        assert_ne!(hash(&clean, config), hash(&clean, on_black));
+    }
+
+    #[test]
+    fn test_path_bytes_and_image_hashes_agree() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let img =
+            image::RgbImage::from_fn(48, 32, |x, y| image::Rgb([(x * 5) as u8, (y * 7) as u8, 90]));
+        let path = temp_dir.path().join("image.png");
+        img.save(&path).unwrap();
+
+        let config = HashConfig::square(HashAlgorithm::Gradient, 8);
+        let extras = HashExtras::default();
+        let from_image = generate_perceptual_hash_from_image(
+            &image::DynamicImage::ImageRgb8(img),
+            &config,
+            extras,
+        )
+        .unwrap();
+
+        assert_eq!(generate_perceptual_hash(&path, &config, extras).unwrap(), from_image);
+        let bytes = std::fs::read(&path).unwrap();
+        let from_bytes = generate_perceptual_hash_from_bytes(&bytes, &config, extras).unwrap();
+        assert_eq!(from_bytes, from_image);
+    }
+
+    /// PNG with an EXIF block holding only the orientation tag
+    fn save_with_orientation(img: &image::RgbImage, path: &Path, orientation: u16) {
+        use image::ImageEncoder;
+
+        // Little endian TIFF header, one IFD entry: tag 0x0112, SHORT, count 1
+        let mut exif = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        exif.extend_from_slice(&orientation.to_le_bytes());
+        exif.extend_from_slice(&[0; 6]);
+
+        let file = std::fs::File::create(path).unwrap();
+        let mut encoder = image::codecs::png::PngEncoder::new(file);
+        encoder.set_exif_metadata(exif).unwrap();
+        encoder
+            .write_image(img.as_raw(), img.width(), img.height(), image::ExtendedColorType::Rgb8)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_exif_orientation_is_applied() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let upright = image::RgbImage::from_fn(64, 32, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 8) as u8, ((x + y) * 2) as u8])
+        });
+        let upright_path = temp_dir.path().join("upright.png");
+        upright.save(&upright_path).unwrap();
+        let config = HashConfig::square(HashAlgorithm::Gradient, 8);
+        let expected =
+            generate_perceptual_hash(&upright_path, &config, HashExtras::default()).unwrap();
+
+        // Stored pixels are the upright image turned back by the tagged orientation
+        let cases = [
+            (3, image::imageops::rotate180(&upright)),
+            (6, image::imageops::rotate270(&upright)),
+            (8, image::imageops::rotate90(&upright)),
+        ];
+        for (orientation, stored) in cases {
+            let path = temp_dir.path().join(format!("orientation_{}.png", orientation));
+            save_with_orientation(&stored, &path, orientation);
+
+            assert_eq!(get_image_dimensions(&path).unwrap(), (64, 32), "{}", orientation);
+            let hash = generate_perceptual_hash(&path, &config, HashExtras::default()).unwrap();
+            assert_eq!(hash, expected, "orientation {}", orientation);
+
+            let bytes = std::fs::read(&path).unwrap();
+            let extras = HashExtras::default();
+            let hash = generate_perceptual_hash_from_bytes(&bytes, &config, extras).unwrap();
+            assert_eq!(hash, expected, "orientation {} from bytes", orientation);
+        }
+    }
+
+    #[test]
+    fn test_dominant_colors_most_common_first() {
+        // 60% red, 30% blue, 10% green
+        let img = image::RgbImage::from_fn(10, 10, |x, _| match x {
+            0..6 => image::Rgb([220, 30, 30]),
+            6..9 => image::Rgb([20, 40, 200]),
+            _ => image::Rgb([30, 180, 40]),
+        });
+        let img = image::DynamicImage::ImageRgb8(img);
+
+        let colors = extract_dominant_colors(&img, 3);
+        assert_eq!(colors, [[220, 30, 30], [20, 40, 200], [30, 180, 40]]);
+        assert_eq!(extract_dominant_colors(&img, 1).len(), 1);
+
+        // A flat image has a single color however many are asked for
+        let flat = image::DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+        assert_eq!(extract_dominant_colors(&flat, 3), [[0, 0, 0]]);
+
+        assert_eq!(palette_distance(&colors, &colors), Some(0.0));
+        let opposite = palette_distance(&[[0, 0, 0]], &[[255, 255, 255]]).unwrap();
+        assert!((opposite - 1.0).abs() < 1e-9);
+        assert_eq!(palette_distance(&colors, &[]), None);
     }
 }
@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use image::metadata::Orientation;
+use image::{GenericImageView, ImageDecoder};
+use std::path::Path;
+
+/// Still image formats the `image` crate can't decode, handled by ffmpeg instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfmpegImageFormat {
+    /// HEIF and HEIC, e.g. iPhone photos
+    Heif,
+    Avif,
+}
+
+impl std::fmt::Display for FfmpegImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FfmpegImageFormat::Heif => write!(f, "HEIF"),
+            FfmpegImageFormat::Avif => write!(f, "AVIF"),
+        }
+    }
+}
+
+/// Detect HEIF and AVIF data from the brand of its ISO base media `ftyp` box
+pub fn sniff_ffmpeg_image_format(header: &[u8]) -> Option<FfmpegImageFormat> {
+    if header.get(4..8)? != b"ftyp" {
+        return None;
+    }
+
+    match header.get(8..12)? {
+        b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1" => {
+            Some(FfmpegImageFormat::Heif)
+        }
+        b"avif" | b"avis" => Some(FfmpegImageFormat::Avif),
+        _ => None,
+    }
+}
+
+/// Sniff the first bytes of an image file, unreadable files are left to the
+/// regular decoder to report
+pub fn sniff_ffmpeg_image_file<P: AsRef<Path>>(image_path: P) -> Option<FfmpegImageFormat> {
+    use std::io::Read;
+
+    let mut header = Vec::with_capacity(12);
+    std::fs::File::open(image_path)
+        .ok()?
+        .take(12)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    sniff_ffmpeg_image_format(&header)
+}
+
+/// Decode a HEIF or AVIF file through ffmpeg, see `video::decode_image_with_ffmpeg`
+#[cfg(feature = "video")]
+pub fn decode_image_with_ffmpeg<P: AsRef<Path>>(image_path: P) -> Result<image::RgbImage> {
+    super::video::decode_image_with_ffmpeg(image_path)
+}
+
+/// HEIF and AVIF need ffmpeg, which was not compiled in
+#[cfg(not(feature = "video"))]
+pub fn decode_image_with_ffmpeg<P: AsRef<Path>>(_image_path: P) -> Result<image::RgbImage> {
+    anyhow::bail!("Decoding HEIF and AVIF images requires the `video` feature")
+}
+
+/// Decode HEIF or AVIF data held in memory, see `decode_image_with_ffmpeg`
+pub fn decode_image_bytes_with_ffmpeg(bytes: &[u8]) -> Result<image::RgbImage> {
+    with_temp_file(bytes, |path| decode_image_with_ffmpeg(path))
+}
+
+/// Size of a HEIF or AVIF file as read by ffmpeg, with the rotation applied
+#[cfg(feature = "video")]
+fn ffmpeg_image_dimensions(image_path: &Path) -> Result<(u32, u32)> {
+    super::video::get_video_dimension(image_path)
+}
+
+#[cfg(not(feature = "video"))]
+fn ffmpeg_image_dimensions(_image_path: &Path) -> Result<(u32, u32)> {
+    anyhow::bail!("Reading HEIF and AVIF images requires the `video` feature")
+}
+
+/// ffmpeg only reads files, so in-memory data goes through a temp file
+fn with_temp_file<T>(bytes: &[u8], read: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    let file = tempfile::NamedTempFile::new().context("Failed to create temp file")?;
+    std::fs::write(file.path(), bytes).context("Failed to write image data to temp file")?;
+
+    read(file.path())
+}
+
+/// Name of an image's format for error messages, from its content or extension
+pub fn describe_image_format<P: AsRef<Path>>(image_path: P) -> String {
+    let guessed = image::ImageReader::open(image_path.as_ref())
+        .and_then(|reader| reader.with_guessed_format())
+        .ok()
+        .and_then(|reader| reader.format());
+
+    match guessed {
+        Some(format) => format!("{:?}", format),
+        None => match image_path.as_ref().extension() {
+            Some(extension) => format!("unrecognized .{} format", extension.to_string_lossy()),
+            None => "unrecognized format".to_string(),
+        },
+    }
+}
+
+/// EXIF orientation of an image file, `NoTransforms` when it has none or the
+/// metadata can't be read
+pub fn image_orientation<P: AsRef<Path>>(image_path: P) -> Orientation {
+    image::ImageReader::open(image_path)
+        .ok()
+        .and_then(|reader| reader.with_guessed_format().ok())
+        .and_then(|reader| reader.into_decoder().ok())
+        .and_then(|mut decoder| decoder.orientation().ok())
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+/// EXIF orientation of an encoded image held in memory
+pub fn image_orientation_from_bytes(bytes: &[u8]) -> Orientation {
+    image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_decoder().ok())
+        .and_then(|mut decoder| decoder.orientation().ok())
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+/// Size of a stored image once its orientation is applied
+fn oriented_dimensions((width, height): (u32, u32), orientation: Orientation) -> (u32, u32) {
+    match orientation {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    }
+}
+
+// Get Image Dimensions
+/// Dimensions as displayed, after the EXIF orientation is applied
+/// HEIF and AVIF sizes come from ffmpeg, with the rotation applied
+pub fn get_image_dimensions<P: AsRef<Path>>(image_path: P) -> Result<(u32, u32)> {
+    if let Some(format) = sniff_ffmpeg_image_file(&image_path) {
+        return ffmpeg_image_dimensions(image_path.as_ref())
+            .with_context(|| format!("Failed to read {} image dimensions", format));
+    }
+
+    let img = image::open(image_path.as_ref()).with_context(|| {
+        format!("Failed to open image ({})", describe_image_format(&image_path))
+    })?;
+    Ok(oriented_dimensions(img.dimensions(), image_orientation(&image_path)))
+}
+
+/// Get dimensions of an encoded image held in memory
+pub fn get_image_dimensions_from_bytes(bytes: &[u8]) -> Result<(u32, u32)> {
+    if let Some(format) = sniff_ffmpeg_image_format(bytes) {
+        return with_temp_file(bytes, ffmpeg_image_dimensions)
+            .with_context(|| format!("Failed to read {} image dimensions", format));
+    }
+
+    let img = image::load_from_memory(bytes).with_context(|| {
+        let format = image::guess_format(bytes)
+            .map_or_else(|_| "unrecognized format".to_string(), |format| format!("{:?}", format));
+        format!("Failed to decode image data ({})", format)
+    })?;
+    Ok(oriented_dimensions(img.dimensions(), image_orientation_from_bytes(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heif_family_is_sniffed_from_ftyp_brand() {
+        let ftyp = |brand: &[u8; 4]| [b"\0\0\0\x18ftyp".as_slice(), brand.as_slice()].concat();
+
+        assert_eq!(sniff_ffmpeg_image_format(&ftyp(b"heic")), Some(FfmpegImageFormat::Heif));
+        assert_eq!(sniff_ffmpeg_image_format(&ftyp(b"mif1")), Some(FfmpegImageFormat::Heif));
+        assert_eq!(sniff_ffmpeg_image_format(&ftyp(b"avif")), Some(FfmpegImageFormat::Avif));
+        // Plain MP4 and short or non-ISO data
+        assert_eq!(sniff_ffmpeg_image_format(&ftyp(b"isom")), None);
+        assert_eq!(sniff_ffmpeg_image_format(b"\0\0\0\x18ftyp"), None);
+        assert_eq!(sniff_ffmpeg_image_format(b"\x89PNG\r\n\x1a\n\0\0\0\0"), None);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::visual_grouping::hash::{hamming_distance, hamming_distance_u64, pack_hash};
+
+/// BK-tree over perceptual hashes, answers "everything within N bits" queries
+/// without comparing against every stored hash
+/// All hashes must have the same length, the first insert fixes it
+#[derive(Debug, Clone)]
+pub struct HashIndex<T> {
+    nodes: Vec<Node<T>>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    hash: Vec<u8>,
+    packed: Option<u64>,
+    /// Values inserted with exactly this hash
+    values: Vec<T>,
+    /// Child node indices keyed by their distance to this node
+    children: Vec<(u32, usize)>,
+}
+
+impl<T> Default for HashIndex<T> {
+    fn default() -> Self {
+        HashIndex {
+            nodes: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T: Clone> HashIndex<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of inserted values
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add a value under `hash`, typically an asset id and its first frame hash
+    pub fn insert(&mut self, value: T, hash: &[u8]) -> anyhow::Result<()> {
+        let packed = pack_hash(hash);
+
+        let Some(root) = self.nodes.first() else {
+            self.push_node(value, hash, packed);
+            return Ok(());
+        };
+
+        if root.hash.len() != hash.len() {
+            anyhow::bail!(
+                "Hash of {} bytes can't be added to an index of {} byte hashes",
+                hash.len(),
+                root.hash.len()
+            );
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = Self::distance(&self.nodes[current], hash, packed);
+            if distance == 0 {
+                self.nodes[current].values.push(value);
+                self.len += 1;
+                return Ok(());
+            }
+
+            let child = self.nodes[current]
+                .children
+                .iter()
+                .find(|(edge, _)| *edge == distance)
+                .map(|&(_, child)| child);
+
+            match child {
+                Some(child) => current = child,
+                None => {
+                    let child = self.push_node(value, hash, packed);
+                    self.nodes[current].children.push((distance, child));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Values whose hash is within `max_distance` bits of `hash`, inclusive
+    /// A hash of a different length than the indexed ones matches nothing
+    pub fn query(&self, hash: &[u8], max_distance: u32) -> Vec<T> {
+        let mut matches = Vec::new();
+        if self.nodes.first().is_none_or(|root| root.hash.len() != hash.len()) {
+            return matches;
+        }
+
+        let packed = pack_hash(hash);
+        let mut pending = vec![0];
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = Self::distance(node, hash, packed);
+            if distance <= max_distance {
+                matches.extend(node.values.iter().cloned());
+            }
+
+            // Triangle inequality: only subtrees at distance d ± max_distance can match
+            let low = distance.saturating_sub(max_distance);
+            let high = distance.saturating_add(max_distance);
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(edge, _)| (low..=high).contains(edge))
+                    .map(|&(_, child)| child),
+            );
+        }
+
+        matches
+    }
+
+    fn push_node(&mut self, value: T, hash: &[u8], packed: Option<u64>) -> usize {
+        self.nodes.push(Node {
+            hash: hash.to_vec(),
+            packed,
+            values: vec![value],
+            children: Vec::new(),
+        });
+        self.len += 1;
+        self.nodes.len() - 1
+    }
+
+    fn distance(node: &Node<T>, hash: &[u8], packed: Option<u64>) -> u32 {
+        match (node.packed, packed) {
+            (Some(a), Some(b)) => hamming_distance_u64(a, b),
+            // Lengths are checked on insert and query
+            _ => hamming_distance(&node.hash, hash).unwrap_or(u32::MAX),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_matches_linear_scan() {
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        // Clustered hashes so every radius has hits, plus exact duplicates
+        let mut hashes: Vec<u64> = Vec::new();
+        for _ in 0..40 {
+            let base = next();
+            for _ in 0..8 {
+                hashes.push(base ^ (next() & next() & next()));
+            }
+            hashes.push(base);
+            hashes.push(base);
+        }
+
+        let mut index = HashIndex::new();
+        for (id, hash) in hashes.iter().enumerate() {
+            index.insert(id, &hash.to_be_bytes()).unwrap();
+        }
+        assert_eq!(index.len(), hashes.len());
+
+        for max_distance in [0, 5, 14, 30] {
+            for query in hashes.iter().step_by(7) {
+                let mut found = index.query(&query.to_be_bytes(), max_distance);
+                found.sort_unstable();
+
+                let expected: Vec<usize> = hashes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, hash)| (*hash ^ query).count_ones() <= max_distance)
+                    .map(|(id, _)| id)
+                    .collect();
+
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_mismatched_hash_length() {
+        let mut index = HashIndex::new();
+        index.insert("a", &[0u8; 8]).unwrap();
+
+        assert!(index.insert("b", &[0u8; 32]).is_err());
+        assert!(index.query(&[0u8; 32], 64).is_empty());
+    }
+}
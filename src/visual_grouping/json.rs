@@ -0,0 +1,193 @@
+//! Grouping from and to JSON strings, for asset lists too large to pass
+//! around one object at a time
+
+use anyhow::{Context, Result};
+use serde::de::{DeserializeSeed, SeqAccess, Visitor};
+use std::cell::Cell;
+use std::fmt;
+
+use super::grouping::{ProgressCallback, group_assets_by_visual_similarity};
+use super::{Asset, CancellationToken, GroupingOptions};
+
+/// Parse a JSON array of assets
+/// Errors inside the array name the index of the asset that failed
+pub fn parse_assets_json(json: &str) -> Result<Vec<Asset>> {
+    let current = Cell::new(None);
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+
+    let assets = AssetsSeed { current: &current }
+        .deserialize(&mut deserializer)
+        .and_then(|assets| deserializer.end().map(|()| assets));
+
+    assets.map_err(|err| match current.get() {
+        Some(index) => anyhow::anyhow!("Invalid asset at index {}: {}", index, err),
+        None => anyhow::anyhow!("Invalid asset list: {}", err),
+    })
+}
+
+/// Group a JSON array of assets and return the groups as a JSON array
+/// Assets that fail processing are logged and left out of the groups
+pub fn group_assets_json(
+    json: &str,
+    options: Option<GroupingOptions>,
+    on_progress: Option<&ProgressCallback>,
+    cancel: Option<&CancellationToken>,
+) -> Result<String> {
+    let assets = parse_assets_json(json)?;
+    let result = group_assets_by_visual_similarity(assets, options, on_progress, cancel)?;
+    for failed in &result.failed {
+        log::warn!("Skipping {}: {}", failed.asset_id, failed.reason);
+    }
+
+    serde_json::to_string(&result.groups).context("Failed to serialize groups")
+}
+
+/// Reads the asset array element by element, keeping the index of the one
+/// being read in `current` so errors can point at it
+struct AssetsSeed<'a> {
+    current: &'a Cell<Option<usize>>,
+}
+
+impl<'de> DeserializeSeed<'de> for AssetsSeed<'_> {
+    type Value = Vec<Asset>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for AssetsSeed<'_> {
+    type Value = Vec<Asset>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of assets")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut assets = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        loop {
+            self.current.set(Some(assets.len()));
+            match seq.next_element()? {
+                Some(asset) => assets.push(asset),
+                None => break,
+            }
+        }
+        // Whatever fails past the closing bracket is not about an asset
+        self.current.set(None);
+
+        Ok(assets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::hash::PerceptualHash;
+    use crate::visual_grouping::{
+        AssetGroup, AssetKind, AssetSource, FrameData, HashConfig, PrecomputedHashes,
+    };
+    use std::collections::HashSet;
+
+    /// Clusters of 5 near copies, hashes are precomputed so nothing is decoded
+    fn clustered_assets(clusters: usize) -> Vec<Asset> {
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut assets = Vec::new();
+        for cluster in 0..clusters {
+            let base = next();
+            for variant in 0..5 {
+                let hash = base ^ (next() & next() & next() & next());
+                let hash = PerceptualHash::new(HashConfig::default(), hash.to_be_bytes().to_vec());
+                let id = format!("{}-{}", cluster, variant);
+                assets.push(Asset {
+                    id: id.clone(),
+                    name: format!("{}.png", id),
+                    source: AssetSource::Path(format!("/library/{}.png", id).into()),
+                    mime_type: "image/png".to_string(),
+                    is_video: false,
+                    precomputed: Some(PrecomputedHashes {
+                        frames: vec![FrameData::new(0, 0.0, hash.unwrap())],
+                        width: 100,
+                        height: 100,
+                        duration: None,
+                        dominant_colors: Vec::new(),
+                        audio_fingerprint: None,
+                    }),
+                    kind: AssetKind::File,
+                });
+            }
+        }
+        assets
+    }
+
+    #[test]
+    fn test_parse_assets_json_handles_50k_assets() {
+        let json = serde_json::to_string(&clustered_assets(10_000)).unwrap();
+
+        let assets = parse_assets_json(&json).unwrap();
+        let ids: HashSet<&str> = assets.iter().map(|asset| asset.id.as_str()).collect();
+        assert_eq!(ids.len(), 50_000);
+        let path = assets[0].source.path().unwrap();
+        assert_eq!(path.parent().unwrap().to_str(), Some("/library"));
+        assert_eq!(assets[0].precomputed.as_ref().unwrap().frames.len(), 1);
+    }
+
+    #[test]
+    fn test_group_assets_json_round_trips_groups() {
+        let json = serde_json::to_string(&clustered_assets(200)).unwrap();
+
+        let groups = group_assets_json(&json, None, None, None).unwrap();
+        let groups: Vec<AssetGroup> = serde_json::from_str(&groups).unwrap();
+
+        let ids: HashSet<&str> = groups
+            .iter()
+            .flat_map(|group| &group.assets)
+            .map(|member| member.asset.id.as_str())
+            .collect();
+        assert_eq!(ids.len(), 1_000);
+        assert!(groups.len() < 1_000);
+        let member = &groups[0].assets[0];
+        let path = member.asset.source.path().unwrap();
+        assert_eq!(path.parent().unwrap().to_str(), Some("/library"));
+        assert_eq!(member.asset.precomputed.as_ref().unwrap().frames.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_assets_json_points_at_the_failing_asset() {
+        let asset = |id: &str| {
+            serde_json::json!({
+                "id": id,
+                "name": format!("{}.png", id),
+                "path": format!("{}.png", id),
+                "mime_type": "image/png",
+                "is_video": false,
+            })
+            .to_string()
+        };
+
+        let json = format!("[{},{}]", asset("a"), asset("b"));
+        assert_eq!(parse_assets_json(&json).unwrap().len(), 2);
+
+        let json = format!(r#"[{},{},{{"id":"c"}}]"#, asset("a"), asset("b"));
+        let error = parse_assets_json(&json).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid asset at index 2:"), "{}", error);
+
+        let json = format!("[{},{{\"id\":", asset("a"));
+        let error = parse_assets_json(&json).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid asset at index 1:"), "{}", error);
+
+        let error = parse_assets_json(r#"{"assets":[]}"#).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid asset list:"), "{}", error);
+        let error = parse_assets_json(&format!("[{}] x", asset("a"))).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid asset list:"), "{}", error);
+    }
+}
@@ -1,34 +1,378 @@
+pub mod animation;
+pub mod audio;
+pub mod blurhash;
+pub mod cache;
+pub mod graph;
 pub mod grouping;
 pub mod hash;
+pub mod image_info;
+pub mod index;
+pub mod json;
+pub mod naming;
+pub mod persist;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod scan;
+pub mod sequence;
+pub mod session;
+pub mod thumbnail;
+pub mod tonemap;
+#[cfg(feature = "video")]
 pub mod video;
 
 use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use audio::AudioFingerprint;
+use hash::{HashExtras, ImageHashes, PerceptualHash};
+
+/// Where an asset's content comes from
+/// Serialized flattened into `Asset`, so path based assets keep the `"path"` key.
+/// Paths are kept as the OS hands them out, only JSON can't hold non-UTF-8 ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetSource {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl AssetSource {
+    /// Filesystem path of the asset, if it is not held in memory
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            AssetSource::Path(path) => Some(path),
+            AssetSource::Bytes(_) => None,
+        }
+    }
+    /// http or https URL the asset is fetched from before decoding
+    pub fn url(&self) -> Option<&str> {
+        let url = self.path()?.to_str()?;
+        let (scheme, _) = url.split_once("://")?;
+        let is_http = scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https");
+        is_http.then_some(url)
+    }
+}
+
+/// How the content of an asset is laid out
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AssetKind {
+    /// One image, animation or video, a directory path is still read as an
+    /// image sequence
+    #[default]
+    File,
+    /// Directory of numbered images played as the frames of an animation, see
+    /// `sequence`. `pattern` picks the frame files by name with `*` and `?`
+    /// wildcards, every image in the directory without it
+    ImageSequence { pattern: Option<String> },
+}
+
+impl AssetKind {
+    pub fn is_file(&self) -> bool {
+        *self == AssetKind::File
+    }
+}
 
 /// Asset type with file information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Asset {
     pub id: String,
     pub name: String,
-    pub path: String,
+    #[serde(flatten)]
+    pub source: AssetSource,
     pub mime_type: String,
     pub is_video: bool,
+    /// Hashes from a previous run, used instead of decoding the asset again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precomputed: Option<PrecomputedHashes>,
+    #[serde(default, skip_serializing_if = "AssetKind::is_file")]
+    pub kind: AssetKind,
 }
 
 /// Frame data with hash
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FrameData {
     pub frame_number: usize,
-    pub hash: Vec<u8>,
+    /// Presentation time of the frame in seconds, 0 for images
+    #[serde(default)]
+    pub timestamp_secs: f64,
+    pub hash: PerceptualHash,
+    /// `hash` packed into a u64 when it is exactly 64 bits, for fast comparisons
+    #[serde(skip)]
+    pub packed: Option<u64>,
+    /// Hashes of the mirrored and rotated frame, only computed when
+    /// `GroupingOptions::invariance` asks for them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<PerceptualHash>,
+    /// Hashes of the grid cells of the frame, only computed for crop-resistant matching
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<PerceptualHash>,
+    /// BlurHash placeholder of the frame, only computed for the first frame
+    /// when `GroupingOptions::blurhash` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    /// Consecutive sampled frames this one stands for, more than 1 when
+    /// near-identical frames were collapsed into it
+    #[serde(default = "default_represented_frames", skip_serializing_if = "is_single_frame")]
+    pub represented_frames: usize,
+    /// Black or flat frame kept only because the video has nothing else,
+    /// comparisons skip it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub uniform: bool,
+}
+
+fn default_represented_frames() -> usize {
+    1
+}
+
+fn is_single_frame(represented_frames: &usize) -> bool {
+    *represented_frames == 1
+}
+
+impl FrameData {
+    pub fn new(frame_number: usize, timestamp_secs: f64, hash: PerceptualHash) -> Self {
+        let packed = hash::pack_hash(&hash.bytes);
+        FrameData {
+            frame_number,
+            timestamp_secs,
+            hash,
+            packed,
+            variants: Vec::new(),
+            regions: Vec::new(),
+            blurhash: None,
+            represented_frames: 1,
+            uniform: false,
+        }
+    }
+
+    /// Frame from the hashes of an image, its variants, its grid regions and
+    /// its BlurHash
+    pub fn from_hashes(frame_number: usize, timestamp_secs: f64, hashes: ImageHashes) -> Self {
+        FrameData {
+            blurhash: hashes.blurhash,
+            ..FrameData::new(frame_number, timestamp_secs, hashes.hash)
+                .with_variants(hashes.variants)
+                .with_regions(hashes.regions)
+        }
+    }
+
+    pub fn with_variants(mut self, variants: Vec<PerceptualHash>) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    pub fn with_regions(mut self, regions: Vec<PerceptualHash>) -> Self {
+        self.regions = regions;
+        self
+    }
+
+    /// Number of bits in the hash
+    pub fn bits(&self) -> u32 {
+        self.hash.bits()
+    }
+
+    /// Hamming distance to another frame, using the packed form when both have one
+    /// Fails for hashes produced with different algorithms or sizes
+    pub fn distance(&self, other: &FrameData) -> anyhow::Result<u32> {
+        match (self.packed, other.packed) {
+            (Some(a), Some(b)) => {
+                self.hash.check_comparable(&other.hash)?;
+                Ok(hash::hamming_distance_u64(a, b))
+            }
+            _ => self.hash.distance(&other.hash),
+        }
+    }
+
+    /// Smallest distance when either frame may be a mirrored or rotated copy
+    /// of the other, same as `distance` for frames without variants
+    pub fn min_distance(&self, other: &FrameData) -> anyhow::Result<u32> {
+        let mut best = self.distance(other)?;
+        for variant in &self.variants {
+            best = best.min(variant.distance(&other.hash)?);
+        }
+        for variant in &other.variants {
+            best = best.min(self.hash.distance(variant)?);
+        }
+        Ok(best)
+    }
+
+    /// Number of grid regions of the frame with fewer regions that are under
+    /// `threshold` from any region of the other frame
+    /// Regions are matched regardless of position, so a crop whose cells line
+    /// up with a different row of the original still counts
+    pub fn matching_regions(&self, other: &FrameData, threshold: u32) -> anyhow::Result<usize> {
+        let (fewer, more) = if self.regions.len() <= other.regions.len() {
+            (&self.regions, &other.regions)
+        } else {
+            (&other.regions, &self.regions)
+        };
+
+        let mut matched = 0;
+        for region in fewer {
+            for candidate in more {
+                if region.distance(candidate)? < threshold {
+                    matched += 1;
+                    break;
+                }
+            }
+        }
+        Ok(matched)
+    }
+}
+
+/// Frame hashes and dimensions of an already processed asset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrecomputedHashes {
+    pub frames: Vec<FrameData>,
+    pub width: u32,
+    pub height: u32,
+    /// Video duration in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Most common colors first, the palette check is skipped without them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dominant_colors: Vec<[u8; 3]>,
+    /// Fingerprint of the audio track, `None` counts as no audio under `audio_mode`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_fingerprint: Option<AudioFingerprint>,
+}
+
+/// Computed hashes of an asset, returned so callers can cache them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetHashes {
+    pub asset_id: String,
+    pub frames: Vec<FrameData>,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// True when some video frames could not be decoded and were skipped
+    #[serde(default)]
+    pub degraded: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dominant_colors: Vec<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_fingerprint: Option<AudioFingerprint>,
+    /// How the frames of a video or animation were sampled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling_policy: Option<SamplingPolicy>,
+    /// Packets and frames of a video the decoder failed on
+    #[serde(default)]
+    pub decode_errors: usize,
+}
+
+impl From<&HashedAsset> for AssetHashes {
+    fn from(hashed_asset: &HashedAsset) -> Self {
+        AssetHashes {
+            asset_id: hashed_asset.asset.id.clone(),
+            frames: hashed_asset.frames.clone(),
+            width: hashed_asset.width,
+            height: hashed_asset.height,
+            duration: hashed_asset.duration,
+            degraded: hashed_asset.degraded,
+            dominant_colors: hashed_asset.dominant_colors.clone(),
+            audio_fingerprint: hashed_asset.audio_fingerprint.clone(),
+            sampling_policy: hashed_asset.sampling_policy,
+            decode_errors: hashed_asset.decode_errors,
+        }
+    }
 }
 
 /// Asset with extracted frame hashes
-#[derive(Debug, Clone)]
+/// See `persist` to keep them between runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HashedAsset {
     pub asset: Asset,
     pub frames: Vec<FrameData>,
     pub aspect_ratio: f64,
     pub width: u32,
     pub height: u32,
+    /// Video duration in seconds, `None` for images
+    pub duration: Option<f64>,
+    /// True when some video frames could not be decoded and were skipped
+    pub degraded: bool,
+    /// True for animated GIF and WebP images, which carry several frames and
+    /// a duration like videos
+    pub is_animated: bool,
+    /// `DOMINANT_COLOR_COUNT` colors of the resized image, or of the first
+    /// frame of a video, the most common first
+    pub dominant_colors: Vec<[u8; 3]>,
+    /// Fingerprint of the audio track of a video, computed unless `audio_mode`
+    /// is `Ignore`. `None` for images and videos without audio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_fingerprint: Option<AudioFingerprint>,
+    /// Sampling applied to the frames of a video or animation, `None` for
+    /// still images and precomputed hashes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling_policy: Option<SamplingPolicy>,
+    /// Packets and frames of a video the decoder failed on, frames sampled
+    /// after them may be damaged
+    #[serde(default)]
+    pub decode_errors: usize,
+}
+
+/// Member of a group, with the dimensions found while hashing it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupedAsset {
+    #[serde(flatten)]
+    pub asset: Asset,
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+    /// Placement the aspect ratio is made for: "square", "vertical" or "horizontal"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// BlurHash placeholder of the asset, or of the first frame of a video,
+    /// set when `GroupingOptions::blurhash` is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    /// Dominant colors of the asset, the most common first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dominant_colors: Vec<[u8; 3]>,
+    /// True when the audio differs from the group representative's, set with
+    /// `AudioMode::Annotate`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub audio_differs: bool,
+    /// Ids of the other inputs with the same canonical path, merged into this
+    /// asset when `GroupingOptions::dedupe_by_path` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alias_ids: Vec<String>,
+}
+
+/// Width over height within this distance of 1.0 counts as square,
+/// so 1080x1080 and 1080x1100 exports are the same variant
+const SQUARE_ASPECT_TOLERANCE: f64 = 0.05;
+
+impl From<&HashedAsset> for GroupedAsset {
+    fn from(hashed_asset: &HashedAsset) -> Self {
+        let (width, height) = (hashed_asset.width, hashed_asset.height);
+        let variant = (width > 0 && height > 0).then(|| {
+            let aspect_ratio = width as f64 / height as f64;
+            if (aspect_ratio - 1.0).abs() <= SQUARE_ASPECT_TOLERANCE {
+                "square"
+            } else if aspect_ratio < 1.0 {
+                "vertical"
+            } else {
+                "horizontal"
+            }
+        });
+
+        GroupedAsset {
+            asset: hashed_asset.asset.clone(),
+            width,
+            height,
+            variant: variant.map(str::to_string),
+            blurhash: hashed_asset.frames.first().and_then(|frame| frame.blurhash.clone()),
+            dominant_colors: hashed_asset.dominant_colors.clone(),
+            audio_differs: false,
+            alias_ids: Vec::new(),
+        }
+    }
 }
 
 /// Group of visually similar assets
@@ -36,5 +380,1447 @@ pub struct HashedAsset {
 pub struct AssetGroup {
     pub id: String,
     pub name: String,
-    pub assets: Vec<Asset>,
+    pub assets: Vec<GroupedAsset>,
+    /// Member to show as the group cover, the medoid of the group
+    #[serde(default)]
+    pub representative_asset_id: String,
+    /// How tight the group is, from 0.0 (borderline) to 1.0 (identical hashes)
+    #[serde(default = "default_confidence")]
+    pub confidence: f64,
+    /// Mean frame distance per member pair keyed by "idA|idB", for debugging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distances: Option<BTreeMap<String, f64>>,
+    /// Members that are byte-identical copies of another member, mapped to the
+    /// id of the member that was hashed in their place
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub exact_duplicates: BTreeMap<String, String>,
+    /// Clusters of near-identical members under `strict_threshold`, empty
+    /// unless a strict threshold is set and the group has several members
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subgroups: Vec<AssetGroup>,
+    /// Thumbnail of the representative, when `GroupingOptions::thumbnails` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_path: Option<PathBuf>,
+    /// Why the thumbnail could not be written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_error: Option<String>,
+}
+
+fn default_confidence() -> f64 {
+    1.0
+}
+
+/// Group persisted together with the hashes of its members, so they don't
+/// have to be decoded again when new assets are assigned
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AssetGroupWithHashes {
+    #[serde(flatten)]
+    pub group: AssetGroup,
+    pub hashes: Vec<AssetHashes>,
+}
+
+/// Group a new asset was placed into
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupAssignment {
+    pub asset_id: String,
+    pub group_id: String,
+    /// True when no existing group matched and a new group was created
+    pub created_group: bool,
+}
+
+/// Outcome of assigning new assets into existing groups
+/// `groups` holds the existing groups, updated in place, followed by the new ones
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AssignmentResult {
+    pub groups: Vec<AssetGroupWithHashes>,
+    pub assignments: Vec<GroupAssignment>,
+    pub failed: Vec<FailedAsset>,
+}
+
+/// Detailed outcome of comparing two assets
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SimilarityReport {
+    pub asset_id_a: String,
+    pub asset_id_b: String,
+    /// True when one asset is a video and the other an image, which blocks
+    /// comparison unless `allow_image_video_matching` is set
+    pub type_mismatch: bool,
+    /// True when the aspect ratios differ by more than `max_aspect_ratio_delta`
+    #[serde(default)]
+    pub aspect_ratio_mismatch: bool,
+    /// True when either asset is smaller than `min_resolution`
+    #[serde(default)]
+    pub below_min_resolution: bool,
+    /// True when the video durations differ by more than `max_duration_ratio`
+    #[serde(default)]
+    pub duration_mismatch: bool,
+    /// True when the hashes matched but the dominant colors outweighed them
+    /// under `color_weight`
+    #[serde(default)]
+    pub palette_mismatch: bool,
+    /// Distance between the dominant colors in [0, 1], `None` when either
+    /// asset has none
+    #[serde(default)]
+    pub palette_distance: Option<f64>,
+    /// True when both assets are videos whose audio differs, compared unless
+    /// `audio_mode` is `Ignore`. Only rejects the pair with `AudioMode::Separate`
+    #[serde(default)]
+    pub audio_mismatch: bool,
+    /// Hamming distance of each aligned frame pair
+    pub frame_distances: Vec<u32>,
+    /// Timestamps in seconds of the asset A frame behind each entry of `frame_distances`
+    #[serde(default)]
+    pub frame_timestamps_a: Vec<f64>,
+    /// Timestamps in seconds of the asset B frame behind each entry of `frame_distances`
+    #[serde(default)]
+    pub frame_timestamps_b: Vec<f64>,
+    pub min_distance: Option<u32>,
+    pub max_distance: Option<u32>,
+    pub mean_distance: Option<f64>,
+    pub aspect_ratio_a: f64,
+    pub aspect_ratio_b: f64,
+    pub threshold: u32,
+    /// Frame offset of asset B relative to asset A in the chosen alignment,
+    /// with `detect_clips` where the shorter video starts in the longer one
+    #[serde(default)]
+    pub frame_offset: i32,
+    /// Fraction of compared frames under the threshold
+    #[serde(default)]
+    pub matched_frames_ratio: f64,
+    /// Aggregate similarity in [0, 1] from the mean frame distance, shows how
+    /// close a rejected pair was
+    #[serde(default)]
+    pub score: f64,
+    pub similar: bool,
+}
+
+/// Distance between two assets across their aligned frames
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PairDistance {
+    /// False when the assets can't be compared (image vs video, incompatible hashes)
+    pub comparable: bool,
+    pub min_distance: Option<u32>,
+    pub mean_distance: Option<f64>,
+}
+
+/// Pairwise distances between all processed assets
+/// `pairs[i][j]` holds the distance between `ids[i]` and `ids[j]`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SimilarityMatrix {
+    pub ids: Vec<String>,
+    pub pairs: Vec<Vec<PairDistance>>,
+    pub failed: Vec<FailedAsset>,
+}
+
+/// Threshold suggested from the distribution of pairwise first-frame distances
+/// `histogram[d]` counts the pairs `d` bits apart. With a single mode, e.g.
+/// only unrelated assets, there is no valley to split at: `confidence` stays
+/// low and `threshold` is the configured one (15 by default)
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ThresholdSuggestion {
+    pub threshold: u32,
+    /// How well the threshold separates the distances, in [0, 1]
+    pub confidence: f64,
+    pub histogram: Vec<usize>,
+    pub failed: Vec<FailedAsset>,
+}
+
+/// Candidate matching a query asset
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SimilarMatch {
+    pub candidate_id: String,
+    /// Smallest hamming distance across the compared frames
+    pub min_distance: u32,
+    /// Similarity normalized to [0, 1], 1.0 meaning identical hashes
+    pub score: f64,
+}
+
+/// Two assets whose hashes are within the duplicate distance of each other
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DuplicatePair {
+    pub asset_id_a: String,
+    pub asset_id_b: String,
+    /// Largest hamming distance across the aligned frames
+    pub distance: u32,
+    /// Copy with more pixels, the one worth keeping. `asset_id_a` on a tie
+    pub higher_resolution_asset_id: String,
+}
+
+/// Asset that could not be processed, with the reason it failed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FailedAsset {
+    pub asset_id: String,
+    pub reason: String,
+}
+
+/// Outcome of a grouping run: the groups plus any assets that failed processing
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GroupingResult {
+    pub groups: Vec<AssetGroup>,
+    pub failed: Vec<FailedAsset>,
+    pub hashes: Vec<AssetHashes>,
+    #[serde(default)]
+    pub stats: GroupingStats,
+    /// Time spent on each asset keyed by asset id, failed assets included
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub timings: BTreeMap<String, AssetTimings>,
+}
+
+/// Where the processing of one asset spent its time, in milliseconds
+/// Hashing is whatever the total leaves after probing and extraction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct AssetTimings {
+    pub probe_ms: f64,
+    pub extraction_ms: f64,
+    pub hashing_ms: f64,
+    pub total_ms: f64,
+}
+
+/// Where a grouping run spent its time and how much work it did
+/// Probing and extraction times are summed over the workers, hashing and
+/// comparison are wall times of the two phases
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct GroupingStats {
+    pub probe_ms: f64,
+    pub extraction_ms: f64,
+    pub hashing_ms: f64,
+    pub comparison_ms: f64,
+    /// Video and animation frames decoded for hashing
+    pub frames_extracted: usize,
+    /// Pairs that went through the full frame-by-frame comparison
+    pub comparisons: usize,
+    pub cache_hits: usize,
+    /// Most assets or comparison rows processed at the same time
+    pub peak_workers: usize,
+    /// Videos with frames decoded on a hardware device
+    pub hardware_decoded_assets: usize,
+    /// Packets and frames the video decoders failed on, see `AssetHashes` for
+    /// the count of each asset
+    #[serde(default)]
+    pub decode_errors: usize,
+}
+
+/// Perceptual hash algorithm used for frame hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Blockhash,
+    Mean,
+    Gradient,
+    DoubleGradient,
+    VerticalGradient,
+    /// pHash: mean hash of the low frequency DCT coefficients, robust to
+    /// brightness and contrast shifts
+    Dct,
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "blockhash" => Ok(HashAlgorithm::Blockhash),
+            "mean" => Ok(HashAlgorithm::Mean),
+            "gradient" => Ok(HashAlgorithm::Gradient),
+            "doublegradient" | "double_gradient" => Ok(HashAlgorithm::DoubleGradient),
+            "verticalgradient" | "vertical_gradient" => Ok(HashAlgorithm::VerticalGradient),
+            "dct" | "phash" => Ok(HashAlgorithm::Dct),
+            other => anyhow::bail!("Unknown hash algorithm: {}", other),
+        }
+    }
+}
+
+/// How images are fitted into the square the hash is computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ResizeStrategy {
+    /// Fill the square, cropping the edges of the longer side. Focuses on the
+    /// central content, which survives most resizes of the same creative
+    #[default]
+    Cover,
+    /// Fit the whole image and pad the rest with the background color, for
+    /// creatives whose branding sits at the edges around shared footage
+    Contain,
+    /// Scale each side to the square, distorting the aspect ratio
+    Stretch,
+}
+
+impl std::str::FromStr for ResizeStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "cover" => Ok(ResizeStrategy::Cover),
+            "contain" => Ok(ResizeStrategy::Contain),
+            "stretch" => Ok(ResizeStrategy::Stretch),
+            other => anyhow::bail!("Unknown resize strategy: {}", other),
+        }
+    }
+}
+
+/// Side in pixels of the square images are resized to before hashing
+pub const DEFAULT_TARGET_SIZE: u32 = 256;
+
+/// Algorithm, grid size and preprocessing of a perceptual hash
+/// Hashes are only comparable when they were produced with the same algorithm
+/// and size, which is what a `PerceptualHash` records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashConfig {
+    pub algorithm: HashAlgorithm,
+    pub width: u32,
+    pub height: u32,
+    /// Hash the histogram equalized luma of the image, so re-encodes with
+    /// shifted brightness, gamma or contrast still match
+    #[serde(default)]
+    pub normalize: bool,
+    /// Color transparent pixels are composited onto, so the undefined color
+    /// under fully transparent pixels doesn't affect the hash
+    #[serde(default = "default_alpha_background")]
+    pub alpha_background: [u8; 3],
+    #[serde(default)]
+    pub resize: ResizeStrategy,
+    /// Side of the square images are resized to, in pixels
+    #[serde(default = "default_target_size")]
+    pub target_size: u32,
+}
+
+/// White, what transparent images are usually shown on
+pub const DEFAULT_ALPHA_BACKGROUND: [u8; 3] = [255, 255, 255];
+
+fn default_alpha_background() -> [u8; 3] {
+    DEFAULT_ALPHA_BACKGROUND
+}
+
+fn default_target_size() -> u32 {
+    DEFAULT_TARGET_SIZE
+}
+
+impl HashConfig {
+    /// Config without preprocessing
+    pub fn new(algorithm: HashAlgorithm, width: u32, height: u32) -> Self {
+        HashConfig {
+            algorithm,
+            width,
+            height,
+            normalize: false,
+            alpha_background: DEFAULT_ALPHA_BACKGROUND,
+            resize: ResizeStrategy::Cover,
+            target_size: DEFAULT_TARGET_SIZE,
+        }
+    }
+
+    pub fn square(algorithm: HashAlgorithm, size: u32) -> Self {
+        HashConfig::new(algorithm, size, size)
+    }
+
+    /// Whether `hash` was produced with this algorithm and size
+    pub fn produces(&self, hash: &PerceptualHash) -> bool {
+        hash.config() == HashConfig::new(self.algorithm, self.width, self.height)
+    }
+
+    /// Number of bits in a hash produced with this config
+    pub fn bits(&self) -> u32 {
+        self.width * self.height
+    }
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        HashConfig::square(HashAlgorithm::default(), 8)
+    }
+}
+
+impl std::fmt::Display for HashConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} {}x{}", self.algorithm, self.width, self.height)
+    }
+}
+
+/// Transformed copies of each frame hashed next to the frame itself, so a
+/// flipped or rotated repost still matches its original
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Invariance {
+    /// Frames are compared as they are
+    #[default]
+    None,
+    /// Also match horizontally mirrored frames
+    Mirror,
+    /// Also match mirrored frames and frames rotated by 90, 180 or 270 degrees
+    Rotation,
+}
+
+impl std::str::FromStr for Invariance {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" => Ok(Invariance::None),
+            "mirror" => Ok(Invariance::Mirror),
+            "rotation" => Ok(Invariance::Rotation),
+            other => anyhow::bail!("Unknown invariance: {}", other),
+        }
+    }
+}
+
+/// Crop-resistant matching: frames are also split into a grid of square
+/// regions a third of the longer side wide, and two frames match when enough
+/// regions match even though their whole-frame hashes don't
+/// A square image gets a 3x3 grid, a 16:9 crop of it a single row of 3
+/// regions that lines up with a row of the original
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropResistance {
+    /// Regions that must match for two frames to match
+    pub min_matching_regions: usize,
+    /// Maximum hamming distance (exclusive) for two regions to match
+    pub region_threshold: u32,
+}
+
+impl Default for CropResistance {
+    fn default() -> Self {
+        CropResistance {
+            min_matching_regions: 3,
+            region_threshold: 10,
+        }
+    }
+}
+
+/// How similar pairs are turned into groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Linkage {
+    /// Each unassigned asset collects the assets similar to itself, in asset id order
+    #[default]
+    Anchor,
+    /// All similar pairs are joined, so A~B and B~C put A, B and C in one group
+    Transitive,
+}
+
+impl std::str::FromStr for Linkage {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "anchor" => Ok(Linkage::Anchor),
+            "transitive" => Ok(Linkage::Transitive),
+            other => anyhow::bail!("Unknown linkage: {}", other),
+        }
+    }
+}
+
+/// How groups are named from their members' file names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NamingStrategy {
+    /// Longest common leading words of the member names, see `derive_group_name`
+    #[default]
+    CommonPrefix,
+    /// Base name of the first member, with size and placement suffixes stripped
+    FirstMember,
+}
+
+impl std::str::FromStr for NamingStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "commonprefix" | "common_prefix" => Ok(NamingStrategy::CommonPrefix),
+            "firstmember" | "first_member" => Ok(NamingStrategy::FirstMember),
+            other => anyhow::bail!("Unknown naming strategy: {}", other),
+        }
+    }
+}
+
+/// How sample times are chosen within a video
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum FrameStrategy {
+    /// `max_frames` frames evenly spaced across the whole duration
+    #[default]
+    Uniform,
+    /// One frame every N seconds from the start. Videos needing more than
+    /// `max_frames_per_video` get that many frames evenly spaced instead
+    Interval(f64),
+    /// The first `max` keyframes, decoded in a single pass without seeking
+    Keyframes { max: usize },
+    /// The first frame of each scene, up to `max`. A scene starts when
+    /// consecutive frame hashes differ by more than `cut_threshold` bits
+    Scenes { max: usize, cut_threshold: u32 },
+}
+
+/// Sampling actually applied to a video or animation, which differs from the
+/// configured strategy once `max_frames_per_video` caps interval sampling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SamplingPolicy {
+    Uniform,
+    Interval,
+    /// Interval sampling would have gone over `max_frames_per_video`, that many
+    /// frames were spread evenly over the whole duration instead
+    CappedUniform,
+    Keyframes,
+    Scenes,
+}
+
+impl SamplingPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SamplingPolicy::Uniform => "uniform",
+            SamplingPolicy::Interval => "interval",
+            SamplingPolicy::CappedUniform => "cappedUniform",
+            SamplingPolicy::Keyframes => "keyframes",
+            SamplingPolicy::Scenes => "scenes",
+        }
+    }
+}
+
+impl std::str::FromStr for SamplingPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "uniform" => Ok(SamplingPolicy::Uniform),
+            "interval" => Ok(SamplingPolicy::Interval),
+            "cappeduniform" | "capped_uniform" => Ok(SamplingPolicy::CappedUniform),
+            "keyframes" => Ok(SamplingPolicy::Keyframes),
+            "scenes" => Ok(SamplingPolicy::Scenes),
+            other => anyhow::bail!("Unknown sampling policy: {}", other),
+        }
+    }
+}
+
+/// How the decoder reaches the sample times of `Uniform` and `Interval` sampling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ExtractionMode {
+    /// Seek to every sample time, switching to `SequentialDecode` for the
+    /// remaining ones when a seek fails
+    #[default]
+    Seek,
+    /// Decode the file once from the start and keep the frame closest to each
+    /// sample time as it goes by, never seeking. Faster on short long-GOP
+    /// videos and reliable on containers with a poor index such as MPEG-TS
+    SequentialDecode,
+}
+
+impl std::str::FromStr for ExtractionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "seek" => Ok(ExtractionMode::Seek),
+            "sequential" | "sequentialdecode" | "sequential_decode" => {
+                Ok(ExtractionMode::SequentialDecode)
+            }
+            other => anyhow::bail!("Unknown extraction mode: {}", other),
+        }
+    }
+}
+
+/// Hash bits that must change between consecutive frames to count as a cut
+pub const DEFAULT_SCENE_CUT_THRESHOLD: u32 = 16;
+
+/// Subgroup threshold used when two-tier grouping is asked for without one
+pub const DEFAULT_STRICT_THRESHOLD: u32 = 5;
+
+/// Encoding of video frames written to disk or returned in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FrameFormat {
+    #[default]
+    Png,
+    /// Lossy, `quality` from 1 to 100
+    Jpeg { quality: u8 },
+    /// Lossless WebP
+    Webp,
+}
+
+impl FrameFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FrameFormat::Png => "png",
+            FrameFormat::Jpeg { .. } => "jpg",
+            FrameFormat::Webp => "webp",
+        }
+    }
+
+    /// Encode `frame` into `writer`
+    pub fn write_to<W: std::io::Write + std::io::Seek>(
+        self,
+        frame: &image::RgbImage,
+        writer: &mut W,
+    ) -> image::ImageResult<()> {
+        match self {
+            FrameFormat::Png => frame.write_to(writer, image::ImageFormat::Png),
+            FrameFormat::Jpeg { quality } => frame.write_with_encoder(
+                image::codecs::jpeg::JpegEncoder::new_with_quality(writer, quality),
+            ),
+            FrameFormat::Webp => frame.write_to(writer, image::ImageFormat::WebP),
+        }
+    }
+
+    /// Encoded bytes of `frame`
+    pub fn encode(self, frame: &image::RgbImage) -> anyhow::Result<Vec<u8>> {
+        use anyhow::Context;
+
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        self.write_to(frame, &mut bytes)
+            .with_context(|| format!("Failed to encode frame as {}", self.extension()))?;
+        Ok(bytes.into_inner())
+    }
+}
+
+/// Format and names of video frames written to disk
+/// Frames are always hashed from the decoded image, never from these files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameFileOptions {
+    pub format: FrameFormat,
+    /// File name of each frame, `{index}` becomes the sample index and `{ext}`
+    /// the extension of `format`
+    pub file_name: String,
+}
+
+impl Default for FrameFileOptions {
+    fn default() -> Self {
+        FrameFileOptions {
+            format: FrameFormat::Png,
+            file_name: "frame_{index}.{ext}".to_string(),
+        }
+    }
+}
+
+impl FrameFileOptions {
+    /// Path of frame `index` inside `dir`
+    pub fn frame_path(&self, dir: &Path, index: usize) -> PathBuf {
+        let file_name = self
+            .file_name
+            .replace("{index}", &index.to_string())
+            .replace("{ext}", self.format.extension());
+        dir.join(file_name)
+    }
+
+    /// Encode `frame` into the file at `path`
+    pub fn save(&self, frame: &image::RgbImage, path: &Path) -> anyhow::Result<()> {
+        use anyhow::Context;
+        use std::io::Write;
+
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.format
+            .write_to(frame, &mut writer)
+            .with_context(|| format!("Failed to encode {}", path.display()))?;
+
+        writer.flush().with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let FrameFormat::Jpeg { quality } = self.format
+            && !(1..=100).contains(&quality)
+        {
+            anyhow::bail!("JPEG quality must be between 1 and 100, got {}", quality);
+        }
+
+        // Every frame needs its own file inside the output directory
+        if !self.file_name.contains("{index}") {
+            anyhow::bail!("Frame file name {} has no {{index}}", self.file_name);
+        }
+        if self.file_name.contains(['/', '\\']) {
+            anyhow::bail!("Frame file name {} must not contain a path", self.file_name);
+        }
+
+        Ok(())
+    }
+}
+
+/// Where group thumbnails are written and how large they are
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThumbnailOptions {
+    /// Directory receiving one `<group id>.jpg` per group, created if missing
+    pub dir: PathBuf,
+    /// Longest side in pixels, the aspect ratio is kept
+    pub size: u32,
+}
+
+/// Luma variance under which a video frame counts as uniform, a standard
+/// deviation of 4 levels out of 255
+pub const DEFAULT_MIN_LUMA_VARIANCE: f64 = 16.0;
+
+/// Sampling policy for video frames
+/// Whatever the strategy, a video gets at least one frame, even when shorter
+/// than `min_interval`, and at most `max_frames_per_video`. Sample times
+/// increase and stay within the duration
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameSamplingConfig {
+    pub max_frames: usize,
+    /// Hard limit on the frames of one video, over `max_frames` and the `max`
+    /// of keyframe and scene sampling. Keeps hour-long recordings from
+    /// producing thousands of frames to store and compare
+    pub max_frames_per_video: usize,
+    /// Minimum spacing between uniform samples, so short videos get fewer frames
+    pub min_interval: f64,
+    pub strategy: FrameStrategy,
+    /// Seconds a single video may spend decoding, extraction then stops with
+    /// the frames found so far. `None` never gives up
+    pub timeout_secs: Option<f64>,
+    /// Packets read while looking for one target frame before it is skipped
+    pub max_packets_per_frame: usize,
+    /// Longest side in pixels frames are decoded at, aspect ratio kept.
+    /// Frames are hashed at a far smaller size, so this only bounds memory
+    pub max_dimension: Option<u32>,
+    /// Frames whose luma variance is below this are passed over for the next
+    /// frame nearby, black frames of fades and flat end cards hash alike in
+    /// every video. A video with nothing else keeps one frame flagged `uniform`.
+    /// 0 keeps every frame
+    pub min_luma_variance: f64,
+    /// Seeking or a single sequential pass, keyframe and scene sampling are
+    /// always sequential
+    pub extraction_mode: ExtractionMode,
+    /// Decode on VideoToolbox, VAAPI or D3D11VA when available, software
+    /// decoding takes over when the device or a frame copy fails
+    pub hw_accel: bool,
+}
+
+impl Default for FrameSamplingConfig {
+    fn default() -> Self {
+        FrameSamplingConfig {
+            max_frames: 8,
+            max_frames_per_video: 16,
+            min_interval: 1.0,
+            strategy: FrameStrategy::Uniform,
+            timeout_secs: Some(120.0),
+            max_packets_per_frame: 10_000,
+            max_dimension: Some(512),
+            min_luma_variance: DEFAULT_MIN_LUMA_VARIANCE,
+            extraction_mode: ExtractionMode::Seek,
+            hw_accel: false,
+        }
+    }
+}
+
+impl FrameSamplingConfig {
+    /// Sample times in seconds for a video lasting `duration` seconds
+    /// Uniform samples sit in the middle of equal segments, which skips the
+    /// fade-in of the very first frame
+    /// Keyframe and scene sampling have no target times and return an empty list
+    pub fn frame_times(&self, duration: f64) -> Vec<f64> {
+        if let FrameStrategy::Keyframes { .. } | FrameStrategy::Scenes { .. } = self.strategy {
+            return Vec::new();
+        }
+
+        if duration.is_nan() || duration <= 0.0 {
+            return vec![0.0];
+        }
+
+        let uniform = |count: usize| {
+            let segment = duration / count as f64;
+            (0..count).map(|i| (i as f64 + 0.5) * segment).collect()
+        };
+
+        let mut frame_times = match (self.strategy, self.sampling_policy(duration)) {
+            (_, SamplingPolicy::CappedUniform) => uniform(self.max_frames_per_video),
+            (FrameStrategy::Interval(interval), _) => (0..interval_count(duration, interval))
+                .map(|i| i as f64 * interval)
+                .collect(),
+            (FrameStrategy::Uniform, _) => {
+                let max = self.max_frames.min(self.max_frames_per_video);
+                uniform(((duration / self.min_interval) as usize).clamp(1, max))
+            }
+            (FrameStrategy::Keyframes { .. } | FrameStrategy::Scenes { .. }, _) => Vec::new(),
+        };
+
+        if frame_times.is_empty() {
+            frame_times.push(0.0);
+        }
+
+        frame_times
+    }
+
+    /// Policy `frame_times` follows for a video lasting `duration` seconds
+    pub fn sampling_policy(&self, duration: f64) -> SamplingPolicy {
+        match self.strategy {
+            FrameStrategy::Uniform => SamplingPolicy::Uniform,
+            FrameStrategy::Interval(interval)
+                if interval_count(duration, interval) > self.max_frames_per_video =>
+            {
+                SamplingPolicy::CappedUniform
+            }
+            FrameStrategy::Interval(_) => SamplingPolicy::Interval,
+            FrameStrategy::Keyframes { .. } => SamplingPolicy::Keyframes,
+            FrameStrategy::Scenes { .. } => SamplingPolicy::Scenes,
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_frames == 0 {
+            anyhow::bail!("max_frames must be at least 1");
+        }
+
+        if self.max_frames_per_video == 0 {
+            anyhow::bail!("max_frames_per_video must be at least 1");
+        }
+
+        if self.min_interval.is_nan() || self.min_interval <= 0.0 {
+            anyhow::bail!("min_interval must be positive, got {}", self.min_interval);
+        }
+
+        if let FrameStrategy::Interval(interval) = self.strategy
+            && (interval.is_nan() || interval <= 0.0)
+        {
+            anyhow::bail!("Frame interval must be positive, got {}", interval);
+        }
+
+        if let FrameStrategy::Keyframes { max: 0 } = self.strategy {
+            anyhow::bail!("Keyframe sampling needs at least 1 frame");
+        }
+
+        if let FrameStrategy::Scenes { max: 0, .. } = self.strategy {
+            anyhow::bail!("Scene sampling needs at least 1 frame");
+        }
+
+        if let Some(timeout) = self.timeout_secs
+            && !(timeout > 0.0 && timeout.is_finite())
+        {
+            anyhow::bail!("timeout_secs must be positive, got {}", timeout);
+        }
+
+        if self.max_packets_per_frame == 0 {
+            anyhow::bail!("max_packets_per_frame must be at least 1");
+        }
+
+        if self.max_dimension == Some(0) {
+            anyhow::bail!("max_dimension must be at least 1");
+        }
+
+        if !(self.min_luma_variance >= 0.0 && self.min_luma_variance.is_finite()) {
+            anyhow::bail!("min_luma_variance must be 0 or more, got {}", self.min_luma_variance);
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of `interval` steps from 0 that fall before `duration`
+fn interval_count(duration: f64, interval: f64) -> usize {
+    if duration > 0.0 && interval > 0.0 {
+        (duration / interval).ceil() as usize
+    } else {
+        1
+    }
+}
+
+/// Which members of an existing group new assets are compared against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AssignmentMode {
+    /// Only the group's representative, one comparison per group
+    #[default]
+    Representative,
+    /// Every member, the asset joins when it is similar to any of them
+    AllMembers,
+}
+
+impl std::str::FromStr for AssignmentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "representative" => Ok(AssignmentMode::Representative),
+            "allmembers" | "all_members" => Ok(AssignmentMode::AllMembers),
+            other => anyhow::bail!("Unknown assignment mode: {}", other),
+        }
+    }
+}
+
+/// What a difference in the audio of two matching videos does to their group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AudioMode {
+    /// Audio is not decoded, only the frames decide
+    #[default]
+    Ignore,
+    /// Videos with different audio never share a group
+    Separate,
+    /// Videos with different audio are grouped, members whose audio differs
+    /// from the representative's are flagged
+    Annotate,
+}
+
+impl std::str::FromStr for AudioMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(AudioMode::Ignore),
+            "separate" => Ok(AudioMode::Separate),
+            "annotate" => Ok(AudioMode::Annotate),
+            other => anyhow::bail!("Unknown audio mode: {}", other),
+        }
+    }
+}
+
+/// Tunables for the grouping pipeline, defaults match the original behavior
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupingOptions {
+    /// Maximum hamming distance (exclusive) for two frames to match
+    pub threshold: u32,
+    /// Threshold as a fraction of the hash bits, replaces `threshold` when set
+    /// so the same value works for 8x8 and 16x16 hashes
+    pub threshold_ratio: Option<f64>,
+    /// Tighter threshold splitting every group into subgroups of re-encoded
+    /// copies, `None` keeps the output flat
+    pub strict_threshold: Option<u32>,
+    /// Hash grid side length, the hash has `hash_size * hash_size` bits
+    pub hash_size: u32,
+    pub hash_algorithm: HashAlgorithm,
+    /// Equalize the brightness and contrast of frames before hashing
+    pub normalize: bool,
+    /// RGB color transparent images are composited onto before hashing
+    pub alpha_background: [u8; 3],
+    /// How images and frames are fitted into the square they are hashed from
+    pub resize_strategy: ResizeStrategy,
+    /// Side in pixels of that square
+    pub resize_target_size: u32,
+    /// Mirrored and rotated variants also hashed and matched, costs one extra
+    /// hash per variant per frame
+    pub invariance: Invariance,
+    /// Match crops of the same creative by grid regions, costs one hash per
+    /// region per frame. Aspect ratios aren't compared in this mode
+    pub crop_resistance: Option<CropResistance>,
+    /// How frames are sampled from videos
+    pub frame_sampling: FrameSamplingConfig,
+    /// Fraction of compared frames that must match for two assets to be similar
+    pub match_frames_ratio: f64,
+    /// Abort the whole run on the first asset that fails to process. Missing,
+    /// empty and unreadable files are checked for before any decoding and all
+    /// reported at once, in `failed` otherwise
+    pub fail_fast: bool,
+    /// Attach the pairwise member distances to each group
+    pub include_group_distances: bool,
+    pub linkage: Linkage,
+    pub naming_strategy: NamingStrategy,
+    /// Asset id pairs always put into the same group, whatever their distance
+    pub must_link: Vec<(String, String)>,
+    /// Asset id pairs never put into the same group, wins over `must_link`
+    pub cannot_link: Vec<(String, String)>,
+    /// Maximum frame shift tried when aligning two videos, 0 compares frame i with frame i
+    pub frame_offset_tolerance: usize,
+    /// Minimum number of overlapping frames an alignment needs to be considered
+    pub min_frame_overlap: usize,
+    /// Maximum relative aspect ratio difference, `|a - b| / max(a, b)`, for two
+    /// assets to be compared at all. `None`, the default, disables the check so
+    /// 1:1, 4:5 and 9:16 resizes of one creative still group
+    pub max_aspect_ratio_delta: Option<f64>,
+    /// Minimum length in pixels of the shorter side, smaller assets never match
+    pub min_resolution: u32,
+    /// Narrowest width in pixels an asset can have, narrower ones fail instead
+    /// of being hashed
+    pub min_width: u32,
+    /// Lowest height in pixels an asset can have, lower ones fail instead of
+    /// being hashed
+    pub min_height: u32,
+    /// Longest allowed ratio between two video durations before frames are compared
+    pub max_duration_ratio: f64,
+    /// Compare videos regardless of duration, for trimmed cuts of a longer video
+    pub allow_trimmed_variants: bool,
+    /// Also slide the shorter video's frames along the longer one's, so a
+    /// cutdown matches the master it was cut from. Costs O(n·m) per pair and
+    /// needs frames sampled at a fixed interval to line up
+    pub detect_clips: bool,
+    /// Let an image match a video when its hash is close to any of the video's frames
+    pub allow_image_video_matching: bool,
+    /// Group members compared when assigning new assets into existing groups
+    pub assignment_mode: AssignmentMode,
+    /// Number of assets hashed in parallel, defaults to the core count capped
+    /// at `DEFAULT_MAX_CONCURRENCY` since every video decoder holds its own buffers
+    pub max_concurrency: Option<usize>,
+    /// While grouping, pairs whose first frames are `threshold + slack` or more
+    /// apart are rejected without the full comparison. Exact with the default
+    /// `match_frames_ratio` of 1.0, `None` disables the prefilter
+    pub prefilter_slack: Option<u32>,
+    /// Directory to keep copies of the extracted video frames in, one
+    /// subdirectory per asset. Frames are hashed in memory either way
+    pub frames_dir: Option<PathBuf>,
+    /// Format and names of the frame files written to `frames_dir`
+    pub frame_files: FrameFileOptions,
+    /// File caching frame hashes across runs, keyed by path, size and mtime
+    /// Nothing is cached when unset
+    pub cache_path: Option<PathBuf>,
+    /// Hash the file content of every asset first and decode only one copy of
+    /// each set of byte-identical files
+    pub detect_exact_duplicates: bool,
+    /// Merge inputs pointing at the same canonical path into one asset hashed
+    /// once, the other ids are listed in the member's `alias_ids`
+    pub dedupe_by_path: bool,
+    /// Fail assets whose content contradicts their `is_video` flag instead of
+    /// correcting the flag
+    pub strict_type_check: bool,
+    /// Write a thumbnail of every group's representative after grouping
+    pub thumbnails: Option<ThumbnailOptions>,
+    /// Compute a 4x3 BlurHash of every asset from its comparison image,
+    /// returned on the grouped assets
+    pub blurhash: bool,
+    /// Bits added to the mean frame distance of a matching pair per unit of
+    /// palette distance, as a fraction of the hash bits. Wildly different
+    /// dominant colors then veto borderline matches, 0 ignores colors
+    pub color_weight: f64,
+    /// Consecutive video frames less than this many bits apart are collapsed
+    /// into the first of them before comparing, so a static shot counts once.
+    /// 0 keeps every sampled frame
+    pub frame_dedup_distance: u32,
+    /// Seconds allowed for fetching each http(s) asset, `None` waits indefinitely
+    pub request_timeout_secs: Option<f64>,
+    /// Largest http(s) asset downloaded in bytes, larger ones fail.
+    /// `None` downloads any size
+    pub max_download_bytes: Option<u64>,
+    /// Fingerprint the audio of videos and split or flag matches that sound
+    /// different, such as one cut with several voiceovers
+    pub audio_mode: AudioMode,
+    /// Assets taking longer than this many milliseconds to process are logged
+    /// as warnings, `None` never warns
+    pub slow_asset_threshold_ms: Option<u64>,
+}
+
+/// Dominant colors extracted per asset
+pub const DOMINANT_COLOR_COUNT: usize = 3;
+
+/// Download cap of http(s) assets, 2 GiB
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 2 << 30;
+
+/// Upper bound on the default hashing concurrency
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+impl Default for GroupingOptions {
+    fn default() -> Self {
+        GroupingOptions {
+            threshold: 15,
+            threshold_ratio: None,
+            strict_threshold: None,
+            hash_size: 8,
+            hash_algorithm: HashAlgorithm::Blockhash,
+            normalize: false,
+            alpha_background: DEFAULT_ALPHA_BACKGROUND,
+            resize_strategy: ResizeStrategy::Cover,
+            resize_target_size: DEFAULT_TARGET_SIZE,
+            invariance: Invariance::None,
+            crop_resistance: None,
+            frame_sampling: FrameSamplingConfig::default(),
+            match_frames_ratio: 1.0,
+            fail_fast: false,
+            include_group_distances: false,
+            linkage: Linkage::Anchor,
+            naming_strategy: NamingStrategy::CommonPrefix,
+            must_link: Vec::new(),
+            cannot_link: Vec::new(),
+            frame_offset_tolerance: 0,
+            min_frame_overlap: 1,
+            max_aspect_ratio_delta: None,
+            min_resolution: 0,
+            min_width: 16,
+            min_height: 16,
+            max_duration_ratio: 2.0,
+            allow_trimmed_variants: false,
+            detect_clips: false,
+            allow_image_video_matching: false,
+            assignment_mode: AssignmentMode::Representative,
+            max_concurrency: None,
+            prefilter_slack: Some(8),
+            frames_dir: None,
+            frame_files: FrameFileOptions::default(),
+            cache_path: None,
+            detect_exact_duplicates: false,
+            dedupe_by_path: false,
+            strict_type_check: false,
+            thumbnails: None,
+            blurhash: false,
+            color_weight: 0.0,
+            frame_dedup_distance: 3,
+            request_timeout_secs: Some(60.0),
+            max_download_bytes: Some(DEFAULT_MAX_DOWNLOAD_BYTES),
+            audio_mode: AudioMode::Ignore,
+            slow_asset_threshold_ms: Some(30_000),
+        }
+    }
+}
+
+impl GroupingOptions {
+    /// Hash algorithm and size used for every frame
+    pub fn hash_config(&self) -> HashConfig {
+        HashConfig {
+            normalize: self.normalize,
+            alpha_background: self.alpha_background,
+            resize: self.resize_strategy,
+            target_size: self.resize_target_size,
+            ..HashConfig::square(self.hash_algorithm, self.hash_size)
+        }
+    }
+
+    /// Hashes computed next to the primary hash of every frame
+    pub fn hash_extras(&self) -> HashExtras {
+        HashExtras {
+            invariance: self.invariance,
+            regions: self.crop_resistance.is_some(),
+            blurhash: self.blurhash,
+            dominant_colors: DOMINANT_COLOR_COUNT,
+        }
+    }
+
+    /// Number of bits in a hash produced with these options
+    pub fn hash_bits(&self) -> u32 {
+        self.hash_config().bits()
+    }
+
+    /// Bit threshold for comparing hashes of `hash_bits` bits
+    pub fn threshold_for_bits(&self, hash_bits: u32) -> u32 {
+        match self.threshold_ratio {
+            Some(ratio) => (ratio * hash_bits as f64).round() as u32,
+            None => self.threshold,
+        }
+    }
+
+    /// Bit threshold for hashes produced with these options
+    pub fn effective_threshold(&self) -> u32 {
+        self.threshold_for_bits(self.hash_bits())
+    }
+
+    /// Number of bytes in a hash produced with these options
+    pub fn hash_bytes(&self) -> usize {
+        self.hash_bits().div_ceil(8) as usize
+    }
+
+    /// Number of worker threads used to hash assets
+    pub fn concurrency(&self) -> usize {
+        self.max_concurrency.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map_or(1, |cores| cores.get())
+                .min(DEFAULT_MAX_CONCURRENCY)
+        })
+    }
+
+    /// Reject invalid option combinations before any work starts
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(2..=32).contains(&self.hash_size) {
+            anyhow::bail!("hash_size must be between 2 and 32, got {}", self.hash_size);
+        }
+
+        if !(32..=1024).contains(&self.resize_target_size) {
+            anyhow::bail!(
+                "resize_target_size must be between 32 and 1024, got {}",
+                self.resize_target_size
+            );
+        }
+
+        if let Some(ratio) = self.threshold_ratio
+            && (ratio.is_nan() || ratio <= 0.0 || ratio >= 1.0)
+        {
+            anyhow::bail!("threshold_ratio must be in (0, 1), got {}", ratio);
+        }
+
+        if self.threshold_ratio.is_none() && self.threshold > self.hash_bits() {
+            anyhow::bail!(
+                "threshold {} is larger than the {} bits of a {}x{} hash",
+                self.threshold,
+                self.hash_bits(),
+                self.hash_size,
+                self.hash_size
+            );
+        }
+
+        if let Some(strict_threshold) = self.strict_threshold
+            && strict_threshold > self.effective_threshold()
+        {
+            anyhow::bail!(
+                "strict_threshold {} is larger than the grouping threshold {}",
+                strict_threshold,
+                self.effective_threshold()
+            );
+        }
+
+        if let Some(crop) = self.crop_resistance {
+            if !(1..=9).contains(&crop.min_matching_regions) {
+                anyhow::bail!(
+                    "min_matching_regions must be between 1 and 9, got {}",
+                    crop.min_matching_regions
+                );
+            }
+
+            if crop.region_threshold > self.hash_bits() {
+                anyhow::bail!(
+                    "region_threshold {} is larger than the {} bits of a region hash",
+                    crop.region_threshold,
+                    self.hash_bits()
+                );
+            }
+        }
+
+        self.frame_sampling.validate()?;
+        self.frame_files.validate()?;
+
+        if self.thumbnails.as_ref().is_some_and(|thumbnails| thumbnails.size == 0) {
+            anyhow::bail!("Thumbnail size must be at least 1");
+        }
+
+        let ratio = self.match_frames_ratio;
+        if ratio.is_nan() || ratio <= 0.0 || ratio > 1.0 {
+            anyhow::bail!(
+                "match_frames_ratio must be in (0, 1], got {}",
+                self.match_frames_ratio
+            );
+        }
+
+        if let Some(delta) = self.max_aspect_ratio_delta
+            && (delta.is_nan() || delta < 0.0)
+        {
+            anyhow::bail!("max_aspect_ratio_delta must not be negative, got {}", delta);
+        }
+
+        if self.max_concurrency == Some(0) {
+            anyhow::bail!("max_concurrency must be at least 1");
+        }
+
+        let ratio = self.max_duration_ratio;
+        if ratio.is_nan() || ratio < 1.0 {
+            anyhow::bail!("max_duration_ratio must be at least 1, got {}", ratio);
+        }
+
+        if !self.color_weight.is_finite() || self.color_weight < 0.0 {
+            anyhow::bail!("color_weight must not be negative, got {}", self.color_weight);
+        }
+
+        if let Some(timeout) = self.request_timeout_secs
+            && !(timeout > 0.0 && timeout.is_finite())
+        {
+            anyhow::bail!("request_timeout_secs must be positive, got {}", timeout);
+        }
+
+        Ok(())
+    }
+}
+
+/// Pipeline phase reported through progress events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    Hashing,
+    Grouping,
+}
+
+impl ProgressPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProgressPhase::Hashing => "hashing",
+            ProgressPhase::Grouping => "grouping",
+        }
+    }
+}
+
+/// Progress notification emitted while grouping assets
+#[derive(Debug, Clone)]
+pub struct ProgressEvent {
+    pub phase: ProgressPhase,
+    pub current: usize,
+    pub total: usize,
+    pub asset_id: Option<String>,
+}
+
+/// Shared flag used to abort a running grouping job
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Return a `Cancelled` error if cancellation was requested
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static LOG_SCOPE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Job the log records of this thread belong to, so a logger can keep the
+/// records of concurrent jobs apart. Worker pools take the scope of the
+/// thread that builds them
+pub fn log_scope() -> Option<u64> {
+    LOG_SCOPE.with(Cell::get)
+}
+
+/// Set the log scope of this thread, returning the previous one
+pub fn set_log_scope(scope: Option<u64>) -> Option<u64> {
+    LOG_SCOPE.with(|current| current.replace(scope))
+}
+
+/// Error returned when a job is aborted through its `CancellationToken`
+#[derive(Debug, Clone, Copy)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Error for an asset processed as a video whose file holds no video stream,
+/// such as an audio-only file. Reported as a failed asset without aborting
+/// the run, even with `fail_fast`
+#[derive(Debug, Clone, Copy)]
+pub struct NoVideoStream;
+
+impl std::fmt::Display for NoVideoStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no video stream")
+    }
+}
+
+impl std::error::Error for NoVideoStream {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform_sampling_is_capped_by_duration() {
+        let sampling = FrameSamplingConfig::default();
+
+        assert_eq!(sampling.frame_times(120.0).len(), 8);
+        assert_eq!(sampling.frame_times(3.0), vec![0.5, 1.5, 2.5]);
+        assert_eq!(sampling.frame_times(10.0133).len(), 8);
+    }
+
+    #[test]
+    fn test_short_videos_always_get_a_frame() {
+        let uniform = FrameSamplingConfig::default();
+        assert_eq!(uniform.frame_times(0.4), vec![0.2]);
+        assert_eq!(uniform.frame_times(0.0), vec![0.0]);
+        assert_eq!(uniform.frame_times(f64::NAN), vec![0.0]);
+
+        let interval = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(5.0),
+            ..FrameSamplingConfig::default()
+        };
+        assert_eq!(interval.frame_times(1.5), vec![0.0]);
+        assert_eq!(interval.frame_times(12.0), vec![0.0, 5.0, 10.0]);
+    }
+
+    #[test]
+    fn test_long_videos_are_capped_and_sampled_across_the_whole_duration() {
+        let interval = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(5.0),
+            ..FrameSamplingConfig::default()
+        };
+        assert_eq!(interval.sampling_policy(12.0), SamplingPolicy::Interval);
+
+        // A two hour recording would need 1440 frames at one every 5 seconds
+        let duration = 2.0 * 60.0 * 60.0;
+        assert_eq!(interval.sampling_policy(duration), SamplingPolicy::CappedUniform);
+        let frame_times = interval.frame_times(duration);
+        assert_eq!(frame_times.len(), 16);
+        assert!(frame_times[0] < duration / 16.0);
+        assert!(frame_times[15] > duration * 15.0 / 16.0 && frame_times[15] < duration);
+        assert!(frame_times.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let uniform = FrameSamplingConfig { max_frames: 64, ..FrameSamplingConfig::default() };
+        assert_eq!(uniform.frame_times(duration).len(), 16);
+        assert_eq!(uniform.sampling_policy(duration), SamplingPolicy::Uniform);
+    }
+
+    #[test]
+    fn test_distance_refuses_mixed_hash_configs() {
+        let gradient = HashConfig::square(HashAlgorithm::Gradient, 8);
+        let dct = HashConfig::square(HashAlgorithm::Dct, 8);
+        let frame = |config, bytes| {
+            FrameData::new(0, 0.0, PerceptualHash::new(config, bytes).unwrap())
+        };
+
+        let a = frame(gradient, vec![0; 8]);
+        let b = frame(gradient, vec![0xFF; 8]);
+        let c = frame(dct, vec![0xFF; 8]);
+
+        assert_eq!(a.distance(&b).unwrap(), 64);
+        assert!(a.distance(&c).is_err());
+    }
+
+    #[test]
+    fn test_frame_files_are_named_and_encoded_per_options() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let frame =
+            image::RgbImage::from_fn(40, 30, |x, y| image::Rgb([x as u8 * 6, y as u8 * 8, 0]));
+
+        for (format, expected) in [
+            (FrameFormat::Png, image::ImageFormat::Png),
+            (FrameFormat::Jpeg { quality: 70 }, image::ImageFormat::Jpeg),
+            (FrameFormat::Webp, image::ImageFormat::WebP),
+        ] {
+            let files = FrameFileOptions {
+                format,
+                file_name: "preview-{index}.{ext}".to_string(),
+            };
+            files.validate().unwrap();
+
+            let path = files.frame_path(temp_dir.path(), 3);
+            assert_eq!(
+                path.file_name().unwrap().to_str().unwrap(),
+                format!("preview-3.{}", format.extension())
+            );
+            files.save(&frame, &path).unwrap();
+
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap(), expected);
+            let decoded = image::load_from_memory(&bytes).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (40, 30));
+        }
+
+        let invalid = |format, file_name: &str| {
+            let files = FrameFileOptions {
+                format,
+                file_name: file_name.to_string(),
+            };
+            files.validate().is_err()
+        };
+        assert!(invalid(FrameFormat::Jpeg { quality: 0 }, "frame_{index}.{ext}"));
+        assert!(invalid(FrameFormat::Png, "frame.{ext}"));
+        assert!(invalid(FrameFormat::Png, "frames/{index}.{ext}"));
+    }
+
+    #[test]
+    fn test_grouped_asset_variant_follows_aspect_ratio() {
+        let variant = |width, height| {
+            let hashed_asset = HashedAsset {
+                asset: Asset {
+                    id: "a".to_string(),
+                    name: "a.png".to_string(),
+                    source: AssetSource::Path("a.png".into()),
+                    mime_type: "image/png".to_string(),
+                    is_video: false,
+                    precomputed: None,
+                    kind: AssetKind::File,
+                },
+                frames: Vec::new(),
+                aspect_ratio: width as f64 / height as f64,
+                width,
+                height,
+                duration: None,
+                degraded: false,
+                is_animated: false,
+                dominant_colors: Vec::new(),
+                audio_fingerprint: None,
+                sampling_policy: None,
+                decode_errors: 0,
+            };
+            GroupedAsset::from(&hashed_asset).variant
+        };
+
+        assert_eq!(variant(1080, 1080).as_deref(), Some("square"));
+        assert_eq!(variant(1080, 1100).as_deref(), Some("square"));
+        assert_eq!(variant(1080, 1350).as_deref(), Some("vertical"));
+        assert_eq!(variant(1080, 1920).as_deref(), Some("vertical"));
+        assert_eq!(variant(1920, 1080).as_deref(), Some("horizontal"));
+        assert_eq!(variant(0, 0), None);
+    }
 }
@@ -0,0 +1,217 @@
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+use super::Asset;
+
+/// Name a group after what its members' names have in common
+/// Member names are reduced with `extract_base_name` and split into words on
+/// separators and camelCase boundaries. The name is the longest common run of
+/// leading words (compared case-insensitively), else the word shared by the
+/// most members, else the base name of the first member.
+/// Words keep the casing of the first member and are joined with spaces
+pub fn derive_group_name(assets: &[Asset]) -> String {
+    let Some(first) = assets.first() else {
+        return String::new();
+    };
+    if assets.len() == 1 {
+        return extract_base_name(&first.name);
+    }
+
+    let words: Vec<Vec<String>> = assets
+        .iter()
+        .map(|asset| split_words(&extract_base_name(&asset.name)))
+        .collect();
+    let normalized: Vec<Vec<String>> = words
+        .iter()
+        .map(|words| words.iter().map(|word| word.to_lowercase()).collect())
+        .collect();
+
+    let prefix_len = (0..normalized[0].len())
+        .take_while(|&i| {
+            normalized
+                .iter()
+                .all(|words| words.get(i) == Some(&normalized[0][i]))
+        })
+        .count();
+    if prefix_len > 0 {
+        return words[0][..prefix_len].join(" ");
+    }
+
+    if let Some(word) = most_frequent_word(&words, &normalized) {
+        return word;
+    }
+
+    extract_base_name(&first.name)
+}
+
+/// Word found in the most member names, at least two, ties going to the word
+/// seen first. Bare numbers are skipped, they are usually counters or sizes
+fn most_frequent_word(words: &[Vec<String>], normalized: &[Vec<String>]) -> Option<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut first_seen: Vec<(&str, &str)> = Vec::new();
+
+    for (member_words, member_normalized) in words.iter().zip(normalized) {
+        let mut seen: HashSet<&str> = HashSet::new();
+        for (word, key) in member_words.iter().zip(member_normalized) {
+            let key = key.as_str();
+            if key.chars().all(|c| c.is_ascii_digit()) || !seen.insert(key) {
+                continue;
+            }
+            let count = counts.entry(key).or_insert(0);
+            if *count == 0 {
+                first_seen.push((key, word.as_str()));
+            }
+            *count += 1;
+        }
+    }
+
+    let mut best: Option<(&str, usize)> = None;
+    for (key, word) in first_seen {
+        let count = counts[key];
+        if count >= 2 && best.is_none_or(|(_, best_count)| count > best_count) {
+            best = Some((word, count));
+        }
+    }
+
+    best.map(|(word, _)| word.to_string())
+}
+
+/// Split a name into words on `_`, `-`, `.`, whitespace and lower-to-upper
+/// case changes, so "SummerSale_Story" gives "Summer", "Sale" and "Story"
+fn split_words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+
+    for part in name.split(|c: char| c == '_' || c == '-' || c == '.' || c.is_whitespace()) {
+        let mut word = String::new();
+        let mut after_lower = false;
+        for c in part.chars() {
+            if c.is_uppercase() && after_lower {
+                words.push(std::mem::take(&mut word));
+            }
+            after_lower = c.is_lowercase() || c.is_ascii_digit();
+            word.push(c);
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+    }
+
+    words
+}
+
+/// Trailing noise stripped from file names: sizes and placements (also glued
+/// to the name, as in "Promo1080x1920"), copy counters, "copy", "final",
+/// versions, resolutions, ISO dates and locale tags such as "_EN" or "_en-US"
+static TRAILING_NOISE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?:[\s_\-]*(?:\d{1,4}\s*[:xXw×]\s*\d{1,4}",
+        r"|(?i:post|story|stories|feed|infeed|square|vertical|horizontal|portrait|landscape))",
+        r"|(?:[\s_\-]+|\b)(?:\(\d+\)|\d{4}-\d{2}-\d{2}",
+        r"|(?i:copy(?:\s*\d+)?|final|v\d+|\d{3,4}p|4k))",
+        r"|[_\-](?:[A-Z]{2}|[a-z]{2}[_\-][A-Z]{2}))$",
+    ))
+    .expect("Invalid trailing noise pattern")
+});
+
+/// ISO date in front of a file name, as in "2024-03-01_Spring"
+static LEADING_DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\d{4}-\d{2}-\d{2}[\s_\-]+").expect("Invalid leading date pattern")
+});
+
+/// Extract base name from filename (remove extension and common suffixes)
+/// Suffixes are stripped one at a time until none is left, so chains like
+/// "Promo_Square_v2 (1)" reduce to "Promo". A name made only of noise is
+/// kept as is rather than emptied
+pub fn extract_base_name(filename: &str) -> String {
+    let stem = filename.rsplit_once('.').map_or(filename, |(name, _)| name).trim();
+
+    let mut base = match LEADING_DATE.replace(stem, "") {
+        dated if dated.is_empty() => stem.to_string(),
+        undated => undated.into_owned(),
+    };
+
+    loop {
+        let stripped = TRAILING_NOISE.replace(&base, "");
+        let stripped = stripped.trim_end();
+        if stripped.is_empty() || stripped.len() == base.len() {
+            return base;
+        }
+        base = stripped.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::{AssetKind, AssetSource};
+
+    fn named(names: &[&str]) -> Vec<Asset> {
+        names
+            .iter()
+            .map(|name| Asset {
+                id: name.to_string(),
+                name: name.to_string(),
+                source: AssetSource::Path(name.into()),
+                mime_type: "video/mp4".to_string(),
+                is_video: true,
+                precomputed: None,
+                kind: AssetKind::File,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_derive_group_name() {
+        let cases: [(&[&str], &str); 6] = [
+            (
+                &["SummerSale_Story.mp4", "SummerSale_Feed.mp4", "summer-sale-final.mp4"],
+                "Summer Sale",
+            ),
+            (&["Promo_1080x1920.mp4", "promo_1080x1080.mp4"], "Promo"),
+            (&["launch_teaser_a.mp4", "final_launch_cut.mp4"], "launch"),
+            (&["IMG_0001.jpg", "DSC_0001.jpg"], "IMG_0001"),
+            (&["Holiday_Square.mp4"], "Holiday"),
+            (&[], ""),
+        ];
+
+        for (names, expected) in cases {
+            assert_eq!(derive_group_name(&named(names)), expected, "{:?}", names);
+        }
+    }
+
+    #[test]
+    fn test_extract_base_name() {
+        let cases = [
+            ("SummerSale_1080x1920.mp4", "SummerSale"),
+            ("SummerSale_Story.mp4", "SummerSale"),
+            ("Summer-Sale-Horizontal-1920x1080.mp4", "Summer-Sale"),
+            ("Ad_9:16_Vertical.mp4", "Ad"),
+            ("Product Shot 4x5.png", "Product Shot"),
+            ("Promo_Square_v2 (1).mp4", "Promo"),
+            ("Banner (1).png", "Banner"),
+            ("Banner copy.png", "Banner"),
+            ("Banner - Copy.png", "Banner"),
+            ("Banner copy 2.png", "Banner"),
+            ("Hero_final.jpg", "Hero"),
+            ("Hero_FINAL_v3.jpg", "Hero"),
+            ("Campaign_Feed_final (2).mov", "Campaign"),
+            ("Teaser_1080p.mp4", "Teaser"),
+            ("Teaser-720p-v2.mp4", "Teaser"),
+            ("Brand_4K.mp4", "Brand"),
+            ("Launch_EN.mp4", "Launch"),
+            ("Launch_DE_1080x1080.mp4", "Launch"),
+            ("Launch_en-US.mp4", "Launch"),
+            ("Spring_2024-03-01.jpg", "Spring"),
+            ("2024-03-01_Spring.jpg", "Spring"),
+            ("Photocopy.png", "Photocopy"),
+            ("Final.mp4", "Final"),
+            ("Story.mp4", "Story"),
+            ("logo", "logo"),
+        ];
+
+        for (filename, expected) in cases {
+            assert_eq!(extract_base_name(filename), expected, "{}", filename);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::HashedAsset;
+use super::hash::pack_hash;
+
+/// Bumped whenever the file layout changes
+/// Files from older versions are still read, newer ones are refused
+pub const HASHED_ASSETS_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct HashedAssetsFileRef<'a> {
+    format_version: u32,
+    assets: &'a [HashedAsset],
+}
+
+#[derive(Deserialize)]
+struct HashedAssetsFile {
+    assets: Vec<HashedAsset>,
+}
+
+/// Only the version, read before the rest so a newer layout fails on the
+/// version rather than on some field it changed
+#[derive(Deserialize)]
+struct FormatHeader {
+    format_version: u32,
+}
+
+/// Write hashed assets to a JSON file, replacing it atomically
+/// Load them with `load_hashed_assets` and group them with `group_hashed_assets`
+/// to skip decoding entirely
+pub fn save_hashed_assets<P: AsRef<Path>>(path: P, hashed_assets: &[HashedAsset]) -> Result<()> {
+    let path = path.as_ref();
+    let file = HashedAssetsFileRef {
+        format_version: HASHED_ASSETS_FORMAT_VERSION,
+        assets: hashed_assets,
+    };
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let temp_file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a temp file in {}", dir.display()))?;
+    let mut writer = std::io::BufWriter::new(temp_file);
+    serde_json::to_writer(&mut writer, &file).context("Failed to write the hashed assets")?;
+    let temp_file = writer.into_inner().context("Failed to write the hashed assets")?;
+    temp_file
+        .persist(path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Read hashed assets written by `save_hashed_assets`
+/// Fails on files written by a newer version of the format
+pub fn load_hashed_assets<P: AsRef<Path>>(path: P) -> Result<Vec<HashedAsset>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let header: FormatHeader = serde_json::from_slice(&bytes)
+        .with_context(|| format!("{} is not a hashed assets file", path.display()))?;
+    if header.format_version > HASHED_ASSETS_FORMAT_VERSION {
+        anyhow::bail!(
+            "{} has format version {}, this build reads up to version {}, upgrade to load it",
+            path.display(),
+            header.format_version,
+            HASHED_ASSETS_FORMAT_VERSION
+        );
+    }
+
+    let file: HashedAssetsFile = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let mut hashed_assets = file.assets;
+    for hashed_asset in &mut hashed_assets {
+        // Deserialized frames carry no packed form, rebuild it
+        for frame in &mut hashed_asset.frames {
+            frame.packed = pack_hash(&frame.hash.bytes);
+        }
+    }
+
+    Ok(hashed_assets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::grouping::{group_hashed_assets, process_asset};
+    use crate::visual_grouping::{Asset, AssetKind, AssetSource, GroupingOptions};
+
+    #[test]
+    fn test_hashed_assets_round_trip() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let options = GroupingOptions::default();
+
+        let hashed_assets: Vec<HashedAsset> = [("a", 0), ("b", 4), ("c", 200)]
+            .into_iter()
+            .map(|(id, shift)| {
+                let image = image::RgbImage::from_fn(64, 64, |x, y| {
+                    let value = if (x + y) % 64 < 32 { shift } else { 255 - shift };
+                    image::Rgb([value as u8, 80, 160])
+                });
+                let mut bytes = std::io::Cursor::new(Vec::new());
+                image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+                let asset = Asset {
+                    id: id.to_string(),
+                    name: format!("{}.png", id),
+                    source: AssetSource::Bytes(bytes.into_inner()),
+                    mime_type: "image/png".to_string(),
+                    is_video: false,
+                    precomputed: None,
+                    kind: AssetKind::File,
+                };
+                process_asset(&asset, &options, None).unwrap().0
+            })
+            .collect();
+
+        let path = temp_dir.path().join("hashes").join("assets.json");
+        save_hashed_assets(&path, &hashed_assets).unwrap();
+        let loaded = load_hashed_assets(&path).unwrap();
+
+        assert_eq!(loaded.len(), hashed_assets.len());
+        for (loaded, original) in loaded.iter().zip(&hashed_assets) {
+            assert_eq!(loaded.asset.id, original.asset.id);
+            assert_eq!((loaded.width, loaded.height), (original.width, original.height));
+            assert_eq!(loaded.dominant_colors, original.dominant_colors);
+            assert_eq!(loaded.frames[0].hash, original.frames[0].hash);
+            assert_eq!(loaded.frames[0].packed, original.frames[0].packed);
+        }
+        let group_ids = |hashed_assets: &[HashedAsset]| -> Vec<Vec<String>> {
+            group_hashed_assets(hashed_assets, &options, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|group| group.assets.into_iter().map(|member| member.asset.id).collect())
+                .collect()
+        };
+        assert_eq!(group_ids(&loaded), group_ids(&hashed_assets));
+
+        let newer = std::fs::read_to_string(&path)
+            .unwrap()
+            .replacen(r#""format_version":1"#, r#""format_version":2"#, 1);
+        std::fs::write(&path, newer).unwrap();
+        let error = load_hashed_assets(&path).unwrap_err().to_string();
+        assert!(error.contains("format version 2"), "{}", error);
+    }
+}
@@ -0,0 +1,198 @@
+//! Assets whose path is an http(s) URL, fetched before they are decoded
+//! Images are kept in memory, videos go to a temp file ffmpeg can seek in
+
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tempfile::TempDir;
+
+use super::{Asset, AssetSource, GroupingOptions};
+
+/// Shared so connections to the same host are reused across assets
+static CLIENT: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+
+/// Download `url` and return a copy of `asset` reading from the download
+/// Videos are written to a file in the returned temp directory, which has to
+/// outlive decoding. Fails on error statuses, timeouts and downloads larger
+/// than `max_download_bytes`
+pub fn fetch_asset(
+    asset: &Asset,
+    url: &str,
+    options: &GroupingOptions,
+) -> Result<(Asset, Option<TempDir>)> {
+    let client = CLIENT.get_or_init(reqwest::blocking::Client::new);
+
+    let mut request = client.get(url);
+    if let Some(timeout) = options.request_timeout_secs {
+        request = request.timeout(Duration::from_secs_f64(timeout));
+    }
+    let response = request
+        .send()
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to fetch {}", url))?;
+
+    let max_bytes = options.max_download_bytes;
+    if let (Some(max_bytes), Some(length)) = (max_bytes, response.content_length())
+        && length > max_bytes
+    {
+        anyhow::bail!("{} is {} bytes, over the {} byte download limit", url, length, max_bytes);
+    }
+    // Servers can leave the length out, so the body is capped one byte past the limit
+    let mut body = response.take(max_bytes.map_or(u64::MAX, |max_bytes| max_bytes + 1));
+
+    if asset.is_video {
+        let dir = TempDir::new().context("Failed to create temp directory")?;
+        let video_path = dir.path().join("source_video");
+        let mut file =
+            std::fs::File::create(&video_path).context("Failed to create the download file")?;
+        let written = std::io::copy(&mut body, &mut file)
+            .with_context(|| format!("Failed to download {}", url))?;
+        check_download_size(url, written, max_bytes)?;
+
+        let local = Asset {
+            source: AssetSource::Path(video_path),
+            ..asset.clone()
+        };
+        Ok((local, Some(dir)))
+    } else {
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes)
+            .with_context(|| format!("Failed to download {}", url))?;
+        check_download_size(url, bytes.len() as u64, max_bytes)?;
+
+        let local = Asset {
+            source: AssetSource::Bytes(bytes),
+            ..asset.clone()
+        };
+        Ok((local, None))
+    }
+}
+
+fn check_download_size(url: &str, size: u64, max_bytes: Option<u64>) -> Result<()> {
+    match max_bytes {
+        Some(max_bytes) if size > max_bytes => {
+            anyhow::bail!("{} is over the {} byte download limit", url, max_bytes)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::AssetKind;
+    use crate::visual_grouping::grouping::{group_assets_by_visual_similarity, process_asset};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Serve `files` by path, anything else is a 404. Returns the base URL
+    fn serve(files: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                let _ = reader.read_line(&mut request_line);
+                let mut line = String::new();
+                // Headers end with an empty line
+                while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                    line.clear();
+                }
+
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = match files.iter().find(|(file, _)| *file == path) {
+                    Some((_, body)) => ("200 OK", body.as_slice()),
+                    None => ("404 Not Found", &[][..]),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                // The client hangs up early on bodies over the limit
+                let _ = stream.write_all(head.as_bytes()).and_then(|()| stream.write_all(body));
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    fn png() -> Vec<u8> {
+        let image = image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([
+                (x * 4) as u8,
+                (y * 4) as u8,
+                if (x / 8 + y / 8) % 2 == 0 { 40 } else { 220 },
+            ])
+        });
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    fn remote_asset(id: &str, url: String) -> Asset {
+        Asset {
+            id: id.to_string(),
+            name: format!("{}.png", id),
+            source: AssetSource::Path(url.into()),
+            mime_type: "image/png".to_string(),
+            is_video: false,
+            precomputed: None,
+            kind: AssetKind::File,
+        }
+    }
+
+    #[test]
+    fn test_remote_images_are_hashed_from_memory() {
+        let options = GroupingOptions::default();
+        let base = serve(vec![("/banner.png", png())]);
+
+        let url = format!("{}/banner.png", base);
+        let asset = remote_asset("a", url.clone());
+        let (hashed, temp_dir) = process_asset(&asset, &options, None).unwrap();
+        assert!(temp_dir.is_none());
+        assert_eq!(hashed.asset.source.url(), Some(url.as_str()));
+        assert_eq!((hashed.width, hashed.height), (64, 64));
+
+        let local = Asset {
+            source: AssetSource::Bytes(png()),
+            ..remote_asset("local", String::new())
+        };
+        let (local, _) = process_asset(&local, &options, None).unwrap();
+        assert_eq!(hashed.frames[0].hash, local.frames[0].hash);
+
+        // A missing file fails its own asset, the rest of the batch still groups
+        let assets = vec![
+            remote_asset("b", format!("{}/banner.png", base)),
+            remote_asset("missing", format!("{}/missing.png", base)),
+        ];
+        let result = group_assets_by_visual_similarity(assets, Some(options), None, None).unwrap();
+        assert_eq!(result.groups.len(), 1);
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].asset_id, "missing");
+        assert!(result.failed[0].reason.contains("404"), "{}", result.failed[0].reason);
+    }
+
+    #[test]
+    fn test_downloads_over_the_limit_fail() {
+        let base = serve(vec![("/large.png", png())]);
+        let options = GroupingOptions {
+            max_download_bytes: Some(100),
+            ..GroupingOptions::default()
+        };
+
+        let asset = remote_asset("large", format!("{}/large.png", base));
+        let error = process_asset(&asset, &options, None).unwrap_err();
+        assert!(format!("{:#}", error).contains("100 byte download limit"), "{:#}", error);
+
+        assert_eq!(AssetSource::Path("HTTPS://cdn/a.png".into()).url(), Some("HTTPS://cdn/a.png"));
+        assert_eq!(AssetSource::Path("/srv/http/a.png".into()).url(), None);
+        assert_eq!(AssetSource::Path("ftp://cdn/a.png".into()).url(), None);
+    }
+}
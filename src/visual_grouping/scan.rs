@@ -0,0 +1,325 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+use super::image_info::{FfmpegImageFormat, sniff_ffmpeg_image_format};
+use super::{Asset, AssetKind, AssetSource};
+
+/// Which files `scan_directory` turns into assets
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Glob patterns a file has to match, every media file when empty
+    /// Patterns without a `/` match the file name, others the relative path.
+    /// `*` and `?` stop at `/`, `**` does not
+    pub include: Vec<String>,
+    /// Glob patterns of files to leave out, applied after `include`
+    pub exclude: Vec<String>,
+    /// Descend into subdirectories
+    pub recursive: bool,
+    /// Read the first bytes of files without a known extension to detect
+    /// their type
+    pub sniff_content: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            recursive: true,
+            sniff_content: false,
+        }
+    }
+}
+
+/// Glob pattern compiled to a regex, see `ScanOptions::include`
+struct Glob {
+    regex: Regex,
+    matches_path: bool,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Result<Self> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    // "**/" also matches no directory at all
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Glob {
+            regex: Regex::new(&regex).with_context(|| format!("Invalid pattern {}", pattern))?,
+            matches_path: pattern.contains('/'),
+        })
+    }
+
+    fn is_match(&self, relative_path: &str, file_name: &str) -> bool {
+        self.regex.is_match(if self.matches_path { relative_path } else { file_name })
+    }
+}
+
+/// Build an asset for every image and video file under `dir`
+/// Ids are the paths relative to `dir` with `/` separators, names the file
+/// names. Assets come sorted by id. Files of unknown type are skipped, as are
+/// entries that can't be read, with a warning
+pub fn scan_directory<P: AsRef<Path>>(dir: P, options: &ScanOptions) -> Result<Vec<Asset>> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+
+    let compile = |patterns: &[String]| -> Result<Vec<Glob>> {
+        patterns.iter().map(|pattern| Glob::new(pattern)).collect()
+    };
+    let include = compile(&options.include)?;
+    let exclude = compile(&options.exclude)?;
+
+    let mut walker = walkdir::WalkDir::new(dir).sort_by_file_name();
+    if !options.recursive {
+        walker = walker.max_depth(1);
+    }
+
+    let mut assets = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::warn!("Skipping unreadable entry in {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let Ok(relative) = path.strip_prefix(dir) else {
+            continue;
+        };
+        let relative_path = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let file_name = entry.file_name().to_string_lossy();
+
+        let matches = |globs: &[Glob]| {
+            globs.iter().any(|glob| glob.is_match(&relative_path, &file_name))
+        };
+        if (!include.is_empty() && !matches(&include)) || matches(&exclude) {
+            continue;
+        }
+
+        let media_type = match media_type_from_extension(path) {
+            Some(media_type) => Some(media_type),
+            None if options.sniff_content && path.extension().is_none() => sniff_media_type(path),
+            None => None,
+        };
+        let Some((mime_type, is_video)) = media_type else {
+            continue;
+        };
+
+        assets.push(Asset {
+            id: relative_path,
+            name: file_name.into_owned(),
+            source: AssetSource::Path(path.to_path_buf()),
+            mime_type: mime_type.to_string(),
+            is_video,
+            precomputed: None,
+            kind: AssetKind::File,
+        });
+    }
+
+    assets.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(assets)
+}
+
+/// Mime type of a media file and whether it is a video, from its extension
+pub fn media_type_from_extension(path: &Path) -> Option<(&'static str, bool)> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let media_type = match extension.as_str() {
+        "jpg" | "jpeg" => ("image/jpeg", false),
+        "png" => ("image/png", false),
+        "gif" => ("image/gif", false),
+        "webp" => ("image/webp", false),
+        "bmp" => ("image/bmp", false),
+        "tif" | "tiff" => ("image/tiff", false),
+        "heic" | "heif" => ("image/heif", false),
+        "avif" => ("image/avif", false),
+        "mp4" | "m4v" => ("video/mp4", true),
+        "mov" => ("video/quicktime", true),
+        "webm" => ("video/webm", true),
+        "mkv" => ("video/x-matroska", true),
+        "avi" => ("video/x-msvideo", true),
+        _ => return None,
+    };
+    Some(media_type)
+}
+
+/// Mime type of a media file and whether it is a video, from its first bytes
+/// `None` for unreadable files and other content
+pub fn sniff_media_type(path: &Path) -> Option<(&'static str, bool)> {
+    let mut header = Vec::with_capacity(16);
+    std::fs::File::open(path).ok()?.take(16).read_to_end(&mut header).ok()?;
+    sniff_media_type_from_bytes(&header)
+}
+
+/// Mime type and whether it is a video, from the first 16 bytes of a file
+pub fn sniff_media_type_from_bytes(header: &[u8]) -> Option<(&'static str, bool)> {
+    match sniff_ffmpeg_image_format(header) {
+        Some(FfmpegImageFormat::Heif) => return Some(("image/heif", false)),
+        Some(FfmpegImageFormat::Avif) => return Some(("image/avif", false)),
+        None => {}
+    }
+
+    if header.get(4..8) == Some(b"ftyp".as_slice()) {
+        let quicktime = header.get(8..12) == Some(b"qt  ".as_slice());
+        return Some((if quicktime { "video/quicktime" } else { "video/mp4" }, true));
+    }
+    if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(("video/webm", true));
+    }
+    if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"AVI ".as_slice()) {
+        return Some(("video/x-msvideo", true));
+    }
+
+    let format = image::guess_format(header).ok()?;
+    Some((format.to_mime_type(), false))
+}
+
+/// Check that the file of an asset can be read before any decoding starts:
+/// it exists, symlinks resolve, and it is a non-empty regular file, or a
+/// readable directory for an image sequence. In-memory assets and URLs pass
+pub fn check_asset_file(asset: &Asset) -> Result<()> {
+    let AssetSource::Path(path) = &asset.source else {
+        return Ok(());
+    };
+    if asset.source.url().is_some() {
+        return Ok(());
+    }
+
+    let link = match std::fs::symlink_metadata(path) {
+        Ok(link) => link,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            anyhow::bail!("{} does not exist", path.display())
+        }
+        Err(err) => return Err(err).with_context(|| format!("Cannot access {}", path.display())),
+    };
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if link.file_type().is_symlink() => {
+            let target = std::fs::read_link(path).unwrap_or_default();
+            anyhow::bail!(
+                "{} is a broken symlink to {}: {}",
+                path.display(),
+                target.display(),
+                err
+            )
+        }
+        Err(err) => return Err(err).with_context(|| format!("Cannot access {}", path.display())),
+    };
+
+    // Directories are read as image sequences
+    if metadata.is_dir() {
+        std::fs::read_dir(path)
+            .with_context(|| format!("Cannot read directory {}", path.display()))?;
+        return Ok(());
+    }
+    if let AssetKind::ImageSequence { .. } = asset.kind {
+        anyhow::bail!("Image sequence {} is not a directory", path.display());
+    }
+    if !metadata.is_file() {
+        anyhow::bail!("{} is not a regular file", path.display());
+    }
+    if metadata.len() == 0 {
+        anyhow::bail!("{} is empty", path.display());
+    }
+    std::fs::File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_directory_filters_and_detects_types() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("story/drafts")).unwrap();
+
+        let png = {
+            let mut bytes = std::io::Cursor::new(Vec::new());
+            image::RgbImage::new(4, 4).write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+            bytes.into_inner()
+        };
+        std::fs::write(root.join("banner.png"), &png).unwrap();
+        std::fs::write(root.join("notes.txt"), b"not media").unwrap();
+        std::fs::write(root.join("story/cut.MP4"), b"\0\0\0\x18ftypisom").unwrap();
+        std::fs::write(root.join("story/drafts/old.jpg"), b"").unwrap();
+        std::fs::write(root.join("story/export"), &png).unwrap();
+
+        let scan = |options: ScanOptions| -> Vec<(String, String, bool)> {
+            scan_directory(root, &options)
+                .unwrap()
+                .into_iter()
+                .map(|asset| (asset.id, asset.mime_type, asset.is_video))
+                .collect()
+        };
+        let entry = |id: &str, mime_type: &str, is_video| {
+            (id.to_string(), mime_type.to_string(), is_video)
+        };
+
+        assert_eq!(
+            scan(ScanOptions::default()),
+            [
+                entry("banner.png", "image/png", false),
+                entry("story/cut.MP4", "video/mp4", true),
+                entry("story/drafts/old.jpg", "image/jpeg", false),
+            ]
+        );
+
+        let sniffed = ScanOptions {
+            sniff_content: true,
+            exclude: vec!["**/drafts/**".to_string()],
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            scan(sniffed),
+            [
+                entry("banner.png", "image/png", false),
+                entry("story/cut.MP4", "video/mp4", true),
+                entry("story/export", "image/png", false),
+            ]
+        );
+
+        let flat = ScanOptions {
+            recursive: false,
+            ..ScanOptions::default()
+        };
+        assert_eq!(scan(flat), [entry("banner.png", "image/png", false)]);
+
+        let videos = ScanOptions {
+            include: vec!["*.mp4".to_string(), "story/*.MP4".to_string()],
+            ..ScanOptions::default()
+        };
+        assert_eq!(scan(videos), [entry("story/cut.MP4", "video/mp4", true)]);
+
+        assert!(scan_directory(root.join("banner.png"), &ScanOptions::default()).is_err());
+    }
+}
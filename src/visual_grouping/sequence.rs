@@ -0,0 +1,237 @@
+//! Image sequences: directories of numbered images such as frame_0001.png to
+//! frame_0120.png, hashed like an animation so they can match videos
+//! The images are played at `FRAME_RATE` and sampled like video frames
+
+use anyhow::{Context, Result};
+use image::ImageFormat;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use super::animation::{AnimationFrame, SampledAnimation, sample_times};
+use super::hash::decode_image;
+use super::{Asset, AssetKind, AssetSource, FrameSamplingConfig};
+
+/// Frames per second an image sequence is played at, which gives it a
+/// duration and timestamps comparable to a video's
+pub const FRAME_RATE: f64 = 25.0;
+
+/// Directory and file name pattern of an asset that is an image sequence,
+/// declared as one or given as a directory path
+pub fn image_sequence(asset: &Asset) -> Result<Option<(&Path, Option<&str>)>> {
+    match (&asset.kind, &asset.source) {
+        (AssetKind::ImageSequence { pattern }, AssetSource::Path(dir)) => {
+            Ok(Some((dir, pattern.as_deref())))
+        }
+        (AssetKind::ImageSequence { .. }, AssetSource::Bytes(_)) => {
+            anyhow::bail!("Image sequence {} must be a directory path", asset.id)
+        }
+        (AssetKind::File, AssetSource::Path(path)) if path.is_dir() => Ok(Some((path, None))),
+        (AssetKind::File, _) => Ok(None),
+    }
+}
+
+/// Frame files of a sequence in natural order, so frame_2 comes before frame_10
+/// Without a pattern every file with an image extension is a frame
+pub fn list_frames(dir: &Path, pattern: Option<&str>) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read image sequence {}", dir.display()))?;
+
+    let mut frames: Vec<(String, PathBuf)> = Vec::new();
+    for entry in entries {
+        let path = entry.context("Failed to read image sequence entry")?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let is_frame = match pattern {
+            Some(pattern) => matches_pattern(name, pattern),
+            None => ImageFormat::from_path(&path).is_ok(),
+        };
+        if is_frame && path.is_file() {
+            frames.push((name.to_string(), path));
+        }
+    }
+
+    if frames.is_empty() {
+        anyhow::bail!("No frames found in image sequence {}", dir.display());
+    }
+    frames.sort_by(|(name1, _), (name2, _)| natural_cmp(name1, name2));
+    Ok(frames.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Sample the frames of an image sequence like those of an animation lasting
+/// one `FRAME_RATE` frame per image, only the sampled images are decoded
+pub fn sample_image_sequence(
+    dir: &Path,
+    pattern: Option<&str>,
+    sampling: &FrameSamplingConfig,
+) -> Result<SampledAnimation> {
+    let files = list_frames(dir, pattern)?;
+    let duration = files.len() as f64 / FRAME_RATE;
+
+    // Image showing at each sample time
+    let mut indices: Vec<usize> = sample_times(sampling, duration)
+        .iter()
+        // Sample times that are whole frames may come out a hair early
+        .map(|&time| ((time * FRAME_RATE + 1e-6) as usize).min(files.len() - 1))
+        .collect();
+    indices.dedup();
+
+    let frames = indices
+        .into_iter()
+        .map(|index| {
+            let image = decode_image(&files[index])
+                .with_context(|| format!("Failed to decode frame {}", files[index].display()))?;
+            Ok(AnimationFrame {
+                timestamp_secs: index as f64 / FRAME_RATE,
+                image,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let first = frames.first().context("No frames sampled from image sequence")?;
+    let (width, height) = (first.image.width(), first.image.height());
+
+    Ok(SampledAnimation {
+        frames,
+        duration,
+        width,
+        height,
+    })
+}
+
+/// Compare file names with runs of digits ordered by their value
+pub fn natural_cmp(name1: &str, name2: &str) -> Ordering {
+    let (mut chars1, mut chars2) = (name1.chars().peekable(), name2.chars().peekable());
+    loop {
+        match (chars1.peek().copied(), chars2.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(char1), Some(char2)) if char1.is_ascii_digit() && char2.is_ascii_digit() => {
+                let digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut run = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        run.push(digit);
+                    }
+                    run
+                };
+                let (run1, run2) = (digits(&mut chars1), digits(&mut chars2));
+                let (value1, value2) = (run1.trim_start_matches('0'), run2.trim_start_matches('0'));
+                // Longer runs without leading zeros are bigger numbers, then
+                // fewer leading zeros come first
+                let order = value1
+                    .len()
+                    .cmp(&value2.len())
+                    .then_with(|| value1.cmp(value2))
+                    .then_with(|| run1.len().cmp(&run2.len()));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(char1), Some(char2)) => {
+                if char1 != char2 {
+                    return char1.cmp(&char2);
+                }
+                chars1.next();
+                chars2.next();
+            }
+        }
+    }
+}
+
+/// Match a file name against a pattern where `*` stands for any run of
+/// characters and `?` for a single one
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    // Position in the pattern and the name to go back to after the last `*`
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` take one more character
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    n = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::grouping::process_asset;
+    use crate::visual_grouping::{FrameStrategy, GroupingOptions};
+
+    #[test]
+    fn test_sequence_frames_sort_naturally_and_sample_like_video() {
+        let mut names = vec!["frame_10.png", "frame_2.png", "frame_1.png", "frame_002.png"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["frame_1.png", "frame_2.png", "frame_002.png", "frame_10.png"]);
+
+        assert!(matches_pattern("frame_0001.png", "frame_*.png"));
+        assert!(matches_pattern("frame_0001.png", "frame_????.png"));
+        assert!(!matches_pattern("frame_0001.jpg", "frame_*.png"));
+        assert!(!matches_pattern("thumb.png", "frame_*"));
+
+        // One second of frames, the brightness gives the frame number away
+        let dir = tempfile::TempDir::new().unwrap();
+        for index in 1..=25u8 {
+            let image = image::GrayImage::from_pixel(32, 16, image::Luma([index * 10]));
+            image.save(dir.path().join(format!("frame_{}.png", index))).unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "not a frame").unwrap();
+
+        let frames = list_frames(dir.path(), None).unwrap();
+        assert_eq!(frames.len(), 25);
+        assert!(frames[1].ends_with("frame_2.png") && frames[9].ends_with("frame_10.png"));
+        assert_eq!(list_frames(dir.path(), Some("frame_1?.png")).unwrap().len(), 10);
+
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(0.2),
+            ..FrameSamplingConfig::default()
+        };
+        let animation = sample_image_sequence(dir.path(), None, &sampling).unwrap();
+        assert_eq!((animation.width, animation.height), (32, 16));
+        assert!((animation.duration - 1.0).abs() < 1e-9);
+        let levels: Vec<u8> = animation
+            .frames
+            .iter()
+            .map(|frame| frame.image.to_luma8().get_pixel(0, 0).0[0])
+            .collect();
+        // Every fifth image, from frame_1
+        assert_eq!(levels, [10, 60, 110, 160, 210]);
+
+        // Hashed as an animation even when declared a video
+        let asset = Asset {
+            id: "sequence".to_string(),
+            name: "sequence".to_string(),
+            source: AssetSource::Path(dir.path().to_path_buf()),
+            mime_type: "image/png".to_string(),
+            is_video: true,
+            precomputed: None,
+            kind: AssetKind::ImageSequence {
+                pattern: Some("frame_*.png".to_string()),
+            },
+        };
+        let (hashed, _) = process_asset(&asset, &GroupingOptions::default(), None).unwrap();
+        assert!(!hashed.asset.is_video && hashed.is_animated);
+        assert_eq!(hashed.duration, Some(1.0));
+        assert_eq!((hashed.width, hashed.height), (32, 16));
+    }
+}
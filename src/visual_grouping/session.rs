@@ -0,0 +1,101 @@
+use super::grouping::{group_hashed_assets, hash_assets};
+use super::{Asset, AssetGroup, FailedAsset, GroupingOptions, HashedAsset};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+/// Keeps hashed assets alive across calls so only new assets get processed
+#[derive(Debug, Clone, Default)]
+pub struct GroupingSession {
+    options: GroupingOptions,
+    hashed_assets: Vec<HashedAsset>,
+}
+
+impl GroupingSession {
+    pub fn new(options: GroupingOptions) -> Result<Self> {
+        options.validate().context("Invalid grouping options")?;
+
+        Ok(GroupingSession {
+            options,
+            hashed_assets: Vec::new(),
+        })
+    }
+
+    pub fn options(&self) -> &GroupingOptions {
+        &self.options
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashed_assets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashed_assets.is_empty()
+    }
+
+    pub fn contains(&self, asset_id: &str) -> bool {
+        self.hashed_assets
+            .iter()
+            .any(|hashed_asset| hashed_asset.asset.id == asset_id)
+    }
+
+    /// Assets from `assets` that are not yet part of the session
+    pub fn new_assets(&self, assets: Vec<Asset>) -> Vec<Asset> {
+        let known: HashSet<&str> = self
+            .hashed_assets
+            .iter()
+            .map(|hashed_asset| hashed_asset.asset.id.as_str())
+            .collect();
+
+        assets
+            .into_iter()
+            .filter(|asset| !known.contains(asset.id.as_str()))
+            .collect()
+    }
+
+    /// Store already hashed assets, replacing any entry with the same id
+    pub fn insert(&mut self, hashed_assets: Vec<HashedAsset>) {
+        for hashed_asset in hashed_assets {
+            self.remove_asset(&hashed_asset.asset.id);
+            self.hashed_assets.push(hashed_asset);
+        }
+    }
+
+    /// Hash the assets not yet in the session and store them
+    pub fn add_assets(&mut self, assets: Vec<Asset>) -> Result<Vec<FailedAsset>> {
+        let assets = self.new_assets(assets);
+        let (process_results, failed) = hash_assets(&assets, &self.options, None, None)?;
+
+        self.insert(
+            process_results
+                .into_iter()
+                .map(|(hashed_asset, _)| hashed_asset)
+                .collect(),
+        );
+
+        Ok(failed)
+    }
+
+    /// Remove an asset, returns whether it was part of the session
+    pub fn remove_asset(&mut self, asset_id: &str) -> bool {
+        let len = self.hashed_assets.len();
+        self.hashed_assets
+            .retain(|hashed_asset| hashed_asset.asset.id != asset_id);
+        self.hashed_assets.len() != len
+    }
+
+    pub fn hashed_assets(&self) -> &[HashedAsset] {
+        &self.hashed_assets
+    }
+
+    /// Group the stored assets, optionally overriding the session threshold
+    pub fn groups(&self, threshold: Option<u32>) -> Result<Vec<AssetGroup>> {
+        let mut options = self.options.clone();
+        if let Some(threshold) = threshold {
+            options.threshold = threshold;
+            options.threshold_ratio = None;
+        }
+        options.validate().context("Invalid grouping options")?;
+
+        group_hashed_assets(&self.hashed_assets, &options, None, None)
+    }
+}
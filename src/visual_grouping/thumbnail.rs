@@ -0,0 +1,206 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::path::Path;
+
+use super::hash::{decode_image, decode_image_bytes};
+use super::sequence::{image_sequence, list_frames};
+use super::{AssetGroup, AssetSource, FrameSamplingConfig, GroupedAsset, ThumbnailOptions};
+
+#[cfg(feature = "video")]
+use super::CancellationToken;
+#[cfg(feature = "video")]
+use super::video::extract_frames_as_images;
+
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Write `<dir>/<group id>.jpg` from the representative of every group
+/// Sets `thumbnail_path` on success and `thumbnail_error` otherwise, a group
+/// without a thumbnail never fails the run
+pub fn write_group_thumbnails(
+    groups: &mut [AssetGroup],
+    options: &ThumbnailOptions,
+    sampling: &FrameSamplingConfig,
+) {
+    groups.par_iter_mut().for_each(|group| {
+        let path = options.dir.join(format!("{}.jpg", group.id));
+        match write_thumbnail(group, &path, options.size, sampling) {
+            Ok(()) => {
+                group.thumbnail_path = Some(path);
+                group.thumbnail_error = None;
+            }
+            Err(err) => {
+                log::warn!("Failed to write the thumbnail of group {}: {:#}", group.id, err);
+                group.thumbnail_path = None;
+                group.thumbnail_error = Some(format!("{:#}", err));
+            }
+        }
+    });
+}
+
+fn write_thumbnail(
+    group: &AssetGroup,
+    path: &Path,
+    size: u32,
+    sampling: &FrameSamplingConfig,
+) -> Result<()> {
+    let representative = group
+        .assets
+        .iter()
+        .find(|member| member.asset.id == group.representative_asset_id)
+        .or(group.assets.first())
+        .context("The group has no members")?;
+
+    let image = image::DynamicImage::ImageRgb8(representative_image(representative, sampling)?);
+    let thumbnail = image.thumbnail(size, size).to_rgb8();
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    // Write next to the target and rename, so readers never see half a file
+    let mut temp_file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a temp file in {}", dir.display()))?;
+    thumbnail
+        .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(
+            &mut temp_file,
+            THUMBNAIL_QUALITY,
+        ))
+        .context("Failed to encode the thumbnail")?;
+    temp_file
+        .persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Full size image of a member, the first sampled frame for videos and the
+/// first image of image sequences
+fn representative_image(
+    member: &GroupedAsset,
+    sampling: &FrameSamplingConfig,
+) -> Result<image::RgbImage> {
+    let asset = &member.asset;
+    if let Some((dir, pattern)) = image_sequence(asset)? {
+        let frames = list_frames(dir, pattern)?;
+        return Ok(decode_image(&frames[0])?.to_rgb8());
+    }
+    if asset.is_video {
+        return first_video_frame(&asset.source, sampling)
+            .with_context(|| format!("Failed to sample a frame of {}", asset.name));
+    }
+
+    let image = match &asset.source {
+        AssetSource::Path(path) => decode_image(path)?,
+        AssetSource::Bytes(bytes) => decode_image_bytes(bytes)?,
+    };
+    Ok(image.to_rgb8())
+}
+
+#[cfg(feature = "video")]
+fn first_video_frame(
+    source: &AssetSource,
+    sampling: &FrameSamplingConfig,
+) -> Result<image::RgbImage> {
+    let temp_dir;
+    let video_path = match source {
+        AssetSource::Path(path) => path.clone(),
+        AssetSource::Bytes(bytes) => {
+            temp_dir = tempfile::TempDir::new().context("Failed to create temp directory")?;
+            let video_path = temp_dir.path().join("source_video");
+            std::fs::write(&video_path, bytes).context("Failed to write video data to temp file")?;
+            video_path
+        }
+    };
+
+    // Stop decoding as soon as the first frame is in
+    let stop = CancellationToken::new();
+    let mut first_frame = None;
+    let result = extract_frames_as_images(&video_path, sampling, Some(&stop), |_, _, frame| {
+        first_frame.get_or_insert(frame);
+        stop.cancel();
+        Ok(())
+    });
+
+    match (first_frame, result) {
+        (Some(frame), _) => Ok(frame),
+        (None, Err(err)) => Err(err),
+        (None, Ok(_)) => anyhow::bail!("No frame could be decoded"),
+    }
+}
+
+/// Videos need ffmpeg, which was not compiled in
+#[cfg(not(feature = "video"))]
+fn first_video_frame(
+    _source: &AssetSource,
+    _sampling: &FrameSamplingConfig,
+) -> Result<image::RgbImage> {
+    anyhow::bail!("Video support is not compiled in, rebuild with the `video` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::grouping::group_assets_by_visual_similarity;
+    use crate::visual_grouping::{Asset, AssetKind, GroupingOptions};
+
+    #[test]
+    fn test_thumbnails_are_written_per_group() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let thumbnails_dir = temp_dir.path().join("thumbnails");
+
+        let png = |width, height| {
+            let image = image::RgbImage::from_fn(width, height, |x, _| {
+                image::Rgb([if x < width / 2 { 20 } else { 230 }; 3])
+            });
+            let mut bytes = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+            bytes.into_inner()
+        };
+        let asset = |id: &str, source| Asset {
+            id: id.to_string(),
+            name: format!("{}.png", id),
+            source,
+            mime_type: "image/png".to_string(),
+            is_video: false,
+            precomputed: None,
+            kind: AssetKind::File,
+        };
+        let assets = vec![
+            asset("wide", AssetSource::Bytes(png(400, 200))),
+            asset("broken", AssetSource::Path(temp_dir.path().join("missing.png"))),
+        ];
+
+        let options = GroupingOptions {
+            thumbnails: Some(ThumbnailOptions {
+                dir: thumbnails_dir.clone(),
+                size: 64,
+            }),
+            ..GroupingOptions::default()
+        };
+        let mut result = group_assets_by_visual_similarity(assets, Some(options), None, None)
+            .unwrap();
+        assert_eq!(result.groups.len(), 1);
+        let group = &result.groups[0];
+
+        let path = group.thumbnail_path.clone().unwrap();
+        assert_eq!(path, thumbnails_dir.join(format!("{}.jpg", group.id)));
+        assert_eq!(image::image_dimensions(&path).unwrap(), (64, 32));
+
+        // Rendering again replaces the file, failures are kept on their group
+        result.groups[0].assets[0].asset.source = AssetSource::Bytes(png(100, 300));
+        result.groups.push(AssetGroup {
+            id: "broken".to_string(),
+            assets: Vec::new(),
+            ..result.groups[0].clone()
+        });
+        let options = ThumbnailOptions {
+            dir: thumbnails_dir.clone(),
+            size: 60,
+        };
+        write_group_thumbnails(&mut result.groups, &options, &FrameSamplingConfig::default());
+
+        assert_eq!(image::image_dimensions(&path).unwrap(), (20, 60));
+        assert!(result.groups[1].thumbnail_path.is_none());
+        assert!(result.groups[1].thumbnail_error.is_some());
+        assert_eq!(std::fs::read_dir(&thumbnails_dir).unwrap().count(), 1);
+    }
+}
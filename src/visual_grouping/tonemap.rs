@@ -0,0 +1,177 @@
+//! Conversion of wide gamut and HDR frames down to 8 bit BT.709 RGB
+//! Aimed at hashing, not display: a fixed SDR white, BT.2020 to BT.709
+//! primaries and a soft shoulder for highlights
+
+/// Luminance in nits of SDR reference white, per ITU-R BT.2408
+pub const SDR_WHITE_NITS: f64 = 203.0;
+
+/// Peak luminance in nits HLG is displayed at
+const HLG_PEAK_NITS: f64 = 1000.0;
+
+/// Linear light below this fraction of SDR white is kept as is, the rest is
+/// compressed towards white
+const SHOULDER_KNEE: f32 = 0.8;
+
+/// Exponent of the BT.1886 display transfer SDR frames are encoded with
+const SDR_GAMMA: f64 = 2.4;
+
+/// Steps of the table mapping linear light back to 8 bit code values
+const ENCODE_STEPS: usize = 4096;
+
+const PQ_M1: f64 = 2610.0 / 16384.0;
+const PQ_M2: f64 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f64 = 3424.0 / 4096.0;
+const PQ_C2: f64 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f64 = 2392.0 / 4096.0 * 32.0;
+
+const HLG_A: f64 = 0.178_832_77;
+const HLG_B: f64 = 1.0 - 4.0 * HLG_A;
+const HLG_C: f64 = 0.559_910_73;
+
+/// Linear BT.2020 RGB to linear BT.709 RGB, per ITU-R BT.2087
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+/// Transfer function of BT.2020 frames, which all need their gamut converted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorTransfer {
+    /// SDR gamma, only the primaries differ from BT.709
+    Sdr,
+    /// SMPTE ST 2084 perceptual quantizer, absolute luminance up to 10000 nits
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma, relative scene light
+    Hlg,
+}
+
+/// Maps 16 bit BT.2020 RGB to 8 bit BT.709 RGB through lookup tables built once
+#[derive(Debug, Clone)]
+pub struct ToneMapper {
+    transfer: ColorTransfer,
+    /// Linear light of every 16 bit code value, 1.0 is SDR white
+    /// For HLG this is scene light, the display gamma needs the whole pixel
+    to_linear: Vec<f32>,
+    /// 8 bit code value of linear light from 0 to 1 in `ENCODE_STEPS` steps
+    to_code: Vec<u8>,
+}
+
+impl ToneMapper {
+    pub fn new(transfer: ColorTransfer) -> Self {
+        let to_linear = (0..=u16::MAX)
+            .map(|code| {
+                let value = code as f64 / u16::MAX as f64;
+                let linear = match transfer {
+                    ColorTransfer::Sdr => value.powf(SDR_GAMMA),
+                    ColorTransfer::Pq => pq_to_nits(value) / SDR_WHITE_NITS,
+                    ColorTransfer::Hlg => hlg_to_scene_light(value),
+                };
+                linear as f32
+            })
+            .collect();
+        let to_code = (0..ENCODE_STEPS)
+            .map(|step| {
+                let linear = step as f64 / (ENCODE_STEPS - 1) as f64;
+                (linear.powf(1.0 / SDR_GAMMA) * 255.0).round() as u8
+            })
+            .collect();
+
+        ToneMapper {
+            transfer,
+            to_linear,
+            to_code,
+        }
+    }
+
+    pub fn transfer(&self) -> ColorTransfer {
+        self.transfer
+    }
+
+    /// 8 bit BT.709 RGB of a full range 16 bit BT.2020 RGB pixel
+    pub fn map(&self, rgb: [u16; 3]) -> [u8; 3] {
+        let mut linear = rgb.map(|code| self.to_linear[code as usize]);
+
+        if self.transfer == ColorTransfer::Hlg {
+            // The HLG reference display applies a system gamma of 1.2 on luminance
+            let [red, green, blue] = linear;
+            let luminance = 0.2627 * red + 0.6780 * green + 0.0593 * blue;
+            let scale = luminance.max(0.0).powf(0.2) * (HLG_PEAK_NITS / SDR_WHITE_NITS) as f32;
+            linear = linear.map(|value| value * scale);
+        }
+
+        let converted = BT2020_TO_BT709.map(|row| {
+            let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+            value.max(0.0)
+        });
+
+        converted.map(|value| {
+            let value = match self.transfer {
+                ColorTransfer::Sdr => value.min(1.0),
+                ColorTransfer::Pq | ColorTransfer::Hlg => compress_highlights(value),
+            };
+            self.to_code[(value * (ENCODE_STEPS - 1) as f32).round() as usize]
+        })
+    }
+}
+
+/// Keep linear light below the knee, bend everything above it smoothly towards 1
+fn compress_highlights(value: f32) -> f32 {
+    if value <= SHOULDER_KNEE {
+        return value;
+    }
+    let over = (value - SHOULDER_KNEE) / (1.0 - SHOULDER_KNEE);
+    SHOULDER_KNEE + (1.0 - SHOULDER_KNEE) * over / (over + 1.0)
+}
+
+/// Display luminance in nits of a PQ signal from 0 to 1
+fn pq_to_nits(value: f64) -> f64 {
+    let power = value.powf(1.0 / PQ_M2);
+    let luminance = ((power - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * power)).powf(1.0 / PQ_M1);
+    luminance * 10000.0
+}
+
+/// PQ signal from 0 to 1 of a display luminance in nits
+pub fn nits_to_pq(nits: f64) -> f64 {
+    let power = (nits / 10000.0).clamp(0.0, 1.0).powf(PQ_M1);
+    ((PQ_C1 + PQ_C2 * power) / (1.0 + PQ_C3 * power)).powf(PQ_M2)
+}
+
+/// Scene light from 0 to 1 of an HLG signal from 0 to 1
+fn hlg_to_scene_light(value: f64) -> f64 {
+    if value <= 0.5 {
+        value * value / 3.0
+    } else {
+        (((value - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pq_at_sdr_white_maps_to_sdr_white() {
+        let tone_mapper = ToneMapper::new(ColorTransfer::Pq);
+        let pq = |nits: f64| (nits_to_pq(nits) * u16::MAX as f64).round() as u16;
+
+        assert!((pq_to_nits(nits_to_pq(SDR_WHITE_NITS)) - SDR_WHITE_NITS).abs() < 0.01);
+        assert_eq!(tone_mapper.map([0; 3]), [0; 3]);
+
+        // Grays below the knee come out as the SDR encode would have them
+        let gray = 0.4_f64.powf(SDR_GAMMA);
+        let [red, green, blue] = tone_mapper.map([pq(gray * SDR_WHITE_NITS); 3]);
+        assert!(red.abs_diff(102) <= 1 && red == green && green == blue);
+
+        // White sits on the shoulder, highlights far above it still stay in range
+        let white = tone_mapper.map([pq(SDR_WHITE_NITS); 3]);
+        assert!(white.iter().all(|&value| (230..255).contains(&value)), "{:?}", white);
+        let peak = tone_mapper.map([pq(10000.0); 3]);
+        assert!(peak.iter().all(|&value| value > white[0]), "{:?}", peak);
+
+        // Pure BT.2020 green is out of the BT.709 gamut, red and blue clip to 0
+        let [red, green, blue] = ToneMapper::new(ColorTransfer::Sdr).map([0, u16::MAX, 0]);
+        assert_eq!((red, blue), (0, 0));
+        assert_eq!(green, 255);
+    }
+}
@@ -1,201 +1,2948 @@
 use anyhow::{Context, Result};
 use ffmpeg_next as ffmpeg;
-use image::{save_buffer, GenericImageView};
-use std::path::Path;
-use tempfile::TempDir;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-/// Intialize FFmpeg (must be called once at startup)
+use super::audio::{self, AudioFingerprint};
+use super::tonemap::{ColorTransfer, ToneMapper};
+use super::{
+    CancellationToken, ExtractionMode, FrameFileOptions, FrameFormat, FrameSamplingConfig,
+    FrameStrategy, NoVideoStream, hash,
+};
+
+static FFMPEG_INIT: OnceLock<Result<(), ffmpeg::Error>> = OnceLock::new();
+
+/// Slack in seconds when matching decoded frame times against sample times
+const FRAME_TIME_TOLERANCE: f64 = 0.001;
+
+/// How far past a uniform sample the next non-uniform frame is looked for
+const UNIFORM_SEARCH_SECS: f64 = 1.0;
+
+/// Intialize FFmpeg
+/// Safe to call repeatedly and from multiple threads, only the first call
+/// does any work. Every function touching ffmpeg calls this lazily
 pub fn init_ffmpeg() -> Result<()> {
-    ffmpeg::init().context("Failed to initialize FFmpeg")?;
+    (*FFMPEG_INIT.get_or_init(ffmpeg::init)).context("Failed to initialize FFmpeg")?;
 
     Ok(())
 }
 
-pub fn get_video_duration<P: AsRef<Path>>(video_path: P) -> Result<f64> {
-    let input = ffmpeg::format::input(&video_path).context("Failed to open video file")?;
+/// Open a media file with ffmpeg
+/// ffmpeg takes paths as UTF-8 strings, so on Unix a file with a non-UTF-8 name
+/// is opened through a symlink with a plain name instead
+fn open_input(path: &Path) -> Result<ffmpeg::format::context::Input> {
+    if path.to_str().is_some() {
+        return Ok(ffmpeg::format::input(&path)?);
+    }
+
+    #[cfg(unix)]
+    {
+        let link_dir = tempfile::TempDir::new().context("Failed to create temp directory")?;
+        // Keep the extension, ffmpeg uses it to pick image demuxers
+        let link = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => link_dir.path().join("input").with_extension(extension),
+            None => link_dir.path().join("input"),
+        };
+        let target = std::path::absolute(path).context("Failed to resolve path")?;
+        std::os::unix::fs::symlink(&target, &link).context("Failed to link non-UTF-8 path")?;
+
+        // ffmpeg keeps the file open, the link is not needed past this point
+        Ok(ffmpeg::format::input(&link)?)
+    }
+
+    #[cfg(not(unix))]
+    anyhow::bail!("{} is not a valid Unicode path", path.display())
+}
 
-    let duration = input.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
+/// Duration of the video in seconds, never zero or negative
+/// Some MKV/WebM files carry no container duration (`AV_NOPTS_VALUE`), so this
+/// falls back to the video stream duration and finally to the end of the last
+/// video packet, which needs one pass over the packets
+pub fn get_video_duration<P: AsRef<Path>>(video_path: P) -> Result<f64> {
+    init_ffmpeg()?;
+    let mut input = open_input(video_path.as_ref()).context("Failed to open video file")?;
 
-    Ok(duration)
+    input_duration(&mut input)
+        .with_context(|| format!("No duration for {:?}", video_path.as_ref()))
 }
 
-pub fn get_video_dimension<P: AsRef<Path>>(video_path: P) -> Result<(u32, u32)> {
-    let input = ffmpeg::format::input(&video_path).context("Failed to open video file")?;
+/// Duration fallback chain of `get_video_duration` on an already opened input
+/// Leaves the read position at the end of the file when packets were scanned
+fn input_duration(input: &mut ffmpeg::format::context::Input) -> Result<f64> {
+    let container_duration = input.duration();
+    if container_duration > 0 {
+        return Ok(container_duration as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE));
+    }
+
+    let video_stream = main_video_stream(input)?;
+    let video_stream_index = video_stream.index();
+    let time_base = f64::from(video_stream.time_base());
+    let start_pts = stream_start_pts(&video_stream);
+
+    let stream_duration = video_stream.duration();
+    if stream_duration > 0 {
+        return Ok(stream_duration as f64 * time_base);
+    }
+
+    log::debug!("No duration in the container headers, scanning packets");
+
+    let mut end_pts: Option<i64> = None;
+    for (stream, packet) in input.packets() {
+        if stream.index() == video_stream_index
+            && let Some(pts) = packet.pts()
+        {
+            let packet_end = pts + packet.duration();
+            end_pts = Some(end_pts.map_or(packet_end, |end| end.max(packet_end)));
+        }
+    }
+
+    match end_pts.map(|end_pts| end_pts - start_pts) {
+        Some(end_pts) if end_pts > 0 => Ok(end_pts as f64 * time_base),
+        _ => anyhow::bail!("Could not determine the video duration"),
+    }
+}
 
-    let video_stream = input
+/// The video stream every reader decodes: the largest, then longest, one
+/// that isn't attached cover art. `streams().best()` sometimes settles on a
+/// small embedded preview stream instead of the main one
+fn main_video_stream(
+    input: &ffmpeg::format::context::Input,
+) -> Result<ffmpeg::format::stream::Stream<'_>> {
+    input
         .streams()
-        .best(ffmpeg::media::Type::Video)
-        .context("Could not find video stream")?;
+        .filter(|stream| stream.parameters().medium() == ffmpeg::media::Type::Video)
+        .filter(|stream| {
+            !stream.disposition().contains(ffmpeg::format::stream::Disposition::ATTACHED_PIC)
+        })
+        .max_by_key(|stream| {
+            let parameters = stream.parameters();
+            // SAFETY: the parameters belong to the stream and outlive this read
+            let (width, height) = unsafe {
+                let parameters = parameters.as_ptr();
+                ((*parameters).width.max(0) as u64, (*parameters).height.max(0) as u64)
+            };
+            let duration = stream.duration().max(0) as f64 * f64::from(stream.time_base());
+            (width * height, (duration * 1000.0) as u64)
+        })
+        .ok_or_else(|| NoVideoStream.into())
+}
+
+/// Timestamp of the first frame of a stream in its time base, 0 when unknown
+/// Phones and MPEG-TS muxers often start well above 0
+fn stream_start_pts(stream: &ffmpeg::format::stream::Stream) -> i64 {
+    match stream.start_time() {
+        ffmpeg::ffi::AV_NOPTS_VALUE => 0,
+        start_pts => start_pts,
+    }
+}
+
+/// Properties of a video's best stream, read by `probe_video`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    /// Duration in seconds, see `get_video_duration`
+    pub duration: f64,
+    /// Average frame rate, None when the container doesn't know it
+    pub fps: Option<f64>,
+    /// Short ffmpeg codec name, e.g. "h264"
+    pub codec_name: String,
+    /// Bits per second of the video stream, or of the whole file when the stream has none
+    pub bit_rate: Option<u64>,
+    /// ffmpeg pixel format name, e.g. "yuv420p"
+    pub pixel_format: String,
+    /// Size of the frames as stored in the file
+    pub coded_width: u32,
+    pub coded_height: u32,
+    /// Sample (pixel) aspect ratio as numerator and denominator, 1:1 for square pixels
+    pub sample_aspect_ratio: (u32, u32),
+    /// Clockwise rotation in degrees from the display matrix
+    pub rotation: u32,
+    /// Upright size with square pixels, the size frames are extracted at
+    pub display_width: u32,
+    pub display_height: u32,
+}
+
+impl VideoMetadata {
+    /// Everything but the duration, which may need a pass over the packets
+    fn from_stream(
+        stream: &ffmpeg::format::stream::Stream,
+        decoder: &ffmpeg::decoder::Video,
+    ) -> Self {
+        let coded_width = decoder.width();
+        let coded_height = decoder.height();
+
+        // An unset ratio is reported as 0/1 and means square pixels
+        let sar = decoder.aspect_ratio();
+        let sample_aspect_ratio =
+            match (u32::try_from(sar.numerator()), u32::try_from(sar.denominator())) {
+                (Ok(num), Ok(den)) if num > 0 && den > 0 => (num, den),
+                _ => (1, 1),
+            };
 
-    let decoder = ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())
-        .context("Failed to create decoder context")?
-        .decoder()
-        .video()
-        .context("Failed to create video decoder")?;
+        let rotation = stream_rotation(stream);
 
-    let width = decoder.width();
-    let height = decoder.height();
+        let (width, height) = (scaled_width(coded_width, sample_aspect_ratio), coded_height);
+        let (display_width, display_height) = match rotation {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        };
 
-    Ok((width, height))
+        let frame_rate = stream.avg_frame_rate();
+        let fps = (frame_rate.numerator() > 0 && frame_rate.denominator() > 0)
+            .then(|| f64::from(frame_rate));
+
+        let bit_rate = u64::try_from(decoder.bit_rate())
+            .ok()
+            .filter(|&bit_rate| bit_rate > 0);
+
+        VideoMetadata {
+            duration: 0.0,
+            fps,
+            codec_name: stream.parameters().id().name().to_string(),
+            bit_rate,
+            pixel_format: decoder
+                .format()
+                .descriptor()
+                .map(|descriptor| descriptor.name().to_string())
+                .unwrap_or_default(),
+            coded_width,
+            coded_height,
+            sample_aspect_ratio,
+            rotation,
+            display_width,
+            display_height,
+        }
+    }
+
+    /// Frame size after correcting the pixel aspect, before rotation
+    fn unrotated_display_size(&self) -> (u32, u32) {
+        (scaled_width(self.coded_width, self.sample_aspect_ratio), self.coded_height)
+    }
+}
+
+/// Width with non-square pixels stretched to square ones
+fn scaled_width(coded_width: u32, (num, den): (u32, u32)) -> u32 {
+    let width = (u64::from(coded_width) * u64::from(num) + u64::from(den) / 2) / u64::from(den);
+    u32::try_from(width).unwrap_or(u32::MAX).max(1)
+}
+
+/// Shrink `(width, height)` so neither side exceeds `max_dimension`, keeping
+/// the aspect ratio. Sizes that already fit are returned unchanged
+fn fit_within((width, height): (u32, u32), max_dimension: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_dimension {
+        return (width, height);
+    }
+
+    let scale = |side: u32| {
+        let scaled = (u64::from(side) * u64::from(max_dimension) + u64::from(longest) / 2)
+            / u64::from(longest);
+        (scaled as u32).max(1)
+    };
+    (scale(width), scale(height))
+}
+
+/// Open the video once and read its duration, frame rate, codec, bit rate,
+/// pixel format and frame geometry
+/// Fails with `NoVideoStream` for audio-only files and for still images, which
+/// ffmpeg opens as a single frame stream
+pub fn probe_video<P: AsRef<Path>>(video_path: P) -> Result<VideoMetadata> {
+    let (mut input, mut metadata) = open_video_metadata(&video_path)?;
+
+    let demuxer = input.format().name();
+    if demuxer == "image2" || demuxer.ends_with("_pipe") {
+        return Err(NoVideoStream.into());
+    }
+
+    metadata.duration = input_duration(&mut input)
+        .with_context(|| format!("No duration for {:?}", video_path.as_ref()))?;
+    if metadata.bit_rate.is_none() {
+        metadata.bit_rate = u64::try_from(input.bit_rate()).ok().filter(|&rate| rate > 0);
+    }
+
+    Ok(metadata)
 }
 
-pub fn extract_frames_from_video<P: AsRef<Path>>(
+/// Open a video and read the metadata of its best stream, without the duration
+fn open_video_metadata<P: AsRef<Path>>(
     video_path: P,
-    temp_dir: &TempDir,
-) -> Result<Vec<String>> {
-    let duration = get_video_duration(&video_path)?;
-
-    println!(
-        "Extracting frames from video: {:?}, duration: {:.2}s",
-        video_path.as_ref(),
-        duration
-    );
+) -> Result<(ffmpeg::format::context::Input, VideoMetadata)> {
+    init_ffmpeg()?;
+    let input = open_input(video_path.as_ref()).context("Failed to open video file")?;
 
-    // Calculate frame interval based on video length
-    let frame_interval = if duration == 10.0 {
-        1.5
-    } else if duration <= 30.0 {
-        3.0
-    } else if duration <= 60.0 {
-        4.0
-    } else {
-        5.0
+    let metadata = {
+        let video_stream = main_video_stream(&input)?;
+
+        let decoder =
+            ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())
+                .context("Failed to create decoder context")?
+                .decoder()
+                .video()
+                .context("Failed to create video decoder")?;
+
+        VideoMetadata::from_stream(&video_stream, &decoder)
+    };
+
+    Ok((input, metadata))
+}
+
+/// Display dimensions of a video: non-square pixels are corrected using the
+/// sample aspect ratio, and width and height are swapped when the stream's
+/// rotation metadata turns it by 90 or 270 degrees
+pub fn get_video_dimension<P: AsRef<Path>>(video_path: P) -> Result<(u32, u32)> {
+    let (_, metadata) = open_video_metadata(video_path)?;
+
+    Ok((metadata.display_width, metadata.display_height))
+}
+
+/// Clockwise rotation in degrees (0, 90, 180 or 270) that displays the stream
+/// upright, read from its display matrix side data
+fn stream_rotation(stream: &ffmpeg::format::stream::Stream) -> u32 {
+    let parameters = stream.parameters();
+
+    // SAFETY: the side data is owned by the stream parameters, which outlive
+    // this block, and is only read when it holds a full 3x3 display matrix
+    let angle = unsafe {
+        let parameters = parameters.as_ptr();
+        let side_data = ffmpeg::ffi::av_packet_side_data_get(
+            (*parameters).coded_side_data,
+            (*parameters).nb_coded_side_data,
+            ffmpeg::ffi::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX,
+        );
+        if side_data.is_null() || (*side_data).size < 9 * std::mem::size_of::<i32>() {
+            return 0;
+        }
+
+        ffmpeg::ffi::av_display_rotation_get((*side_data).data as *const i32)
     };
 
-    // calculate frame times
+    if angle.is_nan() {
+        return 0;
+    }
+
+    // The display matrix angle is counterclockwise
+    let quarter_turns = (-angle / 90.0).round() as i64;
+    (quarter_turns * 90).rem_euclid(360) as u32
+}
 
-    let mut frame_times = Vec::new();
-    let mut t = 0.0;
-    while t < duration {
-        frame_times.push(t);
-        t += frame_interval
+/// Turn a decoded frame upright, `rotation` is clockwise in degrees
+fn rotate_frame(image: image::RgbImage, rotation: u32) -> image::RgbImage {
+    match rotation {
+        90 => image::imageops::rotate90(&image),
+        180 => image::imageops::rotate180(&image),
+        270 => image::imageops::rotate270(&image),
+        _ => image,
     }
+}
 
-    println!(
-        "Will extract {} frames at interval {:.2}s",
-        frame_times.len(),
-        frame_interval
-    );
+/// Frames written by `extract_frames_from_video`
+#[derive(Debug, Clone, Default)]
+pub struct ExtractedFrames {
+    /// Paths of the extracted frames, in timestamp order
+    pub frame_paths: Vec<PathBuf>,
+    /// Presentation time in seconds of each frame in `frame_paths`
+    pub frame_timestamps: Vec<f64>,
+    /// Target times that were skipped because seeking, decoding or saving failed
+    pub skipped_frames: usize,
+    /// Extraction stopped at `FrameSamplingConfig::timeout_secs`
+    pub timed_out: bool,
+    /// Packets and frames the decoder failed on, see `FrameExtractionStats::decode_errors`
+    pub decode_errors: usize,
+}
 
-    // TODO: Real Implementation here
+impl ExtractedFrames {
+    /// True when some sampled frames were lost to decode errors or the timeout
+    pub fn is_degraded(&self) -> bool {
+        self.skipped_frames > 0 || self.timed_out
+    }
+}
 
-    let mut input = ffmpeg::format::input(&video_path)
-        .context("Failed to open video file for frame extraction")?;
+/// Counts reported by `extract_frames_as_images`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameExtractionStats {
+    pub extracted_frames: usize,
+    /// Target times that were skipped because seeking, decoding or converting failed
+    pub skipped_frames: usize,
+    /// Extraction stopped at `FrameSamplingConfig::timeout_secs`
+    pub timed_out: bool,
+    /// Decoded frames passed over for being under `FrameSamplingConfig::min_luma_variance`
+    pub uniform_frames: usize,
+    /// Every decoded frame was uniform, the single extracted frame is the first of them
+    pub only_uniform: bool,
+    /// Frames were decoded on a hardware device, see `FrameSamplingConfig::hw_accel`
+    pub hardware_decoded: bool,
+    /// Packets the decoder rejected and frames it failed to output. A frame
+    /// decoded after an error may be damaged even when no target was skipped
+    pub decode_errors: usize,
+}
 
-    let video_stream_index = input
-        .streams()
-        .best(ffmpeg::media::Type::Video)
-        .context("Could  not find video stream")?
-        .index();
+impl FrameExtractionStats {
+    /// True when some sampled frames were lost to decode errors or the timeout
+    pub fn is_degraded(&self) -> bool {
+        self.skipped_frames > 0 || self.timed_out
+    }
+}
 
-    let video_stream = input
-        .stream(video_stream_index)
-        .context("Failed to get Video stream")?;
+/// Cancellation and the per-video deadline, checked between packets
+struct ExtractionLimits<'a> {
+    cancel: Option<&'a CancellationToken>,
+    deadline: Option<Instant>,
+    max_packets_per_frame: usize,
+}
 
-    let context_decoder =
-        ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())
-            .context("Failed to create codec context")?;
+impl<'a> ExtractionLimits<'a> {
+    fn new(sampling: &FrameSamplingConfig, cancel: Option<&'a CancellationToken>) -> Self {
+        ExtractionLimits {
+            cancel,
+            deadline: sampling
+                .timeout_secs
+                .map(|secs| Instant::now() + Duration::from_secs_f64(secs)),
+            max_packets_per_frame: sampling.max_packets_per_frame,
+        }
+    }
 
-    let mut decoder = context_decoder
-        .decoder()
-        .video()
-        .context("Failed to create video decoder")?;
+    /// Error once cancelled, true once the deadline has passed
+    fn timed_out(&self) -> Result<bool> {
+        if let Some(cancel) = self.cancel {
+            cancel.check()?;
+        }
+        Ok(self.deadline.is_some_and(|deadline| Instant::now() >= deadline))
+    }
+}
 
-    let mut scaler = ffmpeg::software::scaling::context::Context::get(
-        decoder.format(),
-        decoder.width(),
-        decoder.height(),
-        ffmpeg::format::Pixel::RGB24,
-        decoder.width(),
-        decoder.height(),
-        ffmpeg::software::scaling::flag::Flags::BILINEAR,
-    )
-    .context("Failed to create scaler")?;
+/// Extract sampled frames from a video as files written into `output_dir`,
+/// encoded and named after `files`.
+/// Sample times follow `sampling`, see `FrameSamplingConfig::frame_times`.
+/// Frames that fail to decode or save are logged and skipped, extraction only
+/// fails when no frame at all could be extracted.
+/// Extraction stops with a `Cancelled` error once `cancel` is triggered
+pub fn extract_frames_from_video<P: AsRef<Path>, O: AsRef<Path>>(
+    video_path: P,
+    output_dir: O,
+    sampling: &FrameSamplingConfig,
+    files: &FrameFileOptions,
+    cancel: Option<&CancellationToken>,
+) -> Result<ExtractedFrames> {
+    files.validate()?;
+    let output_dir = output_dir.as_ref();
+    std::fs::create_dir_all(output_dir).context("Failed to create frame output directory")?;
 
-    let mut frame_paths: Vec<String> = Vec::new();
-    let mut decoded_frame = ffmpeg::util::frame::video::Video::empty();
-    let time_base = input.stream(video_stream_index).unwrap().time_base();
+    let mut frame_paths: Vec<PathBuf> = Vec::new();
+    let mut frame_timestamps = Vec::new();
+    let mut save_failures = 0;
 
-    // Seek and decode frames
+    let stats = extract_frames_as_images(
+        &video_path,
+        sampling,
+        cancel,
+        |idx, timestamp_secs, frame| {
+            let frame_path = files.frame_path(output_dir, idx);
+            match files.save(&frame, &frame_path) {
+                Ok(()) => {
+                    frame_paths.push(frame_path);
+                    frame_timestamps.push(timestamp_secs);
+                }
+                Err(err) => {
+                    log::warn!("Skipping frame {}: {:#}", idx, err);
+                    save_failures += 1;
+                }
+            }
+            Ok(())
+        },
+    )?;
 
-    for (idx, target_time) in frame_times.iter().enumerate() {
-        let timestamp = (target_time / f64::from(time_base)) as i64;
-        input
-            .seek(timestamp, ..timestamp)
-            .context(format!("Failed to seek to time {:.2}s", target_time))?;
+    if frame_paths.is_empty() {
+        anyhow::bail!("Failed to save any frames from video");
+    }
 
-        let mut found_frame = false;
-        for (stream, packet) in input.packets() {
-            if stream.index() == video_stream_index {
-                decoder.send_packet(&packet).ok();
-
-                while decoder.receive_frame(&mut decoded_frame).is_ok() {
-                    let pts = decoded_frame.pts().unwrap_or(0);
-                    let current_time = pts as f64 * f64::from(time_base);
-                    if (current_time - target_time).abs() < frame_interval / 2.0 {
-                        // Convert frame to RGB24
-                        let mut rgb_frame = ffmpeg::util::frame::video::Video::empty();
-                        scaler
-                            .run(&decoded_frame, &mut rgb_frame)
-                            .context("Failed to scale frame")?;
-
-                        let frame_path = temp_dir.path().join(format!("frame_{}.png", idx));
-
-                        save_frame_as_png(&rgb_frame, &frame_path)
-                            .context(format!("Failed to save frame {}", idx))?;
-
-                        println!(
-                            "Extracted frame {} at {:.2}s -> {:?}",
-                            idx, current_time, frame_path
-                        );
+    Ok(ExtractedFrames {
+        frame_paths,
+        frame_timestamps,
+        skipped_frames: stats.skipped_frames + save_failures,
+        timed_out: stats.timed_out,
+        decode_errors: stats.decode_errors,
+    })
+}
 
-                        frame_paths.push(frame_path.to_string_lossy().to_string());
-                        found_frame = true;
-                    }
+/// Frame encoded in memory by `extract_encoded_frames`
+#[derive(Debug, Clone)]
+pub struct EncodedFrame {
+    /// Presentation time of the frame in seconds
+    pub timestamp_secs: f64,
+    pub bytes: Vec<u8>,
+}
+
+/// Extract sampled frames from a video encoded in `format` in memory, without
+/// writing any file. Each frame is encoded as soon as it is decoded, so only
+/// one decoded frame is held at a time. Frames that fail to encode are logged
+/// and skipped like frames that fail to decode
+pub fn extract_encoded_frames<P: AsRef<Path>>(
+    video_path: P,
+    sampling: &FrameSamplingConfig,
+    format: FrameFormat,
+    cancel: Option<&CancellationToken>,
+) -> Result<Vec<EncodedFrame>> {
+    let mut frames = Vec::new();
+    extract_frames_as_images(&video_path, sampling, cancel, |idx, timestamp_secs, frame| {
+        match format.encode(&frame) {
+            Ok(bytes) => frames.push(EncodedFrame {
+                timestamp_secs,
+                bytes,
+            }),
+            Err(err) => log::warn!("Skipping frame {}: {:#}", idx, err),
+        }
+        Ok(())
+    })?;
+
+    if frames.is_empty() {
+        anyhow::bail!("Failed to encode any frames from video");
+    }
+
+    Ok(frames)
+}
+
+/// Decode sampled frames from a video and hand each one to `on_frame` as soon as
+/// it is decoded, together with its sample index and presentation time in
+/// seconds. Nothing is written to disk and
+/// only one frame is held at a time.
+/// Sampling, error skipping and cancellation behave like `extract_frames_from_video`,
+/// an error returned by `on_frame` aborts the extraction
+pub fn extract_frames_as_images<P, F>(
+    video_path: P,
+    sampling: &FrameSamplingConfig,
+    cancel: Option<&CancellationToken>,
+    mut on_frame: F,
+) -> Result<FrameExtractionStats>
+where
+    P: AsRef<Path>,
+    F: FnMut(usize, f64, image::RgbImage) -> Result<()>,
+{
+    init_ffmpeg()?;
+
+    let mut video = VideoDecoder::open(&video_path, sampling.max_dimension, sampling.hw_accel)?;
+    let limits = ExtractionLimits::new(sampling, cancel);
+
+    // Uniform frames are held back, the first one stands in if nothing else turns up
+    let mut first_uniform = None;
+    let mut uniform_frames = 0;
+    let mut filtered = |index: usize, time: f64, image: image::RgbImage| -> Result<bool> {
+        if sampling.min_luma_variance > 0.0 && luma_variance(&image) < sampling.min_luma_variance {
+            log::trace!("Passing over uniform frame at {:.2}s", time);
+            uniform_frames += 1;
+            first_uniform.get_or_insert((index, time, image));
+            return Ok(false);
+        }
+        on_frame(index, time, image)?;
+        Ok(true)
+    };
+
+    let mut stats = match sampling.strategy {
+        FrameStrategy::Keyframes { max } => {
+            let max = max.min(sampling.max_frames_per_video);
+            log::debug!(
+                "Extracting up to {} keyframes from video: {:?}",
+                max,
+                video_path.as_ref()
+            );
+
+            sample_keyframes(&mut video, max, &limits, &mut filtered)?
+        }
+        FrameStrategy::Scenes { max, cut_threshold } => {
+            let max = max.min(sampling.max_frames_per_video);
+            log::debug!(
+                "Extracting up to {} scene frames from video: {:?}",
+                max,
+                video_path.as_ref()
+            );
+
+            sample_scenes(&mut video, max, cut_threshold, &limits, &mut filtered)?
+        }
+        FrameStrategy::Uniform | FrameStrategy::Interval(_) => {
+            let duration = input_duration(&mut video.input)?;
+
+            log::debug!(
+                "Extracting frames from video: {:?}, duration: {:.2}s",
+                video_path.as_ref(),
+                duration
+            );
+
+            let frame_times = sampling.frame_times(duration);
+
+            log::debug!(
+                "Will extract {} frames with {:?} sampling",
+                frame_times.len(),
+                sampling.sampling_policy(duration)
+            );
+
+            match sampling.extraction_mode {
+                ExtractionMode::SequentialDecode => {
+                    // Without a duration in the headers, `input_duration` has
+                    // already read through every packet
+                    let mut video =
+                        VideoDecoder::open(&video_path, sampling.max_dimension, sampling.hw_accel)?;
+                    let mut stats =
+                        sample_sequentially(&mut video, &frame_times, 0, &limits, &mut filtered)?;
+                    stats.hardware_decoded = video.decoder.used_hardware;
+                    stats
                 }
+                ExtractionMode::Seek => {
+                    let (mut stats, seek_failed_at) =
+                        sample_at_times(&mut video, &frame_times, &limits, &mut filtered)?;
 
-                if found_frame {
-                    break;
+                    if let Some(first_index) = seek_failed_at {
+                        log::warn!(
+                            "Seeking failed in {:?}, decoding the remaining {} frames sequentially",
+                            video_path.as_ref(),
+                            frame_times.len() - first_index
+                        );
+                        // Start over from a fresh demuxer, the failed seek may
+                        // have left it anywhere
+                        let mut video = VideoDecoder::open(
+                            &video_path,
+                            sampling.max_dimension,
+                            sampling.hw_accel,
+                        )?;
+                        let rest = sample_sequentially(
+                            &mut video,
+                            &frame_times[first_index..],
+                            first_index,
+                            &limits,
+                            &mut filtered,
+                        )?;
+                        stats.extracted_frames += rest.extracted_frames;
+                        stats.skipped_frames += rest.skipped_frames;
+                        stats.decode_errors += rest.decode_errors;
+                        stats.timed_out = rest.timed_out;
+                        stats.hardware_decoded = video.decoder.used_hardware;
+                    }
+
+                    stats
                 }
             }
         }
+    };
+    stats.hardware_decoded |= video.decoder.used_hardware;
+    stats.uniform_frames = uniform_frames;
+
+    if stats.extracted_frames == 0
+        && let Some((index, time, image)) = first_uniform
+    {
+        log::warn!(
+            "Every frame of {:?} is uniform, keeping the one at {:.2}s",
+            video_path.as_ref(),
+            time
+        );
+        on_frame(index, time, image)?;
+        stats.extracted_frames = 1;
+        stats.only_uniform = true;
     }
 
-    decoder.send_eof().ok();
-    while decoder.receive_frame(&mut decoded_frame).is_ok() {
-        // process any remaining frames if needed
+    if stats.timed_out {
+        let timeout = sampling.timeout_secs.unwrap_or_default();
+        if stats.extracted_frames == 0 {
+            anyhow::bail!("Frame extraction timed out after {}s without a frame", timeout);
+        }
+        log::warn!(
+            "Frame extraction of {:?} timed out after {}s, keeping {} frames",
+            video_path.as_ref(),
+            timeout,
+            stats.extracted_frames
+        );
     }
 
-    if frame_paths.is_empty() {
+    if stats.extracted_frames == 0 {
         anyhow::bail!("Failed to extract any frames from video");
     }
 
-    println!("Successfully extracted {} frames", frame_paths.len());
+    if stats.skipped_frames > 0 || stats.decode_errors > 0 {
+        log::warn!(
+            "Extracted {} frames from {:?}, {} skipped after {} decode errors",
+            stats.extracted_frames,
+            video_path.as_ref(),
+            stats.skipped_frames,
+            stats.decode_errors
+        );
+    } else {
+        log::debug!("Successfully extracted {} frames", stats.extracted_frames);
+    }
 
-    Ok(frame_paths)
+    Ok(stats)
 }
 
-/// Save a video frame as PNG
-fn save_frame_as_png<P: AsRef<Path>>(
-    frame: &ffmpeg::util::frame::video::Video,
-    output_path: P,
-) -> Result<()> {
-    let width = frame.width();
-    let height = frame.height();
+/// Open video file with a decoder for its best video stream and an RGB converter
+struct VideoDecoder {
+    input: ffmpeg::format::context::Input,
+    decoder: FrameDecoder,
+    converter: FrameConverter,
+    stream_index: usize,
+    clock: StreamClock,
+    /// Clockwise rotation applied to every frame before it is handed out
+    rotation: u32,
+}
 
-    let data = frame.data(0);
-    let img_buffer = image::RgbImage::from_raw(width, height, data.to_vec())
-        .context("Failed tp create image buffer from frame")?;
+/// Converts stream timestamps to seconds from the first frame
+#[derive(Debug, Clone, Copy)]
+struct StreamClock {
+    /// Seconds per unit of the video stream's timestamps
+    time_base: f64,
+    /// Timestamp of the first frame, subtracted so sample times start at 0
+    start_pts: i64,
+}
+
+impl StreamClock {
+    /// Presentation time of a decoded frame in seconds
+    fn frame_time(&self, frame: &ffmpeg::util::frame::video::Video) -> f64 {
+        let pts = frame.timestamp().or(frame.pts()).unwrap_or(self.start_pts);
+        (pts - self.start_pts) as f64 * self.time_base
+    }
+
+    /// Position in AV_TIME_BASE units of the container, as format level seeks take
+    fn seek_timestamp(&self, secs: f64) -> i64 {
+        let start_secs = self.start_pts as f64 * self.time_base;
+        ((secs + start_secs) * f64::from(ffmpeg::ffi::AV_TIME_BASE)) as i64
+    }
+}
+
+impl VideoDecoder {
+    /// Frames come out no longer than `max_dimension` on either side
+    /// `hw_accel` decodes on the platform's hardware device when there is one
+    fn open<P: AsRef<Path>>(
+        video_path: P,
+        max_dimension: Option<u32>,
+        hw_accel: bool,
+    ) -> Result<Self> {
+        let input = open_input(video_path.as_ref())
+            .context("Failed to open video file for frame extraction")?;
+
+        let video_stream = main_video_stream(&input)?;
+        let stream_index = video_stream.index();
+        let clock = StreamClock {
+            time_base: f64::from(video_stream.time_base()),
+            start_pts: stream_start_pts(&video_stream),
+        };
+
+        let decoder = FrameDecoder::open(video_stream.parameters(), hw_accel)?;
+
+        // Scale straight to square pixels, the rotation is applied afterwards
+        let metadata = VideoMetadata::from_stream(&video_stream, &decoder);
+        let display_size = metadata.unrotated_display_size();
+        let (output_width, output_height) = match max_dimension {
+            Some(max_dimension) => fit_within(display_size, max_dimension),
+            None => display_size,
+        };
+        // Area averaging keeps large reductions from aliasing
+        let flags = if (output_width, output_height) == display_size {
+            ffmpeg::software::scaling::flag::Flags::BILINEAR
+        } else {
+            ffmpeg::software::scaling::flag::Flags::AREA
+        };
+
+        let converter = FrameConverter::new(&decoder, (output_width, output_height), flags)?;
+
+        Ok(VideoDecoder {
+            input,
+            decoder,
+            converter,
+            stream_index,
+            clock,
+            rotation: metadata.rotation,
+        })
+    }
+}
+
+/// Video decoder that copies frames decoded on a hardware device back to
+/// system memory, and carries on in software when that fails
+/// Derefs to the decoder, only `receive_frame` differs
+struct FrameDecoder {
+    decoder: ffmpeg::decoder::Video,
+    /// Kept to reopen the decoder in software
+    parameters: ffmpeg::codec::Parameters,
+    /// Frame the hardware decoder fills, `None` when decoding in software
+    hw_frame: Option<ffmpeg::util::frame::video::Video>,
+    /// Some frame came back from the hardware device
+    used_hardware: bool,
+}
+
+impl FrameDecoder {
+    fn open(parameters: ffmpeg::codec::Parameters, hw_accel: bool) -> Result<Self> {
+        if hw_accel {
+            match open_decoder(&parameters, true) {
+                Ok(decoder) => {
+                    return Ok(FrameDecoder {
+                        decoder,
+                        parameters,
+                        hw_frame: Some(ffmpeg::util::frame::video::Video::empty()),
+                        used_hardware: false,
+                    });
+                }
+                Err(err) => log::debug!("Decoding in software: {:#}", err),
+            }
+        }
+
+        Ok(FrameDecoder {
+            decoder: open_decoder(&parameters, false)?,
+            parameters,
+            hw_frame: None,
+            used_hardware: false,
+        })
+    }
+
+    /// `receive_frame` of the decoder, with hardware frames copied into `frame`
+    /// When the copy fails the decoder is reopened in software and asks for
+    /// more packets, frames resume from the next keyframe
+    fn receive_frame(
+        &mut self,
+        frame: &mut ffmpeg::util::frame::video::Video,
+    ) -> Result<(), ffmpeg::Error> {
+        let Some(hw_frame) = &mut self.hw_frame else {
+            return self.decoder.receive_frame(frame);
+        };
+        self.decoder.receive_frame(hw_frame)?;
+
+        // SAFETY: the frame was just filled by the decoder
+        let on_device = unsafe { !(*hw_frame.as_ptr()).hw_frames_ctx.is_null() };
+        if !on_device {
+            // The codec has no hardware support, the decoder picked a software format
+            std::mem::swap(frame, hw_frame);
+            return Ok(());
+        }
+
+        // SAFETY: both frames are valid, the transfer allocates the buffers of
+        // the emptied `frame` and the timestamps are copied after it
+        let status = unsafe {
+            ffmpeg::ffi::av_frame_unref(frame.as_mut_ptr());
+            match ffmpeg::ffi::av_hwframe_transfer_data(frame.as_mut_ptr(), hw_frame.as_ptr(), 0) {
+                0 => ffmpeg::ffi::av_frame_copy_props(frame.as_mut_ptr(), hw_frame.as_ptr()),
+                status => status,
+            }
+        };
+        if status >= 0 {
+            self.used_hardware = true;
+            return Ok(());
+        }
+
+        log::warn!(
+            "Copying a frame from the hardware decoder failed ({}), decoding in software",
+            ffmpeg::Error::from(status)
+        );
+        self.decoder = open_decoder(&self.parameters, false).map_err(|err| {
+            log::warn!("Failed to reopen the decoder in software: {:#}", err);
+            ffmpeg::Error::from(status)
+        })?;
+        self.hw_frame = None;
+        Err(ffmpeg::Error::Other {
+            errno: ffmpeg::util::error::EAGAIN,
+        })
+    }
+
+    /// `receive_frame` for decode loops, false once the decoder needs more
+    /// packets or is drained. Any other error is counted in `decode_errors`
+    fn next_frame(
+        &mut self,
+        frame: &mut ffmpeg::util::frame::video::Video,
+        decode_errors: &mut usize,
+    ) -> bool {
+        match self.receive_frame(frame) {
+            Ok(()) => true,
+            Err(err) if is_drained(&err) => false,
+            Err(err) => {
+                log::debug!("Failed to decode a frame: {}", err);
+                *decode_errors += 1;
+                false
+            }
+        }
+    }
+
+    /// Send a packet to the decoder, counting rejected packets in `decode_errors`
+    fn feed(&mut self, packet: &ffmpeg::Packet, decode_errors: &mut usize) -> PacketStatus {
+        match self.decoder.send_packet(packet) {
+            Ok(()) => PacketStatus::Sent,
+            Err(err) if is_drained(&err) => PacketStatus::Full,
+            Err(err) => {
+                log::debug!("Failed to decode packet at {:?}: {}", packet.pts(), err);
+                *decode_errors += 1;
+                PacketStatus::Rejected
+            }
+        }
+    }
+
+    /// `feed` a packet again once the frames the decoder was full with are
+    /// received. Still full means it is stuck on the packet, which is dropped
+    fn resend(&mut self, packet: &ffmpeg::Packet, decode_errors: &mut usize) -> PacketStatus {
+        match self.feed(packet, decode_errors) {
+            PacketStatus::Full => {
+                log::debug!("Decoder still full, dropping packet at {:?}", packet.pts());
+                *decode_errors += 1;
+                PacketStatus::Rejected
+            }
+            status => status,
+        }
+    }
+
+    /// Feed a single pass over the file: the packet the decoder was full for,
+    /// else the next packet of the video stream, else the end of stream.
+    /// False once the end of stream was sent
+    fn feed_in_order(
+        &mut self,
+        packets: &mut ffmpeg::format::context::input::PacketIter<'_>,
+        stream_index: usize,
+        pending: &mut Option<ffmpeg::Packet>,
+        decode_errors: &mut usize,
+    ) -> bool {
+        let (status, packet) = match pending.take() {
+            Some(packet) => (self.resend(&packet, decode_errors), packet),
+            None => match packets.by_ref().find(|(stream, _)| stream.index() == stream_index) {
+                Some((_, packet)) => (self.feed(&packet, decode_errors), packet),
+                None => {
+                    self.decoder.send_eof().ok();
+                    return false;
+                }
+            },
+        };
+        if status == PacketStatus::Full {
+            *pending = Some(packet);
+        }
+        true
+    }
+}
+
+/// Outcome of `FrameDecoder::feed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketStatus {
+    Sent,
+    /// Frames are waiting to be received, the same packet has to be sent again after
+    Full,
+    /// The packet is corrupt, only its own frames are lost
+    Rejected,
+}
+
+/// EAGAIN and end of stream are how the decoder asks for input or output,
+/// not decode errors
+fn is_drained(err: &ffmpeg::Error) -> bool {
+    match err {
+        ffmpeg::Error::Eof => true,
+        ffmpeg::Error::Other { errno } => *errno == ffmpeg::util::error::EAGAIN,
+        _ => false,
+    }
+}
+
+impl std::ops::Deref for FrameDecoder {
+    type Target = ffmpeg::decoder::Video;
+
+    fn deref(&self) -> &Self::Target {
+        &self.decoder
+    }
+}
+
+impl std::ops::DerefMut for FrameDecoder {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.decoder
+    }
+}
+
+/// Open a decoder for `parameters`, on the platform's hardware device with `hw_accel`
+fn open_decoder(
+    parameters: &ffmpeg::codec::Parameters,
+    hw_accel: bool,
+) -> Result<ffmpeg::decoder::Video> {
+    let mut context = ffmpeg::codec::context::Context::from_parameters(parameters.clone())
+        .context("Failed to create codec context")?;
+    if hw_accel {
+        attach_hw_device(&mut context)?;
+    }
+
+    context.decoder().video().context("Failed to create video decoder")
+}
 
-    img_buffer
-        .save(output_path.as_ref())
-        .context("Failed to save frame as PNG")?;
+/// Hardware decoding API of the platform: VideoToolbox, VAAPI or D3D11VA
+fn hw_device_type() -> Option<ffmpeg::ffi::AVHWDeviceType> {
+    use ffmpeg::ffi::AVHWDeviceType;
+
+    if cfg!(target_os = "macos") {
+        Some(AVHWDeviceType::AV_HWDEVICE_TYPE_VIDEOTOOLBOX)
+    } else if cfg!(target_os = "linux") {
+        Some(AVHWDeviceType::AV_HWDEVICE_TYPE_VAAPI)
+    } else if cfg!(windows) {
+        Some(AVHWDeviceType::AV_HWDEVICE_TYPE_D3D11VA)
+    } else {
+        None
+    }
+}
+
+/// Give a codec context not yet opened a hardware device to decode on
+/// ffmpeg's default format negotiation then picks the hardware format when
+/// the codec supports the device, and a software one otherwise
+fn attach_hw_device(context: &mut ffmpeg::codec::context::Context) -> Result<()> {
+    let device_type = hw_device_type().context("No hardware decoding on this platform")?;
+
+    let mut device = std::ptr::null_mut();
+    // SAFETY: on success `device` holds a new reference, which the codec
+    // context takes over and releases when it is freed
+    unsafe {
+        let status = ffmpeg::ffi::av_hwdevice_ctx_create(
+            &mut device,
+            device_type,
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            0,
+        );
+        if status < 0 {
+            anyhow::bail!(
+                "Failed to create a {:?} device: {}",
+                device_type,
+                ffmpeg::Error::from(status)
+            );
+        }
+        (*context.as_mut_ptr()).hw_device_ctx = device;
+    }
 
     Ok(())
 }
 
-// Get Image Dimensions
-pub fn get_image_dimensions<P: AsRef<Path>>(image_path: P) -> Result<(u32, u32)> {
-    let img = image::open(image_path.as_ref()).context("Failed to open image")?;
-    Ok(img.dimensions())
+/// Scales decoded frames to RGB24 with the stream's YUV matrix
+/// BT.2020 streams go through 16 bit RGB and a `ToneMapper` instead, so HDR
+/// and SDR encodes of the same content come out alike
+struct FrameConverter {
+    scaler: ffmpeg::software::scaling::Context,
+    tone_mapper: Option<ToneMapper>,
+    /// Output size and flags, kept to rebuild the scaler for frames that
+    /// differ from the stream parameters, like those copied from a hardware decoder
+    output: (u32, u32),
+    flags: ffmpeg::software::scaling::flag::Flags,
+    color: (ffmpeg::util::color::Space, ffmpeg::util::color::Range),
+}
+
+impl FrameConverter {
+    fn new(
+        decoder: &ffmpeg::decoder::Video,
+        (width, height): (u32, u32),
+        flags: ffmpeg::software::scaling::flag::Flags,
+    ) -> Result<Self> {
+        let tone_mapper = stream_color_transfer(decoder).map(ToneMapper::new);
+        let output_format = match tone_mapper {
+            Some(ref tone_mapper) => {
+                log::debug!(
+                    "Converting {:?} {:?} frames from BT.2020",
+                    decoder.format(),
+                    tone_mapper.transfer()
+                );
+                ffmpeg::format::Pixel::RGB48LE
+            }
+            None => ffmpeg::format::Pixel::RGB24,
+        };
+
+        let color = (decoder.color_space(), decoder.color_range());
+        let input = (decoder.format(), decoder.width(), decoder.height());
+        let scaler = rgb_scaler(input, output_format, (width, height), flags, color)?;
+
+        Ok(FrameConverter {
+            scaler,
+            tone_mapper,
+            output: (width, height),
+            flags,
+            color,
+        })
+    }
+
+    fn convert(&mut self, frame: &ffmpeg::util::frame::video::Video) -> Result<image::RgbImage> {
+        if !scaler_fits(&self.scaler, frame) {
+            let input = (frame.format(), frame.width(), frame.height());
+            let output_format = self.scaler.output().format;
+            self.scaler = rgb_scaler(input, output_format, self.output, self.flags, self.color)?;
+        }
+
+        let mut rgb_frame = ffmpeg::util::frame::video::Video::empty();
+        self.scaler
+            .run(frame, &mut rgb_frame)
+            .context("Failed to scale frame")?;
+
+        match self.tone_mapper {
+            Some(ref tone_mapper) => rgb_image_from_rgb48(
+                rgb_frame.data(0),
+                rgb_frame.stride(0),
+                (rgb_frame.width(), rgb_frame.height()),
+                tone_mapper,
+            ),
+            None => frame_to_rgb_image(&rgb_frame),
+        }
+    }
+}
+
+/// Transfer of streams with BT.2020 primaries or an HDR transfer, `None` for
+/// streams swscale converts well enough on its own
+fn stream_color_transfer(decoder: &ffmpeg::decoder::Video) -> Option<ColorTransfer> {
+    use ffmpeg::util::color::{Primaries, TransferCharacteristic};
+
+    match decoder.color_transfer_characteristic() {
+        TransferCharacteristic::SMPTE2084 => Some(ColorTransfer::Pq),
+        TransferCharacteristic::ARIB_STD_B67 => Some(ColorTransfer::Hlg),
+        _ if decoder.color_primaries() == Primaries::BT2020 => Some(ColorTransfer::Sdr),
+        _ => None,
+    }
+}
+
+/// Whether `scaler` was built for frames of `frame`'s format and size
+fn scaler_fits(
+    scaler: &ffmpeg::software::scaling::Context,
+    frame: &ffmpeg::util::frame::video::Video,
+) -> bool {
+    let input = scaler.input();
+    (input.format, input.width, input.height) == (frame.format(), frame.width(), frame.height())
+}
+
+/// Scaler from `(format, width, height)` frames to `output_format` reading
+/// YUV with the stream's matrix and range
+fn rgb_scaler(
+    (format, width, height): (ffmpeg::format::Pixel, u32, u32),
+    output_format: ffmpeg::format::Pixel,
+    (output_width, output_height): (u32, u32),
+    flags: ffmpeg::software::scaling::flag::Flags,
+    color: (ffmpeg::util::color::Space, ffmpeg::util::color::Range),
+) -> Result<ffmpeg::software::scaling::Context> {
+    let mut scaler = ffmpeg::software::scaling::context::Context::get(
+        format,
+        width,
+        height,
+        output_format,
+        output_width,
+        output_height,
+        flags,
+    )
+    .context("Failed to create scaler")?;
+    set_yuv_coefficients(&mut scaler, color);
+    Ok(scaler)
+}
+
+/// Make the scaler read YUV with the matrix and range the stream is tagged
+/// with, swscale assumes limited range BT.601 otherwise
+fn set_yuv_coefficients(
+    scaler: &mut ffmpeg::software::scaling::Context,
+    (space, range): (ffmpeg::util::color::Space, ffmpeg::util::color::Range),
+) {
+    use ffmpeg::util::color::{Range, Space};
+
+    let coefficients = match space {
+        Space::BT709 => ffmpeg::ffi::SWS_CS_ITU709,
+        Space::FCC => ffmpeg::ffi::SWS_CS_FCC,
+        Space::SMPTE240M => ffmpeg::ffi::SWS_CS_SMPTE240M,
+        Space::BT2020NCL | Space::BT2020CL => ffmpeg::ffi::SWS_CS_BT2020,
+        _ => ffmpeg::ffi::SWS_CS_DEFAULT,
+    };
+    let source_range = i32::from(range == Range::JPEG);
+
+    // SAFETY: the scaler pointer is valid for the duration of the call and
+    // the coefficient tables are static, swscale copies what it needs
+    let status = unsafe {
+        let table = ffmpeg::ffi::sws_getCoefficients(coefficients as i32);
+        ffmpeg::ffi::sws_setColorspaceDetails(
+            scaler.as_mut_ptr(),
+            table,
+            source_range,
+            ffmpeg::ffi::sws_getCoefficients(ffmpeg::ffi::SWS_CS_DEFAULT as i32),
+            1,
+            0,
+            1 << 16,
+            1 << 16,
+        )
+    };
+    if status < 0 {
+        log::debug!("Scaler kept its default YUV coefficients for {:?}", space);
+    }
+}
+
+/// What became of a decoded frame handed to `deliver_frame`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delivery {
+    Delivered,
+    /// The conversion failed and the frame was skipped
+    Failed,
+    /// `on_frame` passed over the frame for being uniform
+    Uniform,
+}
+
+/// Convert a decoded frame to upright RGB and pass it to `on_frame`, which
+/// returns false when it passes over the frame
+fn deliver_frame<F>(
+    converter: &mut FrameConverter,
+    rotation: u32,
+    frame: &ffmpeg::util::frame::video::Video,
+    index: usize,
+    time: f64,
+    on_frame: &mut F,
+    stats: &mut FrameExtractionStats,
+) -> Result<Delivery>
+where
+    F: FnMut(usize, f64, image::RgbImage) -> Result<bool>,
+{
+    let converted = converter.convert(frame).map(|image| rotate_frame(image, rotation));
+
+    match converted {
+        Ok(image) => {
+            log::trace!("Extracted frame {} at {:.2}s", index, time);
+
+            if !on_frame(index, time, image)? {
+                return Ok(Delivery::Uniform);
+            }
+            stats.extracted_frames += 1;
+            Ok(Delivery::Delivered)
+        }
+        Err(err) => {
+            log::warn!("Skipping frame {} at {:.2}s: {:#}", index, time, err);
+            Ok(Delivery::Failed)
+        }
+    }
+}
+
+/// Time up to which a uniform sample at `frame_times[index]` gives way to
+/// the next frame that isn't, never past the next sample time
+fn uniform_search_end(frame_times: &[f64], index: usize) -> f64 {
+    let limit = frame_times[index] + UNIFORM_SEARCH_SECS;
+    frame_times.get(index + 1).map_or(limit, |&next_time| next_time.min(limit))
+}
+
+/// Seek to each target time and decode the first frame at or after it
+/// A target is skipped after `max_packets_per_frame` packets without its frame.
+/// Stops at the first target that can't be seeked to and returns its index, the
+/// remaining targets are left to `sample_sequentially`
+fn sample_at_times<F>(
+    video: &mut VideoDecoder,
+    frame_times: &[f64],
+    limits: &ExtractionLimits,
+    on_frame: &mut F,
+) -> Result<(FrameExtractionStats, Option<usize>)>
+where
+    F: FnMut(usize, f64, image::RgbImage) -> Result<bool>,
+{
+    let mut stats = FrameExtractionStats::default();
+    let mut decoded_frame = ffmpeg::util::frame::video::Video::empty();
+
+    for (idx, &target_time) in frame_times.iter().enumerate() {
+        if limits.timed_out()? {
+            stats.timed_out = true;
+            stats.skipped_frames += frame_times.len() - idx;
+            break;
+        }
+
+        // Capping the range at the target lands on the keyframe at or before
+        // it, like AVSEEK_FLAG_BACKWARD
+        let timestamp = video.clock.seek_timestamp(target_time);
+        if let Err(err) = video.input.seek(timestamp, ..timestamp) {
+            log::debug!("Failed to seek to {:.2}s for frame {}: {}", target_time, idx, err);
+            return Ok((stats, Some(idx)));
+        }
+
+        // Drop frames buffered from before the seek
+        video.decoder.flush();
+
+        let search_end = uniform_search_end(frame_times, idx);
+        let mut found_frame = false;
+        let mut frame_failed = false;
+        let mut passed_uniform = false;
+        for (packets_read, (stream, packet)) in video.input.packets().enumerate() {
+            if packets_read >= limits.max_packets_per_frame {
+                log::warn!(
+                    "Skipping frame {}: no frame near {:.2}s after {} packets",
+                    idx,
+                    target_time,
+                    packets_read
+                );
+                break;
+            }
+            if limits.timed_out()? {
+                stats.timed_out = true;
+                break;
+            }
+
+            if stream.index() != video.stream_index {
+                continue;
+            }
+
+            // A corrupted packet only costs this packet, keep reading
+            let mut status = video.decoder.feed(&packet, &mut stats.decode_errors);
+            while status != PacketStatus::Rejected {
+                while video.decoder.next_frame(&mut decoded_frame, &mut stats.decode_errors) {
+                    let current_time = video.clock.frame_time(&decoded_frame);
+                    // Decoding restarts at the keyframe before the target, the
+                    // first frame at or after the target is the sample
+                    if current_time + FRAME_TIME_TOLERANCE < target_time {
+                        continue;
+                    }
+                    if passed_uniform && current_time + FRAME_TIME_TOLERANCE >= search_end {
+                        frame_failed = true;
+                        break;
+                    }
+
+                    // One frame per target time
+                    match deliver_frame(
+                        &mut video.converter,
+                        video.rotation,
+                        &decoded_frame,
+                        idx,
+                        current_time,
+                        on_frame,
+                        &mut stats,
+                    )? {
+                        Delivery::Delivered => found_frame = true,
+                        Delivery::Failed => frame_failed = true,
+                        Delivery::Uniform => {
+                            passed_uniform = true;
+                            continue;
+                        }
+                    }
+                    break;
+                }
+
+                if found_frame || frame_failed || status == PacketStatus::Sent {
+                    break;
+                }
+                status = video.decoder.resend(&packet, &mut stats.decode_errors);
+            }
+
+            if found_frame || frame_failed {
+                break;
+            }
+        }
+
+        // Only uniform frames near the target is no decode error
+        if !found_frame && !passed_uniform {
+            stats.skipped_frames += 1;
+        }
+        if stats.timed_out {
+            stats.skipped_frames += frame_times.len() - idx - 1;
+            break;
+        }
+    }
+
+    video.decoder.send_eof().ok();
+    while video.decoder.next_frame(&mut decoded_frame, &mut stats.decode_errors) {
+        // process any remaining frames if needed
+    }
+
+    Ok((stats, None))
+}
+
+/// Decode every frame once from the current position and deliver, for each
+/// target time, whichever of the frames just before and at or after it is
+/// closer. Never seeks. `first_index` is the sample index of `frame_times[0]`
+fn sample_sequentially<F>(
+    video: &mut VideoDecoder,
+    frame_times: &[f64],
+    first_index: usize,
+    limits: &ExtractionLimits,
+    on_frame: &mut F,
+) -> Result<FrameExtractionStats>
+where
+    F: FnMut(usize, f64, image::RgbImage) -> Result<bool>,
+{
+    let mut stats = FrameExtractionStats::default();
+    let mut decoded_frame = ffmpeg::util::frame::video::Video::empty();
+    // Last frame before the next target, swapped rather than copied
+    let mut previous_frame = ffmpeg::util::frame::video::Video::empty();
+    let mut previous_time = None;
+    let mut next = 0;
+    let mut passed_uniform = false;
+    let mut eof_sent = false;
+    let mut pending = None;
+    let mut packets = video.input.packets();
+
+    while next < frame_times.len() {
+        if limits.timed_out()? {
+            stats.timed_out = true;
+            break;
+        }
+
+        if !video.decoder.next_frame(&mut decoded_frame, &mut stats.decode_errors) {
+            if eof_sent {
+                break;
+            }
+            // A corrupted packet only costs this packet, keep reading
+            eof_sent = !video.decoder.feed_in_order(
+                &mut packets,
+                video.stream_index,
+                &mut pending,
+                &mut stats.decode_errors,
+            );
+            continue;
+        }
+
+        let time = video.clock.frame_time(&decoded_frame);
+        let mut delivered_current = false;
+
+        // One frame may be the closest to several targets
+        while next < frame_times.len() && time + FRAME_TIME_TOLERANCE >= frame_times[next] {
+            let target_time = frame_times[next];
+            let index = first_index + next;
+
+            let search_end = uniform_search_end(frame_times, next);
+            if passed_uniform && time + FRAME_TIME_TOLERANCE >= search_end {
+                // Nothing but uniform frames near this target
+                next += 1;
+                passed_uniform = false;
+                continue;
+            }
+
+            // The frame before the target wins when it is closer
+            if let Some(before) = previous_time.take()
+                && !passed_uniform
+                && target_time - before < time - target_time
+            {
+                let delivery = deliver_frame(
+                    &mut video.converter,
+                    video.rotation,
+                    &previous_frame,
+                    index,
+                    before,
+                    on_frame,
+                    &mut stats,
+                )?;
+                match delivery {
+                    Delivery::Delivered => {
+                        next += 1;
+                        continue;
+                    }
+                    Delivery::Failed => {
+                        stats.skipped_frames += 1;
+                        next += 1;
+                        continue;
+                    }
+                    // The current frame is the next one nearby
+                    Delivery::Uniform => passed_uniform = true,
+                }
+            }
+
+            let delivery = deliver_frame(
+                &mut video.converter,
+                video.rotation,
+                &decoded_frame,
+                index,
+                time,
+                on_frame,
+                &mut stats,
+            )?;
+            match delivery {
+                Delivery::Delivered => {
+                    delivered_current = true;
+                    passed_uniform = false;
+                    next += 1;
+                }
+                Delivery::Failed => {
+                    stats.skipped_frames += 1;
+                    passed_uniform = false;
+                    next += 1;
+                }
+                Delivery::Uniform => {
+                    passed_uniform = true;
+                    break;
+                }
+            }
+        }
+
+        // A delivered frame is not offered to the next target again
+        if delivered_current {
+            previous_time = None;
+        } else {
+            std::mem::swap(&mut decoded_frame, &mut previous_frame);
+            previous_time = Some(time);
+        }
+    }
+
+    // Targets past the last frame take the last frame when it wasn't used yet
+    if !stats.timed_out
+        && next < frame_times.len()
+        && !passed_uniform
+        && let Some(before) = previous_time
+    {
+        let delivery = deliver_frame(
+            &mut video.converter,
+            video.rotation,
+            &previous_frame,
+            first_index + next,
+            before,
+            on_frame,
+            &mut stats,
+        )?;
+        if delivery != Delivery::Uniform {
+            stats.skipped_frames += usize::from(delivery == Delivery::Failed);
+            next += 1;
+        }
+    }
+
+    // Only uniform frames near the target is no decode error
+    let unfinished = frame_times.len() - next;
+    stats.skipped_frames += unfinished.saturating_sub(usize::from(passed_uniform));
+
+    Ok(stats)
+}
+
+/// Read the file once and decode only keyframe packets, without seeking
+/// Stops after `max` frames
+fn sample_keyframes<F>(
+    video: &mut VideoDecoder,
+    max: usize,
+    limits: &ExtractionLimits,
+    on_frame: &mut F,
+) -> Result<FrameExtractionStats>
+where
+    F: FnMut(usize, f64, image::RgbImage) -> Result<bool>,
+{
+    let mut stats = FrameExtractionStats::default();
+    let mut decoded_frame = ffmpeg::util::frame::video::Video::empty();
+
+    for (stream, packet) in video.input.packets() {
+        if stats.extracted_frames >= max {
+            break;
+        }
+        if limits.timed_out()? {
+            stats.timed_out = true;
+            break;
+        }
+
+        if stream.index() != video.stream_index || !packet.is_key() {
+            continue;
+        }
+
+        let mut status = video.decoder.feed(&packet, &mut stats.decode_errors);
+        if status == PacketStatus::Rejected {
+            stats.skipped_frames += 1;
+            continue;
+        }
+
+        loop {
+            while stats.extracted_frames < max
+                && video.decoder.next_frame(&mut decoded_frame, &mut stats.decode_errors)
+            {
+                let time = video.clock.frame_time(&decoded_frame);
+                let index = stats.extracted_frames;
+                let delivered = deliver_frame(
+                    &mut video.converter,
+                    video.rotation,
+                    &decoded_frame,
+                    index,
+                    time,
+                    on_frame,
+                    &mut stats,
+                )?;
+                if delivered == Delivery::Failed {
+                    stats.skipped_frames += 1;
+                }
+            }
+
+            if status != PacketStatus::Full || stats.extracted_frames >= max {
+                break;
+            }
+            status = video.decoder.resend(&packet, &mut stats.decode_errors);
+            stats.skipped_frames += usize::from(status == PacketStatus::Rejected);
+        }
+    }
+
+    // Keyframes still buffered in the decoder
+    video.decoder.send_eof().ok();
+    while video.decoder.next_frame(&mut decoded_frame, &mut stats.decode_errors) {
+        if stats.extracted_frames >= max {
+            continue;
+        }
+
+        let time = video.clock.frame_time(&decoded_frame);
+        let index = stats.extracted_frames;
+        let delivered = deliver_frame(
+            &mut video.converter,
+            video.rotation,
+            &decoded_frame,
+            index,
+            time,
+            on_frame,
+            &mut stats,
+        )?;
+        if delivered == Delivery::Failed {
+            stats.skipped_frames += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Side of the grayscale thumbnail used for scene cut detection
+/// A 9x8 difference hash gives 64 bits, like the default perceptual hash
+const SCENE_HASH_WIDTH: u32 = 9;
+const SCENE_HASH_HEIGHT: u32 = 8;
+
+/// Decode every frame at thumbnail resolution and sample the first frame of
+/// each scene. A scene starts whenever the hash distance to the previous frame
+/// exceeds `cut_threshold` bits. The first frame always opens a scene.
+/// Stops after `max` frames
+fn sample_scenes<F>(
+    video: &mut VideoDecoder,
+    max: usize,
+    cut_threshold: u32,
+    limits: &ExtractionLimits,
+    on_frame: &mut F,
+) -> Result<FrameExtractionStats>
+where
+    F: FnMut(usize, f64, image::RgbImage) -> Result<bool>,
+{
+    let scene_detector = |format, width, height| {
+        ffmpeg::software::scaling::context::Context::get(
+            format,
+            width,
+            height,
+            ffmpeg::format::Pixel::GRAY8,
+            SCENE_HASH_WIDTH,
+            SCENE_HASH_HEIGHT,
+            ffmpeg::software::scaling::flag::Flags::AREA,
+        )
+        .context("Failed to create scene detection scaler")
+    };
+    let mut detector =
+        scene_detector(video.decoder.format(), video.decoder.width(), video.decoder.height())?;
+
+    let mut stats = FrameExtractionStats::default();
+    let mut decoded_frame = ffmpeg::util::frame::video::Video::empty();
+    let mut previous_hash = None;
+    let mut eof_sent = false;
+    let mut pending = None;
+    let mut packets = video.input.packets();
+
+    while stats.extracted_frames < max {
+        if limits.timed_out()? {
+            stats.timed_out = true;
+            break;
+        }
+
+        if !video.decoder.next_frame(&mut decoded_frame, &mut stats.decode_errors) {
+            if eof_sent {
+                break;
+            }
+            eof_sent = !video.decoder.feed_in_order(
+                &mut packets,
+                video.stream_index,
+                &mut pending,
+                &mut stats.decode_errors,
+            );
+            continue;
+        }
+
+        if !scaler_fits(&detector, &decoded_frame) {
+            let (width, height) = (decoded_frame.width(), decoded_frame.height());
+            detector = scene_detector(decoded_frame.format(), width, height)?;
+        }
+        let mut thumbnail = ffmpeg::util::frame::video::Video::empty();
+        let frame_hash = match detector.run(&decoded_frame, &mut thumbnail) {
+            Ok(()) => difference_hash(&thumbnail),
+            Err(err) => {
+                log::debug!("Failed to scale frame for scene detection: {}", err);
+                continue;
+            }
+        };
+
+        let is_cut = match previous_hash {
+            Some(previous) => hash::hamming_distance_u64(previous, frame_hash) > cut_threshold,
+            None => true,
+        };
+        previous_hash = Some(frame_hash);
+
+        if is_cut {
+            let time = video.clock.frame_time(&decoded_frame);
+            let index = stats.extracted_frames;
+            let delivered = deliver_frame(
+                &mut video.converter,
+                video.rotation,
+                &decoded_frame,
+                index,
+                time,
+                on_frame,
+                &mut stats,
+            )?;
+            if delivered == Delivery::Failed {
+                stats.skipped_frames += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Variance of the BT.601 luma of an image, in squared levels out of 255
+fn luma_variance(image: &image::RgbImage) -> f64 {
+    let count = image.width() as usize * image.height() as usize;
+    if count == 0 {
+        return 0.0;
+    }
+
+    let (sum, sum_of_squares) = image.pixels().fold((0.0, 0.0), |(sum, squares), pixel| {
+        let [red, green, blue] = pixel.0.map(f64::from);
+        let luma = 0.299 * red + 0.587 * green + 0.114 * blue;
+        (sum + luma, squares + luma * luma)
+    });
+    let mean = sum / count as f64;
+    (sum_of_squares / count as f64 - mean * mean).max(0.0)
+}
+
+/// 64 bit difference hash of a 9x8 GRAY8 frame, one bit per horizontal
+/// neighbour pair that gets brighter
+fn difference_hash(frame: &ffmpeg::util::frame::video::Video) -> u64 {
+    let data = frame.data(0);
+    let stride = frame.stride(0);
+
+    let mut hash = 0u64;
+    for y in 0..SCENE_HASH_HEIGHT as usize {
+        let row = &data[y * stride..y * stride + SCENE_HASH_WIDTH as usize];
+        for pair in row.windows(2) {
+            hash = (hash << 1) | u64::from(pair[1] > pair[0]);
+        }
+    }
+
+    hash
+}
+
+/// Copy an RGB24 video frame into an image buffer
+fn frame_to_rgb_image(frame: &ffmpeg::util::frame::video::Video) -> Result<image::RgbImage> {
+    rgb_image_from_strided(frame.data(0), frame.stride(0), frame.width(), frame.height())
+}
+
+/// Build an image from packed RGB24 rows that are `stride` bytes apart
+/// ffmpeg pads each row (`linesize`) for alignment, so only the first
+/// `width * 3` bytes of every row are pixels
+fn rgb_image_from_strided(
+    data: &[u8],
+    stride: usize,
+    width: u32,
+    height: u32,
+) -> Result<image::RgbImage> {
+    let row_len = width as usize * 3;
+    if stride < row_len {
+        anyhow::bail!("Frame stride {} is shorter than a {} pixel row", stride, width);
+    }
+
+    let mut pixels = Vec::with_capacity(row_len * height as usize);
+    for row in 0..height as usize {
+        let start = row * stride;
+        let row_data = data
+            .get(start..start + row_len)
+            .context("Frame data is shorter than its dimensions")?;
+        pixels.extend_from_slice(row_data);
+    }
+
+    image::RgbImage::from_raw(width, height, pixels)
+        .context("Failed to create image buffer from frame")
+}
+
+/// Tone map packed little endian RGB48 rows that are `stride` bytes apart
+fn rgb_image_from_rgb48(
+    data: &[u8],
+    stride: usize,
+    (width, height): (u32, u32),
+    tone_mapper: &ToneMapper,
+) -> Result<image::RgbImage> {
+    let row_len = width as usize * 6;
+    if stride < row_len {
+        anyhow::bail!("Frame stride {} is shorter than a {} pixel row", stride, width);
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 3);
+    for row in 0..height as usize {
+        let start = row * stride;
+        let row_data = data
+            .get(start..start + row_len)
+            .context("Frame data is shorter than its dimensions")?;
+        for pixel in row_data.chunks_exact(6) {
+            let channel = |offset: usize| u16::from_le_bytes([pixel[offset], pixel[offset + 1]]);
+            pixels.extend_from_slice(&tone_mapper.map([channel(0), channel(2), channel(4)]));
+        }
+    }
+
+    image::RgbImage::from_raw(width, height, pixels)
+        .context("Failed to create image buffer from frame")
+}
+
+/// Decode a still image through ffmpeg, for HEIF and AVIF files
+/// The display matrix rotation is applied. HEIF grid images decode to the
+/// primary stream ffmpeg picks, which may be a single tile
+pub fn decode_image_with_ffmpeg<P: AsRef<Path>>(image_path: P) -> Result<image::RgbImage> {
+    init_ffmpeg()?;
+
+    let mut video = VideoDecoder::open(&image_path, None, false)?;
+    let mut decoded_frame = ffmpeg::util::frame::video::Video::empty();
+    let mut eof_sent = false;
+    let mut packets = video.input.packets();
+
+    loop {
+        if video.decoder.receive_frame(&mut decoded_frame).is_ok() {
+            let image = video.converter.convert(&decoded_frame).context("Failed to convert image")?;
+            return Ok(rotate_frame(image, video.rotation));
+        }
+
+        if eof_sent {
+            anyhow::bail!("No image could be decoded");
+        }
+
+        match packets.by_ref().find(|(stream, _)| stream.index() == video.stream_index) {
+            Some((_, packet)) => {
+                video.decoder.send_packet(&packet).context("Failed to decode image")?;
+            }
+            None => {
+                video.decoder.send_eof().ok();
+                eof_sent = true;
+            }
+        }
+    }
+}
+
+/// Fingerprint of the first `audio::MAX_SECONDS` of the best audio stream,
+/// `None` when the file has no audio stream
+pub fn audio_fingerprint<P: AsRef<Path>>(path: P) -> Result<Option<AudioFingerprint>> {
+    let samples = decode_mono_audio(path.as_ref())?;
+    Ok(samples.map(|samples| audio::fingerprint_samples(&samples)))
+}
+
+/// Samples of the best audio stream downmixed to mono at `audio::SAMPLE_RATE`
+fn decode_mono_audio(path: &Path) -> Result<Option<Vec<f32>>> {
+    init_ffmpeg()?;
+    let mut input = open_input(path).context("Failed to open video file")?;
+
+    let (stream_index, mut decoder) = {
+        let Some(stream) = input.streams().best(ffmpeg::media::Type::Audio) else {
+            return Ok(None);
+        };
+        let decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+            .context("Failed to create decoder context")?
+            .decoder()
+            .audio()
+            .context("Failed to create audio decoder")?;
+        (stream.index(), decoder)
+    };
+
+    let mut mono = Vec::new();
+    let mut rate = 0;
+    let mut frame = ffmpeg::util::frame::audio::Audio::empty();
+    let mut eof_sent = false;
+    let mut packets = input.packets();
+
+    loop {
+        while decoder.receive_frame(&mut frame).is_ok() {
+            rate = frame.rate();
+            downmix_frame(&frame, &mut mono);
+        }
+
+        let enough = rate > 0 && mono.len() as f64 >= audio::MAX_SECONDS * rate as f64;
+        if eof_sent || enough {
+            break;
+        }
+
+        match packets.by_ref().find(|(stream, _)| stream.index() == stream_index) {
+            Some((_, packet)) => {
+                // A corrupt packet only loses its own samples
+                if let Err(err) = decoder.send_packet(&packet) {
+                    log::debug!("Skipping audio packet: {}", err);
+                }
+            }
+            None => {
+                decoder.send_eof().ok();
+                eof_sent = true;
+            }
+        }
+    }
+
+    if rate == 0 {
+        return Ok(Some(Vec::new()));
+    }
+    mono.truncate((audio::MAX_SECONDS * rate as f64) as usize);
+    Ok(Some(resample(&mono, rate, audio::SAMPLE_RATE)))
+}
+
+/// Append the mean over the channels of every sample of a decoded audio frame
+fn downmix_frame(frame: &ffmpeg::util::frame::audio::Audio, mono: &mut Vec<f32>) {
+    use ffmpeg::format::Sample;
+
+    let format = frame.format();
+    let (size, read): (usize, fn(&[u8]) -> f32) = match format {
+        Sample::U8(_) => (1, |bytes| (bytes[0] as f32 - 128.0) / 128.0),
+        Sample::I16(_) => (2, |bytes| {
+            i16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / 32768.0
+        }),
+        Sample::I32(_) => (4, |bytes| {
+            bytes.try_into().map_or(0.0, |bytes| i32::from_ne_bytes(bytes) as f32 / 2_147_483_648.0)
+        }),
+        Sample::F32(_) => (4, |bytes| bytes.try_into().map_or(0.0, f32::from_ne_bytes)),
+        Sample::F64(_) => (8, |bytes| {
+            bytes.try_into().map_or(0.0, |bytes| f64::from_ne_bytes(bytes) as f32)
+        }),
+        _ => return,
+    };
+
+    // SAFETY: the frame was filled by the decoder, so its channel layout is set
+    let channels = unsafe { (*frame.as_ptr()).ch_layout.nb_channels }.max(1) as usize;
+    let planar = format.is_planar();
+    // Planes past the ones exposed are extended data, those channels are left out
+    let channels = if planar { channels.min(frame.planes()) } else { channels };
+
+    for index in 0..frame.samples() {
+        let sum: f32 = (0..channels)
+            .map(|channel| {
+                let (plane, offset) = if planar {
+                    (channel, index * size)
+                } else {
+                    (0, (index * channels + channel) * size)
+                };
+                frame.data(plane).get(offset..offset + size).map_or(0.0, read)
+            })
+            .sum();
+        mono.push(sum / channels as f32);
+    }
+}
+
+/// Resample by averaging the source samples behind each output sample, which
+/// also keeps most of the content above the new Nyquist frequency out
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    let step = from_rate as f64 / to_rate as f64;
+    let count = (samples.len() as f64 / step) as usize;
+
+    (0..count)
+        .map(|index| {
+            let start = (index as f64 * step) as usize;
+            let end = (((index + 1) as f64 * step) as usize).clamp(start + 1, samples.len());
+            let span = &samples[start..end];
+            span.iter().sum::<f32>() / span.len() as f32
+        })
+        .collect()
+}
+
+/// Layout of the sheet written by `generate_contact_sheet`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactSheetOptions {
+    /// Frames shown on the sheet, pass the grouping sampling to show exactly
+    /// the frames that were hashed
+    pub sampling: FrameSamplingConfig,
+    /// Height in pixels of every tile, the width follows the video's aspect ratio
+    pub tile_height: u32,
+    /// Tiles per row, all tiles go on one row when `None`
+    pub columns: Option<usize>,
+    /// Pixels between tiles and around the edge of the sheet
+    pub spacing: u32,
+    /// Draw each frame's timestamp in the bottom left corner of its tile
+    pub show_timestamps: bool,
+}
+
+impl Default for ContactSheetOptions {
+    fn default() -> Self {
+        ContactSheetOptions {
+            sampling: FrameSamplingConfig::default(),
+            tile_height: 180,
+            columns: None,
+            spacing: 4,
+            show_timestamps: true,
+        }
+    }
+}
+
+/// Background of the contact sheet and of the timestamp labels
+const SHEET_BACKGROUND: image::Rgb<u8> = image::Rgb([24, 24, 24]);
+
+/// Write one image showing the sampled frames of a video side by side
+/// The output format follows the extension of `output_path`
+pub fn generate_contact_sheet<P: AsRef<Path>, O: AsRef<Path>>(
+    video_path: P,
+    output_path: O,
+    options: &ContactSheetOptions,
+) -> Result<()> {
+    if options.tile_height == 0 {
+        anyhow::bail!("tile_height must be at least 1");
+    }
+    if options.columns == Some(0) {
+        anyhow::bail!("columns must be at least 1");
+    }
+    options.sampling.validate()?;
+
+    // Frames are shrunk to tiles as they arrive, so only tiles are held
+    let mut tiles = Vec::new();
+    extract_frames_as_images(&video_path, &options.sampling, None, |_, timestamp_secs, frame| {
+        let width = (u64::from(frame.width()) * u64::from(options.tile_height)
+            / u64::from(frame.height().max(1))) as u32;
+        let tile = image::imageops::resize(
+            &frame,
+            width.max(1),
+            options.tile_height,
+            image::imageops::FilterType::Triangle,
+        );
+        tiles.push((tile, timestamp_secs));
+        Ok(())
+    })?;
+
+    let output_path = output_path.as_ref();
+    compose_contact_sheet(&tiles, options)
+        .save(output_path)
+        .with_context(|| format!("Failed to write contact sheet {}", output_path.display()))
+}
+
+/// Lay out equally sized tiles in rows of `options.columns`
+fn compose_contact_sheet(
+    tiles: &[(image::RgbImage, f64)],
+    options: &ContactSheetOptions,
+) -> image::RgbImage {
+    let tile_width = tiles.iter().map(|(tile, _)| tile.width()).max().unwrap_or(0);
+    let tile_height = options.tile_height;
+    let columns = options.columns.unwrap_or(tiles.len()).clamp(1, tiles.len().max(1));
+    let rows = tiles.len().div_ceil(columns);
+    let spacing = options.spacing;
+
+    let mut sheet = image::RgbImage::from_pixel(
+        columns as u32 * (tile_width + spacing) + spacing,
+        rows as u32 * (tile_height + spacing) + spacing,
+        SHEET_BACKGROUND,
+    );
+
+    for (index, (tile, timestamp_secs)) in tiles.iter().enumerate() {
+        let mut tile = tile.clone();
+        if options.show_timestamps {
+            draw_label(&mut tile, &format_timestamp(*timestamp_secs));
+        }
+
+        let column = (index % columns) as u32;
+        let row = (index / columns) as u32;
+        let x = spacing + column * (tile_width + spacing);
+        let y = spacing + row * (tile_height + spacing);
+        image::imageops::replace(&mut sheet, &tile, i64::from(x), i64::from(y));
+    }
+
+    sheet
+}
+
+/// Timestamp as `m:ss.s`
+fn format_timestamp(secs: f64) -> String {
+    let tenths = (secs.max(0.0) * 10.0).round() as u64;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+/// Rows of a 3x5 pixel glyph, the high bit of each row is its left pixel
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0; 5],
+    }
+}
+
+/// Draw white text on a dark box in the bottom left corner of `tile`
+/// Glyphs scale with the tile, pixels falling outside it are dropped
+fn draw_label(tile: &mut image::RgbImage, text: &str) {
+    let scale = (tile.height() / 40).max(1);
+    let box_width = (text.chars().count() as u32 * 4 + 1) * scale;
+    let box_height = 7 * scale;
+    let top = tile.height().saturating_sub(box_height);
+
+    let mut put = |x: u32, y: u32, color: image::Rgb<u8>| {
+        if x < tile.width() && y < tile.height() {
+            tile.put_pixel(x, y, color);
+        }
+    };
+
+    for y in top..top + box_height {
+        for x in 0..box_width {
+            put(x, y, SHEET_BACKGROUND);
+        }
+    }
+
+    for (index, c) in text.chars().enumerate() {
+        let left = (index as u32 * 4 + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = left + column * scale + dx;
+                        let y = top + (row as u32 + 1) * scale + dy;
+                        put(x, y, image::Rgb([255, 255, 255]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visual_grouping::grouping::group_assets_by_visual_similarity;
+    use crate::visual_grouping::{
+        Asset, AssetKind, AssetSource, DEFAULT_SCENE_CUT_THRESHOLD, GroupingOptions,
+    };
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    /// Encode a test video with the ffmpeg CLI from lavfi inputs
+    /// Tests calling it are ignored by default, run them with `--ignored`
+    fn generate_video(dir: &Path, file_name: &str, args: &[&str]) -> PathBuf {
+        let output = dir.join(file_name);
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-v", "error"])
+            .args(args)
+            .arg(&output)
+            .status()
+            .expect("ffmpeg CLI not found");
+        assert!(status.success(), "ffmpeg failed to generate {}: {}", file_name, status);
+        output
+    }
+
+    /// Red, green and blue segments of two seconds each
+    fn three_color_video(dir: &Path) -> PathBuf {
+        generate_video(
+            dir,
+            "colors.mp4",
+            &[
+                "-f", "lavfi", "-i", "color=c=red:s=64x64:r=25:d=2",
+                "-f", "lavfi", "-i", "color=c=lime:s=64x64:r=25:d=2",
+                "-f", "lavfi", "-i", "color=c=blue:s=64x64:r=25:d=2",
+                "-filter_complex", "concat=n=3:v=1:a=0",
+                "-pix_fmt", "yuv420p",
+            ],
+        )
+    }
+
+    /// Index of the strongest color channel of the image
+    fn dominant_channel(image: &image::RgbImage) -> usize {
+        let mut sums = [0u64; 3];
+        for pixel in image.pixels() {
+            for (sum, value) in sums.iter_mut().zip(pixel.0) {
+                *sum += value as u64;
+            }
+        }
+        (0..3).max_by_key(|&channel| sums[channel]).unwrap_or(0)
+    }
+
+    /// Encode a WebM to a pipe, the muxer can't seek back to write the duration
+    fn streamed_webm_video(dir: &Path) -> PathBuf {
+        let output = dir.join("streamed.webm");
+        let file = std::fs::File::create(&output).unwrap();
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-v", "error", "-f", "lavfi", "-i", "testsrc=s=64x64:r=25:d=3"])
+            .args(["-c:v", "libvpx", "-f", "webm", "pipe:1"])
+            .stdout(file)
+            .status()
+            .expect("ffmpeg CLI not found");
+        assert!(status.success(), "ffmpeg failed to encode VP8: {}", status);
+        output
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI with libvpx"]
+    fn test_duration_without_container_duration() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let video_path = streamed_webm_video(temp_dir.path());
+
+        let duration = get_video_duration(&video_path).unwrap();
+        assert!((duration - 3.0).abs() < 0.2, "duration was {}", duration);
+
+        let stats =
+            extract_frames_as_images(&video_path, &FrameSamplingConfig::default(), None, |_, _, _| {
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(stats.extracted_frames, 3);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_seeking_extracts_frames_over_time() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let video_path = three_color_video(temp_dir.path());
+
+        let mut channels = Vec::new();
+        let mut timestamps = Vec::new();
+        // Solid colors are uniform frames, keep them
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(2.0),
+            min_luma_variance: 0.0,
+            ..FrameSamplingConfig::default()
+        };
+        let stats = extract_frames_as_images(&video_path, &sampling, None, |_, time, frame| {
+            channels.push(dominant_channel(&frame));
+            timestamps.push(time);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(stats.extracted_frames, 3);
+        assert_eq!(channels, vec![0, 1, 2]);
+        for (timestamp, expected) in timestamps.iter().zip([0.0, 2.0, 4.0]) {
+            assert!((timestamp - expected).abs() < 0.05, "frame at {}s", timestamp);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_sequential_decode_samples_transport_stream() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // MPEG-TS carries no index, one keyframe every ten seconds
+        let video_path = generate_video(
+            temp_dir.path(),
+            "long_gop.ts",
+            &[
+                "-f", "lavfi", "-i", "testsrc=s=64x64:r=25:d=4",
+                "-g", "250", "-pix_fmt", "yuv420p",
+                "-muxdelay", "0", "-muxpreload", "0",
+            ],
+        );
+
+        let sample = |extraction_mode| {
+            let sampling = FrameSamplingConfig {
+                strategy: FrameStrategy::Interval(1.0),
+                extraction_mode,
+                ..FrameSamplingConfig::default()
+            };
+            let mut samples = Vec::new();
+            let stats = extract_frames_as_images(&video_path, &sampling, None, |index, time, _| {
+                samples.push((index, time));
+                Ok(())
+            })
+            .unwrap();
+            (stats, samples)
+        };
+
+        let (stats, samples) = sample(ExtractionMode::SequentialDecode);
+        assert_eq!(stats.extracted_frames, 4);
+        assert!(!stats.is_degraded());
+        for (&(index, time), expected) in samples.iter().zip([0.0, 1.0, 2.0, 3.0]) {
+            assert_eq!(index as f64, expected);
+            assert!((time - expected).abs() < 0.03, "frame {} at {}s", index, time);
+        }
+
+        let (stats, seeked) = sample(ExtractionMode::Seek);
+        assert_eq!(stats.extracted_frames, 4);
+        assert_eq!(seeked.len(), samples.len());
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_hw_accel_matches_software_decoding() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let video_path = generate_video(
+            temp_dir.path(),
+            "pattern.mp4",
+            &["-f", "lavfi", "-i", "testsrc2=s=256x144:r=25:d=3", "-pix_fmt", "yuv420p"],
+        );
+
+        // Without a usable device the hardware option quietly decodes in software
+        let sample = |hw_accel| {
+            let sampling = FrameSamplingConfig {
+                hw_accel,
+                ..FrameSamplingConfig::default()
+            };
+            let mut frames = Vec::new();
+            let stats = extract_frames_as_images(&video_path, &sampling, None, |_, time, frame| {
+                frames.push((time, frame));
+                Ok(())
+            })
+            .unwrap();
+            (stats, frames)
+        };
+        let (software_stats, software) = sample(false);
+        let (hardware_stats, hardware) = sample(true);
+
+        assert!(!software_stats.hardware_decoded);
+        assert!(!hardware_stats.is_degraded());
+        assert_eq!(hardware.len(), software.len());
+        for ((time, software), (hardware_time, hardware)) in software.iter().zip(&hardware) {
+            assert!((time - hardware_time).abs() < 0.001);
+            assert_eq!(software.dimensions(), hardware.dimensions());
+            let difference: u64 = software
+                .as_raw()
+                .iter()
+                .zip(hardware.as_raw())
+                .map(|(a, b)| u64::from(a.abs_diff(*b)))
+                .sum();
+            let mean = difference as f64 / software.as_raw().len() as f64;
+            assert!(mean < 4.0, "frame at {}s differs by {} on average", time, mean);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_sampling_ignores_stream_start_offset() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let video_path = generate_video(
+            temp_dir.path(),
+            "offset.mp4",
+            &[
+                "-f", "lavfi", "-i", "testsrc=s=64x64:r=25:d=4",
+                "-pix_fmt", "yuv420p", "-output_ts_offset", "1.4",
+            ],
+        );
+
+        let video = VideoDecoder::open(&video_path, None, false).unwrap();
+        let start_secs = video.clock.start_pts as f64 * video.clock.time_base;
+        assert!((start_secs - 1.4).abs() < 0.05, "stream starts at {}s", start_secs);
+        drop(video);
+
+        for extraction_mode in [ExtractionMode::Seek, ExtractionMode::SequentialDecode] {
+            let sampling = FrameSamplingConfig {
+                strategy: FrameStrategy::Interval(1.0),
+                extraction_mode,
+                ..FrameSamplingConfig::default()
+            };
+            let mut times = Vec::new();
+            let stats = extract_frames_as_images(&video_path, &sampling, None, |_, time, _| {
+                times.push(time);
+                Ok(())
+            })
+            .unwrap();
+
+            assert_eq!(stats.extracted_frames, 4, "{:?}", extraction_mode);
+            assert!(!stats.is_degraded());
+            for (time, expected) in times.iter().zip([0.0, 1.0, 2.0, 3.0]) {
+                assert!((time - expected).abs() < 0.03, "{:?}: {}s", extraction_mode, time);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_timeout_keeps_frames_found_before_it() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let video_path = three_color_video(temp_dir.path());
+
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(2.0),
+            timeout_secs: Some(0.2),
+            min_luma_variance: 0.0,
+            ..FrameSamplingConfig::default()
+        };
+        // A slow first frame uses up the whole budget
+        let stats = extract_frames_as_images(&video_path, &sampling, None, |_, _, _| {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stats.extracted_frames, 1);
+        assert_eq!(stats.skipped_frames, 2);
+        assert!(stats.timed_out && stats.is_degraded());
+
+        let instant = FrameSamplingConfig {
+            timeout_secs: Some(1e-9),
+            ..sampling
+        };
+        let err = extract_frames_as_images(&video_path, &instant, None, |_, _, _| Ok(()))
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_scene_sampling_captures_each_scene() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Color bars, then the same bars mirrored so the blue bar moves to the left edge
+        let video_path = generate_video(
+            temp_dir.path(),
+            "scenes.mp4",
+            &[
+                "-f", "lavfi", "-i", "smptebars=s=64x64:r=25:d=2",
+                "-f", "lavfi", "-i", "smptebars=s=64x64:r=25:d=2",
+                "-filter_complex", "[1]hflip[mirrored];[0][mirrored]concat=n=2:v=1:a=0",
+                "-pix_fmt", "yuv420p",
+            ],
+        );
+
+        let mut left_edges = Vec::new();
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Scenes {
+                max: 8,
+                cut_threshold: DEFAULT_SCENE_CUT_THRESHOLD,
+            },
+            ..FrameSamplingConfig::default()
+        };
+        let stats = extract_frames_as_images(&video_path, &sampling, None, |_, _, frame| {
+            left_edges.push(frame.get_pixel(1, 1).0);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(stats.extracted_frames, 2);
+        let [first, second] = [left_edges[0], left_edges[1]];
+        assert!(first[2] < first[0] + 40, "first scene starts gray: {:?}", first);
+        assert!(second[2] > second[0] + 100, "second scene starts blue: {:?}", second);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_rotation_metadata_is_applied() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Red left half and blue right half, 64x32 as coded
+        let landscape_path = generate_video(
+            temp_dir.path(),
+            "landscape.mp4",
+            &[
+                "-f", "lavfi", "-i", "color=c=red:s=32x32:r=25:d=1",
+                "-f", "lavfi", "-i", "color=c=blue:s=32x32:r=25:d=1",
+                "-filter_complex", "hstack",
+                "-pix_fmt", "yuv420p",
+            ],
+        );
+        // Tag a 90 degree counterclockwise display rotation without re-encoding
+        let video_path = generate_video(
+            temp_dir.path(),
+            "rotated.mp4",
+            &["-display_rotation", "90", "-i", landscape_path.to_str().unwrap(), "-c", "copy"],
+        );
+
+        assert_eq!(get_video_dimension(&video_path).unwrap(), (32, 64));
+
+        let mut frames = Vec::new();
+        extract_frames_as_images(&video_path, &FrameSamplingConfig::default(), None, |_, _, frame| {
+            frames.push(frame);
+            Ok(())
+        })
+        .unwrap();
+
+        // Turned upright the right half ends up on top
+        let frame = &frames[0];
+        assert_eq!(frame.dimensions(), (32, 64));
+        let top = image::imageops::crop_imm(frame, 0, 0, 32, 32).to_image();
+        let bottom = image::imageops::crop_imm(frame, 0, 32, 32, 32).to_image();
+        assert_eq!(dominant_channel(&top), 2);
+        assert_eq!(dominant_channel(&bottom), 0);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_main_stream_is_chosen_over_preview_and_cover_art() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cover_path = generate_video(
+            temp_dir.path(),
+            "cover.png",
+            &["-f", "lavfi", "-i", "color=c=orange:s=640x360", "-frames:v", "1"],
+        );
+        // A short preview stream first, the main stream, then cover art larger than both
+        let video_path = generate_video(
+            temp_dir.path(),
+            "with_preview.mp4",
+            &[
+                "-f", "lavfi", "-i", "testsrc=s=32x18:r=25:d=1",
+                "-f", "lavfi", "-i", "testsrc2=s=128x72:r=25:d=3",
+                "-i", cover_path.to_str().unwrap(),
+                "-map", "0", "-map", "1", "-map", "2",
+                "-pix_fmt", "yuv420p", "-c:v:2", "mjpeg", "-disposition:v:2", "attached_pic",
+            ],
+        );
+
+        assert_eq!(get_video_dimension(&video_path).unwrap(), (128, 72));
+        let metadata = probe_video(&video_path).unwrap();
+        assert_eq!((metadata.coded_width, metadata.coded_height), (128, 72));
+        assert!((metadata.duration - 3.0).abs() < 0.1, "duration was {}", metadata.duration);
+
+        let mut sizes = Vec::new();
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(1.0),
+            ..FrameSamplingConfig::default()
+        };
+        let stats = extract_frames_as_images(&video_path, &sampling, None, |_, _, frame| {
+            sizes.push(frame.dimensions());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stats.extracted_frames, 3);
+        assert!(sizes.iter().all(|&size| size == (128, 72)), "{:?}", sizes);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_anamorphic_video_uses_display_size() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // 32x32 coded with pixels twice as wide as they are tall
+        let video_path = generate_video(
+            temp_dir.path(),
+            "anamorphic.mp4",
+            &["-f", "lavfi", "-i", "testsrc=s=32x32:r=25:d=1,setsar=2", "-pix_fmt", "yuv420p"],
+        );
+
+        let metadata = probe_video(&video_path).unwrap();
+        assert_eq!((metadata.coded_width, metadata.coded_height), (32, 32));
+        assert_eq!(metadata.pixel_format, "yuv420p");
+        assert_eq!(metadata.fps, Some(25.0));
+        assert!((metadata.duration - 1.0).abs() < 0.1, "duration was {}", metadata.duration);
+        assert_eq!(metadata.sample_aspect_ratio, (2, 1));
+        assert_eq!(get_video_dimension(&video_path).unwrap(), (64, 32));
+
+        let mut sizes = Vec::new();
+        extract_frames_as_images(&video_path, &FrameSamplingConfig::default(), None, |_, _, frame| {
+            sizes.push(frame.dimensions());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sizes, vec![(64, 32)]);
+
+        // Frames shrink to the cap while the probed size stays the source size
+        let capped = FrameSamplingConfig {
+            max_dimension: Some(16),
+            ..FrameSamplingConfig::default()
+        };
+        sizes.clear();
+        extract_frames_as_images(&video_path, &capped, None, |_, _, frame| {
+            sizes.push(frame.dimensions());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sizes, vec![(16, 8)]);
+        assert_eq!(get_video_dimension(&video_path).unwrap(), (64, 32));
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_videos_fading_to_black_do_not_group() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // One second of content fading out, then three seconds of black
+        let fading = |file_name: &str, source: &str| {
+            generate_video(
+                temp_dir.path(),
+                file_name,
+                &[
+                    "-f", "lavfi", "-i", source,
+                    "-f", "lavfi", "-i", "color=c=black:s=64x64:r=25:d=3",
+                    "-filter_complex", "[0]fade=t=out:st=0.6:d=0.4[faded];[faded][1]concat",
+                    "-pix_fmt", "yuv420p",
+                ],
+            )
+        };
+        let black = |file_name: &str, duration: &str| {
+            let source = format!("color=c=black:s=64x64:r=25:d={}", duration);
+            generate_video(temp_dir.path(), file_name, &["-f", "lavfi", "-i", source.as_str()])
+        };
+        let (bars, pattern, black_short, black_long) = (
+            fading("bars.mp4", "smptebars=s=64x64:r=25:d=1"),
+            fading("pattern.mp4", "testsrc=s=64x64:r=25:d=1"),
+            black("black_short.mp4", "2"),
+            black("black_long.mp4", "3"),
+        );
+
+        let sampling = FrameSamplingConfig::default();
+        let stats = extract_frames_as_images(&bars, &sampling, None, |_, _, _| Ok(())).unwrap();
+        assert!(stats.uniform_frames > 0 && !stats.only_uniform);
+        let mut kept = Vec::new();
+        let stats = extract_frames_as_images(&black_short, &sampling, None, |_, _, frame| {
+            kept.push(frame);
+            Ok(())
+        })
+        .unwrap();
+        assert!(stats.only_uniform && !stats.is_degraded());
+        assert_eq!(kept.len(), 1);
+
+        let assets = [&bars, &pattern, &black_short, &black_long]
+            .into_iter()
+            .map(|path| Asset {
+                id: path.file_stem().unwrap().to_string_lossy().into_owned(),
+                name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                source: AssetSource::Path(path.clone()),
+                mime_type: "video/mp4".to_string(),
+                is_video: true,
+                precomputed: None,
+                kind: AssetKind::File,
+            })
+            .collect();
+        // Lenient enough that the shared black frames alone used to group them
+        let options = GroupingOptions {
+            match_frames_ratio: 0.5,
+            ..GroupingOptions::default()
+        };
+        let result = group_assets_by_visual_similarity(assets, Some(options), None, None).unwrap();
+
+        assert!(result.failed.is_empty());
+        assert_eq!(result.groups.len(), 4);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_hdr_and_sdr_encodes_group_together() {
+        use crate::visual_grouping::tonemap::{SDR_WHITE_NITS, nits_to_pq};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Colored quadrants over a horizontal ramp, in 8 bit BT.709
+        let picture = image::RgbImage::from_fn(64, 64, |x, y| {
+            let ramp = (x * 4) as u8;
+            match (x < 32, y < 32) {
+                (true, true) => image::Rgb([200, 40, 40]),
+                (false, true) => image::Rgb([40, 160, 60]),
+                (true, false) => image::Rgb([ramp, ramp, ramp]),
+                (false, false) => image::Rgb([50, 70, 190]),
+            }
+        });
+
+        // The same picture as PQ coded BT.2020 light, SDR white at 203 nits
+        let bt709_to_bt2020 = [
+            [0.6274, 0.3293, 0.0433],
+            [0.0691, 0.9195, 0.0114],
+            [0.0164, 0.0880, 0.8956],
+        ];
+        let mut hdr_raw = Vec::new();
+        for pixel in picture.pixels() {
+            let linear = pixel.0.map(|value| (value as f64 / 255.0).powf(2.4));
+            for row in bt709_to_bt2020 {
+                let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                let code = (nits_to_pq(value * SDR_WHITE_NITS) * u16::MAX as f64).round() as u16;
+                hdr_raw.extend_from_slice(&code.to_le_bytes());
+            }
+        }
+        let sdr_input = temp_dir.path().join("sdr.rgb");
+        let hdr_input = temp_dir.path().join("hdr.rgb");
+        std::fs::write(&sdr_input, picture.as_raw().repeat(20)).unwrap();
+        std::fs::write(&hdr_input, hdr_raw.repeat(20)).unwrap();
+
+        let encode = |file_name: &str, input: &Path, input_format: &str, tags: &[&str]| {
+            let mut args = vec![
+                "-f", "rawvideo", "-pix_fmt", input_format, "-s", "64x64", "-r", "10",
+                "-i", input.to_str().unwrap(),
+                "-c:v", "ffv1",
+            ];
+            args.extend_from_slice(tags);
+            generate_video(temp_dir.path(), file_name, &args)
+        };
+        let (sdr, hdr) = (
+            encode(
+                "sdr.mkv",
+                &sdr_input,
+                "rgb24",
+                &[
+                    "-vf", "scale=out_color_matrix=bt709:out_range=tv", "-pix_fmt", "yuv420p",
+                    "-colorspace", "bt709", "-color_primaries", "bt709", "-color_trc", "bt709",
+                ],
+            ),
+            encode(
+                "hdr.mkv",
+                &hdr_input,
+                "rgb48le",
+                &[
+                    "-vf", "scale=out_color_matrix=bt2020:out_range=tv", "-pix_fmt", "yuv420p10le",
+                    "-colorspace", "bt2020nc", "-color_primaries", "bt2020",
+                    "-color_trc", "smpte2084",
+                ],
+            ),
+        );
+        let first_frame = |path: &Path| {
+            let mut frames = Vec::new();
+            let sampling = FrameSamplingConfig::default();
+            extract_frames_as_images(path, &sampling, None, |_, _, frame| {
+                frames.push(frame);
+                Ok(())
+            })
+            .unwrap();
+            frames.swap_remove(0)
+        };
+        let (sdr_frame, hdr_frame) = (first_frame(&sdr), first_frame(&hdr));
+        // Away from the chroma edges the colors agree up to the shoulder
+        let (sdr_red, hdr_red) = (sdr_frame.get_pixel(8, 8).0, hdr_frame.get_pixel(8, 8).0);
+        for (sdr_value, hdr_value) in sdr_red.iter().zip(hdr_red) {
+            assert!(sdr_value.abs_diff(hdr_value) <= 12, "{:?} vs {:?}", sdr_red, hdr_red);
+        }
+
+        let assets = [&sdr, &hdr]
+            .into_iter()
+            .map(|path| Asset {
+                id: path.file_stem().unwrap().to_string_lossy().into_owned(),
+                name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                source: AssetSource::Path(path.clone()),
+                mime_type: "video/x-matroska".to_string(),
+                is_video: true,
+                precomputed: None,
+                kind: AssetKind::File,
+            })
+            .collect();
+        let result = group_assets_by_visual_similarity(assets, None, None, None).unwrap();
+
+        assert!(result.failed.is_empty());
+        assert_eq!(result.groups.len(), 1);
+        assert_eq!(result.groups[0].assets.len(), 2);
+    }
+
+    #[test]
+    fn test_contact_sheet_layout() {
+        let tile = |color| (image::RgbImage::from_pixel(16, 10, image::Rgb(color)), 0.0);
+        let tiles = [tile([255, 0, 0]), tile([0, 255, 0]), tile([0, 0, 255])];
+        let options = ContactSheetOptions {
+            tile_height: 10,
+            columns: Some(2),
+            spacing: 2,
+            show_timestamps: false,
+            ..ContactSheetOptions::default()
+        };
+
+        let sheet = compose_contact_sheet(&tiles, &options);
+        assert_eq!(sheet.dimensions(), (38, 26));
+        assert_eq!(sheet.get_pixel(2, 2).0, [255, 0, 0]);
+        assert_eq!(sheet.get_pixel(20, 2).0, [0, 255, 0]);
+        assert_eq!(sheet.get_pixel(2, 14).0, [0, 0, 255]);
+        assert_eq!(*sheet.get_pixel(20, 14), SHEET_BACKGROUND);
+
+        let single_row = ContactSheetOptions {
+            columns: None,
+            ..options.clone()
+        };
+        let row = compose_contact_sheet(&tiles, &single_row);
+        assert_eq!(row.dimensions(), (56, 14));
+
+        // The label covers the bottom of the tile, the top keeps the frame
+        let labelled = compose_contact_sheet(
+            &tiles[..1],
+            &ContactSheetOptions { show_timestamps: true, ..options.clone() },
+        );
+        assert_eq!(*labelled.get_pixel(2, 11), SHEET_BACKGROUND);
+        assert_eq!(labelled.get_pixel(2, 2).0, [255, 0, 0]);
+
+        assert_eq!(format_timestamp(0.0), "0:00.0");
+        assert_eq!(format_timestamp(65.25), "1:05.3");
+    }
+
+    #[test]
+    fn test_fit_within_keeps_aspect_ratio() {
+        assert_eq!(fit_within((3840, 2160), 512), (512, 288));
+        assert_eq!(fit_within((1080, 1920), 512), (288, 512));
+        assert_eq!(fit_within((640, 360), 512), (512, 288));
+        assert_eq!(fit_within((320, 240), 512), (320, 240));
+        assert_eq!(fit_within((10000, 1), 512), (512, 1));
+    }
+
+    #[test]
+    fn test_padded_rows_are_copied_without_skew() {
+        // 30 px wide RGB24 rows are 90 bytes, padded to 96 like ffmpeg's 32 byte alignment
+        let (width, height, stride) = (30u32, 4u32, 96usize);
+        let mut data = vec![0xAAu8; stride * height as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let offset = y * stride + x * 3;
+                data[offset..offset + 3].copy_from_slice(&[x as u8, y as u8, (x + y) as u8]);
+            }
+        }
+
+        let image = rgb_image_from_strided(&data, stride, width, height).unwrap();
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let frame_path = temp_dir.path().join("frame.png");
+        image.save(&frame_path).unwrap();
+        let decoded = image::open(&frame_path).unwrap().to_rgb8();
+
+        assert_eq!(decoded.dimensions(), (width, height));
+        for (x, y, pixel) in decoded.enumerate_pixels() {
+            assert_eq!(pixel.0, [x as u8, y as u8, (x + y) as u8]);
+        }
+    }
+
+    #[test]
+    fn test_truncated_frame_data_is_rejected() {
+        let data = vec![0u8; 96 * 3];
+        assert!(rgb_image_from_strided(&data, 96, 30, 4).is_err());
+        assert!(rgb_image_from_strided(&data, 60, 30, 1).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_audio_mode_splits_videos_with_different_voiceovers() {
+        use crate::visual_grouping::AudioMode;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let encode = |file_name: &str, audio: Option<&str>| {
+            let mut args = vec!["-f", "lavfi", "-i", "testsrc=s=64x64:r=10:d=4"];
+            if let Some(audio) = audio {
+                args.extend(["-f", "lavfi", "-i", audio, "-c:a", "aac", "-ac", "2", "-shortest"]);
+            }
+            args.extend(["-pix_fmt", "yuv420p"]);
+            generate_video(temp_dir.path(), file_name, &args)
+        };
+        // Same picture throughout, "b" is "a" in another container
+        let english = "anoisesrc=d=4:c=pink:seed=1";
+        let (a, b, c, d) = (
+            encode("a.mp4", Some(english)),
+            encode("b.mkv", Some(english)),
+            encode("c.mp4", Some("anoisesrc=d=4:c=pink:seed=2")),
+            encode("d.mp4", None),
+        );
+
+        assert!(!audio_fingerprint(&a).unwrap().unwrap().codes.is_empty());
+        assert_eq!(audio_fingerprint(&a).unwrap(), audio_fingerprint(&b).unwrap());
+        assert_eq!(audio_fingerprint(&d).unwrap(), None);
+
+        let group = |audio_mode| {
+            let assets = [&a, &b, &c, &d]
+                .into_iter()
+                .map(|path| Asset {
+                    id: path.file_stem().unwrap().to_string_lossy().into_owned(),
+                    name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                    source: AssetSource::Path(path.clone()),
+                    mime_type: "video/mp4".to_string(),
+                    is_video: true,
+                    precomputed: None,
+                    kind: AssetKind::File,
+                })
+                .collect();
+            let options = GroupingOptions {
+                audio_mode,
+                ..GroupingOptions::default()
+            };
+            let result = group_assets_by_visual_similarity(assets, Some(options), None, None);
+            let mut groups = result.unwrap().groups;
+            groups.sort_by(|group1, group2| group1.id.cmp(&group2.id));
+            groups
+        };
+        let ids = |group: &crate::visual_grouping::AssetGroup| -> Vec<String> {
+            group.assets.iter().map(|member| member.asset.id.clone()).collect()
+        };
+
+        assert_eq!(group(AudioMode::Ignore).len(), 1);
+
+        let separate = group(AudioMode::Separate);
+        let mut separate: Vec<Vec<String>> = separate.iter().map(ids).collect();
+        separate.sort();
+        assert_eq!(separate, [vec!["a", "b"], vec!["c"], vec!["d"]]);
+
+        // One group, the voiceover that differs from "a" and the silent cut are flagged
+        let annotate = group(AudioMode::Annotate);
+        assert_eq!(annotate.len(), 1);
+        assert_eq!(annotate[0].representative_asset_id, "a");
+        let differs: Vec<&str> = annotate[0]
+            .assets
+            .iter()
+            .filter(|member| member.audio_differs)
+            .map(|member| member.asset.id.as_str())
+            .collect();
+        assert_eq!(differs, ["c", "d"]);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_audio_only_files_fail_alone_and_mislabeled_images_recover() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let voiceover = generate_video(
+            temp_dir.path(),
+            "voiceover.m4a",
+            &["-f", "lavfi", "-i", "sine=d=1", "-c:a", "aac"],
+        );
+        // TGA has no magic bytes, so only decoding tells it apart from a video
+        let poster = image::RgbImage::from_fn(64, 64, |x, y| {
+            let check = if (x / 16 + y / 16) % 2 == 0 { 30 } else { 220 };
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, check])
+        });
+        let tga = temp_dir.path().join("poster.tga");
+        let png = temp_dir.path().join("poster.png");
+        poster.save(&tga).unwrap();
+        poster.save(&png).unwrap();
+
+        let asset = |path: &Path, mime_type: &str, is_video: bool| Asset {
+            id: path.file_name().unwrap().to_string_lossy().into_owned(),
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            source: AssetSource::Path(path.to_path_buf()),
+            mime_type: mime_type.to_string(),
+            is_video,
+            precomputed: None,
+            kind: AssetKind::File,
+        };
+        let assets = vec![
+            asset(&voiceover, "audio/mp4", true),
+            asset(&tga, "video/mp4", true),
+            asset(&png, "image/png", false),
+        ];
+        assert!(probe_video(&voiceover).unwrap_err().is::<NoVideoStream>());
+
+        // Neither aborts the batch, even when failures are meant to
+        let options = GroupingOptions {
+            fail_fast: true,
+            ..GroupingOptions::default()
+        };
+        let result = group_assets_by_visual_similarity(assets, Some(options), None, None).unwrap();
+
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].asset_id, "voiceover.m4a");
+        assert_eq!(result.failed[0].reason, "no video stream");
+        assert_eq!(result.groups.len(), 1);
+        let members = &result.groups[0].assets;
+        assert_eq!(members.len(), 2);
+        assert!(members.iter().all(|member| !member.asset.is_video));
+        assert_eq!(members[1].asset.mime_type, "image/x-targa");
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_decode_errors_are_counted_and_extraction_carries_on() {
+        assert!(is_drained(&ffmpeg::Error::Eof));
+        assert!(is_drained(&ffmpeg::Error::Other {
+            errno: ffmpeg::util::error::EAGAIN,
+        }));
+        assert!(!is_drained(&ffmpeg::Error::InvalidData));
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Every MJPEG packet is a whole JPEG, so one broken frame stays one packet
+        let video_path = generate_video(
+            temp_dir.path(),
+            "mjpeg.avi",
+            &[
+                "-f", "lavfi", "-i", "testsrc=s=64x64:r=10:d=3",
+                "-c:v", "mjpeg", "-pix_fmt", "yuvj420p",
+            ],
+        );
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(0.5),
+            ..FrameSamplingConfig::default()
+        };
+
+        let clean =
+            extract_frames_as_images(&video_path, &sampling, None, |_, _, _| Ok(())).unwrap();
+        assert_eq!(clean.extracted_frames, 6);
+        assert_eq!(clean.decode_errors, 0);
+
+        // Break the start of image marker of the frames in the second second
+        let mut bytes = std::fs::read(&video_path).unwrap();
+        let markers: Vec<usize> = bytes
+            .windows(3)
+            .enumerate()
+            .filter(|(_, window)| *window == [0xFF, 0xD8, 0xFF])
+            .map(|(offset, _)| offset)
+            .collect();
+        assert_eq!(markers.len(), 30);
+        for &offset in &markers[10..20] {
+            bytes[offset..offset + 3].fill(0);
+        }
+        let corrupt_path = temp_dir.path().join("corrupt.avi");
+        std::fs::write(&corrupt_path, bytes).unwrap();
+
+        let corrupt =
+            extract_frames_as_images(&corrupt_path, &sampling, None, |_, _, _| Ok(())).unwrap();
+        assert!(corrupt.decode_errors > 0);
+        assert!(corrupt.extracted_frames >= 4, "{:?}", corrupt);
+    }
+
+    #[test]
+    #[ignore = "needs the ffmpeg CLI"]
+    fn test_extracts_encoded_frames_in_memory() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let video_path = three_color_video(temp_dir.path());
+        let sampling = FrameSamplingConfig {
+            strategy: FrameStrategy::Interval(2.0),
+            min_luma_variance: 0.0,
+            ..FrameSamplingConfig::default()
+        };
+
+        let png = FrameFormat::Png;
+        let frames = extract_encoded_frames(&video_path, &sampling, png, None).unwrap();
+        assert_eq!(frames.len(), 3);
+        let expected = [(0.0, 0), (2.0, 1), (4.0, 2)];
+        for (frame, (expected_time, expected_channel)) in frames.iter().zip(expected) {
+            assert!((frame.timestamp_secs - expected_time).abs() < 0.05);
+            assert_eq!(image::guess_format(&frame.bytes).unwrap(), image::ImageFormat::Png);
+            let decoded = image::load_from_memory(&frame.bytes).unwrap().to_rgb8();
+            assert_eq!(decoded.dimensions(), (64, 64));
+            assert_eq!(dominant_channel(&decoded), expected_channel);
+        }
+
+        let jpeg = FrameFormat::Jpeg { quality: 80 };
+        let frames = extract_encoded_frames(&video_path, &sampling, jpeg, None).unwrap();
+        assert!(frames.iter().all(|frame| frame.bytes.starts_with(&[0xFF, 0xD8])));
+        // Nothing was written next to the video
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
 }